@@ -0,0 +1,463 @@
+//! The module for the anonymization of snapshots for sharing them outside of the organisation (`--snapshot-anonymize <directory>`).
+//!
+//! The snapshots from `--begin` to `--end` (default all snapshots) are written as a redacted copy in a new snapshot directory,
+//! with their own snapshot.index file and the same snapshot numbers, so the copy can be used with `--snapshot-dir` like any snapshot directory,
+//! for example to create an export archive of it.
+//!
+//! The redaction is done per column of the CSV files with the rules in [RULES]:
+//! - Hostnames and IP addresses get a pseudonym (`host-1`), the port is kept: `192.168.66.80:9000` becomes `host-1:9000`.
+//! - Keyspace (database) and table names get a pseudonym (`keyspace-1`, `table-1`), except for the names of the system keyspaces and tables in [SYSTEM_NAMES].
+//! - SQL text has its literals replaced by `?`, see [strip_literals]. The parameters of YCQL calls are removed.
+//! - Free text, such as log messages, gflag values, node_exporter labels and SQL text, has the IP addresses, the hostnames and the names that have a pseudonym replaced.
+//!   The nodename of node_uname_info gets a host pseudonym, see [Anonymizer::uname_nodename].
+//!   The gflag values of flags with names such as password, key or cert are removed.
+//!
+//! The pseudonyms are the same in all files and all snapshots of the copy, so the redacted snapshots can be compared with a diff.
+//! To make sure that a name gets its pseudonym in free text in every snapshot, all snapshots are read once to assign the pseudonyms before the copy is written.
+//! The cluster name gets a pseudonym too, the comments of the snapshots are redacted as free text.
+//! The pprof and mems files contain hostnames in their names and data, and are not copied.
+use std::{fs, io::Read, collections::{BTreeMap, HashMap}, error::Error, path::Path};
+use regex::Regex;
+use log::*;
+use crate::snapshot::{Snapshot, snapshot_directory};
+use crate::schema::open_snapshot_csv;
+use crate::manifest::SnapshotManifest;
+use crate::storage::is_csv_file;
+use crate::compression::strip_compression_extension;
+use crate::archive::write_index;
+use crate::verify::VERIFY_FILES;
+
+/// The text that replaces a removed value.
+const REDACTED: &str = "<redacted>";
+/// The keyspace and table names of the system, which are not sensitive, and are kept.
+const SYSTEM_NAMES: &[&str] = &["system", "system_schema", "system_auth", "system_distributed", "system_traces", "system_platform", "system_redis",
+    "template0", "template1", "postgres", "yugabyte", "sys.catalog", "transactions"];
+/// The gflags with a name that matches this regex have their value removed.
+const SECRET_FLAGS: &str = r"(?i)password|passwd|secret|token|key|cert|credential|ldap|hba|ident|ssl|tls";
+/// The machine label values of node_uname_info, which are not a hostname.
+const UNAME_MACHINES: &[&str] = &["x86_64", "aarch64", "arm64", "ppc64le", "s390x"];
+
+/// The redaction of a column.
+#[derive(Debug, Clone, Copy)]
+pub enum Rule {
+    /// A hostname or IP address.
+    Host,
+    /// A hostname or IP address with an optional port.
+    HostPort,
+    Keyspace,
+    Table,
+    /// SQL text: the literals are removed, and the free text rule is applied.
+    Sql,
+    /// Free text: the IP addresses, hostnames and names with a pseudonym are replaced.
+    Text,
+    /// The value of a flag, of which the name is in the column: a secret flag is removed, otherwise the free text rule is applied.
+    Flag { name_column: &'static str },
+    /// The value is removed.
+    Remove,
+}
+
+/// The redaction rules: the file (`*` for all files), the column and the rule.
+pub const RULES: &[(&str, &str, Rule)] = &[
+    ("*", "hostname_port", Rule::HostPort),
+    ("endpoints", "host", Rule::Host),
+    ("master_rpc_addresses", "host", Rule::Host),
+    ("master_http_addresses", "host", Rule::Host),
+    ("master_errors", "message", Rule::Text),
+    ("master_errors", "errors", Rule::Text),
+    ("tablet_servers", "tserver_hostname_port", Rule::HostPort),
+    ("tablet_servers_pathmetrics", "tserver_hostname_port", Rule::HostPort),
+    ("replicas", "addr", Rule::HostPort),
+    ("keyspaces", "keyspace_name", Rule::Keyspace),
+    ("tables", "keyspace_name", Rule::Keyspace),
+    ("tables", "table_name", Rule::Table),
+    ("values", "attribute_namespace", Rule::Keyspace),
+    ("values", "attribute_table_name", Rule::Table),
+    ("countsum", "attribute_namespace", Rule::Keyspace),
+    ("countsum", "attribute_table_name", Rule::Table),
+    ("countsumrows", "attribute_namespace", Rule::Keyspace),
+    ("countsumrows", "attribute_table_name", Rule::Table),
+    ("statements", "query", Rule::Sql),
+    ("ysqlrpc", "db_name", Rule::Keyspace),
+    ("ysqlrpc", "host", Rule::Host),
+    ("ysqlrpc", "query", Rule::Sql),
+    ("inboundrpc", "remote_ip", Rule::HostPort),
+    ("outboundrpc", "remote_ip", Rule::HostPort),
+    ("cqldetails", "remote_ip", Rule::HostPort),
+    ("cqldetails", "keyspace", Rule::Keyspace),
+    ("cqldetails", "sql_string", Rule::Sql),
+    ("cqldetails", "params", Rule::Remove),
+    ("headers", "remote_ip", Rule::HostPort),
+    ("gflags", "gflag_value", Rule::Flag { name_column: "gflag_name" }),
+    ("vars", "value", Rule::Flag { name_column: "name" }),
+    ("loglines", "message", Rule::Text),
+    ("nodeexporter", "node_exporter_labels", Rule::Text),
+];
+
+/// The pseudonyms of the names, which are assigned in the order the names are found.
+pub struct Anonymizer {
+    pseudonyms: HashMap<(&'static str, String), String>,
+    counts: HashMap<&'static str, usize>,
+    ip_address: Regex,
+    word: Regex,
+    secret_flag: Regex,
+}
+
+impl Anonymizer {
+    pub fn new() -> Anonymizer {
+        Anonymizer {
+            pseudonyms: HashMap::new(),
+            counts: HashMap::new(),
+            ip_address: Regex::new(r"\b\d{1,3}\.\d{1,3}\.\d{1,3}\.\d{1,3}\b").unwrap(),
+            word: Regex::new(r"[A-Za-z0-9_]+").unwrap(),
+            secret_flag: Regex::new(SECRET_FLAGS).unwrap(),
+        }
+    }
+    /// The pseudonym of a name of a kind (host, keyspace, table or cluster). An empty name stays empty.
+    fn pseudonym(
+        &mut self,
+        kind: &'static str,
+        name: &str,
+    ) -> String
+    {
+        if name.is_empty() || (kind != "host" && SYSTEM_NAMES.contains(&name)) {
+            return name.to_string();
+        }
+        if let Some(pseudonym) = self.pseudonyms.get(&(kind, name.to_string())) {
+            return pseudonym.to_string();
+        }
+        let count = self.counts.entry(kind).or_default();
+        *count += 1;
+        let pseudonym = format!("{}-{}", kind, count);
+        self.pseudonyms.insert((kind, name.to_string()), pseudonym.clone());
+        pseudonym
+    }
+    /// The pseudonym of a hostname or IP address with an optional port, which is kept.
+    fn host_port(
+        &mut self,
+        hostname_port: &str,
+    ) -> String
+    {
+        match hostname_port.rsplit_once(':') {
+            Some((host, port)) if !port.is_empty() && port.chars().all(|c| c.is_ascii_digit()) => format!("{}:{}", self.pseudonym("host", host), port),
+            _ => self.pseudonym("host", hostname_port),
+        }
+    }
+    /// This function assigns a host pseudonym to the nodename in the labels of node_uname_info.
+    /// The labels are the label values sorted and joined with `_` (see [crate::node_exporter]), so the label names are not known.
+    /// The values of sysname (Linux), version (`#1 SMP ...`), release and an empty domainname (`(none)`) sort before the lowercase values,
+    /// which leaves the nodename, the machine and a domainname after the sysname. A hostname can't contain `_`.
+    fn uname_nodename(
+        &mut self,
+        labels: &str,
+    )
+    {
+        let Some((_, lowercase_values)) = labels.rsplit_once("_Linux_") else { return };
+        let mut values = format!("_{}_", lowercase_values);
+        for machine in UNAME_MACHINES {
+            values = values.replace(&format!("_{}_", machine), "_");
+        }
+        for value in values.split('_').filter(|value| !value.is_empty() && value.chars().all(|c| c.is_alphanumeric() || c == '.' || c == '-')) {
+            self.pseudonym("host", value);
+        }
+    }
+    /// This function replaces the IP addresses, the hostnames and the names with a pseudonym in free text.
+    fn text(
+        &mut self,
+        text: &str,
+    ) -> String
+    {
+        let ip_addresses = self.ip_address.find_iter(text).map(|ip_address| ip_address.as_str().to_string()).collect::<Vec<String>>();
+        for ip_address in ip_addresses {
+            self.pseudonym("host", &ip_address);
+        }
+        let pseudonyms = &self.pseudonyms;
+        let mut text = self.ip_address.replace_all(text, |captures: &regex::Captures| pseudonyms[&("host", captures[0].to_string())].to_string()).to_string();
+        // The longest hostnames first, so a hostname that is a part of another hostname doesn't break the longer one.
+        let mut hosts = self.pseudonyms.iter()
+            .filter(|((kind, name), _)| *kind == "host" && !name.is_empty())
+            .map(|((_, name), pseudonym)| (name.to_string(), pseudonym.to_string()))
+            .collect::<Vec<(String, String)>>();
+        hosts.sort_by_key(|(name, _)| std::cmp::Reverse(name.len()));
+        for (name, pseudonym) in hosts {
+            text = text.replace(&name, &pseudonym);
+        }
+        self.word.replace_all(&text, |captures: &regex::Captures| {
+            ["keyspace", "table"].iter()
+                .find_map(|kind| self.pseudonyms.get(&(*kind, captures[0].to_string())))
+                .cloned()
+                .unwrap_or_else(|| captures[0].to_string())
+        }).to_string()
+    }
+    /// This function redacts a field with the rule. The flag name is the value of the name column for [Rule::Flag].
+    /// If free text is false, only the pseudonyms are assigned, and the free text is not changed.
+    pub fn field(
+        &mut self,
+        rule: Rule,
+        field: &str,
+        flag_name: &str,
+        free_text: bool,
+    ) -> String
+    {
+        match rule {
+            Rule::Host => self.pseudonym("host", field),
+            Rule::HostPort => self.host_port(field),
+            Rule::Keyspace => self.pseudonym("keyspace", field),
+            Rule::Table => self.pseudonym("table", field),
+            Rule::Remove if field.is_empty() => String::new(),
+            Rule::Remove => REDACTED.to_string(),
+            _ if !free_text => field.to_string(),
+            Rule::Sql => {
+                let sql = strip_literals(field);
+                self.text(&sql)
+            },
+            Rule::Flag { .. } if self.secret_flag.is_match(flag_name) && !field.is_empty() => REDACTED.to_string(),
+            Rule::Text | Rule::Flag { .. } => self.text(field),
+        }
+    }
+    /// This function redacts a CSV file with the rules of the file.
+    fn csv(
+        &mut self,
+        file: &str,
+        data: impl Read,
+        free_text: bool,
+    ) -> Result<Vec<u8>, Box<dyn Error>>
+    {
+        let mut reader = csv::Reader::from_reader(data);
+        let headers = reader.headers()?.clone();
+        let rules = headers.iter()
+            .map(|header| RULES.iter()
+                .find(|(rule_file, column, _)| (*rule_file == "*" || *rule_file == file) && *column == header)
+                .map(|(_, _, rule)| *rule))
+            .collect::<Vec<Option<Rule>>>();
+        let mut writer = csv::Writer::from_writer(Vec::new());
+        if !headers.is_empty() {
+            writer.write_record(&headers)?;
+        }
+        for record in reader.records() {
+            let record = record?;
+            if file == "nodeexporter" && headers.iter().position(|header| header == "node_exporter_name").and_then(|column| record.get(column)) == Some("node_uname_info") {
+                let labels = headers.iter().position(|header| header == "node_exporter_labels").and_then(|column| record.get(column)).unwrap_or_default();
+                self.uname_nodename(labels);
+            }
+            let mut redacted = Vec::new();
+            for (field, rule) in record.iter().zip(&rules) {
+                match rule {
+                    Some(rule) => {
+                        let flag_name = match rule {
+                            Rule::Flag { name_column } => headers.iter().position(|header| header == *name_column).and_then(|column| record.get(column)).unwrap_or_default(),
+                            _ => "",
+                        };
+                        redacted.push(self.field(*rule, field, flag_name, free_text));
+                    },
+                    None => redacted.push(field.to_string()),
+                }
+            }
+            writer.write_record(&redacted)?;
+        }
+        Ok(writer.into_inner()?)
+    }
+}
+
+/// This function replaces the literals in SQL text by `?`: the strings in single quotes, the dollar quoted strings and the numbers.
+/// The identifiers in double quotes and the parameters, such as `$1`, are kept.
+pub fn strip_literals(
+    sql: &str,
+) -> String
+{
+    let chars = sql.chars().collect::<Vec<char>>();
+    let is_identifier = |c: char| c.is_alphanumeric() || c == '_' || c == '$';
+    let mut result = String::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        if c == '\'' {
+            // A quote in a string is written as two quotes.
+            i += 1;
+            while i < chars.len() {
+                if chars[i] == '\'' && chars.get(i + 1) == Some(&'\'') {
+                    i += 2;
+                } else if chars[i] == '\'' {
+                    break;
+                } else {
+                    i += 1;
+                }
+            }
+            result.push('?');
+            i += 1;
+        } else if c == '"' {
+            let end = chars[i + 1..].iter().position(|&c| c == '"').map(|position| i + 1 + position).unwrap_or(chars.len() - 1);
+            result.extend(&chars[i..=end]);
+            i = end + 1;
+        } else if c == '$' && (i == 0 || !is_identifier(chars[i - 1])) && !chars.get(i + 1).map(|c| c.is_ascii_digit()).unwrap_or(false) {
+            // A dollar quoted string: $$text$$ or $tag$text$tag$.
+            let tag_end = chars[i + 1..].iter().position(|&c| !(c.is_alphanumeric() || c == '_')).map(|position| i + 1 + position);
+            match tag_end {
+                Some(tag_end) if chars[tag_end] == '$' => {
+                    let tag = chars[i..=tag_end].iter().collect::<String>();
+                    let rest = chars[tag_end + 1..].iter().collect::<String>();
+                    let end = rest.find(&tag).map(|position| tag_end + 1 + rest[..position].chars().count() + tag.chars().count()).unwrap_or(chars.len());
+                    result.push('?');
+                    i = end;
+                },
+                _ => {
+                    result.push(c);
+                    i += 1;
+                },
+            }
+        } else if c.is_ascii_digit() && (i == 0 || !is_identifier(chars[i - 1])) {
+            while i < chars.len() && (chars[i].is_ascii_alphanumeric() || chars[i] == '.') {
+                i += 1;
+            }
+            result.push('?');
+        } else {
+            result.push(c);
+            i += 1;
+        }
+    }
+    result
+}
+
+/// This function writes a redacted copy of the snapshots from begin to end (both optional) in the directory, and returns the copied snapshots.
+/// The directory must not contain snapshots already.
+pub fn anonymize_snapshots(
+    directory: &str,
+    begin: Option<i32>,
+    end: Option<i32>,
+) -> Result<Vec<Snapshot>, Box<dyn Error>>
+{
+    let target_directory = Path::new(directory);
+    if target_directory.join("snapshot.index").exists() {
+        return Err(format!("directory {} already contains snapshots", directory).into());
+    }
+    fs::create_dir_all(target_directory)?;
+    if fs::canonicalize(target_directory)? == fs::canonicalize(snapshot_directory())? {
+        return Err(format!("directory {} is the snapshot directory", directory).into());
+    }
+    let snapshots = Snapshot::read_snapshots().into_iter()
+        .filter(|snapshot| begin.map(|begin| snapshot.number >= begin).unwrap_or(true))
+        .filter(|snapshot| end.map(|end| snapshot.number <= end).unwrap_or(true))
+        .collect::<Vec<Snapshot>>();
+    if snapshots.is_empty() {
+        return Err("no snapshots selected for anonymization".into());
+    }
+
+    let mut anonymizer = Anonymizer::new();
+    // The first pass assigns the pseudonyms, so these are known for the free text in all snapshots.
+    for snapshot in &snapshots {
+        for verify_file in VERIFY_FILES {
+            if let Some(data) = read_csv(snapshot.number, verify_file.file)? {
+                anonymizer.csv(verify_file.file, data.as_slice(), false)?;
+            }
+        }
+    }
+
+    let mut anonymized = Vec::new();
+    for snapshot in &snapshots {
+        info!("anonymize snapshot {}", snapshot.number);
+        let current_directory = target_directory.join(snapshot.number.to_string());
+        fs::create_dir_all(&current_directory)?;
+        let mut bytes = BTreeMap::new();
+        for verify_file in VERIFY_FILES {
+            if let Some(data) = read_csv(snapshot.number, verify_file.file)? {
+                let data = anonymizer.csv(verify_file.file, data.as_slice(), true)?;
+                fs::write(current_directory.join(verify_file.file), &data)?;
+                bytes.insert(verify_file.file.to_string(), data.len() as u64);
+            }
+        }
+        if let Ok(mut manifest) = SnapshotManifest::read_snapshot(&snapshot.number.to_string()) {
+            manifest.cluster = anonymizer.pseudonym("cluster", &manifest.cluster);
+            for endpoint in manifest.endpoints.iter_mut() {
+                endpoint.hostname_port = anonymizer.host_port(&endpoint.hostname_port);
+            }
+            manifest.files.retain(|file| is_csv_file(&file.name));
+            for file in manifest.files.iter_mut() {
+                file.name = strip_compression_extension(&file.name).to_string();
+                file.bytes = bytes.get(&file.name).copied().unwrap_or(file.bytes);
+            }
+            fs::write(current_directory.join("manifest"), serde_json::to_vec_pretty(&manifest)?)?;
+        }
+        anonymized.push(Snapshot {
+            number: snapshot.number,
+            timestamp: snapshot.timestamp,
+            comment: anonymizer.text(&snapshot.comment),
+            cluster: anonymizer.pseudonym("cluster", &snapshot.cluster),
+        });
+    }
+    fs::write(target_directory.join("snapshot.index"), write_index(&anonymized)?)?;
+    Ok(anonymized)
+}
+
+/// This function reads a CSV file of a snapshot, upgraded to the current layout, or returns None if the file doesn't exist.
+fn read_csv(
+    snapshot_number: i32,
+    file: &str,
+) -> Result<Option<Vec<u8>>, Box<dyn Error>>
+{
+    let path = snapshot_directory().join(snapshot_number.to_string()).join(file);
+    match open_snapshot_csv(&path) {
+        Ok(mut reader) => {
+            let mut data = Vec::new();
+            reader.read_to_end(&mut data)?;
+            Ok(Some(data))
+        },
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(format!("error reading {}: {}", path.display(), e).into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unit_strip_literals() {
+        assert_eq!(strip_literals("select * from t1 where id = 42 and name = 'O''Brien'"), "select * from t1 where id = ? and name = ?");
+        assert_eq!(strip_literals("select $1, 1.5e3, col2 from \"Table 1\" limit $2"), "select $1, ?, col2 from \"Table 1\" limit $2");
+        assert_eq!(strip_literals("do $body$ begin perform 'x'; end $body$; select $$a'b$$"), "do ?; select ?");
+    }
+    #[test]
+    fn unit_pseudonyms_consistent() {
+        let mut anonymizer = Anonymizer::new();
+        assert_eq!(anonymizer.field(Rule::HostPort, "192.168.66.80:9000", "", true), "host-1:9000");
+        assert_eq!(anonymizer.field(Rule::HostPort, "192.168.66.81:7000", "", true), "host-2:7000");
+        assert_eq!(anonymizer.field(Rule::Host, "192.168.66.80", "", true), "host-1");
+        assert_eq!(anonymizer.field(Rule::HostPort, "192.168.66.80:54321", "", true), "host-1:54321");
+        assert_eq!(anonymizer.field(Rule::Table, "orders", "", true), "table-1");
+        assert_eq!(anonymizer.field(Rule::Table, "sys.catalog", "", true), "sys.catalog");
+        assert_eq!(anonymizer.field(Rule::Keyspace, "", "", true), "");
+        assert_eq!(anonymizer.field(Rule::Remove, "[1, 'secret']", "", true), REDACTED);
+    }
+    #[test]
+    fn unit_free_text_and_flags() {
+        let mut anonymizer = Anonymizer::new();
+        anonymizer.field(Rule::HostPort, "yb-1.example.com:9000", "", true);
+        anonymizer.field(Rule::Table, "orders", "", true);
+        assert_eq!(anonymizer.field(Rule::Flag { name_column: "gflag_name" }, "yb-1.example.com:7100,10.1.2.3:7100", "master_addresses", true), "host-1:7100,host-2:7100");
+        assert_eq!(anonymizer.field(Rule::Flag { name_column: "gflag_name" }, "/opt/certs", "certs_dir", true), REDACTED);
+        assert_eq!(anonymizer.field(Rule::Text, "compaction of orders on yb-1.example.com done, orders_id kept", "", true), "compaction of table-1 on host-1 done, orders_id kept");
+        assert_eq!(anonymizer.field(Rule::Sql, "select * from orders where id = 5", "", true), "select * from table-1 where id = ?");
+        // without free text, the pseudonyms are assigned but free text is not changed.
+        assert_eq!(anonymizer.field(Rule::Text, "orders", "", false), "orders");
+    }
+    #[test]
+    fn unit_anonymize_csv() {
+        let mut anonymizer = Anonymizer::new();
+        let data = "hostname_port,timestamp,remote_ip,state,processed_call_count,serial_nr\n192.168.66.80:9100,2023-01-10T10:12:31+01:00,192.168.66.82:40123,OPEN,10,1\n";
+        let result = String::from_utf8(anonymizer.csv("inboundrpc", data.as_bytes(), true).unwrap()).unwrap();
+        assert_eq!(result, "hostname_port,timestamp,remote_ip,state,processed_call_count,serial_nr\nhost-1:9100,2023-01-10T10:12:31+01:00,host-2:40123,OPEN,10,1\n");
+        assert!(anonymizer.csv("inboundrpc", "".as_bytes(), true).unwrap().is_empty());
+    }
+    #[test]
+    fn unit_anonymize_nodeexporter_labels() {
+        let mut anonymizer = Anonymizer::new();
+        let data = "hostname_port,timestamp,node_exporter_name,node_exporter_type,node_exporter_labels,node_exporter_category,node_exporter_value\n\
+            192.168.66.80:9300,2023-01-10T10:12:31+01:00,node_filesystem_avail_bytes,gauge,_/dev/sda1_xfs_/mnt/yb-1.example.com,all,1000\n\
+            192.168.66.80:9300,2023-01-10T10:12:31+01:00,node_uname_info,gauge,_#1 SMP PREEMPT_DYNAMIC_(none)_5.14.0-284.el9_2.x86_64_Linux_x86_64_yb-1.example.com,all,1\n";
+        // the first pass assigns the pseudonyms, so the nodename is known in the rows before node_uname_info too.
+        anonymizer.csv("nodeexporter", data.as_bytes(), false).unwrap();
+        let result = String::from_utf8(anonymizer.csv("nodeexporter", data.as_bytes(), true).unwrap()).unwrap();
+        assert!(!result.contains("yb-1.example.com"));
+        assert!(result.contains("_/dev/sda1_xfs_/mnt/host-2,"));
+        assert!(result.contains("_#1 SMP PREEMPT_DYNAMIC_(none)_5.14.0-284.el9_2.x86_64_Linux_x86_64_host-2,"));
+    }
+}
@@ -0,0 +1,231 @@
+//! The module for the export and import of snapshots as a single archive.
+//!
+//! The export ([export_snapshots]) packs the snapshots from `--begin` to `--end` (default all snapshots) into a gzip compressed tar archive:
+//! - `snapshot.index`: the rows of the snapshot.index file of the exported snapshots.
+//! - `<nr>/`: the snapshot directories with all the files as these are stored, including compressed files. The files of the sqlite storage are exported as CSV files.
+//!
+//! The import ([import_snapshots]) adds the snapshots of an archive to the snapshot directory as new snapshots, in the order of their original numbers.
+//! The snapshots get the next free snapshot numbers, and keep the timestamp, comment and cluster of the original snapshot.
+//! The original number, timestamp, comment and cluster are recorded in the file `import` (JSON) in the snapshot directory, see [SnapshotImport].
+use chrono::{DateTime, Local};
+use std::{fs, io::{self, Read}, path::Path, error::Error, process};
+use serde_derive::{Serialize, Deserialize};
+use log::*;
+use crate::snapshot::{Snapshot, snapshot_directory};
+use crate::compression::{create_snapshot_file, open_snapshot_file};
+use crate::storage::database_files;
+
+/// The name of the snapshot index in the archive.
+const ARCHIVE_INDEX: &str = "snapshot.index";
+
+/// The struct that is used to store and retrieve the origin of an imported snapshot in JSON using serde.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct SnapshotImport {
+    pub archive: String,
+    pub import_time: DateTime<Local>,
+    pub original_number: i32,
+    pub original_timestamp: DateTime<Local>,
+    pub original_comment: String,
+    pub original_cluster: String,
+}
+
+impl SnapshotImport {
+    /// This function saves the import metadata as JSON in the snapshot directory indicated by the snapshot number.
+    fn save_snapshot(
+        &self,
+        snapshot_number: i32,
+    ) -> Result<(), Box<dyn Error>>
+    {
+        let current_snapshot_directory = snapshot_directory().join(snapshot_number.to_string());
+
+        let import_file = &current_snapshot_directory.join("import");
+        let mut file = create_snapshot_file(import_file)?;
+        serde_json::to_writer_pretty(&mut file, self)?;
+        file.finish()?;
+
+        Ok(())
+    }
+    /// This function takes a snapshot number and reads the import metadata JSON, which only exists for an imported snapshot.
+    pub fn read_snapshot(
+        snapshot_number: &String,
+    ) -> Result<SnapshotImport, Box<dyn Error>>
+    {
+        let current_snapshot_directory = snapshot_directory().join(snapshot_number);
+
+        let import_file = &current_snapshot_directory.join("import");
+        let file = open_snapshot_file(import_file)?;
+
+        Ok(serde_json::from_reader(file)?)
+    }
+    /// This function prints the import metadata.
+    pub fn print(&self) {
+        println!("      imported from {} at {}: original number {}, timestamp {}, cluster {}, comment {}", self.archive, self.import_time, self.original_number, self.original_timestamp, self.original_cluster, self.original_comment);
+    }
+}
+
+/// This function exports the snapshots from begin to end (both optional) to the archive file, and returns the exported snapshots.
+pub fn export_snapshots(
+    archive_file: &str,
+    begin: Option<i32>,
+    end: Option<i32>,
+) -> Result<Vec<Snapshot>, Box<dyn Error>>
+{
+    if !snapshot_directory().join("snapshot.index").exists() {
+        return Err(format!("no snapshots found in {}", snapshot_directory().display()).into());
+    }
+    let snapshots = Snapshot::read_snapshots().into_iter()
+        .filter(|snapshot| begin.map(|begin| snapshot.number >= begin).unwrap_or(true))
+        .filter(|snapshot| end.map(|end| snapshot.number <= end).unwrap_or(true))
+        .collect::<Vec<Snapshot>>();
+    if snapshots.is_empty() {
+        return Err("no snapshots selected for export".into());
+    }
+
+    let file = fs::File::create(archive_file)
+        .map_err(|e| format!("error creating archive {}: {}", archive_file, e))?;
+    let mut builder = tar::Builder::new(flate2::write::GzEncoder::new(file, flate2::Compression::default()));
+
+    let index = write_index(&snapshots)?;
+    let mut header = tar::Header::new_gnu();
+    header.set_size(index.len() as u64);
+    header.set_mode(0o644);
+    header.set_mtime(Local::now().timestamp() as u64);
+    header.set_cksum();
+    builder.append_data(&mut header, ARCHIVE_INDEX, index.as_slice())?;
+
+    for snapshot in &snapshots {
+        let current_snapshot_directory = snapshot_directory().join(snapshot.number.to_string());
+        if !current_snapshot_directory.is_dir() {
+            return Err(format!("snapshot directory {} is not found", current_snapshot_directory.display()).into());
+        }
+        info!("export snapshot {}", snapshot.number);
+        builder.append_dir_all(snapshot.number.to_string(), &current_snapshot_directory)?;
+        // With the sqlite storage, the files are in the database, and are exported as files.
+        for file in database_files(snapshot.number)? {
+            let mut data = Vec::new();
+            io::copy(&mut open_snapshot_file(&current_snapshot_directory.join(&file.name))?, &mut data)?;
+            let mut header = tar::Header::new_gnu();
+            header.set_size(data.len() as u64);
+            header.set_mode(0o644);
+            header.set_mtime(Local::now().timestamp() as u64);
+            header.set_cksum();
+            builder.append_data(&mut header, format!("{}/{}", snapshot.number, file.name), data.as_slice())?;
+        }
+    }
+    builder.into_inner()?.finish()?;
+
+    Ok(snapshots)
+}
+
+/// This function imports the snapshots of the archive file as new snapshots, and returns the original snapshots with their new snapshot numbers.
+/// The archive is unpacked in a temporary directory in the snapshot directory, which is removed afterwards.
+pub fn import_snapshots(
+    archive_file: &str,
+) -> Result<Vec<(Snapshot, i32)>, Box<dyn Error>>
+{
+    let file = fs::File::open(archive_file)
+        .map_err(|e| format!("error opening archive {}: {}", archive_file, e))?;
+    let unpack_directory = snapshot_directory().join(format!(".import.{}", process::id()));
+    fs::create_dir_all(&unpack_directory)?;
+
+    let result = tar::Archive::new(flate2::read::MultiGzDecoder::new(file))
+        .unpack(&unpack_directory)
+        .map_err(|e| format!("error unpacking archive {}: {}", archive_file, e).into())
+        .and_then(|_| import_unpacked(archive_file, &unpack_directory));
+
+    fs::remove_dir_all(&unpack_directory)
+        .unwrap_or_else(|e| warn!("Warning: error removing directory {}: {}", unpack_directory.display(), e));
+    result
+}
+
+/// This function adds the unpacked snapshots as new snapshots, and moves their files into the new snapshot directories.
+fn import_unpacked(
+    archive_file: &str,
+    unpack_directory: &Path,
+) -> Result<Vec<(Snapshot, i32)>, Box<dyn Error>>
+{
+    let index = fs::File::open(unpack_directory.join(ARCHIVE_INDEX))
+        .map_err(|_| format!("archive {} is not a yb_stats export: {} is not found", archive_file, ARCHIVE_INDEX))?;
+    let mut snapshots = read_index(index)?;
+    snapshots.sort_by_key(|snapshot| snapshot.number);
+    // All the snapshot directories are validated before the first snapshot is added to the snapshot.index file.
+    if let Some(snapshot) = snapshots.iter().find(|snapshot| !unpack_directory.join(snapshot.number.to_string()).is_dir()) {
+        return Err(format!("archive {} does not contain the directory of snapshot {}", archive_file, snapshot.number).into());
+    }
+
+    let mut imported = Vec::new();
+    for snapshot in snapshots {
+        let snapshot_number = Snapshot::reserve_snapshot_number();
+        let current_snapshot_directory = snapshot_directory().join(snapshot_number.to_string());
+        for entry in fs::read_dir(unpack_directory.join(snapshot.number.to_string()))? {
+            let entry = entry?;
+            fs::rename(entry.path(), current_snapshot_directory.join(entry.file_name()))?;
+        }
+        SnapshotImport {
+            archive: archive_file.to_string(),
+            import_time: Local::now(),
+            original_number: snapshot.number,
+            original_timestamp: snapshot.timestamp,
+            original_comment: snapshot.comment.clone(),
+            original_cluster: snapshot.cluster.clone(),
+        }.save_snapshot(snapshot_number)?;
+        Snapshot::insert_snapshot(snapshot_number, snapshot.timestamp, snapshot.comment.clone(), snapshot.cluster.clone())
+            .map_err(|e| format!("error adding snapshot {} to the snapshot index: {}, the data of the snapshot is kept in {}", snapshot_number, e, current_snapshot_directory.display()))?;
+        imported.push((snapshot, snapshot_number));
+    }
+    Ok(imported)
+}
+
+/// This function writes the snapshots as CSV in the format of the snapshot.index file.
+pub fn write_index(
+    snapshots: &[Snapshot],
+) -> Result<Vec<u8>, Box<dyn Error>>
+{
+    let mut writer = csv::Writer::from_writer(Vec::new());
+    for row in snapshots {
+        writer.serialize(row)?;
+    }
+    Ok(writer.into_inner()?)
+}
+
+/// This function reads the snapshots from CSV in the format of the snapshot.index file.
+fn read_index(
+    index: impl Read,
+) -> Result<Vec<Snapshot>, Box<dyn Error>>
+{
+    let mut snapshots = Vec::new();
+    let mut reader = csv::Reader::from_reader(index);
+    for row in reader.deserialize() {
+        let data: Snapshot = row?;
+        snapshots.push(data);
+    }
+    Ok(snapshots)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unit_archive_index_write_and_read() {
+        let snapshots = vec![
+            Snapshot { number: 3, timestamp: Local::now(), comment: "before upgrade".to_string(), cluster: "prod".to_string() },
+            Snapshot { number: 7, timestamp: Local::now(), comment: String::new(), cluster: String::new() },
+        ];
+        let index = write_index(&snapshots).unwrap();
+        let result = read_index(index.as_slice()).unwrap();
+        assert_eq!(result.len(), 2);
+        assert_eq!(result[0].number, 3);
+        assert_eq!(result[0].comment, "before upgrade");
+        assert_eq!(result[0].cluster, "prod");
+        assert_eq!(result[1].timestamp, snapshots[1].timestamp);
+    }
+    #[test]
+    fn unit_archive_index_without_cluster() {
+        // the snapshot.index of older versions of yb_stats has no cluster column.
+        let index = "number,timestamp,comment\n0,2023-01-10T10:12:31.123445+01:00,first snapshot\n";
+        let result = read_index(index.as_bytes()).unwrap();
+        assert_eq!(result[0].comment, "first snapshot");
+        assert_eq!(result[0].cluster, "");
+    }
+}
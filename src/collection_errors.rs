@@ -0,0 +1,135 @@
+//! The module for the errors that occurred while collecting the data for a snapshot.
+//!
+//! Every http request that did not give a usable answer is recorded via [record_collection_error] by [crate::utility::http_get].
+//! When a snapshot is performed, the recorded errors are saved in the snapshot as the `collection_errors` CSV file via [AllStoredCollectionErrors::perform_snapshot],
+//! so that after a snapshot it's visible which data is missing.
+//! The modes that read the endpoints without saving a snapshot (top and exporter) clear the recorded errors with [clear_recorded] after every read.
+//! The polls of watch take the recorded errors with [AllStoredCollectionErrors::take_recorded], so that a poll that is saved as a snapshot keeps only its own errors.
+use chrono::{DateTime, Local};
+use std::{error::Error, process, sync::Mutex, time::Duration};
+use serde_derive::{Serialize,Deserialize};
+use log::*;
+use crate::snapshot::snapshot_directory;
+use crate::utility::HttpErrorKind;
+use crate::compression::create_snapshot_file;
+use crate::schema::open_snapshot_csv;
+
+/// The errors recorded since the last snapshot.
+static COLLECTION_ERRORS: Mutex<Vec<StoredCollectionError>> = Mutex::new(Vec::new());
+
+/// The struct that is used to store and retrieve a collection error in CSV using serde.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct StoredCollectionError {
+    pub hostname_port: String,
+    pub timestamp: DateTime<Local>,
+    /// The url of the endpoint, for example: `api/v1/varz`.
+    pub endpoint: String,
+    /// The kind of error, see [HttpErrorKind].
+    pub error_kind: String,
+    /// The total time spent on the endpoint including all retries, in milliseconds.
+    pub duration_ms: u128,
+    pub attempts: u32,
+}
+
+/// This function records an error for a hostname:port and endpoint.
+pub fn record_collection_error(
+    hostname_port: &str,
+    endpoint: &str,
+    error_kind: HttpErrorKind,
+    duration: Duration,
+    attempts: u32,
+)
+{
+    COLLECTION_ERRORS.lock().unwrap().push(StoredCollectionError {
+        hostname_port: hostname_port.to_string(),
+        timestamp: Local::now(),
+        endpoint: endpoint.to_string(),
+        error_kind: error_kind.to_string(),
+        duration_ms: duration.as_millis(),
+        attempts,
+    });
+}
+
+/// This function clears the recorded errors, and returns the number of errors that were recorded.
+pub fn clear_recorded() -> usize {
+    let mut collection_errors = COLLECTION_ERRORS.lock().unwrap();
+    let errors = collection_errors.len();
+    collection_errors.clear();
+    errors
+}
+
+/// This struct is used to handle the [StoredCollectionError] struct.
+#[derive(Debug, Default)]
+pub struct AllStoredCollectionErrors {
+    pub stored_collection_errors: Vec<StoredCollectionError>,
+}
+
+impl AllStoredCollectionErrors {
+    /// This function takes the errors recorded since the last snapshot, and saves these in the snapshot indicated by the snapshot_number.
+    /// It must be called after all the sources are read. The file is always created, so an empty file means no errors occurred.
+    pub fn perform_snapshot(
+        snapshot_number: i32,
+    )
+    {
+        AllStoredCollectionErrors::take_recorded().perform_save(snapshot_number);
+    }
+    /// This function saves errors that were taken earlier in the snapshot indicated by the snapshot_number.
+    pub fn perform_save(
+        self,
+        snapshot_number: i32,
+    )
+    {
+        if !self.stored_collection_errors.is_empty() {
+            warn!("snapshot {}: {} endpoint(s) could not be read, see collection_errors in the snapshot directory", snapshot_number, self.stored_collection_errors.len());
+        }
+        self.save_snapshot(snapshot_number)
+            .unwrap_or_else(|e| {
+                error!("error saving snapshot: {}", e);
+                process::exit(1);
+            });
+    }
+    /// This function takes the recorded errors, and clears them for the next snapshot or poll.
+    pub fn take_recorded() -> AllStoredCollectionErrors
+    {
+        let mut stored_collection_errors: Vec<StoredCollectionError> = COLLECTION_ERRORS.lock().unwrap().drain(..).collect();
+        stored_collection_errors.sort_by(|a, b| a.hostname_port.cmp(&b.hostname_port).then(a.endpoint.cmp(&b.endpoint)));
+        AllStoredCollectionErrors { stored_collection_errors }
+    }
+    fn save_snapshot(
+        self,
+        snapshot_number: i32,
+    ) -> Result<(), Box<dyn Error>>
+    {
+        let current_snapshot_directory = snapshot_directory().join(snapshot_number.to_string());
+
+        let collection_errors_file = &current_snapshot_directory.join("collection_errors");
+        let file = create_snapshot_file(collection_errors_file)?;
+        let mut writer = csv::Writer::from_writer(file);
+        for row in self.stored_collection_errors {
+            writer.serialize(row)?;
+        }
+        writer.into_inner()?.finish()?;
+
+        Ok(())
+    }
+    /// This function takes a snapshot number and reads the collection_errors CSV.
+    pub fn read_snapshot(
+        snapshot_number: &String,
+    ) -> Result<AllStoredCollectionErrors, Box<dyn Error>>
+    {
+        let mut allstoredcollectionerrors = AllStoredCollectionErrors::default();
+
+        let current_snapshot_directory = snapshot_directory().join(snapshot_number);
+
+        let collection_errors_file = &current_snapshot_directory.join("collection_errors");
+        let file = open_snapshot_csv(collection_errors_file)?;
+
+        let mut reader = csv::Reader::from_reader(file);
+        for row in reader.deserialize() {
+            let data: StoredCollectionError = row?;
+            allstoredcollectionerrors.stored_collection_errors.push(data);
+        }
+
+        Ok(allstoredcollectionerrors)
+    }
+}
@@ -0,0 +1,280 @@
+//! The module for the compression of the files in the snapshot directories.
+//!
+//! The compression is set with `--compression none|gzip|zstd` (default none), and is used for all the files that are written in a snapshot directory.
+//! A compressed file gets the extension of the compression: `values.gz` or `values.zst`.
+//!
+//! The files are read with [open_snapshot_file], which finds the file with or without a compression extension,
+//! and detects the compression by the first bytes of the file, so snapshots taken with any compression setting, or before compression existed, can be read.
+use std::{fmt, fs, io::{self, BufRead, BufReader, Read, Write}, path::{Path, PathBuf}, error::Error, str::FromStr, sync::OnceLock};
+use log::*;
+use crate::storage::{DatabaseWriter, Storage, read_file, storage};
+
+/// The first bytes of a gzip file.
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+/// The first bytes of a zstd frame.
+const ZSTD_MAGIC: [u8; 4] = [0x28, 0xb5, 0x2f, 0xfd];
+/// The compression level for zstd (the zstd default).
+const ZSTD_LEVEL: i32 = 3;
+
+/// The compression of the files that are written in a snapshot directory, which is set once in main using [set_compression].
+static COMPRESSION: OnceLock<Compression> = OnceLock::new();
+
+/// The compression of a file in a snapshot directory.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Compression {
+    #[default]
+    None,
+    Gzip,
+    Zstd,
+}
+
+impl FromStr for Compression {
+    type Err = String;
+
+    fn from_str(compression: &str) -> Result<Self, Self::Err> {
+        match compression {
+            "none" => Ok(Compression::None),
+            "gzip" => Ok(Compression::Gzip),
+            "zstd" => Ok(Compression::Zstd),
+            _ => Err(format!("invalid compression: {}, valid compressions are: none, gzip, zstd", compression)),
+        }
+    }
+}
+
+impl fmt::Display for Compression {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Compression::None => write!(f, "none"),
+            Compression::Gzip => write!(f, "gzip"),
+            Compression::Zstd => write!(f, "zstd"),
+        }
+    }
+}
+
+impl Compression {
+    /// The extension that is added to the name of a file with this compression.
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Compression::None => "",
+            Compression::Gzip => ".gz",
+            Compression::Zstd => ".zst",
+        }
+    }
+    /// This function detects the compression by the first bytes of a file.
+    fn detect(first_bytes: &[u8]) -> Compression {
+        if first_bytes.starts_with(&GZIP_MAGIC) {
+            Compression::Gzip
+        } else if first_bytes.starts_with(&ZSTD_MAGIC) {
+            Compression::Zstd
+        } else {
+            Compression::None
+        }
+    }
+}
+
+/// This function sets the compression of the files that are written in a snapshot directory for the whole run of yb_stats.
+pub fn set_compression(compression: Compression) {
+    COMPRESSION.set(compression).unwrap_or_else(|_| warn!("compression is already set"));
+}
+
+/// The compression of the files that are written in a snapshot directory.
+pub fn compression() -> Compression {
+    COMPRESSION.get().copied().unwrap_or_default()
+}
+
+/// The path of the file with the extension of the compression added.
+fn path_with_extension(
+    path: &Path,
+    compression: Compression,
+) -> PathBuf
+{
+    let mut file_name = path.file_name().unwrap_or_default().to_os_string();
+    file_name.push(compression.extension());
+    path.with_file_name(file_name)
+}
+
+/// A file in a snapshot directory that is written, which is returned by [create_snapshot_file].
+/// The file must be completed with [SnapshotFile::finish], which returns the errors of writing the end of a compressed file, or of saving the data in the database.
+pub enum SnapshotFile {
+    Plain(fs::File),
+    Gzip(flate2::write::GzEncoder<fs::File>),
+    Zstd(zstd::Encoder<'static, fs::File>),
+    Database(DatabaseWriter),
+}
+
+impl SnapshotFile {
+    /// This function completes the file: the end of a compressed file is written, and with the sqlite storage the data is saved in the database.
+    pub fn finish(self) -> Result<(), Box<dyn Error>> {
+        match self {
+            SnapshotFile::Plain(mut file) => file.flush()?,
+            SnapshotFile::Gzip(encoder) => encoder.finish()?.flush()?,
+            SnapshotFile::Zstd(encoder) => encoder.finish()?.flush()?,
+            SnapshotFile::Database(writer) => writer.finish()?,
+        }
+        Ok(())
+    }
+}
+
+impl Write for SnapshotFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            SnapshotFile::Plain(file) => file.write(buf),
+            SnapshotFile::Gzip(encoder) => encoder.write(buf),
+            SnapshotFile::Zstd(encoder) => encoder.write(buf),
+            SnapshotFile::Database(writer) => writer.write(buf),
+        }
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            SnapshotFile::Plain(file) => file.flush(),
+            SnapshotFile::Gzip(encoder) => encoder.flush(),
+            SnapshotFile::Zstd(encoder) => encoder.flush(),
+            SnapshotFile::Database(writer) => writer.flush(),
+        }
+    }
+}
+
+impl fmt::Debug for SnapshotFile {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SnapshotFile::Plain(_) => write!(f, "SnapshotFile::Plain"),
+            SnapshotFile::Gzip(_) => write!(f, "SnapshotFile::Gzip"),
+            SnapshotFile::Zstd(_) => write!(f, "SnapshotFile::Zstd"),
+            SnapshotFile::Database(_) => write!(f, "SnapshotFile::Database"),
+        }
+    }
+}
+
+/// This function creates (or truncates) a file in a snapshot directory, with the compression set with [set_compression].
+/// The path is the name of the file without a compression extension.
+/// The file is completely written when [SnapshotFile::finish] is called.
+/// With the sqlite storage (see [crate::storage]), the data is saved in the database instead.
+pub fn create_snapshot_file(
+    path: &Path,
+) -> io::Result<SnapshotFile>
+{
+    match storage() {
+        Storage::Csv => create_file(path, compression()),
+        Storage::Sqlite => Ok(SnapshotFile::Database(DatabaseWriter::new(path))),
+    }
+}
+
+fn create_file(
+    path: &Path,
+    compression: Compression,
+) -> io::Result<SnapshotFile>
+{
+    let file = fs::OpenOptions::new()
+        .create(true)
+        .write(true)
+        .truncate(true)
+        .open(path_with_extension(path, compression))?;
+    Ok(match compression {
+        Compression::None => SnapshotFile::Plain(file),
+        Compression::Gzip => SnapshotFile::Gzip(flate2::write::GzEncoder::new(file, flate2::Compression::default())),
+        Compression::Zstd => SnapshotFile::Zstd(zstd::Encoder::new(file, ZSTD_LEVEL)?),
+    })
+}
+
+/// This function finds a file in a snapshot directory, with or without a compression extension.
+/// The path is the name of the file without a compression extension. If no file is found, the path is returned.
+pub fn find_snapshot_file(
+    path: &Path,
+) -> PathBuf
+{
+    [Compression::None, Compression::Gzip, Compression::Zstd].into_iter()
+        .map(|compression| path_with_extension(path, compression))
+        .find(|path| path.exists())
+        .unwrap_or_else(|| path.to_path_buf())
+}
+
+/// This function opens a file in a snapshot directory for reading, and decompresses it if it's compressed.
+/// The path is the name of the file without a compression extension.
+/// If the file is not found in the snapshot directory, it's read from the database of the sqlite storage (see [crate::storage]).
+pub fn open_snapshot_file(
+    path: &Path,
+) -> io::Result<Box<dyn Read + Send>>
+{
+    let file = find_snapshot_file(path);
+    if !file.exists() {
+        let data = read_file(path)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("error reading {} from database: {}", path.display(), e)))?;
+        if let Some(data) = data {
+            return Ok(Box::new(io::Cursor::new(data)));
+        }
+    }
+    let mut reader = BufReader::new(fs::File::open(file)?);
+    Ok(match Compression::detect(reader.fill_buf()?) {
+        Compression::None => Box::new(reader),
+        Compression::Gzip => Box::new(flate2::bufread::MultiGzDecoder::new(reader)),
+        Compression::Zstd => Box::new(zstd::Decoder::with_buffer(reader)?),
+    })
+}
+
+/// The name of a file in a snapshot directory without the compression extension.
+pub fn strip_compression_extension(
+    file_name: &str,
+) -> &str
+{
+    file_name.strip_suffix(Compression::Gzip.extension())
+        .or_else(|| file_name.strip_suffix(Compression::Zstd.extension()))
+        .unwrap_or(file_name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+
+    fn write_and_read(
+        name: &str,
+        compression: Compression,
+    ) -> (PathBuf, String)
+    {
+        let directory = env::temp_dir().join(format!("yb_stats_compression_{}", std::process::id()));
+        fs::create_dir_all(&directory).unwrap();
+        let path = directory.join(name);
+        {
+            let mut writer = csv::Writer::from_writer(create_file(&path, compression).unwrap());
+            writer.write_record(["hostname_port", "value"]).unwrap();
+            writer.write_record(["192.168.66.80:9000", "42"]).unwrap();
+            writer.into_inner().unwrap().finish().unwrap();
+        }
+        let mut data = String::new();
+        open_snapshot_file(&path).unwrap().read_to_string(&mut data).unwrap();
+        (find_snapshot_file(&path), data)
+    }
+
+    #[test]
+    fn unit_compression_from_str() {
+        assert_eq!("gzip".parse::<Compression>(), Ok(Compression::Gzip));
+        assert_eq!("zstd".parse::<Compression>(), Ok(Compression::Zstd));
+        assert_eq!("none".parse::<Compression>(), Ok(Compression::None));
+        assert!("lz4".parse::<Compression>().is_err());
+    }
+    #[test]
+    fn unit_compression_detect() {
+        assert_eq!(Compression::detect(&[0x1f, 0x8b, 0x08]), Compression::Gzip);
+        assert_eq!(Compression::detect(&[0x28, 0xb5, 0x2f, 0xfd, 0x00]), Compression::Zstd);
+        assert_eq!(Compression::detect(b"hostname_port,value"), Compression::None);
+        assert_eq!(Compression::detect(b""), Compression::None);
+    }
+    #[test]
+    fn unit_compression_write_and_read() {
+        for (name, compression, file_name) in [
+            ("values", Compression::None, "values"),
+            ("countsum", Compression::Gzip, "countsum.gz"),
+            ("countsumrows", Compression::Zstd, "countsumrows.zst"),
+        ] {
+            let (path, data) = write_and_read(name, compression);
+            assert_eq!(path.file_name().unwrap().to_str().unwrap(), file_name);
+            assert_eq!(data, "hostname_port,value\n192.168.66.80:9000,42\n");
+        }
+    }
+    #[test]
+    fn unit_strip_compression_extension() {
+        assert_eq!(strip_compression_extension("values.gz"), "values");
+        assert_eq!(strip_compression_extension("values.zst"), "values");
+        assert_eq!(strip_compression_extension("pprof_growth_192.168.66.80:9000"), "pprof_growth_192.168.66.80:9000");
+    }
+}
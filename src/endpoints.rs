@@ -0,0 +1,413 @@
+//! The module for the endpoints (hostname:port combinations) that are used to collect data from.
+//!
+//! The endpoints are either the combination of the hosts and ports that are set via `--hosts` and `--ports`: [AllStoredEndpoints::new_from_hosts_ports],
+//! the endpoints of a cluster profile in the inventory file: [AllStoredEndpoints::new_from_endpoints],
+//! or discovered from a single master http address via `--discover`: [AllStoredEndpoints::discover].
+//!
+//! Discovery works as follows:
+//!  1. /api/v1/masters is read from the master, which provides the http addresses of all the masters.
+//!  2. /api/v1/tablet-servers is read from all the masters, which provides the http addresses of all the tablet servers.
+//!  3. /api/v1/varz is read from all the tablet servers, which provides the YSQL (pgsql_proxy_webserver_port) and YCQL (cql_proxy_webserver_port) web ports.
+//!
+//! The endpoints that are not discovered are classified by probing: [AllStoredEndpoints::classify].
+//! The role of an endpoint (master, tserver, ysql, ycql, yedis or node_exporter) determines which sources are read from it: see [has_role].
+//!
+//! The endpoints are stored in every snapshot, so it's known what endpoints were used for collecting the data.
+use chrono::{DateTime, Local};
+use std::{error::Error, process, collections::{BTreeSet, HashMap}, sync::OnceLock, time::Instant};
+use serde_derive::{Serialize,Deserialize};
+use log::*;
+use crate::snapshot::snapshot_directory;
+use futures::future::join_all;
+use crate::masters::{AllMasters, AllStoredMasters};
+use crate::tservers::AllStoredTabletServers;
+use crate::vars::{AllVars, AllStoredVars};
+use crate::utility::{port_role, http_get};
+use crate::metrics::metrics_source;
+use crate::compression::create_snapshot_file;
+use crate::schema::open_snapshot_csv;
+
+/// The default port of the master webserver, used if the discovery address has no port.
+const DEFAULT_MASTER_PORT: &str = "7000";
+
+/// The metrics filter of the probe of [AllStoredEndpoints::classify]: the handler latencies are metrics of the server entity.
+const PROBE_METRICS_FILTER: &str = "handler_latency_yb_";
+
+/// The roles of the endpoints by hostname:port, which are set once in main using [AllStoredEndpoints::set_endpoint_roles].
+static ENDPOINT_ROLES: OnceLock<HashMap<String, String>> = OnceLock::new();
+
+/// The struct that is used to store and retrieve an endpoint in CSV using serde.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct StoredEndpoints {
+    pub hostname_port: String,
+    pub host: String,
+    pub port: String,
+    /// The role of the endpoint: master, tserver, ysql, ycql, yedis, node_exporter, or unknown.
+    pub role: String,
+    /// How the role is determined: discovery, probe, or port (the role based on the port number, see [port_role]).
+    pub detected_by: String,
+    pub timestamp: DateTime<Local>,
+}
+
+impl StoredEndpoints {
+    fn new(host: &str, port: &str, role: &str, detected_by: &str) -> Self {
+        Self {
+            hostname_port: format!("{}:{}", host, port),
+            host: host.to_string(),
+            port: port.to_string(),
+            role: role.to_string(),
+            detected_by: detected_by.to_string(),
+            timestamp: Local::now(),
+        }
+    }
+}
+
+/// This struct is used to handle the [StoredEndpoints] struct.
+#[derive(Debug, Default)]
+pub struct AllStoredEndpoints {
+    pub stored_endpoints: Vec<StoredEndpoints>,
+}
+
+impl AllStoredEndpoints {
+    /// This function creates the endpoints from all combinations of hosts and ports.
+    pub fn new_from_hosts_ports(
+        hosts: &[&str],
+        ports: &[&str],
+    ) -> AllStoredEndpoints
+    {
+        let mut allstoredendpoints = AllStoredEndpoints::default();
+        for host in hosts {
+            for port in ports {
+                allstoredendpoints.stored_endpoints.push(StoredEndpoints::new(host, port, port_role(port), "port"));
+            }
+        }
+        allstoredendpoints
+    }
+    /// This function creates the endpoints from a list of host and port combinations, such as the endpoints of a cluster profile.
+    pub fn new_from_endpoints(
+        endpoints: &[(String, String)],
+    ) -> AllStoredEndpoints
+    {
+        let mut allstoredendpoints = AllStoredEndpoints::default();
+        for (host, port) in endpoints {
+            allstoredendpoints.stored_endpoints.push(StoredEndpoints::new(host, port, port_role(port), "port"));
+        }
+        allstoredendpoints
+    }
+    /// This function discovers the endpoints of the cluster, starting from a single master http address (`host:port` or `host`).
+    /// The ports in `extra_ports` that are not found via discovery, such as the node_exporter port, are added for all discovered hosts.
+    pub async fn discover(
+        master_address: &str,
+        extra_ports: &[&str],
+    ) -> AllStoredEndpoints
+    {
+        info!("begin discovery");
+        let timer = Instant::now();
+
+        let (master_host, master_port) = split_host_port(master_address);
+        let masters = AllStoredMasters::read_http(&master_host, &master_port).await;
+        if masters.masters.is_empty() {
+            error!("Fatal: no masters found via {}:{}", master_host, master_port);
+            process::exit(1);
+        }
+        let mut master_endpoints = master_http_addresses(masters);
+        // Older versions do not report the http addresses of the masters: then only the discovery address is used.
+        if master_endpoints.is_empty() {
+            master_endpoints.insert((master_host, master_port));
+        }
+
+        let mut handles = Vec::new();
+        for (host, port) in &master_endpoints {
+            let handle = async move {
+                AllStoredTabletServers::read_http(host, port).await
+            };
+            handles.push(handle);
+        }
+        let mut tserver_endpoints: BTreeSet<(String, String)> = BTreeSet::new();
+        for tabletservers in join_all(handles).await {
+            for tserver_hostname_port in tabletservers.tabletservers.keys() {
+                tserver_endpoints.insert(split_host_port(tserver_hostname_port));
+            }
+        }
+
+        let mut handles = Vec::new();
+        for (host, port) in &tserver_endpoints {
+            let handle = async move {
+                (host, AllStoredVars::read_http(host, port).await)
+            };
+            handles.push(handle);
+        }
+        let mut proxy_endpoints: BTreeSet<(String, String, &str)> = BTreeSet::new();
+        for (host, vars) in join_all(handles).await {
+            for (port, role) in proxy_ports(&vars) {
+                proxy_endpoints.insert((host.to_string(), port, role));
+            }
+        }
+
+        let mut allstoredendpoints = AllStoredEndpoints::default();
+        for (host, port) in &master_endpoints {
+            allstoredendpoints.stored_endpoints.push(StoredEndpoints::new(host, port, "master", "discovery"));
+        }
+        for (host, port) in &tserver_endpoints {
+            allstoredendpoints.stored_endpoints.push(StoredEndpoints::new(host, port, "tserver", "discovery"));
+        }
+        for (host, port, role) in &proxy_endpoints {
+            allstoredendpoints.stored_endpoints.push(StoredEndpoints::new(host, port, role, "discovery"));
+        }
+        let discovered_ports: BTreeSet<String> = allstoredendpoints.stored_endpoints.iter().map(|e| e.port.to_string()).collect();
+        for host in allstoredendpoints.hosts() {
+            for port in extra_ports.iter().filter(|port| !discovered_ports.contains(**port)) {
+                allstoredendpoints.stored_endpoints.push(StoredEndpoints::new(host, port, port_role(port), "port"));
+            }
+        }
+        allstoredendpoints.stored_endpoints.sort();
+        allstoredendpoints.stored_endpoints.dedup_by(|a, b| a.hostname_port == b.hostname_port);
+
+        info!("end discovery: {} endpoints {:?}", allstoredendpoints.stored_endpoints.len(), timer.elapsed());
+        allstoredendpoints
+    }
+    /// This function classifies the endpoints of which the role is not discovered, by probing the endpoint.
+    /// The probe reads the metrics endpoint of the metrics source (/metrics or /prometheus-metrics, see [crate::metrics::MetricsSource])
+    /// with a filter for the handler latencies, which are only present in the server entity, so the tablet metrics of a tablet server are not transferred:
+    /// the YugabyteDB servers return a server entity that tells the server type, node_exporter ignores the filter and returns node_ metrics in prometheus format.
+    /// Only if the filtered probe does not tell the role, the full metrics endpoint is read.
+    /// If the probes do not tell the role, the role based on the port number is kept.
+    pub async fn classify(&mut self)
+    {
+        info!("begin classify");
+        let timer = Instant::now();
+
+        let mut handles = Vec::new();
+        for endpoint in self.stored_endpoints.iter_mut().filter(|endpoint| endpoint.detected_by != "discovery") {
+            let handle = async move {
+                let metrics = http_get(&endpoint.host, &endpoint.port, &format!("{}?metrics={}", metrics_source().url(), PROBE_METRICS_FILTER)).await;
+                let role = match role_from_metrics(&metrics) {
+                    Some(role) => Some(role),
+                    None => role_from_metrics(&http_get(&endpoint.host, &endpoint.port, metrics_source().url()).await),
+                };
+                if let Some(role) = role {
+                    endpoint.role = role.to_string();
+                    endpoint.detected_by = "probe".to_string();
+                }
+                debug!("classified {} as {} by {}", endpoint.hostname_port, endpoint.role, endpoint.detected_by);
+            };
+            handles.push(handle);
+        }
+        join_all(handles).await;
+
+        info!("end classify {:?}", timer.elapsed());
+    }
+    /// This function sets the roles of the endpoints for the whole run of yb_stats, which are used by [has_role].
+    pub fn set_endpoint_roles(&self) {
+        let endpoint_roles = self.stored_endpoints.iter().map(|endpoint| (endpoint.hostname_port.to_string(), endpoint.role.to_string())).collect();
+        ENDPOINT_ROLES.set(endpoint_roles).unwrap_or_else(|_| warn!("endpoint roles are already set"));
+    }
+    /// The unique hosts of the endpoints, in order of appearance.
+    pub fn hosts(&self) -> Vec<&'static str> {
+        let mut hosts: Vec<&'static str> = Vec::new();
+        for endpoint in &self.stored_endpoints {
+            if !hosts.contains(&endpoint.host.as_str()) {
+                hosts.push(Box::leak(endpoint.host.clone().into_boxed_str()));
+            }
+        }
+        hosts
+    }
+    /// The unique ports of the endpoints, in order of appearance.
+    pub fn ports(&self) -> Vec<&'static str> {
+        let mut ports: Vec<&'static str> = Vec::new();
+        for endpoint in &self.stored_endpoints {
+            if !ports.contains(&endpoint.port.as_str()) {
+                ports.push(Box::leak(endpoint.port.clone().into_boxed_str()));
+            }
+        }
+        ports
+    }
+    /// This function saves the endpoints as CSV in the snapshot directory indicated by the snapshot number.
+    pub fn save_snapshot(
+        &self,
+        snapshot_number: i32,
+    ) -> Result<(), Box<dyn Error>>
+    {
+        let current_snapshot_directory = snapshot_directory().join(snapshot_number.to_string());
+
+        let endpoints_file = &current_snapshot_directory.join("endpoints");
+        let file = create_snapshot_file(endpoints_file)?;
+        let mut writer = csv::Writer::from_writer(file);
+        for row in &self.stored_endpoints {
+            writer.serialize(row)?;
+        }
+        writer.into_inner()?.finish()?;
+
+        Ok(())
+    }
+    /// This function takes a snapshot number and reads the endpoints CSV.
+    #[allow(dead_code)]
+    pub fn read_snapshot(
+        snapshot_number: &String,
+    ) -> Result<AllStoredEndpoints, Box<dyn Error>>
+    {
+        let mut allstoredendpoints = AllStoredEndpoints::default();
+
+        let current_snapshot_directory = snapshot_directory().join(snapshot_number);
+
+        let endpoints_file = &current_snapshot_directory.join("endpoints");
+        let file = open_snapshot_csv(endpoints_file)?;
+
+        let mut reader = csv::Reader::from_reader(file);
+        for row in reader.deserialize() {
+            let data: StoredEndpoints = row?;
+            allstoredendpoints.stored_endpoints.push(data);
+        }
+
+        Ok(allstoredendpoints)
+    }
+}
+
+/// This function tells if the endpoint host:port has one of the roles, and thus should be read by a source that is available for these roles.
+/// An endpoint with the role unknown is read by all sources. A host:port combination that is not an endpoint is not read.
+/// If the endpoint roles are not set, all endpoints are read.
+pub fn has_role(
+    host: &str,
+    port: &str,
+    roles: &[&str],
+) -> bool
+{
+    match ENDPOINT_ROLES.get() {
+        Some(endpoint_roles) => match endpoint_roles.get(&format!("{}:{}", host, port)) {
+            Some(role) => role == "unknown" || roles.contains(&role.as_str()),
+            None => false,
+        },
+        None => true,
+    }
+}
+
+/// This function returns the role of the endpoint host:port, if the endpoint roles are set and the role is known.
+pub fn endpoint_role(
+    host: &str,
+    port: &str,
+) -> Option<&'static str>
+{
+    ENDPOINT_ROLES.get()
+        .and_then(|endpoint_roles| endpoint_roles.get(&format!("{}:{}", host, port)))
+        .map(|role| role.as_str())
+        .filter(|role| *role != "unknown")
+}
+
+/// The server entity ids in the metrics of the YugabyteDB servers, and the role belonging to it.
+const SERVER_ENTITY_ROLES: [(&str, &str); 5] = [
+    ("yb.master", "master"),
+    ("yb.tabletserver", "tserver"),
+    ("yb.ysqlserver", "ysql"),
+    ("yb.cqlserver", "ycql"),
+    ("yb.redisserver", "yedis"),
+];
+
+/// Determine the role of an endpoint using the /metrics (JSON) or /prometheus-metrics output.
+fn role_from_metrics(metrics: &str) -> Option<&'static str> {
+    if let Ok(serde_json::Value::Array(entities)) = serde_json::from_str::<serde_json::Value>(metrics) {
+        return entities.iter()
+            .filter(|entity| entity["type"] == "server")
+            .find_map(|entity| SERVER_ENTITY_ROLES.iter().find(|(id, _)| entity["id"] == *id).map(|(_, role)| *role));
+    }
+    if let Some((_, role)) = SERVER_ENTITY_ROLES.iter().find(|(id, _)| metrics.contains(&format!("metric_id=\"{}\"", id))) {
+        return Some(role);
+    }
+    if metrics.lines().any(|line| line.starts_with("node_")) {
+        return Some("node_exporter");
+    }
+    None
+}
+
+/// Split `host:port` into host and port. If there is no port, the default master port is used.
+fn split_host_port(hostname_port: &str) -> (String, String) {
+    match hostname_port.rsplit_once(':') {
+        Some((host, port)) => (host.to_string(), port.to_string()),
+        None => (hostname_port.to_string(), DEFAULT_MASTER_PORT.to_string()),
+    }
+}
+
+/// Take the http addresses of the masters from /api/v1/masters.
+fn master_http_addresses(masters: AllMasters) -> BTreeSet<(String, String)> {
+    masters.masters
+        .into_iter()
+        .filter_map(|master| master.registration.http_addresses)
+        .flatten()
+        .map(|http_address| (http_address.host, http_address.port.to_string()))
+        .collect()
+}
+
+/// Take the YSQL and YCQL webserver ports from the /api/v1/varz flags of a tablet server.
+/// The YSQL port is only returned if YSQL is enabled, and the YCQL port if the YCQL proxy is started.
+fn proxy_ports(vars: &AllVars) -> Vec<(String, &'static str)> {
+    let flag = |name: &str| vars.flags.iter().find(|flag| flag.name == name).map(|flag| flag.value.to_string());
+    let mut ports = Vec::new();
+    if flag("enable_ysql").unwrap_or_default() != "false" {
+        if let Some(port) = flag("pgsql_proxy_webserver_port") {
+            ports.push((port, "ysql"));
+        }
+    }
+    if flag("start_cql_proxy").unwrap_or_default() != "false" {
+        if let Some(port) = flag("cql_proxy_webserver_port") {
+            ports.push((port, "ycql"));
+        }
+    }
+    ports
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unit_split_host_port() {
+        assert_eq!(split_host_port("yb-1.local:7100"), ("yb-1.local".to_string(), "7100".to_string()));
+        assert_eq!(split_host_port("192.168.66.80"), ("192.168.66.80".to_string(), "7000".to_string()));
+    }
+
+    #[test]
+    fn unit_master_http_addresses() {
+        // This is a (shortened) /api/v1/masters output.
+        let json = r#"{"masters":[{"instance_id":{"permanent_uuid":"a","instance_seqno":1},"registration":{"private_rpc_addresses":[{"host":"yb-1.local","port":7100}],"http_addresses":[{"host":"yb-1.local","port":7000}]},"role":"LEADER"},{"instance_id":{"permanent_uuid":"b","instance_seqno":1},"registration":{"private_rpc_addresses":[{"host":"yb-2.local","port":7100}],"http_addresses":[{"host":"yb-2.local","port":7000}]},"role":"FOLLOWER"}]}"#;
+        let masters: AllMasters = serde_json::from_str(json).unwrap();
+        let result = master_http_addresses(masters);
+        assert_eq!(result.len(), 2);
+        assert!(result.contains(&("yb-2.local".to_string(), "7000".to_string())));
+    }
+
+    #[test]
+    fn unit_proxy_ports() {
+        // This is a (shortened) /api/v1/varz output of a tablet server.
+        let json = r#"{"flags":[{"name":"enable_ysql","value":"true","type":"Default"},{"name":"pgsql_proxy_webserver_port","value":"13000","type":"Default"},{"name":"start_cql_proxy","value":"false","type":"Custom"},{"name":"cql_proxy_webserver_port","value":"12000","type":"Default"}]}"#;
+        let vars: AllVars = serde_json::from_str(json).unwrap();
+        let result = proxy_ports(&vars);
+        assert_eq!(result, vec![("13000".to_string(), "ysql")]);
+    }
+
+    #[test]
+    fn unit_role_from_metrics_server_entity() {
+        // This is a (shortened) /metrics output of a YSQL webserver.
+        let json = r#"[{"type":"server","id":"yb.ysqlserver","metrics":[{"name":"handler_latency_yb_ysqlserver_SQLProcessor_SelectStmt","count":0,"sum":0,"rows":0}]}]"#;
+        assert_eq!(role_from_metrics(json), Some("ysql"));
+        let json = r#"[{"type":"tablet","id":"abc","attributes":{},"metrics":[]},{"type":"server","id":"yb.tabletserver","attributes":{},"metrics":[]}]"#;
+        assert_eq!(role_from_metrics(json), Some("tserver"));
+    }
+
+    #[test]
+    fn unit_role_from_metrics_node_exporter() {
+        let prometheus = "# HELP node_cpu_seconds_total Seconds the CPUs spent in each mode.\n# TYPE node_cpu_seconds_total counter\nnode_cpu_seconds_total{cpu=\"0\",mode=\"idle\"} 1.2\n";
+        assert_eq!(role_from_metrics(prometheus), Some("node_exporter"));
+        let prometheus = "handler_latency_yb_master_MasterClient_Ping_count{metric_id=\"yb.master\",metric_type=\"server\",exported_instance=\"yb-1.local:7000\"} 2 1670000000000\n";
+        assert_eq!(role_from_metrics(prometheus), Some("master"));
+        assert_eq!(role_from_metrics(""), None);
+        assert_eq!(role_from_metrics("Error 404: Not Found"), None);
+    }
+
+    #[test]
+    fn unit_new_from_hosts_ports() {
+        let result = AllStoredEndpoints::new_from_hosts_ports(&["h1", "h2"], &["7000", "9300"]);
+        assert_eq!(result.stored_endpoints.len(), 4);
+        assert_eq!(result.stored_endpoints[1].role, "node_exporter");
+        assert_eq!(result.hosts(), vec!["h1", "h2"]);
+        assert_eq!(result.ports(), vec!["7000", "9300"]);
+    }
+}
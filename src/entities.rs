@@ -55,12 +55,21 @@
 //! 
 use serde_derive::{Serialize,Deserialize};
 use chrono::{DateTime, Local};
-use std::{fs, process, collections::{BTreeMap, HashMap}, time::Instant, env, error::Error, sync::mpsc::channel};
+use std::{process, collections::{BTreeMap, HashMap}, time::Instant, error::Error};
 use log::*;
+use crate::snapshot::snapshot_directory;
+use futures::future::join_all;
 use regex::Regex;
 use colored::*;
 use crate::isleader::AllStoredIsLeader;
-use crate::utility::{scan_host_port, http_get};
+use crate::utility::http_get;
+use crate::endpoints::has_role;
+use crate::compression::create_snapshot_file;
+use crate::schema::open_snapshot_csv;
+use crate::output::{self, DiffRecord, push_changed_attribute};
+
+/// The endpoint roles that provide /dump-entities.
+pub const ROLES: &[&str] = &["master"];
 
 #[derive(Serialize, Deserialize, Debug, Default)]
 pub struct Entities {
@@ -231,13 +240,12 @@ impl AllStoredEntities
         hosts: &Vec<&str>,
         ports: &Vec<&str>,
         snapshot_number: i32,
-        parallel: usize,
     )
     {
         info!("begin snapshot");
         let timer = Instant::now();
 
-        let allstoredentities = AllStoredEntities::read_entities(hosts, ports, parallel);
+        let allstoredentities = AllStoredEntities::read_entities(hosts, ports);
         allstoredentities.await.save_snapshot(snapshot_number)
             .unwrap_or_else(|e| {
                 error!("error saving snasphot: {}", e);
@@ -252,7 +260,6 @@ impl AllStoredEntities
     pub async fn read_entities (
         hosts: &Vec<&str>,
         ports: &Vec<&str>,
-        parallel: usize,
     ) -> AllStoredEntities
     {
         info!("begin parallel http read");
@@ -289,41 +296,38 @@ impl AllStoredEntities
 
          */
 
-        let pool = rayon::ThreadPoolBuilder::new().num_threads(parallel).build().unwrap();
-        let (tx, rx) = channel();
-        pool.scope(move |s| {
-            for host in hosts {
-                for port in ports {
-                    let tx = tx.clone();
-                    s.spawn(move |_|  {
-                        let detail_snapshot_time = Local::now();
-                        let entities = AllStoredEntities::read_http(host, port);
-                        tx.send((format!("{}:{}", host, port), detail_snapshot_time, entities)).expect("error sending data via tx (entities)");
-                    });
+        let mut handles = Vec::new();
+        for host in hosts {
+            for port in ports {
+                if !has_role(host, port, ROLES) {
+                    continue;
                 }
+                let handle = async move {
+                    let detail_snapshot_time = Local::now();
+                    let entities = AllStoredEntities::read_http(host, port).await;
+                    (format!("{}:{}", host, port), detail_snapshot_time, entities)
+                };
+                handles.push(handle);
             }
-        });
+        }
+        let results = join_all(handles).await;
 
         info!("end parallel http read {:?}", timer.elapsed());
 
         let mut allstoredentities = AllStoredEntities::new();
 
-        for (hostname_port, detail_snapshot_time, entities) in rx {
+        for (hostname_port, detail_snapshot_time, entities) in results {
             allstoredentities.split_into_vectors(entities, &hostname_port, detail_snapshot_time);
         }
 
         allstoredentities
     }
-    fn read_http(
+    async fn read_http(
         host: &str,
         port: &str,
     ) -> Entities
     {
-        let data_from_http = if scan_host_port( host, port) {
-            http_get(host, port, "dump-entities")
-        } else {
-            String::new()
-        };
+        let data_from_http = http_get(host, port, "dump-entities").await;
         AllStoredEntities::parse_entities(data_from_http, host, port)
     }
     fn parse_entities(
@@ -363,52 +367,39 @@ impl AllStoredEntities
     }
     fn save_snapshot ( self, snapshot_number: i32 ) -> Result<(), Box<dyn Error>>
     {
-        let current_directory = env::current_dir()?;
-        let current_snapshot_directory = current_directory.join("yb_stats.snapshots").join(&snapshot_number.to_string());
+        let current_snapshot_directory = snapshot_directory().join(snapshot_number.to_string());
 
         let tables_file = &current_snapshot_directory.join("tables");
-        let file = fs::OpenOptions::new()
-            .create(true)
-            .write(true)
-            .open(tables_file)?;
+        let file = create_snapshot_file(tables_file)?;
         let mut writer = csv::Writer::from_writer(file);
         for row in self.stored_tables {
             writer.serialize(row)?;
         }
-        writer.flush()?;
+        writer.into_inner()?.finish()?;
 
         let tablets_file = &current_snapshot_directory.join("tablets");
-        let file = fs::OpenOptions::new()
-            .create(true)
-            .write(true)
-            .open(tablets_file)?;
+        let file = create_snapshot_file(tablets_file)?;
         let mut writer = csv::Writer::from_writer(file);
         for row in self.stored_tablets {
             writer.serialize(row)?;
         }
-        writer.flush()?;
+        writer.into_inner()?.finish()?;
 
         let replicas_file = &current_snapshot_directory.join("replicas");
-        let file = fs::OpenOptions::new()
-            .create(true)
-            .write(true)
-            .open(replicas_file)?;
+        let file = create_snapshot_file(replicas_file)?;
         let mut writer = csv::Writer::from_writer(file);
         for row in self.stored_replicas {
             writer.serialize(row)?;
         }
-        writer.flush()?;
+        writer.into_inner()?.finish()?;
 
         let keyspaces_file = &current_snapshot_directory.join("keyspaces");
-        let file = fs::OpenOptions::new()
-            .create(true)
-            .write(true)
-            .open(keyspaces_file)?;
+        let file = create_snapshot_file(keyspaces_file)?;
         let mut writer = csv::Writer::from_writer(file);
         for row in self.stored_keyspaces {
             writer.serialize(row)?;
         }
-        writer.flush()?;
+        writer.into_inner()?.finish()?;
 
         Ok(())
     }
@@ -417,11 +408,10 @@ impl AllStoredEntities
         //let mut allstoredentities = AllStoredEntities { stored_keyspaces: Vec::new(), stored_tables: Vec::new(), stored_tablets: Vec::new(), stored_replicas: Vec::new() };
         let mut allstoredentities = AllStoredEntities::new();
 
-        let current_directory = env::current_dir()?;
-        let current_snapshot_directory = current_directory.join("yb_stats.snapshots").join(snapshot_number);
+        let current_snapshot_directory = snapshot_directory().join(snapshot_number);
 
         let keyspaces_file = &current_snapshot_directory.join("keyspaces");
-        let file = fs::File::open(keyspaces_file)?;
+        let file = open_snapshot_csv(keyspaces_file)?;
 
         let mut reader = csv::Reader::from_reader(file);
         for row in reader.deserialize() {
@@ -430,7 +420,7 @@ impl AllStoredEntities
         };
 
         let tables_file = &current_snapshot_directory.join("tables");
-        let file = fs::File::open(tables_file)?;
+        let file = open_snapshot_csv(tables_file)?;
 
         let mut reader = csv::Reader::from_reader(file);
         for row in reader.deserialize() {
@@ -439,7 +429,7 @@ impl AllStoredEntities
         };
 
         let tablets_file = &current_snapshot_directory.join("tablets");
-        let file = fs::File::open(tablets_file)?;
+        let file = open_snapshot_csv(tablets_file)?;
 
         let mut reader = csv::Reader::from_reader(file);
         for row in reader.deserialize() {
@@ -448,7 +438,7 @@ impl AllStoredEntities
         };
 
         let replicas_file = &current_snapshot_directory.join("replicas");
-        let file = fs::File::open(replicas_file)?;
+        let file = open_snapshot_csv(replicas_file)?;
 
         let mut reader = csv::Reader::from_reader(file);
         for row in reader.deserialize() {
@@ -640,12 +630,11 @@ impl AllStoredEntities
         details_enable: &bool,
         hosts: &Vec<&str>,
         ports: &Vec<&str>,
-        parallel: usize,
     )
     {
         info!("print_entities");
 
-        let leader_hostname = AllStoredIsLeader::return_leader_http(hosts, ports, parallel).await;
+        let leader_hostname = AllStoredIsLeader::return_leader_http(hosts, ports).await;
 
         let mut tables_btreemap: BTreeMap<(String, String, String), StoredTables> = BTreeMap::new();
 
@@ -1322,30 +1311,158 @@ impl SnapshotDiffBTreeMapsEntities {
         &mut self,
         hosts: &Vec<&str>,
         ports: &Vec<&str>,
-        parallel: usize,
     )
     {
-        let allstoredentities = AllStoredEntities::read_entities(hosts, ports, parallel).await;
-        let master_leader= AllStoredIsLeader::return_leader_http(hosts, ports, parallel).await;
+        let allstoredentities = AllStoredEntities::read_entities(hosts, ports).await;
+        let master_leader= AllStoredIsLeader::return_leader_http(hosts, ports).await;
         self.first_snapshot(allstoredentities, master_leader, &false );
     }
     pub async fn adhoc_read_second_snapshot(
         &mut self,
         hosts: &Vec<&str>,
         ports: &Vec<&str>,
-        parallel: usize,
     )
     {
-        let allstoredentities = AllStoredEntities::read_entities(hosts, ports, parallel).await;
-        let master_leader= AllStoredIsLeader::return_leader_http(hosts, ports, parallel).await;
+        let allstoredentities = AllStoredEntities::read_entities(hosts, ports).await;
+        let master_leader= AllStoredIsLeader::return_leader_http(hosts, ports).await;
         self.second_snapshot(allstoredentities, master_leader, &false);
     }
+    /// This function returns the changes that are printed by [SnapshotDiffBTreeMapsEntities::print] as records for the machine-readable output, see [crate::output].
+    /// The metric_type of a record is the kind of object: database, table, tablet or replica.
+    pub fn records(
+        &self,
+    ) -> Vec<DiffRecord>
+    {
+        let mut records = Vec::new();
+        if ! self.master_found {
+            return records;
+        }
+        let keyspace = |keyspace_id: &String| self.keyspace_id_lookup.get(keyspace_id).cloned().unwrap_or_default();
+        let table_keyspace = |table_id: &String| self.table_keyspace_lookup.get(table_id).map(keyspace).unwrap_or_default();
+        let table_name = |table_id: &String| self.table_id_lookup.get(table_id).cloned().unwrap_or_default();
+        let server = |server_uuid: &String| self.server_id_lookup.get(server_uuid).cloned().unwrap_or_else(|| "?".to_string());
+        for (keyspace_id, keyspace_row) in self.btreemap_snapshotdiff_keyspaces.iter() {
+            let (change, keyspace_type, keyspace_name) = if keyspace_row.first_keyspace_name.is_empty() && keyspace_row.first_keyspace_type.is_empty() {
+                ("+", &keyspace_row.second_keyspace_type, &keyspace_row.second_keyspace_name)
+            } else if keyspace_row.second_keyspace_name.is_empty() && keyspace_row.second_keyspace_type.is_empty() {
+                ("-", &keyspace_row.first_keyspace_type, &keyspace_row.first_keyspace_name)
+            } else {
+                continue;
+            };
+            let colocated_parent = format!("{}.colocated.parent.uuid", &keyspace_id);
+            let colocation = if self.btreemap_snapshotdiff_tablets.values()
+                .any(|v| if change == "+" { v.second_table_id == colocated_parent } else { v.first_table_id == colocated_parent }) {
+                " [colocated]"
+            } else {
+                ""
+            };
+            let description = format!("{}.{}{}", keyspace_type, keyspace_name, colocation);
+            records.push(DiffRecord::object("entities", change, DiffRecord {
+                metric_type: String::from("database"),
+                metric_id: keyspace_id.to_string(),
+                namespace: keyspace_name.to_string(),
+                name: keyspace_name.to_string(),
+                first: if change == "-" { description.clone() } else { String::new() },
+                second: if change == "+" { description } else { String::new() },
+                ..Default::default()
+            }));
+        }
+        for (table_id, table_row) in &self.btreemap_snapshotdiff_tables {
+            let (change, keyspace_id, name, state) = if table_row.first_table_name.is_empty() && table_row.first_table_state.is_empty() && table_row.first_keyspace_id.is_empty() {
+                ("+", &table_row.second_keyspace_id, &table_row.second_table_name, &table_row.second_table_state)
+            } else if table_row.second_table_name.is_empty() && table_row.second_table_state.is_empty() && table_row.second_keyspace_id.is_empty() {
+                ("-", &table_row.first_keyspace_id, &table_row.first_table_name, &table_row.first_table_state)
+            } else {
+                continue;
+            };
+            let (keyspace_type, keyspace_name) = keyspace(keyspace_id);
+            let description = format!("{}.{}.{}, state: {}", keyspace_type, keyspace_name, name, state);
+            records.push(DiffRecord::object("entities", change, DiffRecord {
+                metric_type: String::from("table"),
+                metric_id: table_id.to_string(),
+                namespace: keyspace_name,
+                table_name: name.to_string(),
+                name: name.to_string(),
+                first: if change == "-" { description.clone() } else { String::new() },
+                second: if change == "+" { description } else { String::new() },
+                ..Default::default()
+            }));
+        }
+        for (tablet_id, tablet_row) in &self.btreemap_snapshotdiff_tablets {
+            let (change, table_id) = if tablet_row.first_table_id.is_empty() && tablet_row.first_tablet_state.is_empty() && tablet_row.first_leader.is_empty() {
+                ("+", &tablet_row.second_table_id)
+            } else if tablet_row.second_table_id.is_empty() && tablet_row.second_tablet_state.is_empty() && tablet_row.second_leader.is_empty() {
+                ("-", &tablet_row.first_table_id)
+            } else {
+                ("*", &tablet_row.first_table_id)
+            };
+            let (keyspace_type, keyspace_name) = table_keyspace(table_id);
+            let record = DiffRecord::object("entities", change, DiffRecord {
+                metric_type: String::from("tablet"),
+                metric_id: tablet_id.to_string(),
+                namespace: keyspace_name.to_string(),
+                table_name: table_name(table_id),
+                name: tablet_id.to_string(),
+                ..Default::default()
+            });
+            match change {
+                "+" => records.push(DiffRecord {
+                    second: format!("{}.{}.{}.{} state: {}, leader: {}", keyspace_type, keyspace_name, record.table_name, tablet_id, tablet_row.second_tablet_state, server(&tablet_row.second_leader)),
+                    ..record
+                }),
+                "-" => records.push(DiffRecord {
+                    first: format!("{}.{}.{}.{} state: {}, leader: {}", keyspace_type, keyspace_name, record.table_name, tablet_id, tablet_row.first_tablet_state, server(&tablet_row.first_leader)),
+                    ..record
+                }),
+                _ => {
+                    push_changed_attribute(&mut records, &record, "state", &tablet_row.first_tablet_state, &tablet_row.second_tablet_state);
+                    push_changed_attribute(&mut records, &record, "leader", &server(&tablet_row.first_leader), &server(&tablet_row.second_leader));
+                },
+            }
+        }
+        for ((tablet_id, _server_uuid), replica_row) in &self.btreemap_snapshotdiff_replicas {
+            let table_id = self.tablet_table_lookup.get(tablet_id).cloned().unwrap_or_default();
+            let (keyspace_type, keyspace_name) = table_keyspace(&table_id);
+            let (change, addr) = if replica_row.first_addr.is_empty() && replica_row.first_replica_type.is_empty() {
+                ("+", &replica_row.second_addr)
+            } else if replica_row.second_addr.is_empty() && replica_row.second_replica_type.is_empty() {
+                ("-", &replica_row.first_addr)
+            } else {
+                ("*", &replica_row.first_addr)
+            };
+            let record = DiffRecord::object("entities", change, DiffRecord {
+                hostname_port: addr.to_string(),
+                metric_type: String::from("replica"),
+                metric_id: tablet_id.to_string(),
+                namespace: keyspace_name.to_string(),
+                table_name: table_name(&table_id),
+                name: tablet_id.to_string(),
+                ..Default::default()
+            });
+            match change {
+                "+" => records.push(DiffRecord {
+                    second: format!("{}.{}.{}.{} server: {}, type: {}", keyspace_type, keyspace_name, record.table_name, tablet_id, addr, replica_row.second_replica_type),
+                    ..record
+                }),
+                "-" => records.push(DiffRecord {
+                    first: format!("{}.{}.{}.{} server: {}, type: {}", keyspace_type, keyspace_name, record.table_name, tablet_id, addr, replica_row.first_replica_type),
+                    ..record
+                }),
+                _ => push_changed_attribute(&mut records, &record, "type", &replica_row.first_replica_type, &replica_row.second_replica_type),
+            }
+        }
+        records
+    }
     pub fn print(
         &self,
     )
     {
         if ! self.master_found {
-            println!("Master leader was not found in hosts specified, skipping entity diff.");
+            output::print_message("Master leader was not found in hosts specified, skipping entity diff.");
+            return;
+        }
+        if output::is_machine_readable() {
+            output::write_records(&self.records());
             return;
         }
         for (keyspace_id, keyspace_row) in self.btreemap_snapshotdiff_keyspaces.iter() {
@@ -1570,14 +1687,14 @@ mod tests {
 
     use crate::utility;
 
-    #[test]
-    fn integration_parse_entities() {
+    #[tokio::test]
+    async fn integration_parse_entities() {
         let mut allstoredentities = AllStoredEntities::new();
 
         let hostname = utility::get_hostname_master();
         let port = utility::get_port_master();
 
-        let json = AllStoredEntities::read_http(&hostname, &port);
+        let json = AllStoredEntities::read_http(&hostname, &port).await;
         allstoredentities.split_into_vectors(json, format!("{}:{}", hostname, port).as_str(), Local::now());
 
         assert!(!allstoredentities.stored_tables.is_empty());
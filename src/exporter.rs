@@ -0,0 +1,336 @@
+//! The module for the exporter mode (`--exporter <address:port>`): yb_stats as a long-lived process that serves the rates of the metrics in the prometheus format.
+//!
+//! The exporter scrapes the metrics of the cluster every `--exporter-interval` seconds (default 15).
+//! The previous scrape is kept in a [SnapshotDiffBTreeMapsMetrics], so every scrape results in the differences over the interval (see [crate::rates]),
+//! which are served on `/metrics` of the exporter's own http endpoint:
+//! - value statistics that are counters: `yb_stats_<metric>_rate`, the difference per second.
+//! - countsum statistics: `yb_stats_<metric>_count_rate`, the count difference per second, and `yb_stats_<metric>_average`, the sum difference divided by the count difference.
+//!   The average can't be derived by prometheus itself from the server metrics, because the server resets its histograms when these are fetched.
+//! - countsumrows (YSQL) statistics: `yb_stats_<metric>_count_rate`, `yb_stats_<metric>_rows_rate` and `yb_stats_<metric>_average`.
+//!
+//! The rates are labelled with `host`, `metric_type`, and, for table and tablet statistics, `namespace` and `table`: the statistics of the tablets of a table are added up.
+//! A statistic that is new, such as of a tablet that is created or moved to the host, is served from the second scrape it is in, and a statistic that is reset by a restart is left out of that scrape.
+//! The `--hostname-match`, `--stat-name-match` and `--table-name-match` filters limit the statistics that are served.
+use std::{collections::BTreeMap, process, sync::{Arc, RwLock}, time::Duration};
+use chrono::Local;
+use log::*;
+use tokio::{io::{AsyncReadExt, AsyncWriteExt}, net::{TcpListener, TcpStream}};
+use crate::metrics::SnapshotDiffBTreeMapsMetrics;
+use crate::value_statistic_details::ValueStatistics;
+use crate::countsum_statistic_details::CountSumStatistics;
+use crate::collection_errors;
+use crate::rates::{interval_differences, Grouping, StatisticType};
+use crate::utility::Filters;
+
+/// The default scrape interval in seconds.
+pub const DEFAULT_EXPORTER_INTERVAL: u64 = 15;
+/// The prefix of all the metric names that are served.
+const PREFIX: &str = "yb_stats";
+/// The maximum size of a request that is read.
+const MAX_REQUEST_SIZE: usize = 8192;
+
+/// The labels of a rate: the statistics of the tablets of a table are added up, because the labels do not contain the tablet.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+struct Labels {
+    host: String,
+    metric_type: String,
+    namespace: String,
+    table: String,
+}
+
+impl Labels {
+    fn new(
+        host: &str,
+        metric_type: &str,
+        namespace: &str,
+        table: &str,
+    ) -> Self
+    {
+        Labels { host: host.to_string(), metric_type: metric_type.to_string(), namespace: namespace.to_string(), table: table.to_string() }
+    }
+    /// The labels in the prometheus format. Namespace and table are "-" for statistics that are not table or tablet statistics, these are left out.
+    fn format(&self) -> String {
+        let mut labels = vec![format!("host=\"{}\"", escape(&self.host)), format!("metric_type=\"{}\"", escape(&self.metric_type))];
+        if self.namespace != "-" && !self.namespace.is_empty() {
+            labels.push(format!("namespace=\"{}\"", escape(&self.namespace)));
+        }
+        if self.table != "-" && !self.table.is_empty() {
+            labels.push(format!("table=\"{}\"", escape(&self.table)));
+        }
+        labels.join(",")
+    }
+}
+
+/// The metric families with their help text, and the rates per labels.
+#[derive(Default)]
+struct Families {
+    families: BTreeMap<String, (String, BTreeMap<Labels, f64>)>,
+}
+
+impl Families {
+    fn add(
+        &mut self,
+        name: String,
+        help: String,
+        labels: Labels,
+        value: f64,
+    )
+    {
+        self.families.entry(name)
+            .or_insert_with(|| (help, BTreeMap::new()))
+            .1
+            .insert(labels, value);
+    }
+    fn format(&self) -> String {
+        let mut output = String::new();
+        for (name, (help, samples)) in &self.families {
+            output.push_str(&format!("# HELP {} {}\n# TYPE {} gauge\n", name, help, name));
+            for (labels, value) in samples {
+                output.push_str(&format!("{}{{{}}} {}\n", name, labels.format(), value));
+            }
+        }
+        output
+    }
+}
+
+/// Escape a label value for the prometheus format.
+fn escape(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+/// A statistic name as a valid prometheus metric name.
+fn metric_name(name: &str, suffix: &str) -> String {
+    let name = name.chars().map(|c| if c.is_ascii_alphanumeric() || c == '_' { c } else { '_' }).collect::<String>();
+    format!("{}_{}_{}", PREFIX, name, suffix)
+}
+
+/// This function takes the differences in [SnapshotDiffBTreeMapsMetrics] and returns the rates and averages in the prometheus format.
+pub fn render(
+    metrics: &SnapshotDiffBTreeMapsMetrics,
+    filters: &Filters,
+) -> String
+{
+    let value_statistics = ValueStatistics::create();
+    let countsum_statistics = CountSumStatistics::create();
+
+    let mut families = Families::default();
+    for (key, difference) in interval_differences(metrics, filters, Grouping::Table) {
+        let name = key.metric_name.as_str();
+        let labels = Labels::new(&key.hostname_port, &key.metric_type, &key.namespace, &key.table_name);
+        match key.statistic_type {
+            StatisticType::Value => {
+                let unit = &value_statistics.lookup(name).unit;
+                families.add(metric_name(name, "rate"), format!("{} ({}) per second", name, unit), labels, difference.rate());
+            },
+            StatisticType::CountSum | StatisticType::CountSumRows => {
+                let unit = if key.statistic_type == StatisticType::CountSum { countsum_statistics.lookup(name).unit.to_string() } else { String::from("ms") };
+                if let Some(average) = difference.average() {
+                    families.add(metric_name(name, "average"), format!("{} average per call ({})", name, unit), labels.clone(), average);
+                }
+                if key.statistic_type == StatisticType::CountSumRows {
+                    families.add(metric_name(name, "rows_rate"), format!("{} rows per second", name), labels.clone(), difference.rows_rate());
+                }
+                families.add(metric_name(name, "count_rate"), format!("{} calls per second", name), labels, difference.rate());
+            },
+        }
+    }
+    families.format()
+}
+
+/// This function creates the http response for a request: `/metrics` returns the rates, `/` a page with a link to `/metrics`.
+fn response(
+    request: &str,
+    body: &str,
+) -> String
+{
+    let path = request.lines()
+        .next()
+        .and_then(|request_line| {
+            let mut parts = request_line.split_whitespace();
+            match (parts.next(), parts.next()) {
+                (Some("GET"), Some(path)) => Some(path),
+                _ => None,
+            }
+        });
+    let (status, content_type, body) = match path.map(|path| path.split('?').next().unwrap_or_default()) {
+        Some("/metrics") => ("200 OK", "text/plain; version=0.0.4", body.to_string()),
+        Some("/") => ("200 OK", "text/html", String::from("<html><head><title>yb_stats exporter</title></head><body><a href=\"/metrics\">metrics</a></body></html>\n")),
+        Some(_) => ("404 Not Found", "text/plain", String::from("not found\n")),
+        None => ("400 Bad Request", "text/plain", String::from("bad request\n")),
+    };
+    format!("HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}", status, content_type, body.len(), body)
+}
+
+/// This function handles a single http connection.
+async fn handle_connection(
+    mut stream: TcpStream,
+    body: Arc<RwLock<String>>,
+) -> Result<(), Box<dyn std::error::Error>>
+{
+    let mut request = Vec::new();
+    let mut buffer = [0_u8; 1024];
+    while !request.windows(4).any(|window| window == b"\r\n\r\n") && request.len() < MAX_REQUEST_SIZE {
+        let bytes = tokio::time::timeout(Duration::from_secs(5), stream.read(&mut buffer)).await??;
+        if bytes == 0 {
+            break;
+        }
+        request.extend_from_slice(&buffer[..bytes]);
+    }
+    let body = body.read().unwrap_or_else(|e| e.into_inner()).clone();
+    stream.write_all(response(&String::from_utf8_lossy(&request), &body).as_bytes()).await?;
+    stream.shutdown().await?;
+    Ok(())
+}
+
+/// This function runs the exporter: it serves the rates on the address, and scrapes the metrics of the hosts and ports every interval, until yb_stats is stopped.
+pub async fn exporter(
+    hosts: &Vec<&str>,
+    ports: &Vec<&str>,
+    address: &str,
+    interval: u64,
+    filters: &Filters<'_>,
+)
+{
+    let listener = TcpListener::bind(address).await
+        .unwrap_or_else(|e| {
+            error!("Fatal: error binding the exporter to {}: {}", address, e);
+            process::exit(1);
+        });
+    println!("Serving rates on http://{}/metrics, scraping every {} seconds", address, interval);
+
+    let body = Arc::new(RwLock::new(String::new()));
+    let server_body = body.clone();
+    tokio::spawn(async move {
+        loop {
+            match listener.accept().await {
+                Ok((stream, peer)) => {
+                    let body = server_body.clone();
+                    tokio::spawn(async move {
+                        handle_connection(stream, body).await
+                            .unwrap_or_else(|e| debug!("error handling exporter request from {}: {}", peer, e));
+                    });
+                },
+                Err(e) => warn!("error accepting exporter connection: {}", e),
+            }
+        }
+    });
+
+    let mut ticker = tokio::time::interval(Duration::from_secs(interval));
+    ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    ticker.tick().await;
+    let mut metrics = SnapshotDiffBTreeMapsMetrics::new();
+    let mut previous_snapshot_time = Local::now();
+    // The first scrape only fills the second snapshot, which becomes the first snapshot of the next scrape.
+    metrics.adhoc_read_next_snapshot(hosts, ports, &previous_snapshot_time).await;
+    collection_errors::clear_recorded();
+    loop {
+        ticker.tick().await;
+        let snapshot_time = Local::now();
+        metrics.adhoc_read_next_snapshot(hosts, ports, &previous_snapshot_time).await;
+        previous_snapshot_time = snapshot_time;
+        let rendered = render(&metrics, filters);
+        info!("exporter scrape: {} bytes", rendered.len());
+        *body.write().unwrap_or_else(|e| e.into_inner()) = rendered;
+        let errors = collection_errors::clear_recorded();
+        if errors > 0 {
+            info!("exporter scrape: {} endpoint(s) could not be read", errors);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+    use regex::Regex;
+    use crate::metrics::{SnapshotDiffValues, SnapshotDiffCountSumRows, StoredValues, AllStoredMetrics};
+
+    #[test]
+    fn unit_exporter_render() {
+        let time = |second: u32| Local.with_ymd_and_hms(2023, 1, 10, 10, 0, second).unwrap();
+        let mut metrics = SnapshotDiffBTreeMapsMetrics::new();
+        let value = |first_snapshot_value: i64, second_snapshot_value: i64| SnapshotDiffValues {
+            table_name: String::from("t1"),
+            namespace: String::from("yugabyte"),
+            first_snapshot_time: time(0),
+            second_snapshot_time: time(10),
+            first_snapshot_value,
+            second_snapshot_value,
+        };
+        let key = |metric_id: &str, metric_name: &str| (String::from("yb-1:9000"), String::from("tablet"), metric_id.to_string(), metric_name.to_string());
+        metrics.btreemap_snapshotdiff_values.insert(key("tablet1", "rows_inserted"), value(100, 200));
+        metrics.btreemap_snapshotdiff_values.insert(key("tablet2", "rows_inserted"), value(100, 150));
+        metrics.btreemap_snapshotdiff_values.insert(key("tablet1", "active_task_metrics_compaction_tasks_added"), value(1, 5));
+        metrics.btreemap_snapshotdiff_countsumrows.insert((String::from("yb-1:13000"), String::from("server"), String::from("yb.ysqlserver"), String::from("handler_latency_yb_ysqlserver_SQLProcessor_SelectStmt")), SnapshotDiffCountSumRows {
+            table_name: String::from("-"),
+            namespace: String::from("-"),
+            first_snapshot_time: time(0),
+            second_snapshot_time: time(10),
+            first_snapshot_count: 10,
+            first_snapshot_sum: 100,
+            first_snapshot_rows: 10,
+            second_snapshot_count: 30,
+            second_snapshot_sum: 500,
+            second_snapshot_rows: 110,
+        });
+        let match_all = Regex::new(".*").unwrap();
+        let filters = Filters { hostname_filter: &match_all, stat_name_filter: &match_all, table_name_filter: &match_all, details_enable: false, gauges_enable: false, sql_length: 80 };
+
+        let rendered = render(&metrics, &filters);
+        assert!(rendered.contains("# TYPE yb_stats_rows_inserted_rate gauge\n"));
+        assert!(rendered.contains("yb_stats_rows_inserted_rate{host=\"yb-1:9000\",metric_type=\"tablet\",namespace=\"yugabyte\",table=\"t1\"} 15\n"));
+        // 400 microseconds for 20 calls.
+        assert!(rendered.contains("# HELP yb_stats_handler_latency_yb_ysqlserver_SQLProcessor_SelectStmt_average handler_latency_yb_ysqlserver_SQLProcessor_SelectStmt average per call (ms)\n"));
+        assert!(rendered.contains("yb_stats_handler_latency_yb_ysqlserver_SQLProcessor_SelectStmt_average{host=\"yb-1:13000\",metric_type=\"server\"} 0.02\n"));
+        assert!(rendered.contains("yb_stats_handler_latency_yb_ysqlserver_SQLProcessor_SelectStmt_count_rate{host=\"yb-1:13000\",metric_type=\"server\"} 2\n"));
+        assert!(rendered.contains("yb_stats_handler_latency_yb_ysqlserver_SQLProcessor_SelectStmt_rows_rate{host=\"yb-1:13000\",metric_type=\"server\"} 10\n"));
+        // gauges have no rate.
+        assert!(!rendered.contains("active_task_metrics_compaction_tasks_added"));
+
+        let hostname_filter = Regex::new("9000").unwrap();
+        let filters = Filters { hostname_filter: &hostname_filter, stat_name_filter: &match_all, table_name_filter: &match_all, details_enable: false, gauges_enable: false, sql_length: 80 };
+        assert!(!render(&metrics, &filters).contains("SelectStmt"));
+    }
+    #[test]
+    fn unit_exporter_new_tablet_and_restart() {
+        let time = |second: u32| Local.with_ymd_and_hms(2023, 1, 10, 10, 0, second).unwrap();
+        let stored_value = |second: u32, metric_id: &str, metric_value: i64| StoredValues {
+            hostname_port: String::from("yb-1:9000"),
+            timestamp: time(second),
+            metric_type: String::from("tablet"),
+            metric_id: metric_id.to_string(),
+            attribute_namespace: String::from("yugabyte"),
+            attribute_table_name: String::from("t1"),
+            metric_name: String::from("rows_inserted"),
+            metric_value,
+        };
+        let scrape = |stored_values: Vec<StoredValues>| AllStoredMetrics { stored_values, stored_countsum: Vec::new(), stored_countsumrows: Vec::new() };
+        let match_all = Regex::new(".*").unwrap();
+        let filters = Filters { hostname_filter: &match_all, stat_name_filter: &match_all, table_name_filter: &match_all, details_enable: false, gauges_enable: false, sql_length: 80 };
+        let rate = "yb_stats_rows_inserted_rate{host=\"yb-1:9000\",metric_type=\"tablet\",namespace=\"yugabyte\",table=\"t1\"}";
+
+        let mut metrics = SnapshotDiffBTreeMapsMetrics::new();
+        metrics.next_snapshot(scrape(vec![stored_value(0, "tablet1", 100), stored_value(0, "tablet2", 100)]), &time(0));
+        // tablet3 appears with its lifetime counter: it has no rate in this interval.
+        metrics.next_snapshot(scrape(vec![stored_value(10, "tablet1", 200), stored_value(10, "tablet2", 150), stored_value(10, "tablet3", 1_000_000)]), &time(0));
+        assert!(render(&metrics, &filters).contains(&format!("{} 15\n", rate)));
+        // in the next interval tablet3 has a rate, and tablet2 is reset, which must not lower the sum of the other tablets.
+        metrics.next_snapshot(scrape(vec![stored_value(20, "tablet1", 300), stored_value(20, "tablet2", 10), stored_value(20, "tablet3", 1_000_100)]), &time(10));
+        assert!(render(&metrics, &filters).contains(&format!("{} 20\n", rate)));
+    }
+    #[test]
+    fn unit_exporter_response() {
+        let response_metrics = response("GET /metrics HTTP/1.1\r\nHost: localhost\r\n\r\n", "rates\n");
+        assert!(response_metrics.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response_metrics.contains("Content-Length: 6\r\n"));
+        assert!(response_metrics.ends_with("\r\n\r\nrates\n"));
+        assert!(response("GET /metrics?x=1 HTTP/1.1\r\n\r\n", "").starts_with("HTTP/1.1 200 OK"));
+        assert!(response("GET /other HTTP/1.1\r\n\r\n", "").starts_with("HTTP/1.1 404"));
+        assert!(response("POST /metrics HTTP/1.1\r\n\r\n", "").starts_with("HTTP/1.1 400"));
+    }
+    #[test]
+    fn unit_exporter_escape_and_name() {
+        assert_eq!(escape("a\"b\\c\nd"), "a\\\"b\\\\c\\nd");
+        assert_eq!(metric_name("handler_latency.x-y", "rate"), "yb_stats_handler_latency_x_y_rate");
+    }
+}
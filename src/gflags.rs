@@ -1,13 +1,18 @@
-use std::path::PathBuf;
 use std::time::Instant;
 use chrono::{DateTime, Local};
-use port_scanner::scan_port_addr;
 use regex::Regex;
-use std::fs;
 use std::process;
 use serde_derive::{Serialize,Deserialize};
-use std::sync::mpsc::channel;
 use log::*;
+use futures::future::join_all;
+use crate::utility::http_get;
+use crate::endpoints::has_role;
+use crate::compression::create_snapshot_file;
+use crate::schema::open_snapshot_csv;
+use crate::snapshot::snapshot_directory;
+
+/// The endpoint roles that provide /varz.
+pub const ROLES: &[&str] = &["master", "tserver"];
 
 #[derive(Debug)]
 pub struct GFlag {
@@ -24,31 +29,11 @@ pub struct StoredGFlags {
 }
 
 #[allow(dead_code)]
-pub fn read_gflags(
+pub async fn read_gflags(
     host: &str,
     port: &str,
 ) -> Vec<GFlag> {
-    if ! scan_port_addr( format!("{}:{}", host, port)) {
-        warn!("hostname:port {}:{} cannot be reached, skipping (gflags)",host ,port);
-        return Vec::new();
-    }
-    /*
-    if let Ok(data_from_http) = reqwest::blocking::get(format!("http://{}:{}/varz?raw",host ,port)) {
-        parse_gflags(data_from_http.text().unwrap())
-    } else {
-        parse_gflags(String::from(""))
-    }
-
-     */
-    let data_from_http = reqwest::blocking::Client::builder()
-        .danger_accept_invalid_certs(true)
-        .build()
-        .unwrap()
-        .get(format!("http://{}:{}/varz?raw", host, port))
-        .send()
-        .unwrap()
-        .text()
-        .unwrap();
+    let data_from_http = http_get(host, port, "varz?raw").await;
     parse_gflags(data_from_http)
 }
 
@@ -58,38 +43,35 @@ pub async fn perform_gflags_snapshot(
     hosts: &Vec<&str>,
     ports: &Vec<&str>,
     snapshot_number: i32,
-    yb_stats_directory: &PathBuf,
-    parallel: usize
 ) {
     info!("begin parallel http read");
     let timer = Instant::now();
 
-    let pool = rayon::ThreadPoolBuilder::new().num_threads(parallel).build().unwrap();
-    let (tx, rx) = channel();
-    pool.scope(move |s| {
-        for host in hosts {
-            for port in ports {
-                let tx = tx.clone();
-                s.spawn(move |_| {
-                    let detail_snapshot_time = Local::now();
-                    let gflags = read_gflags(host, port);
-                    tx.send((format!("{}:{}", host, port), detail_snapshot_time, gflags)).expect("error sending data via tx (gflags)");
-                });
-            }}
-    });
+    let mut handles = Vec::new();
+    for host in hosts {
+        for port in ports {
+            if !has_role(host, port, ROLES) {
+                continue;
+            }
+            let handle = async move {
+                let detail_snapshot_time = Local::now();
+                let gflags = read_gflags(host, port).await;
+                (format!("{}:{}", host, port), detail_snapshot_time, gflags)
+            };
+            handles.push(handle);
+        }
+    }
+    let results = join_all(handles).await;
     info!("end parallel http read {:?}", timer.elapsed());
 
     let mut stored_gflags: Vec<StoredGFlags> = Vec::new();
-    for (hostname_port, detail_snapshot_time, gflags) in rx {
+    for (hostname_port, detail_snapshot_time, gflags) in results {
         add_to_gflags_vector(gflags, &hostname_port, detail_snapshot_time, &mut stored_gflags);
     }
 
-    let current_snapshot_directory = &yb_stats_directory.join(&snapshot_number.to_string());
+    let current_snapshot_directory = &snapshot_directory().join(&snapshot_number.to_string());
     let gflags_file = &current_snapshot_directory.join("gflags");
-    let file = fs::OpenOptions::new()
-        .create(true)
-        .write(true)
-        .open(gflags_file)
+    let file = create_snapshot_file(gflags_file)
         .unwrap_or_else(|e| {
             error!("Fatal: error writing gflags data in snapshot directory {}: {}", &gflags_file.clone().into_os_string().into_string().unwrap(), e);
             process::exit(1);
@@ -98,7 +80,7 @@ pub async fn perform_gflags_snapshot(
     for row in stored_gflags {
         writer.serialize(row).unwrap();
     }
-    writer.flush().unwrap();
+    writer.into_inner().unwrap().finish().unwrap();
 }
 
 #[allow(dead_code)]
@@ -128,10 +110,10 @@ fn parse_gflags( gflags_data: String ) -> Vec<GFlag> {
 }
 
 #[allow(clippy::ptr_arg)]
-fn read_gflags_snapshot(snapshot_number: &String, yb_stats_directory: &PathBuf) -> Vec<StoredGFlags> {
+fn read_gflags_snapshot(snapshot_number: &String) -> Vec<StoredGFlags> {
     let mut stored_gflags: Vec<StoredGFlags> = Vec::new();
-    let gflags_file = &yb_stats_directory.join(snapshot_number).join("gflags");
-    let file = fs::File::open(gflags_file)
+    let gflags_file = &snapshot_directory().join(snapshot_number).join("gflags");
+    let file = open_snapshot_csv(gflags_file)
         .unwrap_or_else(|e| {
             error!("Fatal: error reading file: {}: {}", &gflags_file.clone().into_os_string().into_string().unwrap(), e);
             process::exit(1);
@@ -146,12 +128,11 @@ fn read_gflags_snapshot(snapshot_number: &String, yb_stats_directory: &PathBuf)
 
 pub fn print_gflags_data(
     snapshot_number: &String,
-    yb_stats_directory: &PathBuf,
     hostname_filter: &Regex,
     stat_name_filter: &Regex,
 ) {
     info!("print_gflags");
-    let stored_gflags: Vec<StoredGFlags> = read_gflags_snapshot(snapshot_number, yb_stats_directory);
+    let stored_gflags: Vec<StoredGFlags> = read_gflags_snapshot(snapshot_number);
     let mut previous_hostname_port = String::from("");
     for row in stored_gflags {
         if hostname_filter.is_match(&row.hostname_port) &&
@@ -1010,26 +991,26 @@ Command-line Flags--TEST_xcluster_simulate_have_more_records=false
     }
 
     use crate::utility;
-    #[test]
-    fn integration_parse_gflags_master() {
+    #[tokio::test]
+    async fn integration_parse_gflags_master() {
         let mut stored_gflags: Vec<StoredGFlags> = Vec::new();
         let detail_snapshot_time = Local::now();
         let hostname = utility::get_hostname_master();
         let port = utility::get_port_master();
 
-        let gflags = read_gflags(hostname.as_str(), port.as_str());
+        let gflags = read_gflags(hostname.as_str(), port.as_str()).await;
         add_to_gflags_vector(gflags, format!("{}:{}", hostname, port).as_str(), detail_snapshot_time, &mut stored_gflags);
         // the master must have gflags
         assert!(!stored_gflags.is_empty());
     }
-    #[test]
-    fn integration_parse_gflags_tserver() {
+    #[tokio::test]
+    async fn integration_parse_gflags_tserver() {
         let mut stored_gflags: Vec<StoredGFlags> = Vec::new();
         let detail_snapshot_time = Local::now();
         let hostname = utility::get_hostname_tserver();
         let port = utility::get_port_tserver();
 
-        let gflags = read_gflags(hostname.as_str(), port.as_str());
+        let gflags = read_gflags(hostname.as_str(), port.as_str()).await;
         add_to_gflags_vector(gflags, format!("{}:{}", hostname, port).as_str(), detail_snapshot_time, &mut stored_gflags);
         // the tserver must have gflags
         assert!(!stored_gflags.is_empty());
@@ -0,0 +1,252 @@
+//! The module for the interval mode: taking a snapshot every N seconds (`--snapshot-interval <seconds>`).
+//!
+//! The interval mode takes snapshots for `--snapshot-iterations` iterations, or until it is interrupted with Ctrl-C (SIGINT).
+//! - The snapshots start at the wall-clock boundaries of the interval in local time: with an interval of 300, a snapshot is taken at 10:00:00, 10:05:00 and so on.
+//!   If a snapshot takes longer than the interval, the boundaries that are passed are skipped.
+//! - The comment (`--snapshot-comment`) is a template, in which `{iteration}`, `{number}` (the snapshot number), `{time}` and `{collection}` (full or light) are replaced.
+//! - With `--snapshot-full-every K`, only every Kth iteration, starting with the first, collects all sources. The other iterations are light:
+//!   these only collect the sources of `--snapshot-light-sources` (default metrics, statements and node_exporter), the other sources are recorded as disabled in the manifest.
+//!
+//! The first Ctrl-C stops the interval mode after the current snapshot is finished.
+//! A second Ctrl-C stops immediately: the current snapshot is added to the snapshot index with `[incomplete]` added to its comment, so it's recognizable.
+use chrono::{DateTime, Duration, Local, TimeZone};
+use std::{process, sync::Mutex, thread};
+use tokio::sync::watch;
+use log::*;
+use crate::manifest::SOURCES;
+use crate::snapshot::Snapshot;
+
+/// The sources of a light iteration if `--snapshot-light-sources` is not set.
+const DEFAULT_LIGHT_SOURCES: &str = "metrics,statements,node_exporter";
+/// The text that is added to the comment of a snapshot that is interrupted.
+const INCOMPLETE: &str = "[incomplete]";
+
+/// The settings of the interval mode.
+#[derive(Debug)]
+pub struct SnapshotInterval {
+    /// The interval in seconds.
+    pub interval: i64,
+    /// The number of iterations, or None to continue until interrupted.
+    pub iterations: Option<u64>,
+    /// Every Kth iteration collects all sources, or None if all iterations collect all sources.
+    pub full_every: Option<u64>,
+    pub light_sources: Vec<String>,
+    pub comment_template: Option<String>,
+}
+
+impl SnapshotInterval {
+    /// This function validates and creates the settings of the interval mode.
+    pub fn new(
+        interval: u64,
+        iterations: Option<u64>,
+        full_every: Option<u64>,
+        light_sources: Option<String>,
+        comment_template: Option<String>,
+    ) -> Result<SnapshotInterval, String>
+    {
+        if interval == 0 {
+            return Err("the snapshot interval must be at least 1 second".to_string());
+        }
+        if full_every == Some(0) {
+            return Err("--snapshot-full-every must be at least 1".to_string());
+        }
+        if light_sources.is_some() && full_every.is_none() {
+            return Err("--snapshot-light-sources requires --snapshot-full-every".to_string());
+        }
+        let light_sources = light_sources.unwrap_or_else(|| DEFAULT_LIGHT_SOURCES.to_string())
+            .split(',')
+            .map(|source| source.trim().to_string())
+            .collect::<Vec<String>>();
+        if let Some(source) = light_sources.iter().find(|source| !SOURCES.contains(&source.as_str())) {
+            return Err(format!("invalid light source: {}, valid sources are: {}", source, SOURCES.join(", ")));
+        }
+        Ok(SnapshotInterval { interval: interval as i64, iterations, full_every, light_sources, comment_template })
+    }
+    /// This function tells if the iteration (starting at 1) collects all sources.
+    pub fn is_full(
+        &self,
+        iteration: u64,
+    ) -> bool
+    {
+        self.full_every.map(|full_every| (iteration - 1) % full_every == 0).unwrap_or(true)
+    }
+    /// The sources that are not collected in the iteration.
+    pub fn disabled_sources(
+        &self,
+        iteration: u64,
+        disable_threads: bool,
+    ) -> Vec<&'static str>
+    {
+        SOURCES.iter()
+            .filter(|source| (disable_threads && **source == "threads") || (!self.is_full(iteration) && !self.light_sources.iter().any(|light_source| light_source == *source)))
+            .copied()
+            .collect()
+    }
+    /// The comment of the snapshot of the iteration, with the placeholders of the template replaced.
+    pub fn comment(
+        &self,
+        iteration: u64,
+        snapshot_number: i32,
+        time: DateTime<Local>,
+    ) -> Option<String>
+    {
+        self.comment_template.as_ref().map(|template| template
+            .replace("{iteration}", &iteration.to_string())
+            .replace("{number}", &snapshot_number.to_string())
+            .replace("{time}", &time.format("%Y-%m-%d %H:%M:%S").to_string())
+            .replace("{collection}", if self.is_full(iteration) { "full" } else { "light" }))
+    }
+    /// The next wall-clock boundary of the interval after the time, in local time.
+    pub fn next_start(
+        &self,
+        time: DateTime<Local>,
+    ) -> DateTime<Local>
+    {
+        let offset = time.offset().local_minus_utc() as i64;
+        let local_seconds = time.timestamp() + offset;
+        let boundary = (local_seconds.div_euclid(self.interval) + 1) * self.interval - offset;
+        Local.timestamp_opt(boundary, 0).single().unwrap_or_else(|| time + Duration::seconds(self.interval))
+    }
+}
+
+/// The snapshot that is currently performed, which is marked incomplete if Ctrl-C is pressed twice.
+static CURRENT_SNAPSHOT: Mutex<Option<CurrentSnapshot>> = Mutex::new(None);
+
+/// A snapshot that is being performed in the interval mode.
+#[derive(Debug, Clone)]
+pub struct CurrentSnapshot {
+    pub snapshot_number: i32,
+    pub start_time: DateTime<Local>,
+    pub comment: Option<String>,
+    pub cluster: Option<String>,
+}
+
+/// This function sets (or with None: clears) the snapshot that is currently performed.
+/// A snapshot that is added to the snapshot index is cleared with [complete_current_snapshot].
+pub fn set_current_snapshot(current_snapshot: Option<CurrentSnapshot>) {
+    *CURRENT_SNAPSHOT.lock().unwrap_or_else(|e| e.into_inner()) = current_snapshot;
+}
+
+/// This function clears the snapshot that is currently performed, and adds it to the snapshot index using `insert` while the current snapshot is locked.
+/// A second Ctrl-C waits for the lock, and then finds no current snapshot, so the snapshot is not added again as incomplete.
+pub fn complete_current_snapshot<T>(insert: impl FnOnce() -> T) -> T {
+    let mut current_snapshot = CURRENT_SNAPSHOT.lock().unwrap_or_else(|e| e.into_inner());
+    *current_snapshot = None;
+    insert()
+}
+
+/// This function starts listening for Ctrl-C (SIGINT), and returns a receiver with the number of times Ctrl-C is pressed.
+/// Once this is called, the first Ctrl-C does not stop yb_stats anymore, but must be handled using the receiver.
+/// The second Ctrl-C stops yb_stats directly, after marking the current snapshot as incomplete.
+///
+/// The listener runs in its own thread with its own runtime, so the second Ctrl-C is handled even when the threads of the main runtime are blocked,
+/// such as while waiting for the lock of the snapshot index, or while writing the snapshot files.
+pub fn listen_for_interrupts() -> watch::Receiver<usize> {
+    let (sender, receiver) = watch::channel(0);
+    let (registered_sender, registered_receiver) = std::sync::mpsc::channel();
+    thread::spawn(move || {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap_or_else(|e| {
+                error!("Fatal: error creating runtime for interrupt handling: {}", e);
+                process::exit(1);
+            });
+        runtime.block_on(async move {
+            let mut interrupts = 0;
+            loop {
+                let ctrl_c = tokio::signal::ctrl_c();
+                tokio::pin!(ctrl_c);
+                // poll once to register the signal handler before the caller continues.
+                if futures::poll!(&mut ctrl_c).is_ready() {
+                    continue;
+                }
+                let _ = registered_sender.send(());
+                if ctrl_c.await.is_err() {
+                    break;
+                }
+                interrupts += 1;
+                if interrupts == 1 {
+                    println!("Interrupted: stopping after the current snapshot, press Ctrl-C again to stop immediately.");
+                    let _ = sender.send(interrupts);
+                } else {
+                    println!("Interrupted: stopping immediately.");
+                    if let Some(current_snapshot) = CURRENT_SNAPSHOT.lock().unwrap_or_else(|e| e.into_inner()).take() {
+                        mark_incomplete(current_snapshot);
+                    }
+                    process::exit(130);
+                }
+            }
+        });
+    });
+    let _ = registered_receiver.recv();
+    receiver
+}
+
+/// This function waits until Ctrl-C is pressed.
+pub async fn interrupted(
+    receiver: &mut watch::Receiver<usize>,
+)
+{
+    while *receiver.borrow() == 0 {
+        if receiver.changed().await.is_err() {
+            std::future::pending::<()>().await;
+        }
+    }
+}
+
+/// This function adds a snapshot that is interrupted to the snapshot index, with [INCOMPLETE] added to the comment.
+pub fn mark_incomplete(current_snapshot: CurrentSnapshot) {
+    let comment = match current_snapshot.comment {
+        Some(comment) if !comment.is_empty() => format!("{} {}", comment, INCOMPLETE),
+        _ => INCOMPLETE.to_string(),
+    };
+    match Snapshot::insert_snapshot(current_snapshot.snapshot_number, current_snapshot.start_time, comment, current_snapshot.cluster.unwrap_or_default()) {
+        Ok(_) => println!("snapshot number {} is incomplete", current_snapshot.snapshot_number),
+        Err(e) => error!("error adding incomplete snapshot {} to the snapshot index: {}", current_snapshot.snapshot_number, e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unit_interval_new() {
+        assert!(SnapshotInterval::new(0, None, None, None, None).is_err());
+        assert!(SnapshotInterval::new(60, None, Some(0), None, None).is_err());
+        assert!(SnapshotInterval::new(60, None, None, Some("metrics".to_string()), None).is_err());
+        assert!(SnapshotInterval::new(60, None, Some(5), Some("metrics,tablets".to_string()), None).is_err());
+        let interval = SnapshotInterval::new(60, Some(10), Some(5), None, None).unwrap();
+        assert_eq!(interval.light_sources, vec!["metrics", "statements", "node_exporter"]);
+    }
+    #[test]
+    fn unit_interval_full_and_light() {
+        let interval = SnapshotInterval::new(60, None, Some(3), Some("metrics, statements".to_string()), None).unwrap();
+        assert!(interval.is_full(1) && !interval.is_full(2) && !interval.is_full(3) && interval.is_full(4));
+        assert_eq!(interval.disabled_sources(1, true), vec!["threads"]);
+        let disabled = interval.disabled_sources(2, false);
+        assert_eq!(disabled.len(), SOURCES.len() - 2);
+        assert!(!disabled.contains(&"metrics") && !disabled.contains(&"statements") && disabled.contains(&"entities"));
+        let interval = SnapshotInterval::new(60, None, None, None, None).unwrap();
+        assert!(interval.disabled_sources(2, false).is_empty());
+    }
+    #[test]
+    fn unit_interval_comment_and_next_start() {
+        let interval = SnapshotInterval::new(300, None, Some(2), None, Some("soak {iteration} ({collection}) nr {number} at {time}".to_string())).unwrap();
+        let time = Local.with_ymd_and_hms(2023, 1, 10, 10, 12, 31).unwrap();
+        assert_eq!(interval.comment(2, 14, time), Some("soak 2 (light) nr 14 at 2023-01-10 10:12:31".to_string()));
+        assert_eq!(interval.next_start(time), Local.with_ymd_and_hms(2023, 1, 10, 10, 15, 0).unwrap());
+        // a time on a boundary starts at the next boundary.
+        assert_eq!(interval.next_start(Local.with_ymd_and_hms(2023, 1, 10, 10, 15, 0).unwrap()), Local.with_ymd_and_hms(2023, 1, 10, 10, 20, 0).unwrap());
+        assert_eq!(SnapshotInterval::new(60, None, None, None, None).unwrap().comment(1, 1, time), None);
+    }
+    #[test]
+    fn unit_complete_current_snapshot() {
+        set_current_snapshot(Some(CurrentSnapshot { snapshot_number: 3, start_time: Local::now(), comment: None, cluster: None }));
+        // the current snapshot is locked while the snapshot is added to the snapshot index, so a second Ctrl-C waits for it.
+        let locked = complete_current_snapshot(|| CURRENT_SNAPSHOT.try_lock().is_err());
+        assert!(locked);
+        assert!(CURRENT_SNAPSHOT.lock().unwrap().is_none());
+    }
+}
@@ -0,0 +1,245 @@
+//! The module for the inventory file: a TOML file with named cluster profiles, which are selected with `--cluster <name>`.
+//!
+//! A profile holds the settings of a cluster that otherwise are set with the switches or via the '.env' file.
+//! When a profile is selected, the '.env' file is not read and not written: a switch that is set overrides the setting of the profile for the current run only.
+//! The name of the profile is recorded in the snapshot.index file for every snapshot that is made with it.
+//!
+//! The inventory file is `yb_stats.toml` in the current working directory, or the file set with `--inventory` or `YBSTATS_INVENTORY`:
+//! ```toml
+//! [clusters.prod]
+//! hosts = ["192.168.66.80", "192.168.66.81", "192.168.66.82"]
+//! ports = [7000, 9000, 12000, 13000, 9300]
+//! parallel = 8
+//! snapshot_dir = "/var/lib/yb_stats/prod"
+//! scheme = "https"
+//! ca_cert = "/etc/yugabyte/ca.crt"
+//! credentials_file = "/etc/yb_stats/prod.credentials"
+//!
+//! # hosts with their own list of ports
+//! [clusters.prod.host_ports]
+//! "192.168.66.83" = [9000, 13000, 9300]
+//!
+//! [clusters.test]
+//! discover = "192.168.66.90:7000"
+//! ```
+//! The settings of a profile are:
+//! - hosts, ports, host_ports: the endpoints. The hosts use the ports of the profile, or the default ports if the profile has no ports. The hosts in host_ports use their own ports.
+//! - discover: a master http address to discover the cluster, see [crate::endpoints::AllStoredEndpoints::discover].
+//! - parallel, metrics_source, compression, storage, snapshot_dir.
+//! - scheme, port_schemes (a table with port = scheme), ca_cert, client_cert, client_key and credentials_file: see [crate::utility::HttpSettings].
+//! - connect_timeout, read_timeout (in milliseconds) and retries.
+//!
+//! Credentials cannot be set in the inventory file: use `credentials_file` or the environment.
+use std::{env, fs, error::Error, collections::BTreeMap};
+use serde_derive::Deserialize;
+use log::*;
+
+/// The default inventory file, in the current working directory.
+pub const DEFAULT_INVENTORY: &str = "yb_stats.toml";
+
+/// The struct that is used to read the inventory file using serde.
+#[derive(Deserialize, Debug, Default)]
+#[serde(deny_unknown_fields)]
+pub struct Inventory {
+    #[serde(default)]
+    pub clusters: BTreeMap<String, ClusterProfile>,
+}
+
+/// The struct for a single named cluster profile in the inventory file.
+#[derive(Deserialize, Debug, Default, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct ClusterProfile {
+    #[serde(default)]
+    pub hosts: Vec<String>,
+    #[serde(default)]
+    pub ports: Vec<u16>,
+    /// Hosts with their own ports.
+    #[serde(default)]
+    pub host_ports: BTreeMap<String, Vec<u16>>,
+    pub discover: Option<String>,
+    pub parallel: Option<usize>,
+    pub metrics_source: Option<String>,
+    pub compression: Option<String>,
+    pub storage: Option<String>,
+    pub snapshot_dir: Option<String>,
+    pub scheme: Option<String>,
+    /// The scheme per port: port = scheme.
+    #[serde(default)]
+    pub port_schemes: BTreeMap<String, String>,
+    pub ca_cert: Option<String>,
+    pub client_cert: Option<String>,
+    pub client_key: Option<String>,
+    pub credentials_file: Option<String>,
+    pub connect_timeout: Option<u64>,
+    pub read_timeout: Option<u64>,
+    pub retries: Option<u32>,
+}
+
+impl Inventory {
+    /// This function reads the inventory file.
+    pub fn read(
+        inventory_file: &str,
+    ) -> Result<Inventory, Box<dyn Error>>
+    {
+        let inventory = fs::read_to_string(inventory_file)
+            .map_err(|e| format!("error reading inventory file {}: {}", inventory_file, e))?;
+        Inventory::parse(&inventory)
+            .map_err(|e| format!("error parsing inventory file {}: {}", inventory_file, e).into())
+    }
+    fn parse(
+        inventory: &str,
+    ) -> Result<Inventory, Box<dyn Error>>
+    {
+        Ok(toml::from_str(inventory)?)
+    }
+    /// This function takes the profile of the cluster out of the inventory.
+    pub fn cluster(
+        mut self,
+        cluster: &str,
+    ) -> Result<ClusterProfile, Box<dyn Error>>
+    {
+        let names = self.clusters.keys().cloned().collect::<Vec<_>>().join(", ");
+        self.clusters.remove(cluster)
+            .ok_or_else(|| format!("cluster {} is not found in the inventory, the clusters are: {}", cluster, names).into())
+    }
+}
+
+impl ClusterProfile {
+    /// This function reads the profile of the cluster from the inventory file.
+    /// The inventory file is set with `--inventory`, or else `YBSTATS_INVENTORY`, or else it's [DEFAULT_INVENTORY].
+    pub fn read(
+        inventory_file: Option<String>,
+        cluster: &str,
+    ) -> Result<ClusterProfile, Box<dyn Error>>
+    {
+        let inventory_file = inventory_file
+            .or_else(|| env::var("YBSTATS_INVENTORY").ok())
+            .unwrap_or_else(|| DEFAULT_INVENTORY.to_string());
+        info!("reading cluster {} from inventory file {}", cluster, inventory_file);
+        Inventory::read(&inventory_file)?.cluster(cluster)
+    }
+    /// The settings of the profile by the name of the variable that is used in the '.env' file.
+    /// The hosts are the hosts and the hosts in host_ports.
+    pub fn settings(&self) -> Vec<(&'static str, String)> {
+        let join = |ports: &[u16]| ports.iter().map(|port| port.to_string()).collect::<Vec<_>>().join(",");
+        let mut hosts = self.hosts.clone();
+        hosts.extend(self.host_ports.keys().filter(|host| !self.hosts.contains(host)).cloned());
+
+        let mut settings = Vec::new();
+        if !hosts.is_empty() { settings.push(("YBSTATS_HOSTS", hosts.join(","))) };
+        if !self.ports.is_empty() { settings.push(("YBSTATS_PORTS", join(&self.ports))) };
+        if !self.port_schemes.is_empty() {
+            settings.push(("YBSTATS_PORT_SCHEMES", self.port_schemes.iter().map(|(port, scheme)| format!("{}={}", port, scheme)).collect::<Vec<_>>().join(",")));
+        };
+        for (name, value) in [
+            ("YBSTATS_DISCOVER", self.discover.clone()),
+            ("YBSTATS_PARALLEL", self.parallel.map(|parallel| parallel.to_string())),
+            ("YBSTATS_METRICS_SOURCE", self.metrics_source.clone()),
+            ("YBSTATS_COMPRESSION", self.compression.clone()),
+            ("YBSTATS_STORAGE", self.storage.clone()),
+            ("YBSTATS_SNAPSHOT_DIR", self.snapshot_dir.clone()),
+            ("YBSTATS_SCHEME", self.scheme.clone()),
+            ("YBSTATS_CA_CERT", self.ca_cert.clone()),
+            ("YBSTATS_CLIENT_CERT", self.client_cert.clone()),
+            ("YBSTATS_CLIENT_KEY", self.client_key.clone()),
+            ("YBSTATS_CREDENTIALS_FILE", self.credentials_file.clone()),
+            ("YBSTATS_CONNECT_TIMEOUT", self.connect_timeout.map(|ms| ms.to_string())),
+            ("YBSTATS_READ_TIMEOUT", self.read_timeout.map(|ms| ms.to_string())),
+            ("YBSTATS_RETRIES", self.retries.map(|retries| retries.to_string())),
+        ] {
+            if let Some(value) = value {
+                settings.push((name, value));
+            }
+        }
+        settings
+    }
+    /// This function sets the settings of the profile in the environment, where these are picked up the same way as the settings of the '.env' file.
+    pub fn set_environment(&self) {
+        for (name, value) in self.settings() {
+            info!("cluster profile: {}={}", name, value);
+            env::set_var(name, value);
+        }
+    }
+    /// The endpoints (host, port) of the profile: the hosts with the ports, and the hosts in host_ports with their own ports.
+    /// A host that is in hosts and in host_ports uses the ports of host_ports.
+    pub fn endpoints(
+        &self,
+        ports: &[&str],
+    ) -> Vec<(String, String)>
+    {
+        let mut endpoints = Vec::new();
+        for host in self.hosts.iter().filter(|host| !self.host_ports.contains_key(*host)) {
+            for port in ports {
+                endpoints.push((host.to_string(), port.to_string()));
+            }
+        }
+        for (host, host_ports) in &self.host_ports {
+            for port in host_ports {
+                endpoints.push((host.to_string(), port.to_string()));
+            }
+        }
+        endpoints
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const INVENTORY: &str = r#"
+[clusters.prod]
+hosts = ["192.168.66.80", "192.168.66.81"]
+ports = [7000, 9000]
+parallel = 8
+snapshot_dir = "/var/lib/yb_stats/prod"
+scheme = "https"
+port_schemes = { 9300 = "http" }
+retries = 3
+
+[clusters.prod.host_ports]
+"192.168.66.81" = [9000, 9300]
+"192.168.66.82" = [13000]
+
+[clusters.test]
+discover = "192.168.66.90:7000"
+"#;
+
+    #[test]
+    fn unit_inventory_parse() {
+        let inventory = Inventory::parse(INVENTORY).unwrap();
+        assert_eq!(inventory.clusters.len(), 2);
+        let test = inventory.cluster("test").unwrap();
+        assert_eq!(test.discover, Some("192.168.66.90:7000".to_string()));
+        assert!(test.hosts.is_empty());
+    }
+    #[test]
+    fn unit_inventory_unknown_cluster_or_field() {
+        let inventory = Inventory::parse(INVENTORY).unwrap();
+        assert!(inventory.cluster("dev").unwrap_err().to_string().contains("prod, test"));
+        assert!(Inventory::parse("[clusters.prod]\nhost = [\"192.168.66.80\"]").is_err());
+    }
+    #[test]
+    fn unit_profile_settings() {
+        let prod = Inventory::parse(INVENTORY).unwrap().cluster("prod").unwrap();
+        let settings = prod.settings();
+        assert!(settings.contains(&("YBSTATS_HOSTS", "192.168.66.80,192.168.66.81,192.168.66.82".to_string())));
+        assert!(settings.contains(&("YBSTATS_PORTS", "7000,9000".to_string())));
+        assert!(settings.contains(&("YBSTATS_PARALLEL", "8".to_string())));
+        assert!(settings.contains(&("YBSTATS_PORT_SCHEMES", "9300=http".to_string())));
+        assert!(settings.contains(&("YBSTATS_RETRIES", "3".to_string())));
+        assert!(settings.contains(&("YBSTATS_SNAPSHOT_DIR", "/var/lib/yb_stats/prod".to_string())));
+        assert!(!settings.iter().any(|(name, _)| *name == "YBSTATS_DISCOVER"));
+    }
+    #[test]
+    fn unit_profile_endpoints() {
+        let prod = Inventory::parse(INVENTORY).unwrap().cluster("prod").unwrap();
+        let endpoints = prod.endpoints(&["7000", "9000"]);
+        assert_eq!(endpoints, vec![
+            ("192.168.66.80".to_string(), "7000".to_string()),
+            ("192.168.66.80".to_string(), "9000".to_string()),
+            ("192.168.66.81".to_string(), "9000".to_string()),
+            ("192.168.66.81".to_string(), "9300".to_string()),
+            ("192.168.66.82".to_string(), "13000".to_string()),
+        ]);
+    }
+}
@@ -6,10 +6,19 @@
 //!
 //! This function has no public display function, it is only used to store the and retrieve the master leader.
 use chrono::{DateTime, Local};
-use std::{env, fs, error::Error, process, time::Instant, sync::mpsc::channel};
+use std::{error::Error, process, time::Instant};
 use serde_derive::{Serialize,Deserialize};
 use log::*;
-use crate::utility::{scan_host_port, http_get};
+use crate::snapshot::snapshot_directory;
+use futures::future::join_all;
+use crate::utility::http_get;
+use crate::endpoints::has_role;
+use crate::compression::create_snapshot_file;
+use crate::schema::open_snapshot_csv;
+
+/// The endpoint roles that provide /api/v1/is-leader.
+pub const ROLES: &[&str] = &["master"];
+
 /// The struct that is used to parse the JSON returned from /api/v1/is-leader using serde.
 ///
 /// Please mind that only the leader shows:
@@ -47,13 +56,12 @@ impl AllStoredIsLeader {
         hosts: &Vec<&str>,
         ports: &Vec<&str>,
         snapshot_number: i32,
-        parallel: usize
     )
     {
         info!("begin snapshot");
         let timer = Instant::now();
 
-        let allstoredisleader = AllStoredIsLeader::read_isleader(hosts, ports, parallel);
+        let allstoredisleader = AllStoredIsLeader::read_isleader(hosts, ports);
         allstoredisleader.await.save_snapshot(snapshot_number)
             .unwrap_or_else(|e| {
                 error!("error saving snapshot: {}", e);
@@ -77,45 +85,44 @@ impl AllStoredIsLeader {
     pub async fn return_leader_http (
         hosts: &Vec<&str>,
         ports: &Vec<&str>,
-        parallel: usize,
     ) -> String
     {
-        let allstoredisleader = AllStoredIsLeader::read_isleader(hosts, ports, parallel);
+        let allstoredisleader = AllStoredIsLeader::read_isleader(hosts, ports);
         allstoredisleader.await.stored_isleader.iter().filter(|r| r.status == "OK").map(|r| r.hostname_port.to_string()).next().unwrap_or_default()
         //Ok(result)
     }
-    /// This function takes a vector of hosts and ports, and the allowed parallellism to (try to) read /api/v1/is-leader.
-    /// It creates a threadpool based on parallel, and spawns a task for reading and parsing for all host-port combinations.
+    /// This function takes a vector of hosts and ports to (try to) read /api/v1/is-leader.
+    /// It creates a future for reading and parsing for all host-port combinations, which run concurrently within the limit set by [crate::utility::set_parallel].
     /// When all combinations are read, the results are gathered in Vec<AllStoredIsLeader> and returned.
     async fn read_isleader (
         hosts: &Vec<&str>,
         ports: &Vec<&str>,
-        parallel: usize
     ) -> AllStoredIsLeader
     {
         info!("begin parallel http read");
         let timer = Instant::now();
 
-        let pool = rayon::ThreadPoolBuilder::new().num_threads(parallel).build().unwrap();
-        let (tx, rx) = channel();
-        pool.scope(move |s| {
-            for host in hosts {
-                for port in ports {
-                    let tx = tx.clone();
-                    s.spawn(move |_| {
-                        let detail_snapshot_time = Local::now();
-                        let isleader = AllStoredIsLeader::read_http(host, port);
-                        debug!("{:?}",&isleader);
-                        tx.send((format!("{}:{}", host, port), detail_snapshot_time, isleader)).expect("error sending data via tx (isleader)");
-                    });
+        let mut handles = Vec::new();
+        for host in hosts {
+            for port in ports {
+                if !has_role(host, port, ROLES) {
+                    continue;
                 }
+                let handle = async move {
+                    let detail_snapshot_time = Local::now();
+                    let isleader = AllStoredIsLeader::read_http(host, port).await;
+                    debug!("{:?}",&isleader);
+                    (format!("{}:{}", host, port), detail_snapshot_time, isleader)
+                };
+                handles.push(handle);
             }
-        });
+        }
+        let results = join_all(handles).await;
 
         info!("end parallel http read {:?}", timer.elapsed());
 
         let mut allstoredisleader = AllStoredIsLeader { stored_isleader: Vec::new() };
-        for (hostname_port, detail_snapshot_time, isleader) in rx {
+        for (hostname_port, detail_snapshot_time, isleader) in results {
             debug!("hostname_port: {}, timestamp: {}, isleader: {}", &hostname_port, &detail_snapshot_time, &isleader.status);
             allstoredisleader.stored_isleader.push(StoredIsLeader { hostname_port, timestamp: detail_snapshot_time, status: isleader.status.to_string() } );
         }
@@ -123,16 +130,12 @@ impl AllStoredIsLeader {
     }
     /// Using provided host and port, read http://host:port/api/v1/is-leader and parse the result
     /// via [AllStoredIsLeader::parse_isleader], and return struct [IsLeader].
-    fn read_http(
+    async fn read_http(
         host: &str,
         port: &str,
     ) -> IsLeader
     {
-        let data_from_http = if scan_host_port( host, port) {
-            http_get(host, port, "api/v1/is-leader")
-        } else {
-            String::new()
-        };
+        let data_from_http = http_get(host, port, "api/v1/is-leader").await;
         AllStoredIsLeader::parse_isleader(data_from_http)
         /*
         if ! scan_port_addr( format!("{}:{}", host, port) ) {
@@ -167,19 +170,15 @@ impl AllStoredIsLeader {
     /// This function takes the rows in the vector StoredIsLeader, and saves it as CSV in the snapshot directory indicated by the snapshot number.
     fn save_snapshot ( self, snapshot_number: i32 ) -> Result<(), Box<dyn Error>>
     {
-        let current_directory = env::current_dir()?;
-        let current_snapshot_directory = current_directory.join("yb_stats.snapshots").join(&snapshot_number.to_string());
+        let current_snapshot_directory = snapshot_directory().join(snapshot_number.to_string());
 
         let isleader_file = &current_snapshot_directory.join("isleader");
-        let file = fs::OpenOptions::new()
-            .create(true)
-            .write(true)
-            .open(isleader_file)?;
+        let file = create_snapshot_file(isleader_file)?;
         let mut writer = csv::Writer::from_writer(file);
         for row in self.stored_isleader {
             writer.serialize(row)?;
         }
-        writer.flush()?;
+        writer.into_inner()?.finish()?;
 
         Ok(())
     }
@@ -188,11 +187,10 @@ impl AllStoredIsLeader {
     {
         let mut allstoredisleader = AllStoredIsLeader { stored_isleader: Vec::new() };
 
-        let current_directory = env::current_dir()?;
-        let current_snapshot_directory = current_directory.join("yb_stats.snapshots").join(snapshot_number);
+        let current_snapshot_directory = snapshot_directory().join(snapshot_number);
 
         let isleader_file = &current_snapshot_directory.join("isleader");
-        let file = fs::File::open(isleader_file)?;
+        let file = open_snapshot_csv(isleader_file)?;
 
         let mut reader = csv::Reader::from_reader(file);
         for row in reader.deserialize() {
@@ -247,7 +245,7 @@ File not found
         let hostname = utility::get_hostname_master();
         let port = utility::get_port_master();
 
-        let leader = AllStoredIsLeader::return_leader_http(&vec![&hostname], &vec![&port], 1_usize).await;
+        let leader = AllStoredIsLeader::return_leader_http(&vec![&hostname], &vec![&port]).await;
         //let hostname_port = AllStoredIsLeader::return_leader_snapshot(&"22".to_string());
         println!("{}", leader);
         //let hostname_port = String::from("haha");
@@ -265,11 +263,10 @@ File not found
         let snapshot_number = "22".to_string();
         let mut allstoredisleader = AllStoredIsLeader { stored_isleader: Vec::new() };
 
-        let current_directory = env::current_dir().unwrap();
-        let current_snapshot_directory = current_directory.join("yb_stats.snapshots").join(&snapshot_number);
+        let current_snapshot_directory = snapshot_directory().join(snapshot_number);
 
         let isleader_file = &current_snapshot_directory.join("isleader");
-        let file = fs::File::open(&isleader_file).unwrap();
+        let file = open_snapshot_file(&isleader_file).unwrap();
 
         let mut reader = csv::Reader::from_reader(file);
         for row in reader.deserialize() {
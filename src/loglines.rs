@@ -1,12 +1,17 @@
 use std::process;
 use chrono::{DateTime, Local, TimeZone};
-use std::path::PathBuf;
 use regex::{Regex,Captures};
-use std::fs;
 use serde_derive::{Serialize,Deserialize};
-use std::sync::mpsc::channel;
 use log::*;
-use crate::utility::{scan_host_port, http_get};
+use futures::future::join_all;
+use crate::utility::http_get;
+use crate::endpoints::has_role;
+use crate::compression::create_snapshot_file;
+use crate::schema::open_snapshot_csv;
+use crate::snapshot::snapshot_directory;
+
+/// The endpoint roles that provide /logs.
+pub const ROLES: &[&str] = &["master", "tserver"];
 
 #[derive(Debug)]
 pub struct LogLine {
@@ -41,16 +46,12 @@ impl StoredLogLines {
 }
 
 #[allow(dead_code)]
-pub fn read_loglines(
+pub async fn read_loglines(
     host: &str,
     port: &str,
 ) -> Vec<LogLine>
 {
-    let data_from_http = if scan_host_port( host, port) {
-        http_get(host, port, "logs?raw")
-    } else {
-        String::new()
-    };
+    let data_from_http = http_get(host, port, "logs?raw").await;
     parse_loglines(data_from_http)
     /*
     if ! scan_port_addr( format!("{}:{}", host, port)) {
@@ -78,11 +79,11 @@ pub fn read_loglines(
 
 #[allow(dead_code)]
 #[allow(clippy::ptr_arg)]
-fn read_loglines_snapshot(snapshot_number: &String, yb_stats_directory: &&PathBuf ) -> Vec<StoredLogLines> {
+fn read_loglines_snapshot(snapshot_number: &String) -> Vec<StoredLogLines> {
 
     let mut stored_loglines: Vec<StoredLogLines> = Vec::new();
-    let loglines_file = &yb_stats_directory.join(snapshot_number).join("loglines");
-    let file = fs::File::open(loglines_file)
+    let loglines_file = &snapshot_directory().join(snapshot_number).join("loglines");
+    let file = open_snapshot_csv(loglines_file)
         .unwrap_or_else(|e| {
             error!("Fatal: error reading file: {}: {}", &loglines_file.clone().into_os_string().into_string().unwrap(), e);
             process::exit(1);
@@ -101,33 +102,30 @@ pub async fn perform_loglines_snapshot(
     hosts: &Vec<&str>,
     ports: &Vec<&str>,
     snapshot_number: i32,
-    yb_stats_directory: &PathBuf,
-    parallel: usize
 ) {
     info!("perform_loglines_snapshot");
-    let pool = rayon::ThreadPoolBuilder::new().num_threads(parallel).build().unwrap();
-    let (tx, rx) = channel();
-    pool.scope(move |s| {
-        for host in hosts {
-            for port in ports {
-                let tx = tx.clone();
-                s.spawn(move |_| {
-                    let loglines = read_loglines(host, port);
-                    tx.send((format!("{}:{}", host, port), loglines)).expect("error sending data via tx (logs)");
-                });
-            }}
-    });
+    let mut handles = Vec::new();
+    for host in hosts {
+        for port in ports {
+            if !has_role(host, port, ROLES) {
+                continue;
+            }
+            let handle = async move {
+                let loglines = read_loglines(host, port).await;
+                (format!("{}:{}", host, port), loglines)
+            };
+            handles.push(handle);
+        }
+    }
+    let results = join_all(handles).await;
     let mut stored_loglines: Vec<StoredLogLines> = Vec::new();
-    for (hostname_port, loglines) in rx {
+    for (hostname_port, loglines) in results {
         add_to_loglines_vector(loglines, &hostname_port, &mut stored_loglines);
     }
 
-    let current_snapshot_directory = &yb_stats_directory.join(&snapshot_number.to_string());
+    let current_snapshot_directory = &snapshot_directory().join(&snapshot_number.to_string());
     let loglines_file = &current_snapshot_directory.join("loglines");
-    let file = fs::OpenOptions::new()
-        .create(true)
-        .write(true)
-        .open(loglines_file)
+    let file = create_snapshot_file(loglines_file)
         .unwrap_or_else(|e| {
             error!("Fatal: error writing loglines data in snapshot directory {}: {}", &loglines_file.clone().into_os_string().into_string().unwrap(), e);
             process::exit(1);
@@ -136,18 +134,17 @@ pub async fn perform_loglines_snapshot(
     for row in stored_loglines {
         writer.serialize(row).unwrap();
     }
-    writer.flush().unwrap();
+    writer.into_inner().unwrap().finish().unwrap();
 }
 
 #[allow(dead_code)]
 pub fn print_loglines(
     snapshot_number: &str,
-    yb_stats_directory: &PathBuf,
     hostname_filter: &Regex,
     log_severity: &str
 ) {
     info!("print_log");
-    let stored_loglines: Vec<StoredLogLines> = read_loglines_snapshot(&snapshot_number.to_string(), &yb_stats_directory);
+    let stored_loglines: Vec<StoredLogLines> = read_loglines_snapshot(&snapshot_number.to_string());
     let mut previous_hostname_port = String::from("");
     for row in stored_loglines {
         if hostname_filter.is_match(&row.hostname_port)
@@ -434,25 +431,25 @@ properties: contain_counters: false is_transactional: true consistency_level: ST
 
     use crate::utility;
 
-    #[test]
-    fn integration_parse_loglines_master() {
+    #[tokio::test]
+    async fn integration_parse_loglines_master() {
         let mut stored_loglines: Vec<StoredLogLines> = Vec::new();
         let hostname = utility::get_hostname_master();
         let port = utility::get_port_master();
 
-        let loglines = read_loglines(hostname.as_str(), port.as_str());
+        let loglines = read_loglines(hostname.as_str(), port.as_str()).await;
         add_to_loglines_vector(loglines, format!("{}:{}", hostname, port).as_str(), &mut stored_loglines);
         // it's likely there will be logging
         assert!(!stored_loglines.is_empty());
     }
 
-    #[test]
-    fn integration_parse_loglines_tserver() {
+    #[tokio::test]
+    async fn integration_parse_loglines_tserver() {
         let mut stored_loglines: Vec<StoredLogLines> = Vec::new();
         let hostname = utility::get_hostname_tserver();
         let port = utility::get_port_tserver();
 
-        let loglines = read_loglines(hostname.as_str(), port.as_str());
+        let loglines = read_loglines(hostname.as_str(), port.as_str()).await;
         add_to_loglines_vector(loglines, format!("{}:{}", hostname, port).as_str(), &mut stored_loglines);
         // it's likely there will be logging
         assert!(!stored_loglines.is_empty());
@@ -10,7 +10,7 @@ extern crate csv;
 
 use clap::Parser;
 //use std::{process, env, fs, collections::HashMap, io::{stdin, Write}, sync::Arc, time::Instant};
-use std::{process, env, collections::HashMap, io::stdin, sync::Arc, time::Instant};
+use std::{process, env, collections::HashMap, io::stdin, sync::Arc, time::{Duration, Instant}};
 use regex::Regex;
 use chrono::Local;
 use dotenv::dotenv;
@@ -26,6 +26,7 @@ use crate::statements::SnapshotDiffBTreeMapStatements;
 use crate::tservers::{AllStoredTabletServers, SnapshotDiffBTreeMapsTabletServers};
 use crate::vars::{AllStoredVars, SnapshotDiffBTreeMapsVars};
 use crate::versions::{AllStoredVersions, SnapshotDiffBTreeMapsVersions};
+use crate::manifest::ManifestSource;
 
 mod snapshot;
 mod value_statistic_details;
@@ -49,10 +50,29 @@ mod utility;
 mod isleader;
 mod tservers;
 mod vars;
+mod collection_errors;
+mod endpoints;
+mod inventory;
+mod manifest;
+mod compression;
+mod archive;
+mod schema;
+mod storage;
+mod verify;
+mod anonymize;
+mod interval;
+mod top;
+mod rates;
+mod exporter;
+mod watch;
+mod output;
+mod report;
+mod series;
 
 const DEFAULT_HOSTS: &str = "192.168.66.80,192.168.66.81,192.168.66.82";
 const DEFAULT_PORTS: &str = "7000,9000,12000,13000,9300";
-const DEFAULT_PARALLEL: &str = "1";
+const DEFAULT_PARALLEL: &str = "16";
+const DEFAULT_TOP_ROWS: usize = 20;
 const WRITE_DOTENV: bool = true;
 
 const ACCEPT_INVALID_CERTS: bool = true;
@@ -67,9 +87,30 @@ struct Opts {
     /// Snapshot input port numbers (comma separated)
     #[arg(short, long, value_name = "port,port")]
     ports: Option<String>,
-    /// Snapshot capture parallelism (default 1)
+    /// Use the settings of the named cluster profile in the inventory file, instead of the .env file
+    #[arg(long, value_name = "name")]
+    cluster: Option<String>,
+    /// Inventory file with the cluster profiles (default yb_stats.toml)
+    #[arg(long, value_name = "file")]
+    inventory: Option<String>,
+    /// Snapshot input: discover the hosts and ports of the cluster via a master http address, instead of using --hosts
+    #[arg(long, value_name = "hostname:port")]
+    discover: Option<String>,
+    /// Snapshot capture parallelism: the maximum number of concurrent http requests (default 16)
     #[arg(long, value_name = "nr")]
     parallel: Option<String>,
+    /// Snapshot and ad-hoc mode metrics source: json (/metrics) or prometheus (/prometheus-metrics) (default json)
+    #[arg(long, value_name = "json|prometheus")]
+    metrics_source: Option<String>,
+    /// Snapshot compression of the files in the snapshot directory: none, gzip or zstd (default none)
+    #[arg(long, value_name = "none|gzip|zstd")]
+    compression: Option<String>,
+    /// Snapshot storage backend: csv files or a sqlite database (default csv)
+    #[arg(long, value_name = "csv|sqlite")]
+    storage: Option<String>,
+    /// Output of the snapshot-diff and ad-hoc reports: text, json (a JSON object per line) or csv (default text)
+    #[arg(long, value_name = "text|json|csv")]
+    output: Option<String>,
     /// Output filter for statistic names as regex
     #[arg(short, long, value_name = "regex")]
     stat_name_match: Option<String>,
@@ -91,12 +132,42 @@ struct Opts {
     /// Perform a snapshot (creates stored CSV files)
     #[arg(long)]
     snapshot: bool,
-    /// Snapshot add comment in snapshot overview
+    /// Snapshot add comment in snapshot overview (with --snapshot-interval: a template with {iteration}, {number}, {time} and {collection})
     #[arg(long, value_name = "\"comment\"")]
     snapshot_comment: Option<String>,
+    /// Perform a snapshot every N seconds, aligned to the wall clock, until interrupted with Ctrl-C
+    #[arg(long, value_name = "seconds")]
+    snapshot_interval: Option<u64>,
+    /// The number of snapshots to perform with --snapshot-interval
+    #[arg(long, value_name = "M")]
+    snapshot_iterations: Option<u64>,
+    /// Collect all sources every Kth snapshot with --snapshot-interval, the other snapshots only collect the light sources
+    #[arg(long, value_name = "K")]
+    snapshot_full_every: Option<u64>,
+    /// The sources of the light snapshots (comma separated, default metrics,statements,node_exporter)
+    #[arg(long, value_name = "sources")]
+    snapshot_light_sources: Option<String>,
+    /// Poll the metrics and perform a snapshot when a condition is met, such as "handler_latency_yb_tserver_TabletServerService_Write avg > 50ms" or "node_exporter node_load1 > 16" (can be repeated)
+    #[arg(long, value_name = "\"condition\"")]
+    watch: Vec<String>,
+    /// The poll interval of --watch in seconds (default 10)
+    #[arg(long, value_name = "seconds")]
+    watch_interval: Option<u64>,
+    /// With --watch: save the poll before the trigger as a snapshot too
+    #[arg(long)]
+    watch_pre_trigger: bool,
+    /// With --watch: the number of seconds after a trigger before a condition can trigger again (default 300)
+    #[arg(long, value_name = "seconds")]
+    watch_cooldown: Option<u64>,
     /// Create a performance diff report using a begin and an end snapshot number.
     #[arg(long)]
     snapshot_diff: bool,
+    /// Create a time-series report of the metric rates of every consecutive pair of snapshots from a begin to an end snapshot number.
+    #[arg(long)]
+    snapshot_series: bool,
+    /// Write the snapshot diff of a begin and end snapshot number as a self-contained HTML file
+    #[arg(long, value_name = "file")]
+    html_report: Option<String>,
     /// Create an entity diff report using a begin and end snapshot number.
     #[arg(long)]
     entity_diff: bool,
@@ -106,9 +177,54 @@ struct Opts {
     /// Create an adhoc diff report only for metrics
     #[arg(long)]
     adhoc_metrics_diff: bool,
-    /// Lists the snapshots in the yb_stats.snapshots in the current directory.
+    /// Show a continuously refreshing top-style view of the rates of metrics, statements and node_exporter every N seconds
+    #[arg(long, value_name = "seconds")]
+    top: Option<u64>,
+    /// The number of rows per source in the top-style view (default 20)
+    #[arg(long, value_name = "N")]
+    top_rows: Option<usize>,
+    /// Run as exporter: scrape the metrics every interval and serve the rates in prometheus format on http://<address:port>/metrics
+    #[arg(long, value_name = "address:port")]
+    exporter: Option<String>,
+    /// The scrape interval of the exporter in seconds (default 15)
+    #[arg(long, value_name = "seconds")]
+    exporter_interval: Option<u64>,
+    /// Lists the snapshots in the snapshot directory (with --details-enable: including the manifest).
     #[arg(short = 'l', long)]
     snapshot_list: bool,
+    /// The directory for the snapshots (default yb_stats.snapshots in the current directory)
+    #[arg(long, value_name = "directory")]
+    snapshot_dir: Option<String>,
+    /// Delete a snapshot or a range of snapshots
+    #[arg(long, value_name = "nr|begin-end")]
+    snapshot_delete: Option<String>,
+    /// Delete all snapshots except the last N snapshots
+    #[arg(long, value_name = "N")]
+    snapshot_keep: Option<usize>,
+    /// Delete the snapshots older than the age (s, m, h, d or w, for example 7d)
+    #[arg(long, value_name = "age")]
+    snapshot_older_than: Option<String>,
+    /// Never delete the snapshots with a comment that matches the regex
+    #[arg(long, value_name = "regex")]
+    snapshot_protect: Option<String>,
+    /// Verify the files of the snapshots from --begin to --end (default all) (with --details-enable: including the rows per host)
+    #[arg(long)]
+    snapshot_verify: bool,
+    /// Write a redacted copy of the snapshots from --begin to --end (default all) in a new snapshot directory
+    #[arg(long, value_name = "directory")]
+    snapshot_anonymize: Option<String>,
+    /// Convert the csv snapshots from --begin to --end (default all) into the sqlite database
+    #[arg(long)]
+    sqlite_convert: bool,
+    /// Only list the snapshots that would be deleted
+    #[arg(long)]
+    dry_run: bool,
+    /// Export the snapshots from --begin to --end (default all) to an archive file
+    #[arg(long, value_name = "file")]
+    snapshot_export: Option<String>,
+    /// Import the snapshots of an archive file as new snapshots
+    #[arg(long, value_name = "file")]
+    snapshot_import: Option<String>,
     /// Output setting to specify the begin snapshot number for diff report.
     #[arg(short = 'b', long, value_name = "snapshot nr")]
     begin: Option<i32>,
@@ -154,6 +270,33 @@ struct Opts {
     /// Output setting for the length of the SQL text to display
     #[arg(long, value_name = "nr", default_value = "80")]
     sql_length: usize,
+    /// Snapshot http scheme: auto (try http, then https), http or https (default auto)
+    #[arg(long, value_name = "auto|http|https")]
+    scheme: Option<String>,
+    /// Snapshot http scheme per port, overriding --scheme (comma separated)
+    #[arg(long, value_name = "port=scheme,port=scheme")]
+    port_schemes: Option<String>,
+    /// Snapshot CA certificate file (PEM) to validate the server certificates
+    #[arg(long, value_name = "file")]
+    ca_cert: Option<String>,
+    /// Snapshot client certificate file (PEM) for mutual TLS (requires --client-key)
+    #[arg(long, value_name = "file")]
+    client_cert: Option<String>,
+    /// Snapshot client private key file (PEM, PKCS#8) for mutual TLS (requires --client-cert)
+    #[arg(long, value_name = "file")]
+    client_key: Option<String>,
+    /// Snapshot credentials file for protected endpoints, with lines: <port|role|*> <basic|bearer> <secret>
+    #[arg(long, value_name = "file")]
+    credentials_file: Option<String>,
+    /// Snapshot connect timeout in milliseconds (default 1000)
+    #[arg(long, value_name = "ms")]
+    connect_timeout: Option<String>,
+    /// Snapshot read timeout in milliseconds, the maximum time for a complete response (default 10000)
+    #[arg(long, value_name = "ms")]
+    read_timeout: Option<String>,
+    /// Snapshot number of retries, with backoff, after a connect error, timeout or server error (default 2)
+    #[arg(long, value_name = "retries")]
+    retries: Option<String>,
 }
 
 /// The entrypoint of the executable.
@@ -161,9 +304,53 @@ struct Opts {
 async fn main() {
     env_logger::init();
     let mut changed_options = HashMap::new();
-    dotenv().ok();
     let options = Opts::parse();
 
+    /*
+     * Cluster profile
+     * - if cluster is set, the profile is read from the inventory file, and its settings are set in the environment.
+     *   These are used the same way as the settings of the .env file, which is not read and not written.
+     * - if cluster is not set, the .env file is read.
+     */
+    let profile = match &options.cluster {
+        Some(cluster) => {
+            info!("cluster argument set: using: {}", cluster);
+            let profile = inventory::ClusterProfile::read(options.inventory.clone(), cluster)
+                .unwrap_or_else(|e| {
+                    error!("Fatal: {}", e);
+                    process::exit(1);
+                });
+            profile.set_environment();
+            Some(profile)
+        }
+        None => {
+            dotenv().ok();
+            None
+        }
+    };
+
+    /*
+     * Snapshot directory
+     * - if snapshot_dir is set, it's used and set in the changed_options HashMap for later write.
+     * - if snapshot_dir is not set, the .env (or cluster profile) setting YBSTATS_SNAPSHOT_DIR is used, if set.
+     * - if neither is set, the default (yb_stats.snapshots in the current working directory) is used.
+     * The snapshot directory is used by all the functions that read or write snapshots.
+     */
+    let snapshot_dir = if let Some(snapshot_dir) = options.snapshot_dir {
+        info!("snapshot_dir argument set: using: {}", &snapshot_dir);
+        changed_options.insert("YBSTATS_SNAPSHOT_DIR", snapshot_dir.to_string());
+        Some(snapshot_dir)
+    } else {
+        env::var("YBSTATS_SNAPSHOT_DIR").ok().map(|set_var| {
+            info!("snapshot_dir not set: set via .env: YBSTATS_SNAPSHOT_DIR: {}", set_var);
+            changed_options.insert("YBSTATS_SNAPSHOT_DIR", set_var.to_owned());
+            set_var
+        })
+    };
+    if let Some(snapshot_dir) = snapshot_dir {
+        snapshot::set_snapshot_directory(snapshot_dir.into());
+    }
+
     /*
      * Hosts
      * - if hosts is set, it's detected by is_some() and we take the set value, and set the changed_options HashMap for later write.
@@ -171,6 +358,8 @@ async fn main() {
      *   - If YBSTATS_HOSTS is set, it's detected by Ok(), we set the changed_options HashMap for later write and return the set value.
      *   - if YBSTATS_HOSTS is not set, it will trigger Err(), and DEFAULT_HOSTS is used.
      */
+    let hosts_argument_set = options.hosts.is_some();
+    let ports_argument_set = options.ports.is_some();
     let hosts_string = if options.hosts.is_some() {
         info!("hosts argument set: using: {}", &options.hosts.as_ref().unwrap());
         changed_options.insert("YBSTATS_HOSTS", options.hosts.as_ref().unwrap().to_string());
@@ -243,6 +432,197 @@ async fn main() {
         }
     };
     let parallel: usize = parallel_string.parse().unwrap();
+    utility::set_parallel(parallel);
+
+    /*
+     * Metrics source
+     * - if metrics_source is set, it's used and set in the changed_options HashMap for later write.
+     * - if metrics_source is not set, the .env setting YBSTATS_METRICS_SOURCE is used, if set.
+     * - if neither is set, the default (json) is used.
+     */
+    let metrics_source = if let Some(metrics_source) = options.metrics_source {
+        info!("metrics_source argument set: using: {}", &metrics_source);
+        changed_options.insert("YBSTATS_METRICS_SOURCE", metrics_source.to_string());
+        Some(metrics_source)
+    } else {
+        env::var("YBSTATS_METRICS_SOURCE").ok().map(|set_var| {
+            info!("metrics_source not set: set via .env: YBSTATS_METRICS_SOURCE: {}", set_var);
+            changed_options.insert("YBSTATS_METRICS_SOURCE", set_var.to_owned());
+            set_var
+        })
+    };
+    metrics::set_metrics_source(metrics_source.map(|metrics_source| metrics_source.parse()).unwrap_or(Ok(metrics::MetricsSource::Json))
+        .unwrap_or_else(|e| {
+            error!("Fatal: {}", e);
+            process::exit(1);
+        }));
+
+    /*
+     * Compression
+     * - if compression is set, it's used and set in the changed_options HashMap for later write.
+     * - if compression is not set, the .env setting YBSTATS_COMPRESSION is used, if set.
+     * - if neither is set, the default (none) is used.
+     */
+    let compression = if let Some(compression) = options.compression {
+        info!("compression argument set: using: {}", &compression);
+        changed_options.insert("YBSTATS_COMPRESSION", compression.to_string());
+        Some(compression)
+    } else {
+        env::var("YBSTATS_COMPRESSION").ok().map(|set_var| {
+            info!("compression not set: set via .env: YBSTATS_COMPRESSION: {}", set_var);
+            changed_options.insert("YBSTATS_COMPRESSION", set_var.to_owned());
+            set_var
+        })
+    };
+    compression::set_compression(compression.map(|compression| compression.parse()).unwrap_or(Ok(compression::Compression::None))
+        .unwrap_or_else(|e| {
+            error!("Fatal: {}", e);
+            process::exit(1);
+        }));
+
+    /*
+     * Storage
+     * - if storage is set, it's used and set in the changed_options HashMap for later write.
+     * - if storage is not set, the .env setting YBSTATS_STORAGE is used, if set.
+     * - if neither is set, the default (csv) is used.
+     */
+    let storage = if let Some(storage) = options.storage {
+        info!("storage argument set: using: {}", &storage);
+        changed_options.insert("YBSTATS_STORAGE", storage.to_string());
+        Some(storage)
+    } else {
+        env::var("YBSTATS_STORAGE").ok().map(|set_var| {
+            info!("storage not set: set via .env: YBSTATS_STORAGE: {}", set_var);
+            changed_options.insert("YBSTATS_STORAGE", set_var.to_owned());
+            set_var
+        })
+    };
+    storage::set_storage(storage.map(|storage| storage.parse()).unwrap_or(Ok(storage::Storage::Csv))
+        .unwrap_or_else(|e| {
+            error!("Fatal: {}", e);
+            process::exit(1);
+        }));
+
+    /*
+     * Output
+     * - the output is set for this run only, and is not written to .env.
+     */
+    output::set_output_format(options.output.map(|output| output.parse()).unwrap_or(Ok(output::OutputFormat::Text))
+        .unwrap_or_else(|e| {
+            error!("Fatal: {}", e);
+            process::exit(1);
+        }));
+
+    /*
+     * Http settings: scheme, port schemes, CA certificate, client certificate, client key, credentials file, timeouts and retries.
+     * These follow the same logic as hosts, ports and parallel:
+     * - if the argument is set, it's used and set in the changed_options HashMap for later write.
+     * - if the argument is not set, the .env setting (YBSTATS_SCHEME, YBSTATS_PORT_SCHEMES, YBSTATS_CA_CERT, YBSTATS_CLIENT_CERT, YBSTATS_CLIENT_KEY, YBSTATS_CREDENTIALS_FILE, YBSTATS_CONNECT_TIMEOUT, YBSTATS_READ_TIMEOUT, YBSTATS_RETRIES) is used, if set.
+     * - if neither is set, the default is used: scheme auto, no port schemes, CA certificate, client certificate and key or credentials file, and the default timeouts and retries.
+     * The credentials themselves are never written to .env: only the name of the credentials file is.
+     */
+    let mut http_options: HashMap<&str, String> = HashMap::new();
+    for (option_value, env_name) in [
+        (&options.scheme, "YBSTATS_SCHEME"),
+        (&options.port_schemes, "YBSTATS_PORT_SCHEMES"),
+        (&options.ca_cert, "YBSTATS_CA_CERT"),
+        (&options.client_cert, "YBSTATS_CLIENT_CERT"),
+        (&options.client_key, "YBSTATS_CLIENT_KEY"),
+        (&options.credentials_file, "YBSTATS_CREDENTIALS_FILE"),
+        (&options.connect_timeout, "YBSTATS_CONNECT_TIMEOUT"),
+        (&options.read_timeout, "YBSTATS_READ_TIMEOUT"),
+        (&options.retries, "YBSTATS_RETRIES"),
+    ] {
+        if let Some(value) = option_value {
+            info!("{} argument set: using: {}", env_name, value);
+            changed_options.insert(env_name, value.to_string());
+            http_options.insert(env_name, value.to_string());
+        } else if let Ok(set_var) = env::var(env_name) {
+            info!("{} not set: set via .env: {}", env_name, set_var);
+            changed_options.insert(env_name, set_var.to_owned());
+            http_options.insert(env_name, set_var);
+        }
+    }
+    let http_settings = utility::HttpSettings {
+        scheme: http_options.get("YBSTATS_SCHEME").map(|scheme| scheme.parse()).unwrap_or(Ok(utility::Scheme::Auto))
+            .unwrap_or_else(|e| {
+                error!("Fatal: {}", e);
+                process::exit(1);
+            }),
+        port_schemes: http_options.get("YBSTATS_PORT_SCHEMES").map(|port_schemes| utility::HttpSettings::parse_port_schemes(port_schemes)).unwrap_or_else(|| Ok(HashMap::new()))
+            .unwrap_or_else(|e| {
+                error!("Fatal: {}", e);
+                process::exit(1);
+            }),
+        ca_cert_file: http_options.remove("YBSTATS_CA_CERT"),
+        client_cert_file: http_options.remove("YBSTATS_CLIENT_CERT"),
+        client_key_file: http_options.remove("YBSTATS_CLIENT_KEY"),
+        connect_timeout: Duration::from_millis(http_options.get("YBSTATS_CONNECT_TIMEOUT").map(|ms| ms.parse()).unwrap_or(Ok(utility::DEFAULT_CONNECT_TIMEOUT))
+            .unwrap_or_else(|e| {
+                error!("Fatal: invalid connect timeout: {}", e);
+                process::exit(1);
+            })),
+        read_timeout: Duration::from_millis(http_options.get("YBSTATS_READ_TIMEOUT").map(|ms| ms.parse()).unwrap_or(Ok(utility::DEFAULT_READ_TIMEOUT))
+            .unwrap_or_else(|e| {
+                error!("Fatal: invalid read timeout: {}", e);
+                process::exit(1);
+            })),
+        retries: http_options.get("YBSTATS_RETRIES").map(|retries| retries.parse()).unwrap_or(Ok(utility::DEFAULT_RETRIES))
+            .unwrap_or_else(|e| {
+                error!("Fatal: invalid retries: {}", e);
+                process::exit(1);
+            }),
+        credentials: utility::HttpSettings::read_credentials(http_options.get("YBSTATS_CREDENTIALS_FILE").map(|file| file.as_str()))
+            .unwrap_or_else(|e| {
+                error!("Fatal: {}", e);
+                process::exit(1);
+            }),
+    };
+    utility::set_http_settings(http_settings);
+
+    /*
+     * Discover
+     * - if discover is set, it's detected by is_some() and we take the set value, and set the changed_options HashMap for later write.
+     * - if discover is not set, and hosts is not set as argument, we can detect if it's set via .env by looking at YBSTATS_DISCOVER.
+     *   An explicitly set --hosts argument replaces discovery via .env.
+     * If discover is set, the hosts and ports are taken from the discovered endpoints. The ports that are not discovered, such as the node_exporter port, are added for all hosts.
+     * If discover is not set, the endpoints are all the combinations of hosts and ports.
+     */
+    let discover = if let Some(discover) = options.discover {
+        info!("discover argument set: using: {}", discover);
+        changed_options.insert("YBSTATS_DISCOVER", discover.to_string());
+        Some(discover)
+    } else if !hosts_argument_set {
+        env::var("YBSTATS_DISCOVER").ok().map(|set_var| {
+            info!("discover not set: set via .env: YBSTATS_DISCOVER: {}", set_var);
+            changed_options.insert("YBSTATS_DISCOVER", set_var.to_owned());
+            set_var
+        })
+    } else {
+        None
+    };
+    // Only the modes that read data from the cluster need the endpoints to be discovered and classified.
+    let deletes_snapshots = options.snapshot_delete.is_some() || options.snapshot_keep.is_some() || options.snapshot_older_than.is_some();
+    let reads_from_cluster = !(options.snapshot_diff || options.snapshot_list || options.html_report.is_some() || options.snapshot_series || options.entity_diff || options.masters_diff || deletes_snapshots
+        || options.snapshot_export.is_some() || options.snapshot_import.is_some() || options.sqlite_convert || options.snapshot_verify || options.snapshot_anonymize.is_some()
+        || options.print_memtrackers.is_some() || options.print_log.is_some() || options.print_threads.is_some() || options.print_gflags.is_some() || options.print_rpcs.is_some()
+        || matches!(options.print_version, Some(Some(_))) || matches!(options.print_entities, Some(Some(_))) || matches!(options.print_masters, Some(Some(_)))
+        || matches!(options.print_tablet_servers, Some(Some(_))) || matches!(options.print_vars, Some(Some(_))));
+    let mut endpoints = match discover {
+        Some(master_address) if reads_from_cluster => endpoints::AllStoredEndpoints::discover(&master_address, &ports).await,
+        // The endpoints of a cluster profile can have different ports per host, unless hosts or ports are set as argument.
+        _ => match &profile {
+            Some(profile) if !hosts_argument_set && !ports_argument_set => endpoints::AllStoredEndpoints::new_from_endpoints(&profile.endpoints(&ports)),
+            _ => endpoints::AllStoredEndpoints::new_from_hosts_ports(&hosts, &ports),
+        },
+    };
+    // The endpoints that are not discovered are classified, so every source is only read from the endpoints with a matching role.
+    if reads_from_cluster {
+        endpoints.classify().await;
+        endpoints.set_endpoint_roles();
+    }
+    let hosts = endpoints.hosts();
+    let ports = endpoints.ports();
 
     let stat_name_filter = match options.stat_name_match {
         Some(stat_name_match) => Regex::new(stat_name_match.as_str()).unwrap(),
@@ -256,67 +636,232 @@ async fn main() {
         Some(table_name_match) => Regex::new(table_name_match.as_str()).unwrap(),
         None => Regex::new(".*").unwrap()
     };
+    // The filters and switches for the modes that take these together.
+    let filters = utility::Filters {
+        hostname_filter: &hostname_filter,
+        stat_name_filter: &stat_name_filter,
+        table_name_filter: &table_name_filter,
+        details_enable: options.details_enable,
+        gauges_enable: options.gauges_enable,
+        sql_length: options.sql_length,
+    };
 
-    let current_directory = env::current_dir().unwrap();
-    let yb_stats_directory = current_directory.join("yb_stats.snapshots");
-
-    if options.snapshot {
+    if let Some(seconds) = options.snapshot_interval {
+        info!("snapshot_interval");
+        let snapshot_interval = interval::SnapshotInterval::new(seconds, options.snapshot_iterations, options.snapshot_full_every, options.snapshot_light_sources, options.snapshot_comment)
+            .unwrap_or_else(|e| {
+                error!("Fatal: {}", e);
+                process::exit(1);
+            });
+        perform_interval_snapshots(hosts, ports, &endpoints, &snapshot_interval, options.cluster, options.disable_threads, options.silent).await;
+    } else if !options.watch.is_empty() {
+        info!("watch");
+        let conditions = options.watch.iter()
+            .map(|condition| watch::Condition::parse(condition))
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap_or_else(|e| {
+                error!("Fatal: {}", e);
+                process::exit(1);
+            });
+        let interval = options.watch_interval.unwrap_or(watch::DEFAULT_WATCH_INTERVAL);
+        if interval == 0 {
+            error!("Fatal: the watch interval must be at least 1 second");
+            process::exit(1);
+        }
+        let cooldown = options.watch_cooldown.unwrap_or(watch::DEFAULT_WATCH_COOLDOWN);
+        perform_watch_snapshots(hosts, ports, &endpoints, conditions, interval, cooldown, options.watch_pre_trigger, options.cluster, options.disable_threads, options.silent).await;
+    } else if options.snapshot {
         info!("snapshot option");
-        let snapshot_number: i32 = perform_snapshot(hosts, ports, options.snapshot_comment, parallel, options.disable_threads).await;
+        let snapshot_number = snapshot::Snapshot::reserve_snapshot_number();
+        let disabled_sources = if options.disable_threads { vec!["threads"] } else { Vec::new() };
+        perform_snapshot(hosts, ports, &endpoints, options.snapshot_comment, options.cluster, snapshot_number, &disabled_sources).await;
         if !options.silent {
             println!("snapshot number {}", snapshot_number);
         }
+    } else if deletes_snapshots {
+        info!("snapshot_delete");
+        let retention = snapshot::SnapshotRetention::new(options.snapshot_delete, options.snapshot_keep, options.snapshot_older_than, options.snapshot_protect)
+            .unwrap_or_else(|e| {
+                error!("Fatal: {}", e);
+                process::exit(1);
+            });
+        snapshot::Snapshot::delete_snapshots(&retention, options.dry_run);
+    } else if options.snapshot_verify {
+        info!("snapshot_verify");
+        let problems = verify::SnapshotVerification::verify_snapshots(options.begin, options.end, &options.details_enable);
+        if problems > 0 {
+            error!("snapshot verification found {} problems", problems);
+            process::exit(1);
+        }
+    } else if let Some(directory) = options.snapshot_anonymize {
+        info!("snapshot_anonymize");
+        let snapshots = anonymize::anonymize_snapshots(&directory, options.begin, options.end)
+            .unwrap_or_else(|e| {
+                error!("Fatal: error anonymizing snapshots: {}", e);
+                process::exit(1);
+            });
+        if !options.silent {
+            println!("anonymized {} snapshots to {}", snapshots.len(), directory);
+        }
+    } else if options.sqlite_convert {
+        info!("sqlite_convert");
+        let converted = storage::convert_snapshots(options.begin, options.end)
+            .unwrap_or_else(|e| {
+                error!("Fatal: error converting snapshots: {}", e);
+                process::exit(1);
+            });
+        if !options.silent {
+            for (snapshot_number, files) in converted {
+                println!("converted snapshot {}: {} files", snapshot_number, files);
+            }
+        }
+    } else if let Some(archive_file) = options.snapshot_export {
+        info!("snapshot_export");
+        let snapshots = archive::export_snapshots(&archive_file, options.begin, options.end)
+            .unwrap_or_else(|e| {
+                error!("Fatal: error exporting snapshots: {}", e);
+                process::exit(1);
+            });
+        if !options.silent {
+            println!("exported {} snapshots to {}", snapshots.len(), archive_file);
+        }
+    } else if let Some(archive_file) = options.snapshot_import {
+        info!("snapshot_import");
+        let imported = archive::import_snapshots(&archive_file)
+            .unwrap_or_else(|e| {
+                error!("Fatal: error importing snapshots: {}", e);
+                process::exit(1);
+            });
+        if !options.silent {
+            for (snapshot, snapshot_number) in imported {
+                println!("imported snapshot {} as snapshot number {}", snapshot.number, snapshot_number);
+            }
+        }
+    } else if options.snapshot_series {
+        info!("snapshot_series");
+        if options.begin.is_none() || options.end.is_none() {
+            if output::is_machine_readable() {
+                error!("Fatal: --output {} needs --begin and --end", output::output_format());
+                process::exit(1);
+            }
+            snapshot::Snapshot::print();
+        }
+        let (_begin_snapshot, end_snapshot, begin_snapshot_row) = snapshot::Snapshot::read_begin_end_snapshot_from_user(options.begin, options.end);
+        let snapshots = series::snapshot_range(snapshot::Snapshot::read_snapshots(), begin_snapshot_row.number, end_snapshot.parse().unwrap_or_default())
+            .unwrap_or_else(|e| {
+                error!("Fatal: {}", e);
+                process::exit(1);
+            });
+        let records = series::series(&snapshots, &filters);
+        if output::is_machine_readable() {
+            output::write_records(&records);
+        } else {
+            series::print(&snapshots, &records, options.details_enable);
+        }
+    } else if let Some(report_file) = options.html_report {
+        info!("html_report");
+        if options.begin.is_none() || options.end.is_none() {
+            snapshot::Snapshot::print();
+        }
+        let (begin_snapshot, end_snapshot, _begin_snapshot_row) = snapshot::Snapshot::read_begin_end_snapshot_from_user(options.begin, options.end);
+        manifest::warn_different_endpoints(&begin_snapshot, &end_snapshot);
+        report::write_html_report(&begin_snapshot, &end_snapshot, &filters, &report_file)
+            .unwrap_or_else(|e| {
+                error!("Fatal: error writing the report {}: {}", report_file, e);
+                process::exit(1);
+            });
+        if !options.silent {
+            println!("written report of snapshot {} to {} to {}", begin_snapshot, end_snapshot, report_file);
+        }
     } else if options.snapshot_diff || options.snapshot_list {
         info!("snapshot_diff");
+        if options.snapshot_list {
+            snapshot::Snapshot::print_list(&options.details_enable);
+            process::exit(0);
+        }
         if options.begin.is_none() || options.end.is_none() {
+            if output::is_machine_readable() {
+                error!("Fatal: --output {} needs --begin and --end", output::output_format());
+                process::exit(1);
+            }
             snapshot::Snapshot::print();
         }
-        if options.snapshot_list { process::exit(0) };
 
         let (begin_snapshot, end_snapshot, begin_snapshot_row) = snapshot::Snapshot::read_begin_end_snapshot_from_user(options.begin, options.end);
+        manifest::warn_different_endpoints(&begin_snapshot, &end_snapshot);
 
-        let metrics_diff = metrics::SnapshotDiffBTreeMapsMetrics::snapshot_diff(&begin_snapshot, &end_snapshot, &begin_snapshot_row.timestamp);
-        metrics_diff.print(&hostname_filter, &stat_name_filter, &table_name_filter, &options.details_enable, &options.gauges_enable).await;
-        let statements_diff = statements::SnapshotDiffBTreeMapStatements::snapshot_diff(&begin_snapshot, &end_snapshot, &begin_snapshot_row.timestamp);
-        statements_diff.print(&hostname_filter, options.sql_length).await;
-        let nodeexporter_diff = node_exporter::SnapshotDiffBTreeMapNodeExporter::snapshot_diff(&begin_snapshot, &end_snapshot, &begin_snapshot_row.timestamp);
-        nodeexporter_diff.print(&hostname_filter, &stat_name_filter, &options.gauges_enable, &options.details_enable);
-        let entities_diff = entities::SnapshotDiffBTreeMapsEntities::snapshot_diff(&begin_snapshot, &end_snapshot, &options.details_enable);
-        entities_diff.print();
-        let masters_diff = masters::SnapshotDiffBTreeMapsMasters::snapshot_diff(&begin_snapshot, &end_snapshot);
-        masters_diff.print();
-        let tabletservers_diff = tservers::SnapshotDiffBTreeMapsTabletServers::snapshot_diff(&begin_snapshot, &end_snapshot);
-        tabletservers_diff.print();
-        let vars_diff = vars::SnapshotDiffBTreeMapsVars::snapshot_diff(&begin_snapshot, &end_snapshot);
-        vars_diff.print();
-        let versions_diff = versions::SnapshotDiffBTreeMapsVersions::snapshot_diff(&begin_snapshot, &end_snapshot);
-        versions_diff.print();
+        if manifest::source_in_snapshots(&begin_snapshot, &end_snapshot, "metrics") {
+            let metrics_diff = metrics::SnapshotDiffBTreeMapsMetrics::snapshot_diff(&begin_snapshot, &end_snapshot, &begin_snapshot_row.timestamp);
+            metrics_diff.print(&hostname_filter, &stat_name_filter, &table_name_filter, &options.details_enable, &options.gauges_enable).await;
+        }
+        if manifest::source_in_snapshots(&begin_snapshot, &end_snapshot, "statements") {
+            let statements_diff = statements::SnapshotDiffBTreeMapStatements::snapshot_diff(&begin_snapshot, &end_snapshot, &begin_snapshot_row.timestamp);
+            statements_diff.print(&hostname_filter, options.sql_length).await;
+        }
+        if manifest::source_in_snapshots(&begin_snapshot, &end_snapshot, "node_exporter") {
+            let nodeexporter_diff = node_exporter::SnapshotDiffBTreeMapNodeExporter::snapshot_diff(&begin_snapshot, &end_snapshot, &begin_snapshot_row.timestamp);
+            nodeexporter_diff.print(&hostname_filter, &stat_name_filter, &options.gauges_enable, &options.details_enable);
+        }
+        if manifest::source_in_snapshots(&begin_snapshot, &end_snapshot, "entities") {
+            let entities_diff = entities::SnapshotDiffBTreeMapsEntities::snapshot_diff(&begin_snapshot, &end_snapshot, &options.details_enable);
+            entities_diff.print();
+        }
+        if manifest::source_in_snapshots(&begin_snapshot, &end_snapshot, "masters") {
+            let masters_diff = masters::SnapshotDiffBTreeMapsMasters::snapshot_diff(&begin_snapshot, &end_snapshot);
+            masters_diff.print();
+        }
+        if manifest::source_in_snapshots(&begin_snapshot, &end_snapshot, "tservers") {
+            let tabletservers_diff = tservers::SnapshotDiffBTreeMapsTabletServers::snapshot_diff(&begin_snapshot, &end_snapshot);
+            tabletservers_diff.print();
+        }
+        if manifest::source_in_snapshots(&begin_snapshot, &end_snapshot, "vars") {
+            let vars_diff = vars::SnapshotDiffBTreeMapsVars::snapshot_diff(&begin_snapshot, &end_snapshot);
+            vars_diff.print();
+        }
+        if manifest::source_in_snapshots(&begin_snapshot, &end_snapshot, "versions") {
+            let versions_diff = versions::SnapshotDiffBTreeMapsVersions::snapshot_diff(&begin_snapshot, &end_snapshot);
+            versions_diff.print();
+        }
     } else if options.entity_diff {
         info!("entity_diff");
 
         if options.begin.is_none() || options.end.is_none() {
+            if output::is_machine_readable() {
+                error!("Fatal: --output {} needs --begin and --end", output::output_format());
+                process::exit(1);
+            }
             snapshot::Snapshot::print();
         }
         if options.snapshot_list { process::exit(0) };
 
         let (begin_snapshot, end_snapshot, _begin_snapshot_row) = snapshot::Snapshot::read_begin_end_snapshot_from_user(options.begin, options.end);
-        let entity_diff = entities::SnapshotDiffBTreeMapsEntities::snapshot_diff(&begin_snapshot, &end_snapshot, &options.details_enable);
-        entity_diff.print();
+        manifest::warn_different_endpoints(&begin_snapshot, &end_snapshot);
+        if manifest::source_in_snapshots(&begin_snapshot, &end_snapshot, "entities") {
+            let entity_diff = entities::SnapshotDiffBTreeMapsEntities::snapshot_diff(&begin_snapshot, &end_snapshot, &options.details_enable);
+            entity_diff.print();
+        }
     } else if options.masters_diff {
         info!("masters_diff");
 
         if options.begin.is_none() || options.end.is_none() {
+            if output::is_machine_readable() {
+                error!("Fatal: --output {} needs --begin and --end", output::output_format());
+                process::exit(1);
+            }
             snapshot::Snapshot::print();
         }
         if options.snapshot_list { process::exit(0) };
 
         let (begin_snapshot, end_snapshot, _begin_snapshot_row) = snapshot::Snapshot::read_begin_end_snapshot_from_user(options.begin, options.end);
-        let masters_diff = masters::SnapshotDiffBTreeMapsMasters::snapshot_diff(&begin_snapshot, &end_snapshot);
-        masters_diff.print();
+        manifest::warn_different_endpoints(&begin_snapshot, &end_snapshot);
+        if manifest::source_in_snapshots(&begin_snapshot, &end_snapshot, "masters") {
+            let masters_diff = masters::SnapshotDiffBTreeMapsMasters::snapshot_diff(&begin_snapshot, &end_snapshot);
+            masters_diff.print();
+        }
     } else if options.print_memtrackers.is_some() {
-        memtrackers::print_memtrackers_data(&options.print_memtrackers.unwrap(), &yb_stats_directory, &hostname_filter, &stat_name_filter);
+        memtrackers::print_memtrackers_data(&options.print_memtrackers.unwrap(), &hostname_filter, &stat_name_filter);
     } else if options.print_log.is_some() {
-        loglines::print_loglines(&options.print_log.unwrap(), &yb_stats_directory, &hostname_filter, &options.log_severity);
+        loglines::print_loglines(&options.print_log.unwrap(), &hostname_filter, &options.log_severity);
     } else if options.print_version.is_some() {
         match options.print_version.unwrap() {
             Some(snapshot_number) => {
@@ -328,14 +873,14 @@ async fn main() {
                 versions.print(&hostname_filter);
             }
             None => {
-                let allstoredversions = AllStoredVersions::read_versions(&hosts, &ports, parallel).await;
+                let allstoredversions = AllStoredVersions::read_versions(&hosts, &ports).await;
                 allstoredversions.print(&hostname_filter);
             }
         }
     } else if options.print_threads.is_some() {
-        threads::print_threads_data(&options.print_threads.unwrap(), &yb_stats_directory, &hostname_filter);
+        threads::print_threads_data(&options.print_threads.unwrap(), &hostname_filter);
     } else if options.print_gflags.is_some() {
-        gflags::print_gflags_data(&options.print_gflags.unwrap(), &yb_stats_directory, &hostname_filter, &stat_name_filter);
+        gflags::print_gflags_data(&options.print_gflags.unwrap(), &hostname_filter, &stat_name_filter);
     } else if options.print_entities.is_some() {
         match options.print_entities.unwrap() {
             Some(snapshot_number) => {
@@ -347,8 +892,8 @@ async fn main() {
                 entities.print(&snapshot_number, &table_name_filter, &options.details_enable);
             }
             None => {
-                let allstoredentities = AllStoredEntities::read_entities(&hosts, &ports, parallel).await;
-                allstoredentities.print_adhoc(&table_name_filter, &options.details_enable, &hosts, &ports, parallel).await;
+                let allstoredentities = AllStoredEntities::read_entities(&hosts, &ports).await;
+                allstoredentities.print_adhoc(&table_name_filter, &options.details_enable, &hosts, &ports).await;
             }
         }
     } else if options.print_masters.is_some() {
@@ -362,8 +907,8 @@ async fn main() {
                 masters.print(&snapshot_number, &options.details_enable);
             }
             None => {
-                let allstoredmasters = AllStoredMasters::read_masters(&hosts, &ports, parallel).await;
-                allstoredmasters.print_adhoc(&options.details_enable, &hosts, &ports, parallel).await;
+                let allstoredmasters = AllStoredMasters::read_masters(&hosts, &ports).await;
+                allstoredmasters.print_adhoc(&options.details_enable, &hosts, &ports).await;
             }
         }
     } else if options.print_tablet_servers.is_some() {
@@ -377,8 +922,8 @@ async fn main() {
                 tablet_servers.print(&snapshot_number, &options.details_enable);
             }
             None => {
-                let allstoredtabletservers = AllStoredTabletServers::read_tabletservers(&hosts, &ports, parallel).await;
-                allstoredtabletservers.print_adhoc(&options.details_enable, &hosts, &ports, parallel).await;
+                let allstoredtabletservers = AllStoredTabletServers::read_tabletservers(&hosts, &ports).await;
+                allstoredtabletservers.print_adhoc(&options.details_enable, &hosts, &ports).await;
             }
         }
     } else if options.print_vars.is_some() {
@@ -392,13 +937,32 @@ async fn main() {
                 allstoredvars.print(&options.details_enable, &hostname_filter, &stat_name_filter).await;
             }
             None => {
-                let allstoredvars = AllStoredVars::read_vars(&hosts, &ports, parallel).await;
+                let allstoredvars = AllStoredVars::read_vars(&hosts, &ports).await;
                 allstoredvars.print(&options.details_enable, &hostname_filter, &stat_name_filter).await;
             }
         }
     } else if options.print_rpcs.is_some() {
 
-        rpcs::print_rpcs(&options.print_rpcs.unwrap(), &yb_stats_directory, &hostname_filter, &options.details_enable);
+        rpcs::print_rpcs(&options.print_rpcs.unwrap(), &hostname_filter, &options.details_enable);
+
+    } else if let Some(address) = options.exporter {
+
+        info!("exporter");
+        let interval = options.exporter_interval.unwrap_or(exporter::DEFAULT_EXPORTER_INTERVAL);
+        if interval == 0 {
+            error!("Fatal: the exporter interval must be at least 1 second");
+            process::exit(1);
+        }
+        exporter::exporter(&hosts, &ports, &address, interval, &filters).await;
+
+    } else if let Some(interval) = options.top {
+
+        info!("top");
+        if interval == 0 {
+            error!("Fatal: the top interval must be at least 1 second");
+            process::exit(1);
+        }
+        top::top(&hosts, &ports, interval, options.top_rows.unwrap_or(DEFAULT_TOP_ROWS), &filters).await;
 
     } else if options.adhoc_metrics_diff {
 
@@ -417,7 +981,7 @@ async fn main() {
         let clone_hosts = hosts.clone();
         let clone_ports = ports.clone();
         let handle = tokio::spawn(async move {
-            clone_metrics.lock().await.adhoc_read_first_snapshot(clone_hosts.lock().await.as_ref(), clone_ports.lock().await.as_ref(), parallel).await;
+            clone_metrics.lock().await.adhoc_read_first_snapshot(clone_hosts.lock().await.as_ref(), clone_ports.lock().await.as_ref()).await;
         });
         handles.push(handle);
 
@@ -425,7 +989,7 @@ async fn main() {
         let clone_hosts = hosts.clone();
         let clone_ports = ports.clone();
         let handle = tokio::spawn(async move {
-            clone_statements.lock().await.adhoc_read_first_snapshot(clone_hosts.lock().await.as_ref(), clone_ports.lock().await.as_ref(), parallel).await;
+            clone_statements.lock().await.adhoc_read_first_snapshot(clone_hosts.lock().await.as_ref(), clone_ports.lock().await.as_ref()).await;
         });
         handles.push(handle);
 
@@ -433,7 +997,7 @@ async fn main() {
         let clone_hosts = hosts.clone();
         let clone_ports = ports.clone();
         let handle = tokio::spawn(async move {
-            clone_node_exporter.lock().await.adhoc_read_first_snapshot(clone_hosts.lock().await.as_ref(), clone_ports.lock().await.as_ref(), parallel).await;
+            clone_node_exporter.lock().await.adhoc_read_first_snapshot(clone_hosts.lock().await.as_ref(), clone_ports.lock().await.as_ref()).await;
         });
         handles.push(handle);
 
@@ -441,7 +1005,7 @@ async fn main() {
             handle.await.unwrap();
         }
 
-        println!("Begin ad-hoc in-memory metrics snapshot created, press enter to create end snapshot for difference calculation.");
+        output::print_message("Begin ad-hoc in-memory metrics snapshot created, press enter to create end snapshot for difference calculation.");
         let mut input = String::new();
         stdin().read_line(&mut input).expect("failed");
 
@@ -453,7 +1017,7 @@ async fn main() {
         let clone_hosts = hosts.clone();
         let clone_ports = ports.clone();
         let handle = tokio::spawn(async move {
-            clone_metrics.lock().await.adhoc_read_second_snapshot(clone_hosts.lock().await.as_ref(), clone_ports.lock().await.as_ref(), parallel, &first_snapshot_time).await;
+            clone_metrics.lock().await.adhoc_read_second_snapshot(clone_hosts.lock().await.as_ref(), clone_ports.lock().await.as_ref(), &first_snapshot_time).await;
         });
         handles.push(handle);
 
@@ -461,7 +1025,7 @@ async fn main() {
         let clone_hosts = hosts.clone();
         let clone_ports = ports.clone();
         let handle = tokio::spawn(async move {
-            clone_statements.lock().await.adhoc_read_second_snapshot(clone_hosts.lock().await.as_ref(), clone_ports.lock().await.as_ref(), parallel, &first_snapshot_time).await;
+            clone_statements.lock().await.adhoc_read_second_snapshot(clone_hosts.lock().await.as_ref(), clone_ports.lock().await.as_ref(), &first_snapshot_time).await;
         });
         handles.push(handle);
 
@@ -469,7 +1033,7 @@ async fn main() {
         let clone_hosts = hosts.clone();
         let clone_ports = ports.clone();
         let handle = tokio::spawn(async move {
-            clone_node_exporter.lock().await.adhoc_read_second_snapshot(clone_hosts.lock().await.as_ref(), clone_ports.lock().await.as_ref(), parallel, &first_snapshot_time).await;
+            clone_node_exporter.lock().await.adhoc_read_second_snapshot(clone_hosts.lock().await.as_ref(), clone_ports.lock().await.as_ref(), &first_snapshot_time).await;
         });
         handles.push(handle);
 
@@ -477,7 +1041,7 @@ async fn main() {
             handle.await.unwrap();
         }
 
-        println!("Time between snapshots: {:8.3} seconds", (second_snapshot_time - first_snapshot_time).num_milliseconds() as f64 / 1000_f64);
+        output::print_message(&format!("Time between snapshots: {:8.3} seconds", (second_snapshot_time - first_snapshot_time).num_milliseconds() as f64 / 1000_f64));
         metrics.lock().await.print(&hostname_filter, &stat_name_filter, &table_name_filter, &options.details_enable, &options.gauges_enable).await;
         statements.lock().await.print(&hostname_filter, options.sql_length).await;
         node_exporter.lock().await.print(&hostname_filter, &stat_name_filter, &options.gauges_enable, &options.details_enable);
@@ -503,7 +1067,7 @@ async fn main() {
         let clone_hosts = hosts.clone();
         let clone_ports = ports.clone();
         let handle = tokio::spawn(async move {
-            clone_metrics.lock().await.adhoc_read_first_snapshot(clone_hosts.lock().await.as_ref(), clone_ports.lock().await.as_ref(), parallel).await;
+            clone_metrics.lock().await.adhoc_read_first_snapshot(clone_hosts.lock().await.as_ref(), clone_ports.lock().await.as_ref()).await;
         });
         handles.push(handle);
 
@@ -511,7 +1075,7 @@ async fn main() {
         let clone_hosts = hosts.clone();
         let clone_ports = ports.clone();
         let handle = tokio::spawn(async move {
-            clone_statements.lock().await.adhoc_read_first_snapshot(clone_hosts.lock().await.as_ref(), clone_ports.lock().await.as_ref(), parallel).await;
+            clone_statements.lock().await.adhoc_read_first_snapshot(clone_hosts.lock().await.as_ref(), clone_ports.lock().await.as_ref()).await;
         });
         handles.push(handle);
 
@@ -519,7 +1083,7 @@ async fn main() {
         let clone_hosts = hosts.clone();
         let clone_ports = ports.clone();
         let handle = tokio::spawn(async move {
-            clone_node_exporter.lock().await.adhoc_read_first_snapshot(clone_hosts.lock().await.as_ref(), clone_ports.lock().await.as_ref(), parallel).await;
+            clone_node_exporter.lock().await.adhoc_read_first_snapshot(clone_hosts.lock().await.as_ref(), clone_ports.lock().await.as_ref()).await;
         });
         handles.push(handle);
 
@@ -527,7 +1091,7 @@ async fn main() {
         let clone_hosts = hosts.clone();
         let clone_ports = ports.clone();
         let handle = tokio::spawn(async move {
-            clone_entities.lock().await.adhoc_read_first_snapshot(clone_hosts.lock().await.as_ref(), clone_ports.lock().await.as_ref(), parallel).await;
+            clone_entities.lock().await.adhoc_read_first_snapshot(clone_hosts.lock().await.as_ref(), clone_ports.lock().await.as_ref()).await;
         });
         handles.push(handle);
 
@@ -535,7 +1099,7 @@ async fn main() {
         let clone_hosts = hosts.clone();
         let clone_ports = ports.clone();
         let handle = tokio::spawn(async move {
-            clone_masters.lock().await.adhoc_read_first_snapshot(clone_hosts.lock().await.as_ref(), clone_ports.lock().await.as_ref(), parallel).await;
+            clone_masters.lock().await.adhoc_read_first_snapshot(clone_hosts.lock().await.as_ref(), clone_ports.lock().await.as_ref()).await;
         });
         handles.push(handle);
 
@@ -543,7 +1107,7 @@ async fn main() {
         let clone_hosts = hosts.clone();
         let clone_ports = ports.clone();
         let handle = tokio::spawn(async move {
-            clone_tablet_servers.lock().await.adhoc_read_first_snapshot(clone_hosts.lock().await.as_ref(), clone_ports.lock().await.as_ref(), parallel).await;
+            clone_tablet_servers.lock().await.adhoc_read_first_snapshot(clone_hosts.lock().await.as_ref(), clone_ports.lock().await.as_ref()).await;
         });
         handles.push(handle);
 
@@ -551,7 +1115,7 @@ async fn main() {
         let clone_hosts = hosts.clone();
         let clone_ports = ports.clone();
         let handle = tokio::spawn(async move {
-            clone_vars.lock().await.adhoc_read_first_snapshot(clone_hosts.lock().await.as_ref(), clone_ports.lock().await.as_ref(), parallel).await;
+            clone_vars.lock().await.adhoc_read_first_snapshot(clone_hosts.lock().await.as_ref(), clone_ports.lock().await.as_ref()).await;
         });
         handles.push(handle);
 
@@ -559,7 +1123,7 @@ async fn main() {
         let clone_hosts = hosts.clone();
         let clone_ports = ports.clone();
         let handle = tokio::spawn(async move {
-            clone_versions.lock().await.adhoc_read_first_snapshot(clone_hosts.lock().await.as_ref(), clone_ports.lock().await.as_ref(), parallel).await;
+            clone_versions.lock().await.adhoc_read_first_snapshot(clone_hosts.lock().await.as_ref(), clone_ports.lock().await.as_ref()).await;
         });
         handles.push(handle);
 
@@ -567,7 +1131,7 @@ async fn main() {
             handle.await.unwrap();
         }
 
-        println!("Begin ad-hoc in-memory snapshot created, press enter to create end snapshot for difference calculation.");
+        output::print_message("Begin ad-hoc in-memory snapshot created, press enter to create end snapshot for difference calculation.");
         let mut input = String::new();
         stdin().read_line(&mut input).expect("failed");
 
@@ -578,7 +1142,7 @@ async fn main() {
         let clone_hosts = hosts.clone();
         let clone_ports = ports.clone();
         let handle = tokio::spawn(async move {
-            clone_metrics.lock().await.adhoc_read_second_snapshot(clone_hosts.lock().await.as_ref(), clone_ports.lock().await.as_ref(), parallel, &first_snapshot_time).await;
+            clone_metrics.lock().await.adhoc_read_second_snapshot(clone_hosts.lock().await.as_ref(), clone_ports.lock().await.as_ref(), &first_snapshot_time).await;
         });
         handles.push(handle);
 
@@ -586,7 +1150,7 @@ async fn main() {
         let clone_hosts = hosts.clone();
         let clone_ports = ports.clone();
         let handle = tokio::spawn(async move {
-            clone_statements.lock().await.adhoc_read_second_snapshot(clone_hosts.lock().await.as_ref(), clone_ports.lock().await.as_ref(), parallel, &first_snapshot_time).await;
+            clone_statements.lock().await.adhoc_read_second_snapshot(clone_hosts.lock().await.as_ref(), clone_ports.lock().await.as_ref(), &first_snapshot_time).await;
         });
         handles.push(handle);
 
@@ -594,7 +1158,7 @@ async fn main() {
         let clone_hosts = hosts.clone();
         let clone_ports = ports.clone();
         let handle = tokio::spawn(async move {
-            clone_node_exporter.lock().await.adhoc_read_second_snapshot(clone_hosts.lock().await.as_ref(), clone_ports.lock().await.as_ref(), parallel, &first_snapshot_time).await;
+            clone_node_exporter.lock().await.adhoc_read_second_snapshot(clone_hosts.lock().await.as_ref(), clone_ports.lock().await.as_ref(), &first_snapshot_time).await;
         });
         handles.push(handle);
 
@@ -602,7 +1166,7 @@ async fn main() {
         let clone_hosts = hosts.clone();
         let clone_ports = ports.clone();
         let handle = tokio::spawn(async move {
-            clone_entities.lock().await.adhoc_read_second_snapshot(clone_hosts.lock().await.as_ref(), clone_ports.lock().await.as_ref(), parallel).await;
+            clone_entities.lock().await.adhoc_read_second_snapshot(clone_hosts.lock().await.as_ref(), clone_ports.lock().await.as_ref()).await;
         });
         handles.push(handle);
 
@@ -610,7 +1174,7 @@ async fn main() {
         let clone_hosts = hosts.clone();
         let clone_ports = ports.clone();
         let handle = tokio::spawn(async move {
-            clone_masters.lock().await.adhoc_read_second_snapshot(clone_hosts.lock().await.as_ref(), clone_ports.lock().await.as_ref(), parallel).await;
+            clone_masters.lock().await.adhoc_read_second_snapshot(clone_hosts.lock().await.as_ref(), clone_ports.lock().await.as_ref()).await;
         });
         handles.push(handle);
 
@@ -618,7 +1182,7 @@ async fn main() {
         let clone_hosts = hosts.clone();
         let clone_ports = ports.clone();
         let handle = tokio::spawn(async move {
-            clone_tablet_servers.lock().await.adhoc_read_second_snapshot(clone_hosts.lock().await.as_ref(), clone_ports.lock().await.as_ref(), parallel).await;
+            clone_tablet_servers.lock().await.adhoc_read_second_snapshot(clone_hosts.lock().await.as_ref(), clone_ports.lock().await.as_ref()).await;
         });
         handles.push(handle);
 
@@ -626,7 +1190,7 @@ async fn main() {
         let clone_hosts = hosts.clone();
         let clone_ports = ports.clone();
         let handle = tokio::spawn(async move {
-            clone_vars.lock().await.adhoc_read_second_snapshot(clone_hosts.lock().await.as_ref(), clone_ports.lock().await.as_ref(), parallel).await;
+            clone_vars.lock().await.adhoc_read_second_snapshot(clone_hosts.lock().await.as_ref(), clone_ports.lock().await.as_ref()).await;
         });
         handles.push(handle);
 
@@ -634,7 +1198,7 @@ async fn main() {
         let clone_hosts = hosts.clone();
         let clone_ports = ports.clone();
         let handle = tokio::spawn(async move {
-            clone_versions.lock().await.adhoc_read_second_snapshot(clone_hosts.lock().await.as_ref(), clone_ports.lock().await.as_ref(), parallel).await;
+            clone_versions.lock().await.adhoc_read_second_snapshot(clone_hosts.lock().await.as_ref(), clone_ports.lock().await.as_ref()).await;
         });
         handles.push(handle);
 
@@ -642,7 +1206,7 @@ async fn main() {
             handle.await.unwrap();
         }
 
-        println!("Time between snapshots: {:8.3} seconds", (second_snapshot_time - first_snapshot_time).num_milliseconds() as f64 / 1000_f64);
+        output::print_message(&format!("Time between snapshots: {:8.3} seconds", (second_snapshot_time - first_snapshot_time).num_milliseconds() as f64 / 1000_f64));
         metrics.lock().await.print(&hostname_filter, &stat_name_filter, &table_name_filter, &options.details_enable, &options.gauges_enable).await;
         statements.lock().await.print(&hostname_filter, options.sql_length).await;
         node_exporter.lock().await.print(&hostname_filter, &stat_name_filter, &options.gauges_enable, &options.details_enable);
@@ -654,7 +1218,7 @@ async fn main() {
 
     }
 
-    if !changed_options.is_empty() && WRITE_DOTENV {
+    if !changed_options.is_empty() && WRITE_DOTENV && profile.is_none() {
         info!("Writing .env file");
         let mut file = fs::OpenOptions::new()
             .create(true)
@@ -675,154 +1239,294 @@ async fn main() {
     }
 }
 
-/// The function to perform a snapshot resulting in CSV files.
+/// The function to perform snapshots at an interval, see [interval].
+/// Ctrl-C stops after the current snapshot, a second Ctrl-C stops immediately and marks the current snapshot as incomplete.
+async fn perform_interval_snapshots(
+    hosts: Vec<&'static str>,
+    ports: Vec<&'static str>,
+    endpoints: &endpoints::AllStoredEndpoints,
+    snapshot_interval: &interval::SnapshotInterval,
+    cluster: Option<String>,
+    disable_threads: bool,
+    silent: bool,
+) {
+    let mut interrupts = interval::listen_for_interrupts();
+    let mut previous_start: Option<chrono::DateTime<Local>> = None;
+    let mut iteration: u64 = 1;
+    while snapshot_interval.iterations.map(|iterations| iteration <= iterations).unwrap_or(true) {
+        let start = snapshot_interval.next_start(Local::now());
+        if let Some(previous_start) = previous_start {
+            if start - previous_start > chrono::Duration::seconds(snapshot_interval.interval) {
+                println!("Warning: the snapshot took longer than the interval, the next snapshot starts at {}", start.format("%H:%M:%S"));
+            }
+        }
+        previous_start = Some(start);
+        tokio::select! {
+            _ = tokio::time::sleep((start - Local::now()).to_std().unwrap_or_default()) => {},
+            _ = interval::interrupted(&mut interrupts) => break,
+        }
+
+        let snapshot_number = snapshot::Snapshot::reserve_snapshot_number();
+        let start_time = Local::now();
+        let comment = snapshot_interval.comment(iteration, snapshot_number, start_time);
+        let disabled_sources = snapshot_interval.disabled_sources(iteration, disable_threads);
+        interval::set_current_snapshot(Some(interval::CurrentSnapshot { snapshot_number, start_time, comment: comment.clone(), cluster: cluster.clone() }));
+        perform_snapshot(hosts.clone(), ports.clone(), endpoints, comment, cluster.clone(), snapshot_number, &disabled_sources).await;
+        if !silent {
+            println!("snapshot number {}", snapshot_number);
+        }
+        if *interrupts.borrow() > 0 {
+            break;
+        }
+        iteration += 1;
+    }
+}
+
+/// The function to poll the metrics and perform a snapshot when a condition is met, see [watch].
+/// Ctrl-C stops after the current poll or snapshot, a second Ctrl-C stops immediately and marks the current snapshot as incomplete.
+#[allow(clippy::too_many_arguments)]
+async fn perform_watch_snapshots(
+    hosts: Vec<&'static str>,
+    ports: Vec<&'static str>,
+    endpoints: &endpoints::AllStoredEndpoints,
+    conditions: Vec<watch::Condition>,
+    interval: u64,
+    cooldown: u64,
+    pre_trigger: bool,
+    cluster: Option<String>,
+    disable_threads: bool,
+    silent: bool,
+) {
+    let mut interrupts = interval::listen_for_interrupts();
+    let mut watched_conditions = conditions.into_iter().map(watch::WatchedCondition::new).collect::<Vec<_>>();
+    let disabled_sources = if disable_threads { vec!["threads"] } else { Vec::new() };
+    let mut previous_poll: Option<watch::Poll> = None;
+    loop {
+        let start = Instant::now();
+        let poll = watch::Poll::read(&hosts, &ports, &watched_conditions.iter().map(|watched| watched.condition.clone()).collect::<Vec<_>>()).await;
+        let triggers = watched_conditions.iter_mut()
+            .filter_map(|watched| watched.check(previous_poll.as_ref(), &poll, cooldown))
+            .collect::<Vec<String>>();
+        if !triggers.is_empty() {
+            let trigger = triggers.join("; ");
+            if !silent {
+                println!("{} watch trigger: {}", Local::now().format("%H:%M:%S"), trigger);
+            }
+            if pre_trigger {
+                if let Some(previous_poll) = previous_poll.take() {
+                    let snapshot_number = previous_poll.save_snapshot(endpoints, format!("watch pre-trigger: {}", trigger), cluster.clone()).await;
+                    if !silent {
+                        println!("snapshot number {} (pre-trigger)", snapshot_number);
+                    }
+                }
+            }
+            let snapshot_number = snapshot::Snapshot::reserve_snapshot_number();
+            let comment = Some(format!("watch trigger: {}", trigger));
+            interval::set_current_snapshot(Some(interval::CurrentSnapshot { snapshot_number, start_time: Local::now(), comment: comment.clone(), cluster: cluster.clone() }));
+            perform_snapshot(hosts.clone(), ports.clone(), endpoints, comment, cluster.clone(), snapshot_number, &disabled_sources).await;
+            if !silent {
+                println!("snapshot number {}", snapshot_number);
+            }
+        }
+        previous_poll = Some(poll);
+        if *interrupts.borrow() > 0 {
+            break;
+        }
+        tokio::select! {
+            _ = tokio::time::sleep(Duration::from_secs(interval).saturating_sub(start.elapsed())) => {},
+            _ = interval::interrupted(&mut interrupts) => break,
+        }
+    }
+}
+
+/// The function to perform a snapshot resulting in CSV files, in the snapshot directory of the snapshot number that is reserved with [snapshot::Snapshot::reserve_snapshot_number].
+/// The disabled sources are not read, and are recorded as disabled in the manifest.
 async fn perform_snapshot(
     hosts: Vec<&'static str>,
     ports: Vec<&'static str>,
+    endpoints: &endpoints::AllStoredEndpoints,
     snapshot_comment: Option<String>,
-    parallel: usize,
-    disable_threads: bool,
-) -> i32 {
+    cluster: Option<String>,
+    snapshot_number: i32,
+    disabled_sources: &[&str],
+) {
     info!("begin snapshot");
     let timer = Instant::now();
+    let start_time = Local::now();
 
-    let current_directory = env::current_dir().unwrap();
-    let yb_stats_directory = current_directory.join("yb_stats.snapshots");
-
-    let snapshot_number = snapshot::Snapshot::insert_new_snapshot_number(snapshot_comment);
     info!("using snapshot number: {}", snapshot_number);
 
+    endpoints.save_snapshot(snapshot_number)
+        .unwrap_or_else(|e| {
+            error!("error saving snapshot: {}", e);
+            process::exit(1);
+        });
+
     let arc_hosts = Arc::new(hosts);
     let arc_ports = Arc::new(ports);
-    let arc_yb_stats_directory = Arc::new(yb_stats_directory);
 
     let mut handles = vec![];
 
-    let arc_hosts_clone = arc_hosts.clone();
-    let arc_ports_clone = arc_ports.clone();
-    let handle = tokio::spawn(async move {
-        metrics::AllStoredMetrics::perform_snapshot(&arc_hosts_clone, &arc_ports_clone, snapshot_number, parallel).await;
-    });
-    handles.push(handle);
-
-    let arc_hosts_clone = arc_hosts.clone();
-    let arc_ports_clone = arc_ports.clone();
-    let handle = tokio::spawn(async move {
-        statements::AllStoredStatements::perform_snapshot(&arc_hosts_clone, &arc_ports_clone, snapshot_number, parallel).await;
-    });
-    handles.push(handle);
-
-    let arc_hosts_clone = arc_hosts.clone();
-    let arc_ports_clone = arc_ports.clone();
-    let handle = tokio::spawn(async move {
-        node_exporter::AllStoredNodeExporterValues::perform_snapshot(&arc_hosts_clone, &arc_ports_clone, snapshot_number, parallel).await;
-    });
-    handles.push(handle);
-
-    let arc_hosts_clone = arc_hosts.clone();
-    let arc_ports_clone = arc_ports.clone();
-    let handle = tokio::spawn(async move {
-        isleader::AllStoredIsLeader::perform_snapshot(&arc_hosts_clone, &arc_ports_clone, snapshot_number, parallel).await;
-    });
-    handles.push(handle);
-
-    let arc_hosts_clone = arc_hosts.clone();
-    let arc_ports_clone = arc_ports.clone();
-    let handle = tokio::spawn(async move {
-        entities::AllStoredEntities::perform_snapshot(&arc_hosts_clone, &arc_ports_clone, snapshot_number, parallel).await;
-    });
-    handles.push(handle);
-
-    let arc_hosts_clone = arc_hosts.clone();
-    let arc_ports_clone = arc_ports.clone();
-    let handle = tokio::spawn(async move {
-        masters::AllStoredMasters::perform_snapshot(&arc_hosts_clone, &arc_ports_clone, snapshot_number, parallel).await;
-    });
-    handles.push(handle);
-
-    let arc_hosts_clone = arc_hosts.clone();
-    let arc_ports_clone = arc_ports.clone();
-    let handle = tokio::spawn(async move {
-        tservers::AllStoredTabletServers::perform_snapshot(&arc_hosts_clone, &arc_ports_clone, snapshot_number, parallel).await;
-    });
-    handles.push(handle);
-
-    let arc_hosts_clone = arc_hosts.clone();
-    let arc_ports_clone = arc_ports.clone();
-    let handle = tokio::spawn(async move {
-        vars::AllStoredVars::perform_snapshot(&arc_hosts_clone, &arc_ports_clone, snapshot_number, parallel).await;
-    });
-    handles.push(handle);
-
-    let arc_hosts_clone = arc_hosts.clone();
-    let arc_ports_clone = arc_ports.clone();
-    let handle = tokio::spawn(async move {
-        versions::AllStoredVersions::perform_snapshot(&arc_hosts_clone, &arc_ports_clone, snapshot_number, parallel).await;
-    });
-    handles.push(handle);
-
-    let arc_hosts_clone = arc_hosts.clone();
-    let arc_ports_clone = arc_ports.clone();
-    let arc_yb_stats_directory_clone = arc_yb_stats_directory.clone();
-    let handle = tokio::spawn(async move {
-        gflags::perform_gflags_snapshot(&arc_hosts_clone, &arc_ports_clone, snapshot_number, &arc_yb_stats_directory_clone, parallel).await;
-    });
-    handles.push(handle);
-
-    if !disable_threads {
+    if !disabled_sources.contains(&"metrics") {
         let arc_hosts_clone = arc_hosts.clone();
         let arc_ports_clone = arc_ports.clone();
-        let arc_yb_stats_directory_clone = arc_yb_stats_directory.clone();
-        let handle = tokio::spawn(async move {
-            threads::perform_threads_snapshot(&arc_hosts_clone, &arc_ports_clone, snapshot_number, &arc_yb_stats_directory_clone, parallel).await;
-        });
+        let handle = tokio::spawn(ManifestSource::timed("metrics", async move {
+            metrics::AllStoredMetrics::perform_snapshot(&arc_hosts_clone, &arc_ports_clone, snapshot_number).await;
+        }));
         handles.push(handle);
-    };
+    }
 
-    let arc_hosts_clone = arc_hosts.clone();
-    let arc_ports_clone = arc_ports.clone();
-    let arc_yb_stats_directory_clone = arc_yb_stats_directory.clone();
-    let handle = tokio::spawn(async move {
-        memtrackers::perform_memtrackers_snapshot(&arc_hosts_clone, &arc_ports_clone, snapshot_number, &arc_yb_stats_directory_clone, parallel).await;
-    });
-    handles.push(handle);
-
-    let arc_hosts_clone = arc_hosts.clone();
-    let arc_ports_clone = arc_ports.clone();
-    let arc_yb_stats_directory_clone = arc_yb_stats_directory.clone();
-    let handle = tokio::spawn(async move {
-        loglines::perform_loglines_snapshot(&arc_hosts_clone, &arc_ports_clone, snapshot_number, &arc_yb_stats_directory_clone, parallel).await;
-    });
-    handles.push(handle);
-
-    let arc_hosts_clone = arc_hosts.clone();
-    let arc_ports_clone = arc_ports.clone();
-    let arc_yb_stats_directory_clone = arc_yb_stats_directory.clone();
-    let handle = tokio::spawn(async move {
-        rpcs::perform_rpcs_snapshot(&arc_hosts_clone, &arc_ports_clone, snapshot_number, &arc_yb_stats_directory_clone, parallel).await;
-    });
-    handles.push(handle);
-
-    let arc_hosts_clone = arc_hosts.clone();
-    let arc_ports_clone = arc_ports.clone();
-    let arc_yb_stats_directory_clone = arc_yb_stats_directory.clone();
-    let handle = tokio::spawn(async move {
-        pprof::perform_pprof_snapshot(&arc_hosts_clone, &arc_ports_clone, snapshot_number, &arc_yb_stats_directory_clone, parallel).await;
-    });
-    handles.push(handle);
-
-    let arc_hosts_clone = arc_hosts.clone();
-    let arc_ports_clone = arc_ports.clone();
-    let arc_yb_stats_directory_clone = arc_yb_stats_directory.clone();
-    let handle = tokio::spawn(async move {
-        mems::perform_mems_snapshot(&arc_hosts_clone, &arc_ports_clone, snapshot_number, &arc_yb_stats_directory_clone, parallel).await;
-    });
-    handles.push(handle);
+    if !disabled_sources.contains(&"statements") {
+        let arc_hosts_clone = arc_hosts.clone();
+        let arc_ports_clone = arc_ports.clone();
+        let handle = tokio::spawn(ManifestSource::timed("statements", async move {
+            statements::AllStoredStatements::perform_snapshot(&arc_hosts_clone, &arc_ports_clone, snapshot_number).await;
+        }));
+        handles.push(handle);
+    }
 
+    if !disabled_sources.contains(&"node_exporter") {
+        let arc_hosts_clone = arc_hosts.clone();
+        let arc_ports_clone = arc_ports.clone();
+        let handle = tokio::spawn(ManifestSource::timed("node_exporter", async move {
+            node_exporter::AllStoredNodeExporterValues::perform_snapshot(&arc_hosts_clone, &arc_ports_clone, snapshot_number).await;
+        }));
+        handles.push(handle);
+    }
+
+    if !disabled_sources.contains(&"isleader") {
+        let arc_hosts_clone = arc_hosts.clone();
+        let arc_ports_clone = arc_ports.clone();
+        let handle = tokio::spawn(ManifestSource::timed("isleader", async move {
+            isleader::AllStoredIsLeader::perform_snapshot(&arc_hosts_clone, &arc_ports_clone, snapshot_number).await;
+        }));
+        handles.push(handle);
+    }
+
+    if !disabled_sources.contains(&"entities") {
+        let arc_hosts_clone = arc_hosts.clone();
+        let arc_ports_clone = arc_ports.clone();
+        let handle = tokio::spawn(ManifestSource::timed("entities", async move {
+            entities::AllStoredEntities::perform_snapshot(&arc_hosts_clone, &arc_ports_clone, snapshot_number).await;
+        }));
+        handles.push(handle);
+    }
+
+    if !disabled_sources.contains(&"masters") {
+        let arc_hosts_clone = arc_hosts.clone();
+        let arc_ports_clone = arc_ports.clone();
+        let handle = tokio::spawn(ManifestSource::timed("masters", async move {
+            masters::AllStoredMasters::perform_snapshot(&arc_hosts_clone, &arc_ports_clone, snapshot_number).await;
+        }));
+        handles.push(handle);
+    }
+
+    if !disabled_sources.contains(&"tservers") {
+        let arc_hosts_clone = arc_hosts.clone();
+        let arc_ports_clone = arc_ports.clone();
+        let handle = tokio::spawn(ManifestSource::timed("tservers", async move {
+            tservers::AllStoredTabletServers::perform_snapshot(&arc_hosts_clone, &arc_ports_clone, snapshot_number).await;
+        }));
+        handles.push(handle);
+    }
+
+    if !disabled_sources.contains(&"vars") {
+        let arc_hosts_clone = arc_hosts.clone();
+        let arc_ports_clone = arc_ports.clone();
+        let handle = tokio::spawn(ManifestSource::timed("vars", async move {
+            vars::AllStoredVars::perform_snapshot(&arc_hosts_clone, &arc_ports_clone, snapshot_number).await;
+        }));
+        handles.push(handle);
+    }
+
+    if !disabled_sources.contains(&"versions") {
+        let arc_hosts_clone = arc_hosts.clone();
+        let arc_ports_clone = arc_ports.clone();
+        let handle = tokio::spawn(ManifestSource::timed("versions", async move {
+            versions::AllStoredVersions::perform_snapshot(&arc_hosts_clone, &arc_ports_clone, snapshot_number).await;
+        }));
+        handles.push(handle);
+    }
+
+    if !disabled_sources.contains(&"gflags") {
+        let arc_hosts_clone = arc_hosts.clone();
+        let arc_ports_clone = arc_ports.clone();
+        let handle = tokio::spawn(ManifestSource::timed("gflags", async move {
+            gflags::perform_gflags_snapshot(&arc_hosts_clone, &arc_ports_clone, snapshot_number).await;
+        }));
+        handles.push(handle);
+    }
+
+    if !disabled_sources.contains(&"threads") {
+        let arc_hosts_clone = arc_hosts.clone();
+        let arc_ports_clone = arc_ports.clone();
+        let handle = tokio::spawn(ManifestSource::timed("threads", async move {
+            threads::perform_threads_snapshot(&arc_hosts_clone, &arc_ports_clone, snapshot_number).await;
+        }));
+        handles.push(handle);
+    }
+
+    if !disabled_sources.contains(&"memtrackers") {
+        let arc_hosts_clone = arc_hosts.clone();
+        let arc_ports_clone = arc_ports.clone();
+        let handle = tokio::spawn(ManifestSource::timed("memtrackers", async move {
+            memtrackers::perform_memtrackers_snapshot(&arc_hosts_clone, &arc_ports_clone, snapshot_number).await;
+        }));
+        handles.push(handle);
+    }
+
+    if !disabled_sources.contains(&"loglines") {
+        let arc_hosts_clone = arc_hosts.clone();
+        let arc_ports_clone = arc_ports.clone();
+        let handle = tokio::spawn(ManifestSource::timed("loglines", async move {
+            loglines::perform_loglines_snapshot(&arc_hosts_clone, &arc_ports_clone, snapshot_number).await;
+        }));
+        handles.push(handle);
+    }
+
+    if !disabled_sources.contains(&"rpcs") {
+        let arc_hosts_clone = arc_hosts.clone();
+        let arc_ports_clone = arc_ports.clone();
+        let handle = tokio::spawn(ManifestSource::timed("rpcs", async move {
+            rpcs::perform_rpcs_snapshot(&arc_hosts_clone, &arc_ports_clone, snapshot_number).await;
+        }));
+        handles.push(handle);
+    }
+
+    if !disabled_sources.contains(&"pprof") {
+        let arc_hosts_clone = arc_hosts.clone();
+        let arc_ports_clone = arc_ports.clone();
+        let handle = tokio::spawn(ManifestSource::timed("pprof", async move {
+            pprof::perform_pprof_snapshot(&arc_hosts_clone, &arc_ports_clone, snapshot_number).await;
+        }));
+        handles.push(handle);
+    }
+
+    if !disabled_sources.contains(&"mems") {
+        let arc_hosts_clone = arc_hosts.clone();
+        let arc_ports_clone = arc_ports.clone();
+        let handle = tokio::spawn(ManifestSource::timed("mems", async move {
+            mems::perform_mems_snapshot(&arc_hosts_clone, &arc_ports_clone, snapshot_number).await;
+        }));
+        handles.push(handle);
+    }
+
+    let mut sources = disabled_sources.iter().map(|source| ManifestSource::disabled(source)).collect::<Vec<ManifestSource>>();
     for handle in handles {
-        handle.await.unwrap();
+        sources.push(handle.await.unwrap());
     }
 
+    // The cluster UUID is read before the collection errors are saved, so its errors are in this snapshot too.
+    let cluster_uuid = manifest::SnapshotManifest::read_cluster_uuid(endpoints).await;
+    collection_errors::AllStoredCollectionErrors::perform_snapshot(snapshot_number);
+    manifest::SnapshotManifest::perform_snapshot(snapshot_number, cluster.clone(), cluster_uuid, start_time, endpoints, sources).await;
+
+    // The snapshot is added to the snapshot index when all data is collected.
+    interval::complete_current_snapshot(|| snapshot::Snapshot::insert_snapshot(snapshot_number, start_time, snapshot_comment.unwrap_or_default(), cluster.unwrap_or_default()))
+        .unwrap_or_else(|e| {
+            error!("Fatal: error adding snapshot {} to the snapshot index: {}, the data of the snapshot is kept in {}", snapshot_number, e, snapshot::snapshot_directory().join(snapshot_number.to_string()).display());
+            process::exit(1);
+        });
+
     info!("end snapshot: {:?}", timer.elapsed());
-    snapshot_number
 }
\ No newline at end of file
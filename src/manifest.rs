@@ -0,0 +1,338 @@
+//! The module for the snapshot manifest: the metadata of the collection of a snapshot.
+//!
+//! The manifest is stored as JSON in the file `manifest` in every snapshot directory, and contains:
+//! - the yb_stats version, the format version of the snapshot files (see [crate::schema]) and the cluster profile name.
+//! - the cluster UUID, which is read from /api/v1/cluster-config of a master.
+//! - the endpoints (hostname:port and role) that are used.
+//! - the sources, whether these were enabled, and the start time, end time and duration of each source.
+//! - the files in the snapshot directory with their size, and the row count for the CSV files.
+//!
+//! The manifest is shown with `--snapshot-list --details-enable`, and the diff reports warn if the begin and end snapshot were collected from different endpoints: [warn_different_endpoints].
+//! A source that is disabled in the begin or end snapshot, for example in a light snapshot of the interval mode (see [crate::interval]), is skipped by the diff report: [source_in_snapshots].
+//! Snapshots taken with older versions of yb_stats have no manifest: then nothing is shown and checked.
+use chrono::{DateTime, Local};
+use std::{fs, error::Error, future::Future, process, collections::BTreeSet, time::Instant};
+use serde_derive::{Serialize,Deserialize};
+use log::*;
+use crate::snapshot::snapshot_directory;
+use crate::endpoints::{AllStoredEndpoints, has_role};
+use crate::utility::http_get;
+use crate::compression::{create_snapshot_file, open_snapshot_file, strip_compression_extension};
+use crate::schema::{SNAPSHOT_FORMAT_VERSION, first_format_version};
+use crate::storage::{database_files, is_csv_file};
+use crate::output;
+
+/// The names of the sources of a snapshot.
+pub const SOURCES: &[&str] = &["metrics", "statements", "node_exporter", "isleader", "entities", "masters", "tservers", "vars", "versions", "gflags", "threads", "memtrackers", "loglines", "rpcs", "pprof", "mems"];
+
+/// The endpoint roles that provide /api/v1/cluster-config.
+const ROLES: &[&str] = &["master"];
+
+/// The struct that is used to store and retrieve the manifest of a snapshot in JSON using serde.
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+pub struct SnapshotManifest {
+    pub yb_stats_version: String,
+    /// The format version of the snapshot files, see [crate::schema].
+    #[serde(default = "first_format_version")]
+    pub format_version: u32,
+    pub snapshot_number: i32,
+    /// The name of the cluster profile, or empty if no profile is used.
+    pub cluster: String,
+    /// The cluster UUID, or empty if it could not be read.
+    pub cluster_uuid: String,
+    pub start_time: DateTime<Local>,
+    pub end_time: DateTime<Local>,
+    pub duration_ms: u128,
+    pub endpoints: Vec<ManifestEndpoint>,
+    pub sources: Vec<ManifestSource>,
+    pub files: Vec<ManifestFile>,
+}
+
+/// An endpoint that is used for the snapshot.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct ManifestEndpoint {
+    pub hostname_port: String,
+    pub role: String,
+}
+
+/// A source of the snapshot, such as metrics or threads. A disabled source has no times.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ManifestSource {
+    pub name: String,
+    pub enabled: bool,
+    pub start_time: Option<DateTime<Local>>,
+    pub end_time: Option<DateTime<Local>>,
+    pub duration_ms: Option<u128>,
+}
+
+/// A file in the snapshot directory. The rows are the number of CSV records, and are not set for the files that are not CSV.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ManifestFile {
+    pub name: String,
+    pub bytes: u64,
+    pub rows: Option<u64>,
+}
+
+impl ManifestSource {
+    /// This function performs a source of a snapshot, and returns the source with the times.
+    pub async fn timed<F: Future<Output = ()>>(
+        name: &str,
+        source: F,
+    ) -> ManifestSource
+    {
+        let start_time = Local::now();
+        let timer = Instant::now();
+        source.await;
+        ManifestSource {
+            name: name.to_string(),
+            enabled: true,
+            start_time: Some(start_time),
+            end_time: Some(Local::now()),
+            duration_ms: Some(timer.elapsed().as_millis()),
+        }
+    }
+    /// This function returns a source that is disabled, for example threads with `--disable-threads`.
+    pub fn disabled(
+        name: &str,
+    ) -> ManifestSource
+    {
+        ManifestSource {
+            name: name.to_string(),
+            enabled: false,
+            start_time: None,
+            end_time: None,
+            duration_ms: None,
+        }
+    }
+}
+
+impl SnapshotManifest {
+    /// This function creates and saves the manifest of the snapshot indicated by the snapshot_number.
+    /// It must be called after all the sources and the collection errors are saved, because the row counts are taken from the files in the snapshot directory.
+    /// Therefore, the cluster UUID is read before with [SnapshotManifest::read_cluster_uuid], so that its collection errors are saved in the snapshot too.
+    pub async fn perform_snapshot(
+        snapshot_number: i32,
+        cluster: Option<String>,
+        cluster_uuid: String,
+        start_time: DateTime<Local>,
+        endpoints: &AllStoredEndpoints,
+        sources: Vec<ManifestSource>,
+    )
+    {
+        info!("begin manifest");
+        let mut snapshotmanifest = SnapshotManifest {
+            yb_stats_version: env!("CARGO_PKG_VERSION").to_string(),
+            format_version: SNAPSHOT_FORMAT_VERSION,
+            snapshot_number,
+            cluster: cluster.unwrap_or_default(),
+            cluster_uuid,
+            start_time,
+            endpoints: endpoints.stored_endpoints.iter()
+                .map(|endpoint| ManifestEndpoint { hostname_port: endpoint.hostname_port.to_string(), role: endpoint.role.to_string() })
+                .collect(),
+            sources,
+            ..Default::default()
+        };
+        snapshotmanifest.files = SnapshotManifest::read_files(snapshot_number)
+            .unwrap_or_else(|e| {
+                error!("error reading snapshot files: {}", e);
+                process::exit(1);
+            });
+        snapshotmanifest.end_time = Local::now();
+        snapshotmanifest.duration_ms = (snapshotmanifest.end_time - start_time).num_milliseconds().max(0) as u128;
+        snapshotmanifest.save_snapshot(snapshot_number)
+            .unwrap_or_else(|e| {
+                error!("error saving snapshot: {}", e);
+                process::exit(1);
+            });
+    }
+    /// This function reads the cluster UUID from the masters one by one, and returns the first one found, or empty if none is found.
+    pub async fn read_cluster_uuid(
+        endpoints: &AllStoredEndpoints,
+    ) -> String
+    {
+        for endpoint in endpoints.stored_endpoints.iter().filter(|endpoint| has_role(&endpoint.host, &endpoint.port, ROLES)) {
+            let cluster_config = http_get(&endpoint.host, &endpoint.port, "api/v1/cluster-config").await;
+            if let Some(cluster_uuid) = SnapshotManifest::parse_cluster_uuid(&cluster_config) {
+                return cluster_uuid;
+            }
+        }
+        String::new()
+    }
+    /// This function parses the cluster UUID from the JSON of /api/v1/cluster-config.
+    fn parse_cluster_uuid(
+        cluster_config: &str,
+    ) -> Option<String>
+    {
+        serde_json::from_str::<serde_json::Value>(cluster_config).ok()?
+            .get("cluster_uuid")?
+            .as_str()
+            .map(|cluster_uuid| cluster_uuid.to_string())
+    }
+    /// This function lists the files in the snapshot directory and in the database, with the row count for the CSV files.
+    fn read_files(
+        snapshot_number: i32,
+    ) -> Result<Vec<ManifestFile>, Box<dyn Error>>
+    {
+        let current_snapshot_directory = snapshot_directory().join(snapshot_number.to_string());
+
+        let mut files = Vec::new();
+        for entry in fs::read_dir(current_snapshot_directory)? {
+            let entry = entry?;
+            let name = entry.file_name().to_string_lossy().to_string();
+            if strip_compression_extension(&name) == "manifest" {
+                continue;
+            }
+            let rows = if is_csv_file(&name) {
+                let mut reader = csv::Reader::from_reader(open_snapshot_file(&entry.path())?);
+                Some(reader.records().count() as u64)
+            } else {
+                None
+            };
+            files.push(ManifestFile { name, bytes: entry.metadata()?.len(), rows });
+        }
+        // With the sqlite storage, the files are in the database.
+        for file in database_files(snapshot_number)?.into_iter().filter(|file| file.name != "manifest") {
+            files.push(ManifestFile { name: file.name, bytes: file.bytes, rows: file.rows });
+        }
+        files.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(files)
+    }
+    /// This function saves the manifest as JSON in the snapshot directory indicated by the snapshot number.
+    pub fn save_snapshot(
+        &self,
+        snapshot_number: i32,
+    ) -> Result<(), Box<dyn Error>>
+    {
+        let current_snapshot_directory = snapshot_directory().join(snapshot_number.to_string());
+
+        let manifest_file = &current_snapshot_directory.join("manifest");
+        let mut file = create_snapshot_file(manifest_file)?;
+        serde_json::to_writer_pretty(&mut file, self)?;
+        file.finish()?;
+
+        Ok(())
+    }
+    /// This function takes a snapshot number and reads the manifest JSON.
+    pub fn read_snapshot(
+        snapshot_number: &String,
+    ) -> Result<SnapshotManifest, Box<dyn Error>>
+    {
+        let current_snapshot_directory = snapshot_directory().join(snapshot_number);
+
+        let manifest_file = &current_snapshot_directory.join("manifest");
+        let file = open_snapshot_file(manifest_file)?;
+
+        Ok(serde_json::from_reader(file)?)
+    }
+    /// This function prints the manifest.
+    pub fn print(&self) {
+        println!("      yb_stats version: {}, format version: {}, cluster uuid: {}, duration: {} ms", self.yb_stats_version, self.format_version, self.cluster_uuid, self.duration_ms);
+        println!("      endpoints: {}", self.endpoints.iter().map(|endpoint| format!("{} ({})", endpoint.hostname_port, endpoint.role)).collect::<Vec<_>>().join(", "));
+        for source in &self.sources {
+            match (source.enabled, source.start_time, source.duration_ms) {
+                (true, Some(start_time), Some(duration_ms)) => println!("      source {:20} {:30} {:>10} ms", source.name, start_time, duration_ms),
+                _ => println!("      source {:20} disabled", source.name),
+            }
+        }
+        for file in &self.files {
+            match file.rows {
+                Some(rows) => println!("      file   {:30} {:>12} bytes {:>10} rows", file.name, file.bytes, rows),
+                None => println!("      file   {:30} {:>12} bytes", file.name, file.bytes),
+            }
+        }
+    }
+    /// The set of hostname:port of the endpoints.
+    fn endpoint_set(&self) -> BTreeSet<String> {
+        self.endpoints.iter().map(|endpoint| endpoint.hostname_port.to_string()).collect()
+    }
+}
+
+/// This function prints a warning if the begin and end snapshot are collected from different endpoints, which makes the differences unreliable.
+/// If either snapshot has no manifest, nothing is checked.
+pub fn warn_different_endpoints(
+    begin_snapshot: &String,
+    end_snapshot: &String,
+)
+{
+    if let (Ok(begin_manifest), Ok(end_manifest)) = (SnapshotManifest::read_snapshot(begin_snapshot), SnapshotManifest::read_snapshot(end_snapshot)) {
+        let (only_begin, only_end) = different_endpoints(&begin_manifest, &end_manifest);
+        if !only_begin.is_empty() || !only_end.is_empty() {
+            output::print_message(&format!("Warning: snapshot {} and {} are collected from different endpoints.", begin_snapshot, end_snapshot));
+            if !only_begin.is_empty() {
+                output::print_message(&format!("Warning: only in begin snapshot {}: {}", begin_snapshot, only_begin.join(", ")));
+            }
+            if !only_end.is_empty() {
+                output::print_message(&format!("Warning: only in end snapshot {}: {}", end_snapshot, only_end.join(", ")));
+            }
+        }
+    }
+}
+
+/// This function tells if the source is collected in the begin and the end snapshot, and prints a message if it is not, because then the source cannot be compared.
+/// A snapshot without a manifest, or with a manifest without the source, has all sources.
+pub fn source_in_snapshots(
+    begin_snapshot: &String,
+    end_snapshot: &String,
+    source: &str,
+) -> bool
+{
+    let disabled = [begin_snapshot, end_snapshot].into_iter()
+        .filter(|snapshot| SnapshotManifest::read_snapshot(snapshot)
+            .map(|snapshotmanifest| snapshotmanifest.sources.iter().any(|manifest_source| manifest_source.name == source && !manifest_source.enabled))
+            .unwrap_or(false))
+        .map(|snapshot| snapshot.to_string())
+        .collect::<Vec<String>>();
+    if !disabled.is_empty() {
+        output::print_message(&format!("Source {} is not collected in snapshot {}, it is skipped.", source, disabled.join(" and ")));
+    }
+    disabled.is_empty()
+}
+
+/// The endpoints that are only in the begin manifest, and the endpoints that are only in the end manifest.
+fn different_endpoints(
+    begin_manifest: &SnapshotManifest,
+    end_manifest: &SnapshotManifest,
+) -> (Vec<String>, Vec<String>)
+{
+    let begin_endpoints = begin_manifest.endpoint_set();
+    let end_endpoints = end_manifest.endpoint_set();
+    (
+        begin_endpoints.difference(&end_endpoints).cloned().collect(),
+        end_endpoints.difference(&begin_endpoints).cloned().collect(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn manifest_with_endpoints(endpoints: &[&str]) -> SnapshotManifest {
+        SnapshotManifest {
+            endpoints: endpoints.iter().map(|hostname_port| ManifestEndpoint { hostname_port: hostname_port.to_string(), role: "tserver".to_string() }).collect(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn unit_parse_cluster_uuid() {
+        let cluster_config = r#"{"version":3,"replication_info":{"live_replicas":{"num_replicas":3}},"cluster_uuid":"7d3e1b5a-8a4c-4b5e-9d4b-1b9a4d6e2f10"}"#;
+        assert_eq!(SnapshotManifest::parse_cluster_uuid(cluster_config), Some("7d3e1b5a-8a4c-4b5e-9d4b-1b9a4d6e2f10".to_string()));
+        assert_eq!(SnapshotManifest::parse_cluster_uuid(""), None);
+        assert_eq!(SnapshotManifest::parse_cluster_uuid(r#"{"version":3}"#), None);
+    }
+    #[test]
+    fn unit_different_endpoints() {
+        let begin = manifest_with_endpoints(&["h1:9000", "h2:9000"]);
+        let end = manifest_with_endpoints(&["h2:9000", "h3:9000"]);
+        assert_eq!(different_endpoints(&begin, &end), (vec!["h1:9000".to_string()], vec!["h3:9000".to_string()]));
+        assert_eq!(different_endpoints(&begin, &begin), (vec![], vec![]));
+    }
+    #[tokio::test]
+    async fn unit_timed_source() {
+        let source = ManifestSource::timed("metrics", async {}).await;
+        assert!(source.enabled);
+        assert!(source.start_time.unwrap() <= source.end_time.unwrap());
+        assert!(!ManifestSource::disabled("threads").enabled);
+    }
+}
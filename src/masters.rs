@@ -1,11 +1,20 @@
 use serde_derive::{Serialize,Deserialize};
 use chrono::{DateTime, Local};
-use std::{fs, process, sync::mpsc::channel, time::Instant, env, error::Error};
+use std::{process, time::Instant, error::Error};
 use std::collections::BTreeMap;
 use log::*;
+use crate::snapshot::snapshot_directory;
+use futures::future::join_all;
 use colored::*;
 use crate::isleader::AllStoredIsLeader;
-use crate::utility::{scan_host_port, http_get};
+use crate::utility::http_get;
+use crate::endpoints::has_role;
+use crate::compression::create_snapshot_file;
+use crate::schema::open_snapshot_csv;
+use crate::output::{self, DiffRecord, push_changed_attribute};
+
+/// The endpoint roles that provide /api/v1/masters.
+pub const ROLES: &[&str] = &["master"];
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct AllMasters {
@@ -14,9 +23,9 @@ pub struct AllMasters {
 
 #[derive(Default)]
 pub struct AllStoredMasters {
-    stored_masters: Vec<StoredMasters>,
+    pub stored_masters: Vec<StoredMasters>,
     stored_rpc_addresses: Vec<StoredRpcAddresses>,
-    stored_http_addresses: Vec<StoredHttpAddresses>,
+    pub stored_http_addresses: Vec<StoredHttpAddresses>,
     stored_master_error: Vec<StoredMasterError>,
 }
 
@@ -25,12 +34,11 @@ impl AllStoredMasters {
         hosts: &Vec<&str>,
         ports: &Vec<&str>,
         snapshot_number: i32,
-        parallel: usize,
     ) {
         info!("begin snapshot");
         let timer = Instant::now();
 
-        let allmasters = AllStoredMasters::read_masters(hosts, ports, parallel);
+        let allmasters = AllStoredMasters::read_masters(hosts, ports);
         allmasters.await.save_snapshot(snapshot_number)
             .unwrap_or_else(|e| {
                 error!("error saving snapshot: {}", e);
@@ -45,32 +53,32 @@ impl AllStoredMasters {
     pub async fn read_masters(
         hosts: &Vec<&str>,
         ports: &Vec<&str>,
-        parallel: usize,
     ) -> AllStoredMasters
     {
         info!("begin parallel http read");
         let timer = Instant::now();
 
-        let pool = rayon::ThreadPoolBuilder::new().num_threads(parallel).build().unwrap();
-        let (tx, rx) = channel();
-        pool.scope(move |s| {
-            for host in hosts {
-                for port in ports {
-                    let tx = tx.clone();
-                    s.spawn(move |_| {
-                        let detail_snapshot_time = Local::now();
-                        let masters = AllStoredMasters::read_http(host, port);
-                        tx.send((format!("{}:{}", host, port), detail_snapshot_time, masters)).expect("error sending data via tx (masters)");
-                    });
+        let mut handles = Vec::new();
+        for host in hosts {
+            for port in ports {
+                if !has_role(host, port, ROLES) {
+                    continue;
                 }
+                let handle = async move {
+                    let detail_snapshot_time = Local::now();
+                    let masters = AllStoredMasters::read_http(host, port).await;
+                    (format!("{}:{}", host, port), detail_snapshot_time, masters)
+                };
+                handles.push(handle);
             }
-        });
+        }
+        let results = join_all(handles).await;
 
         info!("end parallel http read {:?}", timer.elapsed());
 
         let mut allstoredmasters = AllStoredMasters::new();
 
-        for (hostname_port, detail_snapshot_time, masters) in rx {
+        for (hostname_port, detail_snapshot_time, masters) in results {
             allstoredmasters.split_into_vectors(masters, &hostname_port, detail_snapshot_time);
         }
 
@@ -139,16 +147,12 @@ impl AllStoredMasters {
             };
         }
     }
-    pub fn read_http(
+    pub async fn read_http(
         host: &str,
         port: &str,
     ) -> AllMasters
     {
-        let data_from_http = if scan_host_port( host, port) {
-            http_get(host, port, "api/v1/masters")
-        } else {
-            String::new()
-        };
+        let data_from_http = http_get(host, port, "api/v1/masters").await;
         AllStoredMasters::parse_masters(data_from_http, host, port)
         /*
         if ! scan_port_addr(format!("{}:{}", host, port)) {
@@ -187,52 +191,39 @@ impl AllStoredMasters {
     }
     fn save_snapshot ( self, snapshot_number: i32 ) -> Result<(), Box<dyn Error>>
     {
-        let current_directory = env::current_dir()?;
-        let current_snapshot_directory = current_directory.join("yb_stats.snapshots").join(&snapshot_number.to_string());
+        let current_snapshot_directory = snapshot_directory().join(snapshot_number.to_string());
 
         let masters_file = &current_snapshot_directory.join("masters");
-        let file = fs::OpenOptions::new()
-            .create(true)
-            .write(true)
-            .open(masters_file)?;
+        let file = create_snapshot_file(masters_file)?;
         let mut writer = csv::Writer::from_writer(file);
         for row in self.stored_masters {
             writer.serialize(row)?;
         }
-        writer.flush()?;
+        writer.into_inner()?.finish()?;
 
         let master_rpc_addresses_file = &current_snapshot_directory.join("master_rpc_addresses");
-        let file = fs::OpenOptions::new()
-            .create(true)
-            .write(true)
-            .open(master_rpc_addresses_file)?;
+        let file = create_snapshot_file(master_rpc_addresses_file)?;
         let mut writer = csv::Writer::from_writer(file);
         for row in self.stored_rpc_addresses {
             writer.serialize(row)?;
         }
-        writer.flush()?;
+        writer.into_inner()?.finish()?;
 
         let master_http_addresses_file = &current_snapshot_directory.join("master_http_addresses");
-        let file = fs::OpenOptions::new()
-            .create(true)
-            .write(true)
-            .open(master_http_addresses_file)?;
+        let file = create_snapshot_file(master_http_addresses_file)?;
         let mut writer = csv::Writer::from_writer(file);
         for row in self.stored_http_addresses {
             writer.serialize(row)?;
         }
-        writer.flush()?;
+        writer.into_inner()?.finish()?;
 
         let master_errors_file = &current_snapshot_directory.join("master_errors");
-        let file = fs::OpenOptions::new()
-            .create(true)
-            .write(true)
-            .open(master_errors_file)?;
+        let file = create_snapshot_file(master_errors_file)?;
         let mut writer = csv::Writer::from_writer(file);
         for row in self.stored_master_error {
             writer.serialize(row)?;
         }
-        writer.flush()?;
+        writer.into_inner()?.finish()?;
 
         Ok(())
     }
@@ -245,11 +236,10 @@ impl AllStoredMasters {
             stored_master_error: Vec::new(),
         };
 
-        let current_directory = env::current_dir()?;
-        let current_snapshot_directory = current_directory.join("yb_stats.snapshots").join(snapshot_number);
+        let current_snapshot_directory = snapshot_directory().join(snapshot_number);
 
         let masters_file = &current_snapshot_directory.join("masters");
-        let file = fs::File::open(masters_file)?;
+        let file = open_snapshot_csv(masters_file)?;
 
         let mut reader = csv::Reader::from_reader(file);
         for row in reader.deserialize() {
@@ -258,7 +248,7 @@ impl AllStoredMasters {
         };
 
         let masters_rpc_addresses_file = &current_snapshot_directory.join("master_rpc_addresses");
-        let file = fs::File::open(masters_rpc_addresses_file)?;
+        let file = open_snapshot_csv(masters_rpc_addresses_file)?;
 
         let mut reader = csv::Reader::from_reader(file);
         for row in reader.deserialize() {
@@ -267,7 +257,7 @@ impl AllStoredMasters {
         };
 
         let masters_http_addresses_file = &current_snapshot_directory.join("master_http_addresses");
-        let file = fs::File::open(masters_http_addresses_file)?;
+        let file = open_snapshot_csv(masters_http_addresses_file)?;
 
         let mut reader = csv::Reader::from_reader(file);
         for row in reader.deserialize() {
@@ -276,7 +266,7 @@ impl AllStoredMasters {
         };
 
         let masters_error_file = &current_snapshot_directory.join("master_errors");
-        let file = fs::File::open(masters_error_file)?;
+        let file = open_snapshot_csv(masters_error_file)?;
 
         let mut reader = csv::Reader::from_reader(file);
         for row in reader.deserialize() {
@@ -351,12 +341,11 @@ impl AllStoredMasters {
         details_enable: &bool,
         hosts: &Vec<&str>,
         ports: &Vec<&str>,
-        parallel: usize,
     )
     {
         info!("print adhoc masters");
 
-        let leader_hostname = AllStoredIsLeader::return_leader_http(hosts, ports, parallel).await;
+        let leader_hostname = AllStoredIsLeader::return_leader_http(hosts, ports).await;
 
         for row in &self.stored_masters {
             if row.hostname_port == leader_hostname
@@ -633,12 +622,62 @@ impl SnapshotDiffBTreeMapsMasters {
             }
         }
     }
+    /// This function returns the changes that are printed by [SnapshotDiffBTreeMapsMasters::print] as records for the machine-readable output, see [crate::output].
+    /// The hostname_port of a record is the http address of the master, and the metric_id is the permanent uuid.
+    pub fn records(
+        &self,
+    ) -> Vec<DiffRecord>
+    {
+        let mut records = Vec::new();
+        if ! self.master_found {
+            return records;
+        }
+        for (permanent_uuid, row) in self.btreemap_snapshotdiff_masters.iter() {
+            let http_addresses = if row.second_instance_seqno == 0 { &self.first_http_addresses } else { &self.second_http_addresses };
+            let hostname_port = http_addresses.iter()
+                .filter(|r| r.permanent_uuid == *permanent_uuid)
+                .map(|r| r.hostname_port.to_string())
+                .collect::<Vec<String>>()
+                .join(" ");
+            let record = DiffRecord {
+                hostname_port,
+                metric_type: String::from("master"),
+                metric_id: permanent_uuid.to_string(),
+                name: permanent_uuid.to_string(),
+                ..Default::default()
+            };
+            if row.second_instance_seqno == 0 {
+                records.push(DiffRecord {
+                    first: format!("{} Cloud: {}, Region: {}, Zone: {}, Seqno: {}, Start time: {}", row.first_role, row.first_registration_cloud_placement_cloud, row.first_registration_cloud_placement_region, row.first_registration_cloud_placement_zone, row.first_instance_seqno, row.first_start_time_us),
+                    ..DiffRecord::object("masters", "-", record)
+                });
+            } else if row.first_instance_seqno == 0 {
+                records.push(DiffRecord {
+                    second: format!("{} Cloud: {}, Region: {}, Zone: {}, Seqno: {}, Start time: {}", row.second_role, row.second_registration_cloud_placement_cloud, row.second_registration_cloud_placement_region, row.second_registration_cloud_placement_zone, row.second_instance_seqno, row.second_start_time_us),
+                    ..DiffRecord::object("masters", "+", record)
+                });
+            } else {
+                let record = DiffRecord::object("masters", "*", record);
+                push_changed_attribute(&mut records, &record, "role", &row.first_role, &row.second_role);
+                push_changed_attribute(&mut records, &record, "cloud", &row.first_registration_cloud_placement_cloud, &row.second_registration_cloud_placement_cloud);
+                push_changed_attribute(&mut records, &record, "region", &row.first_registration_cloud_placement_region, &row.second_registration_cloud_placement_region);
+                push_changed_attribute(&mut records, &record, "zone", &row.first_registration_cloud_placement_zone, &row.second_registration_cloud_placement_zone);
+                push_changed_attribute(&mut records, &record, "seqno", &row.first_instance_seqno.to_string(), &row.second_instance_seqno.to_string());
+                push_changed_attribute(&mut records, &record, "start_time", &row.first_start_time_us.to_string(), &row.second_start_time_us.to_string());
+            }
+        }
+        records
+    }
     pub fn print(
         &self,
     )
     {
         if ! self.master_found {
-            println!("Master leader was not found in hosts specified, skipping masters diff.");
+            output::print_message("Master leader was not found in hosts specified, skipping masters diff.");
+            return;
+        }
+        if output::is_machine_readable() {
+            output::write_records(&self.records());
             return;
         }
         for (permanent_uuid, row) in self.btreemap_snapshotdiff_masters.iter() {
@@ -721,22 +760,20 @@ impl SnapshotDiffBTreeMapsMasters {
         &mut self,
         hosts: &Vec<&str>,
         ports: &Vec<&str>,
-        parallel: usize,
     )
     {
-        let allstoredmasters = AllStoredMasters::read_masters(hosts, ports, parallel).await;
-        let master_leader= AllStoredIsLeader::return_leader_http(hosts, ports, parallel).await;
+        let allstoredmasters = AllStoredMasters::read_masters(hosts, ports).await;
+        let master_leader= AllStoredIsLeader::return_leader_http(hosts, ports).await;
         self.first_snapshot(allstoredmasters, master_leader);
     }
     pub async fn adhoc_read_second_snapshot(
         &mut self,
         hosts: &Vec<&str>,
         ports: &Vec<&str>,
-        parallel: usize,
     )
     {
-        let allstoredmasters = AllStoredMasters::read_masters(hosts, ports, parallel).await;
-        let master_leader= AllStoredIsLeader::return_leader_http(hosts, ports, parallel).await;
+        let allstoredmasters = AllStoredMasters::read_masters(hosts, ports).await;
+        let master_leader= AllStoredIsLeader::return_leader_http(hosts, ports).await;
         self.second_snapshot(allstoredmasters, master_leader);
     }
 }
@@ -956,13 +993,13 @@ mod tests {
 
     use crate::utility;
 
-    #[test]
-    fn integration_parse_masters() {
+    #[tokio::test]
+    async fn integration_parse_masters() {
         let mut allstoredmasters = AllStoredMasters::new();
         let hostname = utility::get_hostname_master();
         let port = utility::get_port_master();
 
-        let data_parsed_from_json = AllStoredMasters::read_http(hostname.as_ref(), port.as_ref());
+        let data_parsed_from_json = AllStoredMasters::read_http(hostname.as_ref(), port.as_ref()).await;
         allstoredmasters.split_into_vectors(data_parsed_from_json, format!("{}:{}", &hostname, &port).as_ref(), Local::now());
 
         // a MASTER only will generate entities on each master (!)
@@ -1,21 +1,21 @@
-use std::path::PathBuf;
-use std::fs;
 use std::io::Write;
 use std::process;
-use std::sync::mpsc::channel;
 use log::*;
-use crate::utility::{scan_host_port, http_get};
+use futures::future::join_all;
+use crate::utility::http_get;
+use crate::endpoints::has_role;
+use crate::compression::create_snapshot_file;
+use crate::snapshot::snapshot_directory;
 
-pub fn read_mems(
+/// The endpoint roles that provide /memz.
+pub const ROLES: &[&str] = &["master", "tserver"];
+
+pub async fn read_mems(
     host: &str,
     port: &str,
 ) -> String
 {
-    if scan_host_port( host, port) {
-        http_get(host, port, "memz?raw=true")
-    } else {
-        String::new()
-    }
+    http_get(host, port, "memz?raw=true").await
     /*
     if ! scan_port_addr( format!("{}:{}", host, port)) {
         warn!("hostname:port {}:{} cannot be reached, skipping (mems)",host ,port);
@@ -44,37 +44,38 @@ pub async fn perform_mems_snapshot(
     hosts: &Vec<&str>,
     ports: &Vec<&str>,
     snapshot_number: i32,
-    yb_stats_directory: &PathBuf,
-    parallel: usize
 ) {
     info!("perform_mems_snapshot");
-    let pool = rayon::ThreadPoolBuilder::new().num_threads(parallel).build().unwrap();
-    let (tx, rx) = channel();
-    pool.scope(move |s| {
-        for host in hosts {
-            for port in ports {
-                let tx = tx.clone();
-                s.spawn(move |_| {
-                    let mems_data = read_mems(host, port);
-                    tx.send((format!("{}:{}", host, port), mems_data)).expect("error sending data via tx (mems)");
-                });
-            }}
-    });
-    for (hostname_port, mems) in rx {
+    let mut handles = Vec::new();
+    for host in hosts {
+        for port in ports {
+            if !has_role(host, port, ROLES) {
+                continue;
+            }
+            let handle = async move {
+                let mems_data = read_mems(host, port).await;
+                (format!("{}:{}", host, port), mems_data)
+            };
+            handles.push(handle);
+        }
+    }
+    let results = join_all(handles).await;
+    for (hostname_port, mems) in results {
 
         if mems.starts_with("------------------------------------------------") {
-            let current_snapshot_directory = &yb_stats_directory.join(&snapshot_number.to_string());
+            let current_snapshot_directory = &snapshot_directory().join(&snapshot_number.to_string());
             let mems_file = &current_snapshot_directory.join(format!("mems_{}", hostname_port));
-            let mut file = fs::OpenOptions::new()
-                .create(true)
-                .write(true)
-                .open(mems_file)
+            let mut file = create_snapshot_file(mems_file)
                 .unwrap_or_else(|e| {
                     error!("Fatal: error writing mems data in snapshot directory {}: {}", &mems_file.clone().into_os_string().into_string().unwrap(), e);
                     process::exit(1);
                 });
             file.write_all(mems.as_bytes()).unwrap_or_else(|e| {
-                error!("Fatal: error mems data in snapshot directory {:?}: {}", &file, e);
+                error!("Fatal: error writing mems data in snapshot directory {}: {}", &mems_file.clone().into_os_string().into_string().unwrap(), e);
+                process::exit(1);
+            });
+            file.finish().unwrap_or_else(|e| {
+                error!("Fatal: error writing mems data in snapshot directory {}: {}", &mems_file.clone().into_os_string().into_string().unwrap(), e);
                 process::exit(1);
             });
         };
@@ -87,20 +88,20 @@ mod tests {
 
     use crate::utility;
 
-    #[test]
-    fn parse_mems_tserver() {
+    #[tokio::test]
+    async fn parse_mems_tserver() {
         // currently, the mems "parsing" is not much parsing.
         // What currently is done, is that the hostname:port/memz output is stored in a file in the snapshot directory named <hostname>:<port>_mems.
         let hostname = utility::get_hostname_tserver();
         let port = utility::get_port_tserver();
-        read_mems(&hostname, &port);
+        read_mems(&hostname, &port).await;
     }
-    #[test]
-    fn parse_mems_master() {
+    #[tokio::test]
+    async fn parse_mems_master() {
         // currently, the mems "parsing" is not much parsing.
         // What currently is done, is that the hostname:port/memz output is stored in a file in the snapshot directory named <hostname>:<port>_mems.
         let hostname = utility::get_hostname_master();
         let port = utility::get_port_master();
-        read_mems(&hostname, &port);
+        read_mems(&hostname, &port).await;
     }
 }
\ No newline at end of file
@@ -1,14 +1,19 @@
 use chrono::{DateTime, Local};
-use std::path::PathBuf;
 use regex::Regex;
-use std::fs;
 use std::process;
 use serde_derive::{Serialize,Deserialize};
 //use rayon;
-use std::sync::mpsc::channel;
 use scraper::{ElementRef, Html, Selector};
 use log::*;
-use crate::utility::{scan_host_port, http_get};
+use futures::future::join_all;
+use crate::utility::http_get;
+use crate::endpoints::has_role;
+use crate::compression::create_snapshot_file;
+use crate::schema::open_snapshot_csv;
+use crate::snapshot::snapshot_directory;
+
+/// The endpoint roles that provide /mem-trackers.
+pub const ROLES: &[&str] = &["master", "tserver"];
 
 #[derive(Debug)]
 pub struct MemTrackers {
@@ -29,17 +34,12 @@ pub struct StoredMemTrackers {
 }
 
 #[allow(dead_code)]
-pub fn read_memtrackers(
+pub async fn read_memtrackers(
     host: &str,
     port: &str,
 ) -> Vec<MemTrackers>
 {
-    let data_from_http = if scan_host_port( host, port) {
-        http_get(host, port, "mem-trackers")
-    } else {
-        debug!("hostname port found not available");
-        String::new()
-    };
+    let data_from_http = http_get(host, port, "mem-trackers").await;
     parse_memtrackers(data_from_http)
 
 
@@ -130,36 +130,31 @@ pub async fn perform_memtrackers_snapshot(
     hosts: &Vec<&str>,
     ports: &Vec<&str>,
     snapshot_number: i32,
-    yb_stats_directory: &PathBuf,
-    parallel: usize
 ) {
     info!("perform_memtrackers_snapshot");
-    let pool = rayon::ThreadPoolBuilder::new().num_threads(parallel).build().unwrap();
-    let (tx, rx) = channel();
-
-    pool.scope(move |s| {
-        for host in hosts {
-            for port in ports {
-                let tx = tx.clone();
-                s.spawn(move |_| {
-                    let detail_snapshot_time = Local::now();
-                    let memtrackers = read_memtrackers( host, port);
-                    tx.send((format!("{}:{}", host, port), detail_snapshot_time, memtrackers)).expect("error sending data via tx (memtrackers)");
-                });
+    let mut handles = Vec::new();
+    for host in hosts {
+        for port in ports {
+            if !has_role(host, port, ROLES) {
+                continue;
             }
+            let handle = async move {
+                let detail_snapshot_time = Local::now();
+                let memtrackers = read_memtrackers(host, port).await;
+                (format!("{}:{}", host, port), detail_snapshot_time, memtrackers)
+            };
+            handles.push(handle);
         }
-    });
+    }
+    let results = join_all(handles).await;
     let mut stored_memtrackers: Vec<StoredMemTrackers> = Vec::new();
-    for (hostname_port, detail_snapshot_time, memtrackers) in rx {
+    for (hostname_port, detail_snapshot_time, memtrackers) in results {
         add_to_memtrackers_vector(memtrackers, &hostname_port, detail_snapshot_time, &mut stored_memtrackers);
     }
 
-    let current_snapshot_directory = &yb_stats_directory.join(&snapshot_number.to_string());
+    let current_snapshot_directory = &snapshot_directory().join(&snapshot_number.to_string());
     let memtrackers_file = &current_snapshot_directory.join("memtrackers");
-    let file = fs::OpenOptions::new()
-        .create(true)
-        .write(true)
-        .open(memtrackers_file)
+    let file = create_snapshot_file(memtrackers_file)
         .unwrap_or_else(|e| {
             error!("Fatal: error writing memtrackers data in snapshot directory {}: {}", &memtrackers_file.clone().into_os_string().into_string().unwrap(), e);
             process::exit(1);
@@ -168,19 +163,18 @@ pub async fn perform_memtrackers_snapshot(
     for row in stored_memtrackers {
         writer.serialize(row).unwrap();
     }
-    writer.flush().unwrap();
+    writer.into_inner().unwrap().finish().unwrap();
 }
 
 
 #[allow(dead_code)]
 pub fn print_memtrackers_data(
     snapshot_number: &String,
-    yb_stats_directory: &PathBuf,
     hostname_filter: &Regex,
     stat_name_filter: &Regex
 ) {
     info!("print_memtrackers");
-    let stored_memtrackers: Vec<StoredMemTrackers> = read_memtrackers_snapshot(snapshot_number, yb_stats_directory);
+    let stored_memtrackers: Vec<StoredMemTrackers> = read_memtrackers_snapshot(snapshot_number);
     let mut previous_hostname_port = String::from("");
     for row in stored_memtrackers {
         if hostname_filter.is_match(&row.hostname_port)
@@ -207,11 +201,10 @@ pub fn print_memtrackers_data(
 #[allow(clippy::ptr_arg)]
 fn read_memtrackers_snapshot(
     snapshot_number: &String,
-    yb_stats_directory: &PathBuf
 ) -> Vec<StoredMemTrackers> {
     let mut stored_memtrackers: Vec<StoredMemTrackers> = Vec::new();
-    let memtrackers_file = &yb_stats_directory.join(snapshot_number).join("memtrackers");
-    let file = fs::File::open(memtrackers_file)
+    let memtrackers_file = &snapshot_directory().join(snapshot_number).join("memtrackers");
+    let file = open_snapshot_csv(memtrackers_file)
         .unwrap_or_else(|e| {
             error!("Fatal: error reading file: {}: {}", &memtrackers_file.clone().into_os_string().into_string().unwrap(), e);
             process::exit(1);
@@ -1308,26 +1301,26 @@ server uuid 05b8d17620eb4cd79eddaddb2fbcbb42</pre></div></footer></body></html>
 
     use crate::utility;
 
-    #[test]
-    fn integration_parse_memtrackers_master() {
+    #[tokio::test]
+    async fn integration_parse_memtrackers_master() {
         let mut stored_memtrackers: Vec<StoredMemTrackers> = Vec::new();
         let detail_snapshot_time = Local::now();
         let hostname = utility::get_hostname_master();
         let port = utility::get_port_master();
 
-        let memtrackers: Vec<MemTrackers> = read_memtrackers(hostname.as_str(), port.as_str());
+        let memtrackers: Vec<MemTrackers> = read_memtrackers(hostname.as_str(), port.as_str()).await;
         add_to_memtrackers_vector(memtrackers, format!("{}:{}", hostname, port).as_str(), detail_snapshot_time, &mut stored_memtrackers);
         // memtrackers must return some rows
         assert!(!stored_memtrackers.is_empty());
     }
-    #[test]
-    fn parse_memtrackers_tserver() {
+    #[tokio::test]
+    async fn parse_memtrackers_tserver() {
         let mut stored_memtrackers: Vec<StoredMemTrackers> = Vec::new();
         let detail_snapshot_time = Local::now();
         let hostname = utility::get_hostname_tserver();
         let port = utility::get_port_tserver();
 
-        let memtrackers: Vec<MemTrackers> = read_memtrackers(hostname.as_str(), port.as_str());
+        let memtrackers: Vec<MemTrackers> = read_memtrackers(hostname.as_str(), port.as_str()).await;
         add_to_memtrackers_vector(memtrackers, format!("{}:{}", hostname, port).as_str(), detail_snapshot_time, &mut stored_memtrackers);
         // memtrackers must return some rows
         assert!(!stored_memtrackers.is_empty());
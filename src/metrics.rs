@@ -2,6 +2,9 @@
 //! (and YEDIS)
 //!
 //! These endpoints provide a separate metrics endpoint in the prometheus format (/prometheus-metrics) too.
+//! The endpoint that is used is set with `--metrics-source` (json or prometheus): see [MetricsSource].
+//! The prometheus data is parsed into the same [MetricEntity] structs as the JSON data using [AllStoredMetrics::parse_prometheus_metrics],
+//! so that the snapshot-diff and ad-hoc output are the same whichever endpoint is used.
 //!
 //! The functionality for metrics has 3 public entries:
 //! 1. Snapshot creation: [AllStoredMetrics::perform_snapshot]
@@ -19,7 +22,7 @@
 //! 2. For metrics, this is via the [AllStoredMetrics::perform_snapshot] method. This method performs two calls.
 //!
 //!   * The method [AllStoredMetrics::read_metrics]
-//!     * This method starts a rayon threadpool, and runs the general function [AllStoredMetrics::read_http] for all host and port combinations.
+//!     * This method concurrently runs the general function [AllStoredMetrics::read_http] for all host and port combinations.
 //!       * [AllStoredMetrics::read_http] calls [AllStoredMetrics::parse_metrics] to parse the http JSON output into a Vector of [MetricEntity].
 //!     * The vector is iterated over and processed in using the function [AllStoredMetrics::split_into_vectors] into the struct [AllStoredMetrics] into the vectors [StoredValues], [StoredCountSum] and [StoredCountSumRows].
 //!
@@ -43,7 +46,7 @@
 //! 1. [SnapshotDiffBTreeMapsMetrics::adhoc_read_first_snapshot]
 //!
 //!   * The method calls [AllStoredMetrics::read_metrics]
-//!     * This method starts a rayon threadpool, and runs the general function [AllStoredMetrics::read_http] for all host and port combinations.
+//!     * This method concurrently runs the general function [AllStoredMetrics::read_http] for all host and port combinations.
 //!       * [AllStoredMetrics::read_http] calls [AllStoredMetrics::parse_metrics] to parse the http JSON output into a Vector of [MetricEntity].
 //!     * The vector is iterated over and processed in using the function [AllStoredMetrics::split_into_vectors] into the struct [AllStoredMetrics] into the vectors [StoredValues], [StoredCountSum] and [StoredCountSumRows].
 //!   * The method [SnapshotDiffBTreeMapsMetrics::first_snapshot] is called with [AllStoredMetrics] as argument to insert the first snapshot data.
@@ -53,7 +56,7 @@
 //! 3. [SnapshotDiffBTreeMapsMetrics::adhoc_read_second_snapshot]
 //!
 //!   * The method calls [AllStoredMetrics::read_metrics]
-//!     * This method starts a rayon threadpool, and runs the general function [AllStoredMetrics::read_http] for all host and port combinations.
+//!     * This method concurrently runs the general function [AllStoredMetrics::read_http] for all host and port combinations.
 //!       * [AllStoredMetrics::read_http] calls [AllStoredMetrics::parse_metrics] to parse the http JSON output into a Vector of [MetricEntity].
 //!     * The vector is iterated over and processed in using the function [AllStoredMetrics::split_into_vectors] into the struct [AllStoredMetrics] into the vectors [StoredValues], [StoredCountSum] and [StoredCountSumRows].
 //!   * The method [SnapshotDiffBTreeMapsMetrics::second_snapshot] is called with [AllStoredMetrics] as argument to insert the second snapshot data.
@@ -61,17 +64,72 @@
 //! 4. [SnapshotDiffBTreeMapsMetrics::print]
 //!
 /// This imports extrnal crates
-use std::{process, fs, env, error::Error, sync::mpsc::channel, collections::BTreeMap, time::Instant};
+use std::{process, error::Error, collections::{BTreeMap, BTreeSet}, str::FromStr, sync::OnceLock, time::Instant};
 use chrono::{DateTime, Local};
 use serde_derive::{Serialize,Deserialize};
 use regex::Regex;
 use substring::Substring;
 use log::*;
+use crate::snapshot::snapshot_directory;
+use futures::future::join_all;
 //use anyhow::{Context, Result}
 /// This imports two utility crates
 use crate::value_statistic_details;
 use crate::countsum_statistic_details;
-use crate::utility::{scan_host_port, http_get};
+use crate::utility::http_get;
+use crate::endpoints::has_role;
+use crate::compression::create_snapshot_file;
+use crate::schema::open_snapshot_csv;
+use crate::output::{self, DiffRecord};
+
+/// The endpoint roles that provide /metrics.
+pub const ROLES: &[&str] = &["master", "tserver", "ysql", "ycql", "yedis"];
+
+/// The endpoint the YugabyteDB metrics are read from.
+/// Both endpoints are parsed into [MetricEntity] structs, so all further processing is the same.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum MetricsSource {
+    /// The JSON /metrics endpoint.
+    #[default]
+    Json,
+    /// The prometheus format /prometheus-metrics endpoint.
+    Prometheus,
+}
+
+impl FromStr for MetricsSource {
+    type Err = String;
+
+    fn from_str(metrics_source: &str) -> Result<Self, Self::Err> {
+        match metrics_source.to_lowercase().as_str() {
+            "json" => Ok(MetricsSource::Json),
+            "prometheus" => Ok(MetricsSource::Prometheus),
+            other => Err(format!("invalid metrics source: {}, valid metrics sources are: json, prometheus", other)),
+        }
+    }
+}
+
+impl MetricsSource {
+    /// The url of the metrics endpoint.
+    pub fn url(&self) -> &'static str {
+        match self {
+            MetricsSource::Json => "metrics",
+            MetricsSource::Prometheus => "prometheus-metrics",
+        }
+    }
+}
+
+static METRICS_SOURCE: OnceLock<MetricsSource> = OnceLock::new();
+
+/// This function sets the metrics source for the whole run of yb_stats.
+pub fn set_metrics_source(metrics_source: MetricsSource) {
+    METRICS_SOURCE.set(metrics_source).unwrap_or_else(|_| warn!("metrics source is already set"));
+}
+
+/// The metrics source that is set, or the default (json).
+pub fn metrics_source() -> MetricsSource {
+    *METRICS_SOURCE.get_or_init(Default::default)
+}
+
 ///
 /// Struct to represent the metric entities found in the YugabyteDB master and tserver metrics endpoint.
 ///
@@ -464,6 +522,18 @@ impl SnapshotDiffValues {
             second_snapshot_value: storedvalues.metric_value,
         }
     }
+    /// This is a private function that turns the second snapshot into the first snapshot, so a next second snapshot can be added, which is used by [SnapshotDiffBTreeMapsMetrics::adhoc_read_next_snapshot].
+    /// The second snapshot time is set to the first snapshot time, and the second snapshot value to 0, identical to [SnapshotDiffValues::first_snapshot].
+    fn second_snapshot_as_first(values_diff_row: &SnapshotDiffValues) -> Self {
+        Self {
+            table_name: values_diff_row.table_name.to_string(),
+            namespace: values_diff_row.namespace.to_string(),
+            first_snapshot_time: values_diff_row.second_snapshot_time,
+            second_snapshot_time: values_diff_row.second_snapshot_time,
+            first_snapshot_value: values_diff_row.second_snapshot_value,
+            second_snapshot_value: 0,
+        }
+    }
     /// This is a private function for a special use of [SnapshotDiffValues], which happens in the [SnapshotDiffBTreeMapsMetrics::print] function.
     /// The special use is if the `--details-enable` flag is not set, statistics that are kept per table, tablet or cdc as metric_type are added together per server.
     /// This is the default mode, in order to try to reduce the amount of output.
@@ -616,6 +686,29 @@ impl SnapshotDiffCountSum {
             first_snapshot_total_sum: 0,
         }
     }
+    /// This is a private function that turns the second snapshot into the first snapshot, so a next second snapshot can be added, which is used by [SnapshotDiffBTreeMapsMetrics::adhoc_read_next_snapshot].
+    /// The second snapshot fields are set identical to [SnapshotDiffCountSum::first_snapshot].
+    fn second_snapshot_as_first(countsum_diff_row: &SnapshotDiffCountSum) -> Self
+    {
+        Self {
+            table_name: countsum_diff_row.table_name.to_string(),
+            namespace: countsum_diff_row.namespace.to_string(),
+            first_snapshot_time: countsum_diff_row.second_snapshot_time,
+            second_snapshot_time: countsum_diff_row.second_snapshot_time,
+            second_snapshot_total_count: 0,
+            second_snapshot_min: 0,
+            second_snapshot_mean: 0.,
+            second_snapshot_percentile_75: 0,
+            second_snapshot_percentile_95: 0,
+            second_snapshot_percentile_99: 0,
+            second_snapshot_percentile_99_9: 0,
+            second_snapshot_percentile_99_99: 0,
+            second_snapshot_max: 0,
+            second_snapshot_total_sum: 0,
+            first_snapshot_total_count: countsum_diff_row.second_snapshot_total_count,
+            first_snapshot_total_sum: countsum_diff_row.second_snapshot_total_sum,
+        }
+    }
     /// This is a private function for a special use of [SnapshotDiffCountSum], which happens in the [SnapshotDiffBTreeMapsMetrics::print] function.
     /// The special use is if the `--details-enable` flag is not set, statistics that are kept per table, tablet or cdc as metric_type are added together per server.
     /// This is the default mode, in order to try to reduce the amount of output.
@@ -758,6 +851,21 @@ impl SnapshotDiffCountSumRows {
             second_snapshot_rows: storedcountsumrows.metric_rows,
         }
     }
+    /// This is a private function that turns the second snapshot into the first snapshot, so a next second snapshot can be added, which is used by [SnapshotDiffBTreeMapsMetrics::adhoc_read_next_snapshot].
+    fn second_snapshot_as_first(countsumrows_diff_row: &SnapshotDiffCountSumRows) -> Self {
+        Self {
+            table_name: countsumrows_diff_row.table_name.to_string(),
+            namespace: countsumrows_diff_row.namespace.to_string(),
+            first_snapshot_time: countsumrows_diff_row.second_snapshot_time,
+            second_snapshot_time: countsumrows_diff_row.second_snapshot_time,
+            first_snapshot_count: countsumrows_diff_row.second_snapshot_count,
+            first_snapshot_sum: countsumrows_diff_row.second_snapshot_sum,
+            first_snapshot_rows: countsumrows_diff_row.second_snapshot_rows,
+            second_snapshot_count: 0,
+            second_snapshot_sum: 0,
+            second_snapshot_rows: 0,
+        }
+    }
 }
 #[allow(rustdoc::private_intra_doc_links)]
 /// [AllStoredMetrics] is a struct that functions as a superstruct for holding [StoredValues], [StoredCountSum] and [StoredCountSumRows].
@@ -777,13 +885,12 @@ impl AllStoredMetrics {
         hosts: &Vec<&str>,
         ports: &Vec<&str>,
         snapshot_number: i32,
-        parallel: usize,
     )
     {
         info!("begin snapshot");
         let timer = Instant::now();
 
-        let allstoredmetrics = AllStoredMetrics::read_metrics(hosts, ports, parallel).await;
+        let allstoredmetrics = AllStoredMetrics::read_metrics(hosts, ports).await;
         allstoredmetrics.save_snapshot(snapshot_number)
             .unwrap_or_else(|e| {
                 error!("error saving snapshot: {}",e);
@@ -798,52 +905,53 @@ impl AllStoredMetrics {
         Default::default()
     }
     /// This function reads all the host/port combinations for metric endpoints and returns an [AllStoredMetrics] struct containing vectors of [StoredValues], [StoredCountSum] and [StoredCountSumRows].
-    async fn read_metrics (
+    pub async fn read_metrics (
         hosts: &Vec<&str>,
         ports: &Vec<&str>,
-        parallel: usize,
     ) -> AllStoredMetrics
     {
         info!("begin parallel http read");
         let timer = Instant::now();
 
-        let pool = rayon::ThreadPoolBuilder::new().num_threads(parallel).build().unwrap();
-        let (tx, rx) = channel();
-        pool.scope(move |s| {
-            for host in hosts {
-                for port in ports {
-                    let tx = tx.clone();
-                    s.spawn(move |_| {
-                        let detail_snapshot_time = Local::now();
-                        let metrics = AllStoredMetrics::read_http(host, port);
-                        tx.send((format!("{}:{}", host, port), detail_snapshot_time, metrics)).expect("error sending data via tx (metrics)");
-                    });
+        let mut handles = Vec::new();
+        for host in hosts {
+            for port in ports {
+                if !has_role(host, port, ROLES) {
+                    continue;
                 }
+                let handle = async move {
+                    let detail_snapshot_time = Local::now();
+                    let metrics = AllStoredMetrics::read_http(host, port).await;
+                    (format!("{}:{}", host, port), detail_snapshot_time, metrics)
+                };
+                handles.push(handle);
             }
-        });
+        }
+        let results = join_all(handles).await;
 
         info!("end parallel http read {:?}", timer.elapsed());
 
         let mut allstoredmetrics = AllStoredMetrics::new();
-        for (hostname_port, detail_snapshot_time, metrics) in rx {
+        for (hostname_port, detail_snapshot_time, metrics) in results {
             allstoredmetrics.split_into_vectors(metrics, &hostname_port, detail_snapshot_time);
         }
 
         allstoredmetrics
     }
     /// This function takes the host and port &str values, and tries to read it, and parse the result.
+    /// The endpoint that is read depends on the [MetricsSource].
     /// This function is public because the integration tests need access to it.
-    pub fn read_http(
+    pub async fn read_http(
         host: &str,
         port: &str,
     ) -> Vec<MetricEntity>
     {
-        let data_from_http = if scan_host_port( host, port) {
-            http_get(host, port, "metrics")
-        } else {
-            String::new()
-        };
-        AllStoredMetrics::parse_metrics(data_from_http, host, port)
+        let metrics_source = metrics_source();
+        let data_from_http = http_get(host, port, metrics_source.url()).await;
+        match metrics_source {
+            MetricsSource::Json => AllStoredMetrics::parse_metrics(data_from_http, host, port),
+            MetricsSource::Prometheus => AllStoredMetrics::parse_prometheus_metrics(data_from_http, host, port),
+        }
     }
     /// This function takes the metrics data as String, and tries to parse the JSON in it to a vector [MetricEntity].
     fn parse_metrics(metrics_data: String, host: &str, port: &str) -> Vec<MetricEntity> {
@@ -853,6 +961,80 @@ impl AllStoredMetrics {
                 Vec::<MetricEntity>::new()
             })
     }
+    /// This function takes the prometheus metrics data as String, and transforms it to a vector [MetricEntity], in the same way as the JSON data.
+    ///
+    /// The samples are grouped into entities by the metric_type and metric_id labels, and the table_id, table_name and namespace_name labels are the attributes.
+    /// Within an entity, the samples are transformed into [Metrics]:
+    /// - A `<name>_count`, `<name>_sum` and `<name>_rows` sample become a [Metrics::MetricCountSumRows].
+    /// - A `<name>_count` and `<name>_sum` sample become a [Metrics::MetricCountSum]. The mean is calculated, the percentiles are taken from a summary if present. Min and max are not available, and set to 0.
+    /// - All other samples become a [Metrics::MetricValue].
+    fn parse_prometheus_metrics(metrics_data: String, host: &str, port: &str) -> Vec<MetricEntity> {
+        let scrape = match prometheus_parse::Scrape::parse(metrics_data.lines().map(|s| Ok(s.to_owned()))) {
+            Ok(scrape) => scrape,
+            Err(e) => {
+                debug!("({}:{}) unable to parse prometheus metrics, error: {}", host, port, e);
+                return Vec::new();
+            },
+        };
+        // per entity (metric_type, metric_id): the attributes, the values by name, and the quantiles by name.
+        type PrometheusEntity = (Attributes, BTreeMap<String, f64>, BTreeMap<String, Vec<(f64, f64)>>);
+        let mut entities: BTreeMap<(String, String), PrometheusEntity> = BTreeMap::new();
+        for sample in scrape.samples {
+            let entity = entities
+                .entry((sample.labels.get("metric_type").unwrap_or("server").to_string(), sample.labels.get("metric_id").unwrap_or("-").to_string()))
+                .or_insert_with(|| (Attributes {
+                    namespace_name: sample.labels.get("namespace_name").map(|x| x.to_string()),
+                    table_name: sample.labels.get("table_name").map(|x| x.to_string()),
+                    table_id: sample.labels.get("table_id").map(|x| x.to_string()),
+                }, BTreeMap::new(), BTreeMap::new()));
+            match sample.value {
+                prometheus_parse::Value::Counter(value) | prometheus_parse::Value::Gauge(value) | prometheus_parse::Value::Untyped(value) => {
+                    entity.1.insert(sample.metric, value);
+                },
+                prometheus_parse::Value::Summary(quantiles) => {
+                    entity.2.insert(sample.metric, quantiles.iter().map(|q| (q.quantile, q.count)).collect());
+                },
+                prometheus_parse::Value::Histogram(_) => {},
+            }
+        }
+
+        let mut metricentities = Vec::new();
+        for ((metrics_type, id), (attributes, values, quantiles)) in entities {
+            let mut metrics = Vec::new();
+            for (name, value) in &values {
+                let base_name = name.strip_suffix("_count").or_else(|| name.strip_suffix("_sum")).or_else(|| name.strip_suffix("_rows"));
+                let countsum = base_name.and_then(|base_name| Some((base_name, values.get(&format!("{}_count", base_name))?, values.get(&format!("{}_sum", base_name))?)));
+                match countsum {
+                    // the countsum is added once, for the _count sample.
+                    Some((base_name, count, sum)) if name.ends_with("_count") => {
+                        match values.get(&format!("{}_rows", base_name)) {
+                            Some(rows) => metrics.push(Metrics::MetricCountSumRows { name: base_name.to_string(), count: *count as u64, sum: *sum as u64, rows: *rows as u64 }),
+                            None => {
+                                let quantile = |q: f64| quantiles.get(base_name).and_then(|quantiles| quantiles.iter().find(|(quantile, _)| (quantile - q).abs() < 1e-9)).map(|(_, value)| *value as u64).unwrap_or_default();
+                                metrics.push(Metrics::MetricCountSum {
+                                    name: base_name.to_string(),
+                                    total_count: *count as u64,
+                                    min: 0,
+                                    mean: if *count > 0. { sum / count } else { 0. },
+                                    percentile_75: quantile(0.75),
+                                    percentile_95: quantile(0.95),
+                                    percentile_99: quantile(0.99),
+                                    percentile_99_9: quantile(0.999),
+                                    percentile_99_99: quantile(0.9999),
+                                    max: 0,
+                                    total_sum: *sum as u64,
+                                });
+                            },
+                        }
+                    },
+                    Some(_) => {},
+                    None => metrics.push(Metrics::MetricValue { name: name.to_string(), value: *value as i64 }),
+                }
+            }
+            metricentities.push(MetricEntity { metrics_type, id, attributes: Some(attributes), metrics });
+        }
+        metricentities
+    }
     /// This function takes the [MetricEntity] struct, and splits the different types of values into [StoredValues], [StoredCountSum] and [StoredCountSumRows] vectors of structs.
     /// It is public because it is used in the integration tests too.
     pub fn split_into_vectors(
@@ -914,43 +1096,33 @@ impl AllStoredMetrics {
     /// The vectors this struct holds are of structs of [StoredValues], [StoredCountSum] and [StoredCountSumRows].
     /// The directory with the snapshot number must exist already.
     /// This function returns a Result.
-    fn save_snapshot ( self, snapshot_number: i32, ) -> Result<(), Box<dyn Error>>
+    pub fn save_snapshot ( self, snapshot_number: i32, ) -> Result<(), Box<dyn Error>>
     {
-        let current_directory = env::current_dir()?;
-        let current_snapshot_directory = current_directory.join("yb_stats.snapshots").join(&snapshot_number.to_string());
+        let current_snapshot_directory = snapshot_directory().join(snapshot_number.to_string());
 
         let values_file = &current_snapshot_directory.join("values");
-        let file = fs::OpenOptions::new()
-            .create(true)
-            .write(true)
-            .open(values_file)?;
+        let file = create_snapshot_file(values_file)?;
         let mut writer = csv::Writer::from_writer(file);
         for row in self.stored_values {
             writer.serialize(row)?;
         }
-        writer.flush()?;
+        writer.into_inner()?.finish()?;
 
         let countsum_file = &current_snapshot_directory.join("countsum");
-        let file = fs::OpenOptions::new()
-            .create(true)
-            .write(true)
-            .open(countsum_file)?;
+        let file = create_snapshot_file(countsum_file)?;
         let mut writer = csv::Writer::from_writer(file);
         for row in self.stored_countsum {
             writer.serialize(row)?;
         }
-        writer.flush()?;
+        writer.into_inner()?.finish()?;
 
         let countsumrows_file = &current_snapshot_directory.join("countsumrows");
-        let file = fs::OpenOptions::new()
-            .create(true)
-            .write(true)
-            .open(countsumrows_file)?;
+        let file = create_snapshot_file(countsumrows_file)?;
         let mut writer = csv::Writer::from_writer(file);
         for row in self.stored_countsumrows {
             writer.serialize(row)?;
         }
-        writer.flush()?;
+        writer.into_inner()?.finish()?;
 
         Ok(())
     }
@@ -961,11 +1133,10 @@ impl AllStoredMetrics {
     {
         let mut allstoredmetrics = AllStoredMetrics::new();
 
-        let current_directory = env::current_dir()?;
-        let current_snapshot_directory = current_directory.join("yb_stats.snapshots").join(snapshot_number);
+        let current_snapshot_directory = snapshot_directory().join(snapshot_number);
 
         let values_file = &current_snapshot_directory.join("values");
-        let file = fs::File::open(values_file)?;
+        let file = open_snapshot_csv(values_file)?;
 
         let mut reader = csv::Reader::from_reader(file);
         for row in reader.deserialize() {
@@ -974,7 +1145,7 @@ impl AllStoredMetrics {
         };
 
         let countsum_file = &current_snapshot_directory.join("countsum");
-        let file = fs::File::open(countsum_file)?;
+        let file = open_snapshot_csv(countsum_file)?;
 
         let mut reader = csv::Reader::from_reader(file);
         for row in reader.deserialize() {
@@ -983,7 +1154,7 @@ impl AllStoredMetrics {
         };
 
         let countsumrows_file = &current_snapshot_directory.join("countsumrows");
-        let file = fs::File::open(countsumrows_file)?;
+        let file = open_snapshot_csv(countsumrows_file)?;
 
         let mut reader = csv::Reader::from_reader(file);
         for row in reader.deserialize() {
@@ -1047,9 +1218,8 @@ impl SnapshotDiffBTreeMapsMetrics {
         &mut self,
         hosts: &Vec<&str>,
         ports: &Vec<&str>,
-        parallel: usize,
     ) {
-        let allstoredmetrics = AllStoredMetrics::read_metrics(hosts, ports, parallel).await;
+        let allstoredmetrics = AllStoredMetrics::read_metrics(hosts, ports).await;
         self.first_snapshot(allstoredmetrics);
     }
     /// This function reads the second snapshot data from the http endpoints itself (=adhoc mode), and stores it as second snapshot data in [SnapshotDiffBTreeMapsMetrics].
@@ -1058,12 +1228,61 @@ impl SnapshotDiffBTreeMapsMetrics {
         &mut self,
         hosts: &Vec<&str>,
         ports: &Vec<&str>,
-        parallel: usize,
         first_snapshot_time: &DateTime<Local>,
     ) {
-        let allstoredmetrics = AllStoredMetrics::read_metrics(hosts, ports, parallel).await;
+        let allstoredmetrics = AllStoredMetrics::read_metrics(hosts, ports).await;
         self.second_snapshot(allstoredmetrics, first_snapshot_time);
     }
+    /// This function reads the next snapshot data from the http endpoints itself, after the second snapshot is turned into the first snapshot (=exporter and top mode).
+    /// This way, the struct keeps the previous snapshot, and only one snapshot is read per interval, see [SnapshotDiffBTreeMapsMetrics::next_snapshot].
+    pub async fn adhoc_read_next_snapshot(
+        &mut self,
+        hosts: &Vec<&str>,
+        ports: &Vec<&str>,
+        previous_snapshot_time: &DateTime<Local>,
+    ) {
+        let allstoredmetrics = AllStoredMetrics::read_metrics(hosts, ports).await;
+        self.next_snapshot(allstoredmetrics, previous_snapshot_time);
+    }
+    /// This function turns the second snapshot into the first snapshot, and adds the data of [AllStoredMetrics] as second snapshot.
+    /// Statistics that are not present in the second snapshot (anymore) are removed, because these would be added with a first snapshot value of 0 if they appear again.
+    /// A statistic that is new in the next snapshot, such as of a tablet that is created, split or moved to the host, has its whole lifetime value as second snapshot value.
+    /// Therefore, it gets no interval: its first snapshot time is set to its second snapshot time, so it has a rate from the next snapshot on.
+    pub fn next_snapshot(
+        &mut self,
+        allstoredmetrics: AllStoredMetrics,
+        previous_snapshot_time: &DateTime<Local>,
+    ) {
+        self.second_snapshot_as_first();
+        let previous_values = self.btreemap_snapshotdiff_values.keys().cloned().collect::<BTreeSet<_>>();
+        let previous_countsum = self.btreemap_snapshotdiff_countsum.keys().cloned().collect::<BTreeSet<_>>();
+        let previous_countsumrows = self.btreemap_snapshotdiff_countsumrows.keys().cloned().collect::<BTreeSet<_>>();
+        self.second_snapshot(allstoredmetrics, previous_snapshot_time);
+        for (_, value_diff_row) in self.btreemap_snapshotdiff_values.iter_mut().filter(|(key, _)| !previous_values.contains(*key)) {
+            value_diff_row.first_snapshot_time = value_diff_row.second_snapshot_time;
+        }
+        for (_, countsum_diff_row) in self.btreemap_snapshotdiff_countsum.iter_mut().filter(|(key, _)| !previous_countsum.contains(*key)) {
+            countsum_diff_row.first_snapshot_time = countsum_diff_row.second_snapshot_time;
+        }
+        for (_, countsumrows_diff_row) in self.btreemap_snapshotdiff_countsumrows.iter_mut().filter(|(key, _)| !previous_countsumrows.contains(*key)) {
+            countsumrows_diff_row.first_snapshot_time = countsumrows_diff_row.second_snapshot_time;
+        }
+    }
+    /// This function turns the second snapshot data into the first snapshot data for all the statistics, and removes the statistics that were not present in the second snapshot.
+    fn second_snapshot_as_first(&mut self) {
+        self.btreemap_snapshotdiff_values.retain(|_, value_diff_row| value_diff_row.second_snapshot_value > 0);
+        for value_diff_row in self.btreemap_snapshotdiff_values.values_mut() {
+            *value_diff_row = SnapshotDiffValues::second_snapshot_as_first(value_diff_row);
+        }
+        self.btreemap_snapshotdiff_countsum.retain(|_, countsum_diff_row| countsum_diff_row.second_snapshot_total_count > 0);
+        for countsum_diff_row in self.btreemap_snapshotdiff_countsum.values_mut() {
+            *countsum_diff_row = SnapshotDiffCountSum::second_snapshot_as_first(countsum_diff_row);
+        }
+        self.btreemap_snapshotdiff_countsumrows.retain(|_, countsumrows_diff_row| countsumrows_diff_row.second_snapshot_count > 0);
+        for countsumrows_diff_row in self.btreemap_snapshotdiff_countsumrows.values_mut() {
+            *countsumrows_diff_row = SnapshotDiffCountSumRows::second_snapshot_as_first(countsumrows_diff_row);
+        }
+    }
     /// This function takes the data from the struct [AllStoredMetrics], creates a struct [SnapshotDiffBTreeMapsMetrics] and adds the data as first snapshot.
     /// The struct [AllStoredMetrics] contains vectors of the structs of [StoredValues], [StoredCountSum] and [StoredCountSumRows].
     /// This function is used in [SnapshotDiffBTreeMapsMetrics::snapshot_diff], [SnapshotDiffBTreeMapsMetrics::adhoc_read_first_snapshot], but never directly, which is why it is private.
@@ -1241,6 +1460,180 @@ impl SnapshotDiffBTreeMapsMetrics {
             }
         }
     }
+    /// This function adds up the value statistics for the table, tablet and cdc types per hostname_port, metric_type and metric_name.
+    /// The other types have a single entry for the hostname_port, metric_type and metric_name.
+    fn sum_values(&self) -> BTreeMap<(String, String, String, String), SnapshotDiffValues> {
+        let mut sum_value_diff: BTreeMap<(String, String, String, String), SnapshotDiffValues> = BTreeMap::new();
+        for ((hostname_port, metric_type, _metric_id, metric_name), value_diff_row) in &self.btreemap_snapshotdiff_values {
+            if metric_type == "table" || metric_type == "tablet" || metric_type == "cdc" || metric_type == "cdcsdk" {
+                /*
+                 * If a table and thus its tablets have been deleted between the first and second snapshot, the second_snapshot_value is 0.
+                 * However, the first_snapshot_value is > 0, it means it can make the subtraction between the second and the first snapshot get negative, and a summary overview be incorrect.
+                 * Therefore we remove individual statistics where the second snapshot value is set to 0.
+                 */
+                if value_diff_row.second_snapshot_value > 0 {
+                    match sum_value_diff.get_mut(&(hostname_port.to_string(), metric_type.to_string(), String::from("-"), metric_name.to_string())) {
+                        Some(sum_value_diff_row) => *sum_value_diff_row = SnapshotDiffValues::diff_sum_existing(sum_value_diff_row, value_diff_row),
+                        None => {
+                            sum_value_diff.insert(( hostname_port.to_string(), metric_type.to_string(), String::from("-"), metric_name.to_string() ),
+                                                  SnapshotDiffValues::diff_sum_new(value_diff_row)
+                            );
+                        },
+                    }
+                }
+            } else {
+                match sum_value_diff.get_mut(&(hostname_port.to_string(), metric_type.to_string(), String::from("-"), metric_name.to_string())) {
+                    Some(_sum_value_diff) => {
+                        panic!("Error: (sum_value_diff) found second entry for hostname: {}, type: {}, id: {}, name: {}", &hostname_port.clone(), &metric_type.clone(), String::from("-"), &metric_name.clone());
+                    },
+                    None => {
+                        sum_value_diff.insert(( hostname_port.to_string(), metric_type.to_string(), String::from("-"), metric_name.to_string() ),
+                                              SnapshotDiffValues::diff_sum_new(value_diff_row)
+                        );
+                    }
+                }
+            }
+        }
+        sum_value_diff
+    }
+    /// This function adds up the countsum statistics for the table, tablet and cdc types per hostname_port, metric_type and metric_name.
+    /// The other types have a single entry for the hostname_port, metric_type and metric_name.
+    fn sum_countsum(&self) -> BTreeMap<(String, String, String, String), SnapshotDiffCountSum> {
+        let mut sum_countsum_diff: BTreeMap<(String, String, String, String), SnapshotDiffCountSum> = BTreeMap::new();
+        for ((hostname_port, metric_type, _metric_id, metric_name), countsum_diff_row) in &self.btreemap_snapshotdiff_countsum {
+            if metric_type == "table" || metric_type == "tablet" || metric_type == "cdc" || metric_type == "cdcsdk" {
+                /*
+                 * If a table and thus its tablets have been deleted between the first and second snapshot, the second_snapshot_value is 0.
+                 * However, the first_snapshot_value is > 0, it means it can make the subtraction between the second and the first snapshot get negative, and a summary overview be incorrect.
+                 * Therefore we remove individual statistics where the second snapshot value is set to 0.
+                 */
+                if countsum_diff_row.second_snapshot_total_count > 0 {
+                    match sum_countsum_diff.get_mut(&(hostname_port.to_string(), metric_type.to_string(), String::from("-"), metric_name.to_string())) {
+                        Some(sum_countsum_diff_row) => *sum_countsum_diff_row = SnapshotDiffCountSum::diff_sum_existing(sum_countsum_diff_row, countsum_diff_row),
+                        None => {
+                            sum_countsum_diff.insert(( hostname_port.to_string(), metric_type.to_string(), String::from("-"), metric_name.to_string() ),
+                                                     SnapshotDiffCountSum::diff_sum_new(countsum_diff_row)
+                            );
+                        }
+                    }
+                }
+            } else {
+                match sum_countsum_diff.get_mut(&(hostname_port.to_string(), metric_type.to_string(), String::from("-"), metric_name.to_string())) {
+                    Some(_sum_countsum_diff_row) => {
+                        panic!("Error: (sum_countsum_diff) found second entry for hostname: {}, type: {}, id: {}, name: {}", &hostname_port.clone(), &metric_type.clone(), String::from("-"), &metric_name.clone());
+                    },
+                    None => {
+                        sum_countsum_diff.insert(( hostname_port.to_string(), metric_type.to_string(), String::from("-"), metric_name.to_string() ),
+                                                 SnapshotDiffCountSum::diff_sum_new(countsum_diff_row)
+                        );
+                    }
+                }
+            }
+        }
+        sum_countsum_diff
+    }
+    /// This function returns the statistics that are printed by [SnapshotDiffBTreeMapsMetrics::print] as records for the machine-readable output, see [crate::output].
+    /// The filters and switches are applied in the same way, but the averages are not rounded.
+    pub fn records(
+        &self,
+        hostname_filter: &Regex,
+        stat_name_filter: &Regex,
+        table_name_filter: &Regex,
+        details_enable: &bool,
+        gauges_enable: &bool
+    ) -> Vec<DiffRecord>
+    {
+        let mut records = Vec::new();
+        let sum_value_diff;
+        let sum_countsum_diff;
+        let (values, countsums): (Vec<_>, Vec<_>) = if *details_enable {
+            (
+                self.btreemap_snapshotdiff_values.iter().filter(|(_, row)| row.second_snapshot_value > 0).collect(),
+                self.btreemap_snapshotdiff_countsum.iter().filter(|(_, row)| row.second_snapshot_total_count > 0).collect(),
+            )
+        } else {
+            sum_value_diff = self.sum_values();
+            sum_countsum_diff = self.sum_countsum();
+            (sum_value_diff.iter().collect(), sum_countsum_diff.iter().collect())
+        };
+        let value_statistics = value_statistic_details::ValueStatistics::create();
+        for ((hostname, metric_type, metric_id, metric_name), value_diff_row) in values {
+            if !(hostname_filter.is_match(hostname)
+                && stat_name_filter.is_match(metric_name)
+                && table_name_filter.is_match(&value_diff_row.table_name)) {
+                continue;
+            }
+            let details = value_statistics.lookup(metric_name);
+            let delta = value_diff_row.second_snapshot_value - value_diff_row.first_snapshot_value;
+            let record = DiffRecord {
+                hostname_port: hostname.to_string(),
+                metric_type: metric_type.to_string(),
+                metric_id: metric_id.to_string(),
+                namespace: value_diff_row.namespace.to_string(),
+                table_name: value_diff_row.table_name.to_string(),
+                name: metric_name.to_string(),
+                delta: Some(delta as f64),
+                unit: details.unit.to_string(),
+                ..Default::default()
+            };
+            if details.stat_type != "gauge" && delta != 0 {
+                records.push(DiffRecord::statistic("metrics", value_diff_row.first_snapshot_time, value_diff_row.second_snapshot_time, record));
+            } else if details.stat_type == "gauge" && *gauges_enable {
+                // the difference of a gauge has no rate.
+                records.push(DiffRecord {
+                    value: Some(value_diff_row.second_snapshot_value as f64),
+                    rate: None,
+                    ..DiffRecord::statistic("metrics", value_diff_row.first_snapshot_time, value_diff_row.second_snapshot_time, record)
+                });
+            }
+        }
+        let countsum_statistics = countsum_statistic_details::CountSumStatistics::create();
+        for ((hostname, metric_type, metric_id, metric_name), countsum_diff_row) in countsums {
+            let count = countsum_diff_row.second_snapshot_total_count as f64 - countsum_diff_row.first_snapshot_total_count as f64;
+            if hostname_filter.is_match(hostname)
+                && stat_name_filter.is_match(metric_name)
+                && table_name_filter.is_match(&countsum_diff_row.table_name)
+                && count != 0_f64 {
+                let sum = countsum_diff_row.second_snapshot_total_sum as f64 - countsum_diff_row.first_snapshot_total_sum as f64;
+                records.push(DiffRecord::statistic("metrics", countsum_diff_row.first_snapshot_time, countsum_diff_row.second_snapshot_time, DiffRecord {
+                    hostname_port: hostname.to_string(),
+                    metric_type: metric_type.to_string(),
+                    metric_id: metric_id.to_string(),
+                    namespace: countsum_diff_row.namespace.to_string(),
+                    table_name: countsum_diff_row.table_name.to_string(),
+                    name: metric_name.to_string(),
+                    delta: Some(count),
+                    average: Some(sum / count),
+                    sum_delta: Some(sum),
+                    unit: countsum_statistics.lookup(metric_name).unit.to_string(),
+                    ..Default::default()
+                }));
+            }
+        }
+        for ((hostname, metric_type, metric_id, metric_name), countsumrows_diff_row) in &self.btreemap_snapshotdiff_countsumrows {
+            let count = countsumrows_diff_row.second_snapshot_count as f64 - countsumrows_diff_row.first_snapshot_count as f64;
+            if hostname_filter.is_match(hostname)
+                && stat_name_filter.is_match(metric_name)
+                && count != 0_f64 {
+                let sum = countsumrows_diff_row.second_snapshot_sum as f64 - countsumrows_diff_row.first_snapshot_sum as f64;
+                records.push(DiffRecord::statistic("metrics", countsumrows_diff_row.first_snapshot_time, countsumrows_diff_row.second_snapshot_time, DiffRecord {
+                    hostname_port: hostname.to_string(),
+                    metric_type: metric_type.to_string(),
+                    metric_id: metric_id.to_string(),
+                    namespace: countsumrows_diff_row.namespace.to_string(),
+                    table_name: countsumrows_diff_row.table_name.to_string(),
+                    name: metric_name.to_string(),
+                    delta: Some(count),
+                    average: Some(sum / count),
+                    sum_delta: Some(sum),
+                    rows_delta: Some(countsumrows_diff_row.second_snapshot_rows as f64 - countsumrows_diff_row.first_snapshot_rows as f64),
+                    unit: String::from("microseconds"),
+                    ..Default::default()
+                }));
+            }
+        }
+        records
+    }
     /// This function prints the BTreeMaps in the [SnapshotDiffBTreeMapsMetrics] struct.
     /// It first is taking the details_enable boolean, which splits the printing between printing per table and tablet or summing it all up per server portnumber combination.
     /// Inside it, it first reads
@@ -1257,6 +1650,10 @@ impl SnapshotDiffBTreeMapsMetrics {
         details_enable: &bool,
         gauges_enable: &bool
     ) {
+        if output::is_machine_readable() {
+            output::write_records(&self.records(hostname_filter, stat_name_filter, table_name_filter, details_enable, gauges_enable));
+            return;
+        }
         /*
          * These are the value and countsum statistics as they have been captured.
          */
@@ -1368,37 +1765,7 @@ impl SnapshotDiffBTreeMapsMetrics {
              */
             // value_diff
             let value_statistics = value_statistic_details::ValueStatistics::create();
-            let mut sum_value_diff: BTreeMap<(String, String, String, String), SnapshotDiffValues> = BTreeMap::new();
-            for ((hostname_port, metric_type, _metric_id, metric_name), value_diff_row) in &self.btreemap_snapshotdiff_values {
-                if metric_type == "table" || metric_type == "tablet" || metric_type == "cdc" || metric_type == "cdcsdk" {
-                    /*
-                     * If a table and thus its tablets have been deleted between the first and second snapshot, the second_snapshot_value is 0.
-                     * However, the first_snapshot_value is > 0, it means it can make the subtraction between the second and the first snapshot get negative, and a summary overview be incorrect.
-                     * Therefore we remove individual statistics where the second snapshot value is set to 0.
-                     */
-                    if value_diff_row.second_snapshot_value > 0 {
-                        match sum_value_diff.get_mut(&(hostname_port.to_string(), metric_type.to_string(), String::from("-"), metric_name.to_string())) {
-                            Some(sum_value_diff_row) => *sum_value_diff_row = SnapshotDiffValues::diff_sum_existing(sum_value_diff_row, value_diff_row),
-                            None => {
-                                sum_value_diff.insert(( hostname_port.to_string(), metric_type.to_string(), String::from("-"), metric_name.to_string() ),
-                                                      SnapshotDiffValues::diff_sum_new(value_diff_row)
-                                );
-                            },
-                        }
-                    }
-                } else {
-                    match sum_value_diff.get_mut(&(hostname_port.to_string(), metric_type.to_string(), String::from("-"), metric_name.to_string())) {
-                        Some(_sum_value_diff) => {
-                            panic!("Error: (sum_value_diff) found second entry for hostname: {}, type: {}, id: {}, name: {}", &hostname_port.clone(), &metric_type.clone(), String::from("-"), &metric_name.clone());
-                        },
-                        None => {
-                            sum_value_diff.insert(( hostname_port.to_string(), metric_type.to_string(), String::from("-"), metric_name.to_string() ),
-                                                  SnapshotDiffValues::diff_sum_new(value_diff_row)
-                            );
-                        }
-                    }
-                }
-            }
+            let sum_value_diff = self.sum_values();
             //for ((hostname, metric_type, metric_id, metric_name), value_diff_row) in &self.btreemap_snapshotdiff_values {
             for ((hostname, metric_type, metric_id, metric_name), value_diff_row) in &sum_value_diff {
                 if hostname_filter.is_match(hostname)
@@ -1460,37 +1827,7 @@ impl SnapshotDiffBTreeMapsMetrics {
             }
             // countsum_diff
             let countsum_statistics = countsum_statistic_details::CountSumStatistics::create();
-            let mut sum_countsum_diff: BTreeMap<(String, String, String, String), SnapshotDiffCountSum> = BTreeMap::new();
-            for ((hostname_port, metric_type, _metric_id, metric_name), countsum_diff_row) in &self.btreemap_snapshotdiff_countsum {
-                if metric_type == "table" || metric_type == "tablet" || metric_type == "cdc" || metric_type == "cdcsdk" {
-                    /*
-                     * If a table and thus its tablets have been deleted between the first and second snapshot, the second_snapshot_value is 0.
-                     * However, the first_snapshot_value is > 0, it means it can make the subtraction between the second and the first snapshot get negative, and a summary overview be incorrect.
-                     * Therefore we remove individual statistics where the second snapshot value is set to 0.
-                     */
-                    if countsum_diff_row.second_snapshot_total_count > 0 {
-                        match sum_countsum_diff.get_mut(&(hostname_port.to_string(), metric_type.to_string(), String::from("-"), metric_name.to_string())) {
-                            Some(sum_countsum_diff_row) => *sum_countsum_diff_row = SnapshotDiffCountSum::diff_sum_existing(sum_countsum_diff_row, countsum_diff_row),
-                            None => {
-                                sum_countsum_diff.insert(( hostname_port.to_string(), metric_type.to_string(), String::from("-"), metric_name.to_string() ),
-                                                         SnapshotDiffCountSum::diff_sum_new(countsum_diff_row)
-                                );
-                            }
-                        }
-                    }
-                } else {
-                    match sum_countsum_diff.get_mut(&(hostname_port.to_string(), metric_type.to_string(), String::from("-"), metric_name.to_string())) {
-                        Some(_sum_countsum_diff_row) => {
-                            panic!("Error: (sum_countsum_diff) found second entry for hostname: {}, type: {}, id: {}, name: {}", &hostname_port.clone(), &metric_type.clone(), String::from("-"), &metric_name.clone());
-                        },
-                        None => {
-                            sum_countsum_diff.insert(( hostname_port.to_string(), metric_type.to_string(), String::from("-"), metric_name.to_string() ),
-                                                     SnapshotDiffCountSum::diff_sum_new(countsum_diff_row)
-                            );
-                        }
-                    }
-                }
-            }
+            let sum_countsum_diff = self.sum_countsum();
             for ((hostname, metric_type, metric_id, metric_name), countsum_diff_row) in sum_countsum_diff {
                 if hostname_filter.is_match(&hostname)
                     && stat_name_filter.is_match(&metric_name)
@@ -1551,6 +1888,44 @@ impl SnapshotDiffBTreeMapsMetrics {
 mod tests {
     use super::*;
 
+    #[test]
+    /// prometheus metrics value, countsum and countsumrows, from a tablet server and a YSQL server
+    fn unit_parse_prometheus_metrics() {
+        let prometheus = r#"# HELP rocksdb_number_db_seek Number of calls to seek
+# TYPE rocksdb_number_db_seek counter
+rocksdb_number_db_seek{table_id="000033e8000030008000000000004000",table_name="t",namespace_name="yugabyte",metric_type="tablet",metric_id="5ca4f25e4b1a4f36a2e9e6c1e2ec04a5",exported_instance="yb-1.local:9000"} 12 1670000000000
+handler_latency_yb_tserver_TabletServerService_Read_count{metric_type="server",metric_id="yb.tabletserver",exported_instance="yb-1.local:9000"} 4 1670000000000
+handler_latency_yb_tserver_TabletServerService_Read_sum{metric_type="server",metric_id="yb.tabletserver",exported_instance="yb-1.local:9000"} 1000 1670000000000
+handler_latency_yb_ysqlserver_SQLProcessor_SelectStmt_count{metric_type="server",metric_id="yb.ysqlserver",exported_instance="yb-1.local:13000"} 25 1670000000000
+handler_latency_yb_ysqlserver_SQLProcessor_SelectStmt_sum{metric_type="server",metric_id="yb.ysqlserver",exported_instance="yb-1.local:13000"} 631456 1670000000000
+handler_latency_yb_ysqlserver_SQLProcessor_SelectStmt_rows{metric_type="server",metric_id="yb.ysqlserver",exported_instance="yb-1.local:13000"} 26 1670000000000
+"#.to_string();
+        let result = AllStoredMetrics::parse_prometheus_metrics(prometheus, "", "");
+        assert_eq!(result.len(), 3);
+        let tablet = result.iter().find(|entity| entity.metrics_type == "tablet").unwrap();
+        assert_eq!(tablet.attributes.as_ref().unwrap().table_name.as_deref(), Some("t"));
+        match &tablet.metrics[0] {
+            Metrics::MetricValue { name, value } => assert_eq!(format!("{}, {}", name, value), "rocksdb_number_db_seek, 12"),
+            other => panic!("expected MetricValue, got {:?}", other),
+        };
+        let tserver = result.iter().find(|entity| entity.id == "yb.tabletserver").unwrap();
+        match &tserver.metrics[..] {
+            [Metrics::MetricCountSum { name, total_count, mean, total_sum, .. }] => assert_eq!(format!("{}, {}, {}, {}", name, total_count, mean, total_sum), "handler_latency_yb_tserver_TabletServerService_Read, 4, 250, 1000"),
+            other => panic!("expected one MetricCountSum, got {:?}", other),
+        };
+        let ysqlserver = result.iter().find(|entity| entity.id == "yb.ysqlserver").unwrap();
+        match &ysqlserver.metrics[..] {
+            [Metrics::MetricCountSumRows { name, count, sum, rows }] => assert_eq!(format!("{}, {}, {}, {}", name, count, sum, rows), "handler_latency_yb_ysqlserver_SQLProcessor_SelectStmt, 25, 631456, 26"),
+            other => panic!("expected one MetricCountSumRows, got {:?}", other),
+        };
+    }
+
+    #[test]
+    fn unit_parse_prometheus_metrics_empty() {
+        let result = AllStoredMetrics::parse_prometheus_metrics(String::new(), "", "");
+        assert!(result.is_empty());
+    }
+
     #[test]
     /// cdcsdk (change data capture software development kit) metrics value
     /// Please mind type cdc has an extra, unique, attribute: stream_id. This is currently not parsed.
@@ -1933,64 +2308,92 @@ mod tests {
 
     use crate::utility;
 
-    fn test_function_read_metrics(
+    #[test]
+    /// the exporter mode turns the second snapshot into the first snapshot, and adds the next snapshot as second snapshot.
+    fn unit_second_snapshot_as_first() {
+        use chrono::TimeZone;
+        let snapshot = |seek: u64, read_count: u64, read_sum: u64, second: u32| {
+            let prometheus = format!(r#"rocksdb_number_db_seek{{table_id="1",table_name="t",namespace_name="yugabyte",metric_type="tablet",metric_id="tablet1",exported_instance="yb-1.local:9000"}} {} 1670000000000
+handler_latency_yb_tserver_TabletServerService_Read_count{{metric_type="server",metric_id="yb.tabletserver",exported_instance="yb-1.local:9000"}} {} 1670000000000
+handler_latency_yb_tserver_TabletServerService_Read_sum{{metric_type="server",metric_id="yb.tabletserver",exported_instance="yb-1.local:9000"}} {} 1670000000000
+"#, seek, read_count, read_sum);
+            let mut allstoredmetrics = AllStoredMetrics::new();
+            allstoredmetrics.split_into_vectors(AllStoredMetrics::parse_prometheus_metrics(prometheus, "", ""), "yb-1.local:9000", Local.with_ymd_and_hms(2023, 1, 10, 10, 0, second).unwrap());
+            allstoredmetrics
+        };
+        let mut diff = SnapshotDiffBTreeMapsMetrics::new();
+        diff.first_snapshot(snapshot(10, 4, 1000, 0));
+        let first_snapshot_time = Local.with_ymd_and_hms(2023, 1, 10, 10, 0, 0).unwrap();
+        diff.second_snapshot(snapshot(15, 6, 1600, 10), &first_snapshot_time);
+        diff.second_snapshot_as_first();
+        let previous_snapshot_time = Local.with_ymd_and_hms(2023, 1, 10, 10, 0, 10).unwrap();
+        diff.second_snapshot(snapshot(35, 10, 3600, 20), &previous_snapshot_time);
+
+        let value = diff.btreemap_snapshotdiff_values.values().next().unwrap();
+        assert_eq!((value.first_snapshot_value, value.second_snapshot_value), (15, 35));
+        assert_eq!((value.second_snapshot_time - value.first_snapshot_time).num_seconds(), 10);
+        let countsum = diff.btreemap_snapshotdiff_countsum.values().next().unwrap();
+        assert_eq!((countsum.first_snapshot_total_count, countsum.second_snapshot_total_count), (6, 10));
+        assert_eq!((countsum.first_snapshot_total_sum, countsum.second_snapshot_total_sum), (1600, 3600));
+    }
+    async fn test_function_read_metrics(
         hostname: String,
         port: String
     ) -> AllStoredMetrics
     {
         let mut allstoredmetrics = AllStoredMetrics::new();
 
-        let data_parsed_from_json = AllStoredMetrics::read_http(hostname.as_str(), port.as_str());
+        let data_parsed_from_json = AllStoredMetrics::read_http(hostname.as_str(), port.as_str()).await;
         allstoredmetrics.split_into_vectors(data_parsed_from_json, format!("{}:{}", hostname, port).as_str(), Local::now());
         allstoredmetrics
     }
-    #[test]
-    fn integration_parse_metrics_master()
+    #[tokio::test]
+    async fn integration_parse_metrics_master()
     {
         let hostname = utility::get_hostname_master();
         let port = utility::get_port_master();
-        let allstoredmetrics = test_function_read_metrics(hostname, port);
+        let allstoredmetrics = test_function_read_metrics(hostname, port).await;
         // a master will produce values and countsum rows, but no countsumrows rows, because that belongs to YSQL.
         assert!(!allstoredmetrics.stored_values.is_empty());
         assert!(!allstoredmetrics.stored_countsum.is_empty());
         assert!(allstoredmetrics.stored_countsumrows.is_empty());
     }
-    #[test]
-    fn integration_parse_metrics_tserver() {
+    #[tokio::test]
+    async fn integration_parse_metrics_tserver() {
         let hostname = utility::get_hostname_tserver();
         let port = utility::get_port_tserver();
-        let allstoredmetrics = test_function_read_metrics(hostname, port);
+        let allstoredmetrics = test_function_read_metrics(hostname, port).await;
         // a tablet server will produce values and countsum rows, but no countsumrows rows, because that belongs to YSQL.
         assert!(!allstoredmetrics.stored_values.is_empty());
         assert!(!allstoredmetrics.stored_countsum.is_empty());
         assert!(allstoredmetrics.stored_countsumrows.is_empty());
     }
-    #[test]
-    fn integration_parse_metrics_ysql() {
+    #[tokio::test]
+    async fn integration_parse_metrics_ysql() {
         let hostname = utility::get_hostname_ysql();
         let port = utility::get_port_ysql();
-        let allstoredmetrics = test_function_read_metrics(hostname, port);
+        let allstoredmetrics = test_function_read_metrics(hostname, port).await;
         // YSQL will produce countsumrows rows, but no value or countsum rows
         assert!(allstoredmetrics.stored_values.is_empty());
         assert!(allstoredmetrics.stored_countsum.is_empty());
         //assert!(!allstoredmetrics.stored_countsumrows.is_empty());
     }
-    #[test]
-    fn integration_parse_metrics_ycql() {
+    #[tokio::test]
+    async fn integration_parse_metrics_ycql() {
         let hostname = utility::get_hostname_ycql();
         let port = utility::get_port_ycql();
-        let allstoredmetrics = test_function_read_metrics(hostname, port);
+        let allstoredmetrics = test_function_read_metrics(hostname, port).await;
         // YCQL will produce values and countsum rows, but no countsumrows rows, because that belongs to YSQL.
         // countsum rows are filtered on count == 0, which is true if it wasn't used. therefore, we do not check on countsum statistics. likely, YCQL wasn't used prior to the test.
         assert!(!allstoredmetrics.stored_values.is_empty());
         //assert!(allstoredmetrics.stored_countsum.len() > 0);
         assert!(allstoredmetrics.stored_countsumrows.is_empty());
     }
-    #[test]
-    fn integration_parse_metrics_yedis() {
+    #[tokio::test]
+    async fn integration_parse_metrics_yedis() {
         let hostname = utility::get_hostname_yedis();
         let port = utility::get_port_yedis();
-        let allstoredmetrics = test_function_read_metrics(hostname, port);
+        let allstoredmetrics = test_function_read_metrics(hostname, port).await;
         // YEDIS will produce values and countsum rows, but no countsumrows rows, because that belongs to YSQL.
         // countsum rows are filtered on count == 0, which is true when it wasn't used. therefore, we do not check on countsum statistics. likely, YEDIS wasn't used prior to the test.
         assert!(!allstoredmetrics.stored_values.is_empty());
@@ -1,11 +1,20 @@
 //! The module for prometheus metrics from /metrics endpoint of node-exporter.
-use std::{collections::BTreeMap, process, sync::mpsc::channel, fs, env, time::Instant, error::Error};
+use std::{collections::BTreeMap, process, time::Instant, error::Error};
 use chrono::{DateTime, Local, Utc};
 use prometheus_parse::Value;
 use serde_derive::{Serialize,Deserialize};
 use regex::Regex;
 use log::*;
-use crate::utility::{scan_host_port, http_get};
+use crate::snapshot::snapshot_directory;
+use futures::future::join_all;
+use crate::utility::http_get;
+use crate::endpoints::has_role;
+use crate::compression::create_snapshot_file;
+use crate::schema::open_snapshot_csv;
+use crate::output::{self, DiffRecord};
+
+/// The endpoint roles that provide /metrics of node_exporter.
+pub const ROLES: &[&str] = &["node_exporter"];
 
 #[derive(Debug)]
 pub struct NodeExporterValues {
@@ -71,6 +80,17 @@ impl SnapshotDiffNodeExporter {
             second_value: storednodeexportervalues.node_exporter_value,
         }
     }
+    fn second_snapshot_as_first(nodeexporter_diff_row: &SnapshotDiffNodeExporter) -> Self
+    {
+        Self {
+            first_snapshot_time: nodeexporter_diff_row.second_snapshot_time,
+            second_snapshot_time: nodeexporter_diff_row.second_snapshot_time,
+            node_exporter_type: nodeexporter_diff_row.node_exporter_type.to_string(),
+            category: nodeexporter_diff_row.category.to_string(),
+            first_value: nodeexporter_diff_row.second_value,
+            second_value: 0.,
+        }
+    }
 }
 
 pub struct AllStoredNodeExporterValues {
@@ -81,12 +101,11 @@ impl AllStoredNodeExporterValues {
         hosts: &Vec<&str>,
         ports: &Vec<&str>,
         snapshot_number: i32,
-        parallel: usize,
     ) {
         info!("begin snapshot");
         let timer = Instant::now();
 
-        let allstorednodeexportervalues = AllStoredNodeExporterValues::read_nodeexporter(hosts, ports, parallel);
+        let allstorednodeexportervalues = AllStoredNodeExporterValues::read_nodeexporter(hosts, ports);
         allstorednodeexportervalues.await.save_snapshot(snapshot_number)
             .unwrap_or_else(|e| {
                 error!("error saving snapshot: {}", e);
@@ -95,21 +114,17 @@ impl AllStoredNodeExporterValues {
 
         info!("end snapshot: {:?}", timer.elapsed())
     }
-    fn save_snapshot(self, snapshot_number: i32) -> Result<(), Box<dyn Error>>
+    pub fn save_snapshot(self, snapshot_number: i32) -> Result<(), Box<dyn Error>>
     {
-        let current_directory = env::current_dir()?;
-        let current_snapshot_directory = current_directory.join("yb_stats.snapshots").join(&snapshot_number.to_string());
+        let current_snapshot_directory = snapshot_directory().join(snapshot_number.to_string());
 
         let nodeexporter_file = &current_snapshot_directory.join("nodeexporter");
-        let file = fs::OpenOptions::new()
-            .create(true)
-            .write(true)
-            .open(nodeexporter_file)?;
+        let file = create_snapshot_file(nodeexporter_file)?;
         let mut writer = csv::Writer::from_writer(file);
         for row in self.stored_nodeexportervalues {
             writer.serialize(row)?;
         }
-        writer.flush()?;
+        writer.into_inner()?.finish()?;
 
         Ok(())
     }
@@ -117,11 +132,10 @@ impl AllStoredNodeExporterValues {
     {
         let mut allstorednodeexportervalues = AllStoredNodeExporterValues { stored_nodeexportervalues: Vec::new() };
 
-        let current_directory = env::current_dir()?;
-        let current_snapshot_directory = current_directory.join("yb_stats.snapshots").join(snapshot_number);
+        let current_snapshot_directory = snapshot_directory().join(snapshot_number);
 
         let nodeexporter_file = &current_snapshot_directory.join("nodeexporter");
-        let file = fs::File::open(nodeexporter_file)?;
+        let file = open_snapshot_csv(nodeexporter_file)?;
 
         let mut reader = csv::Reader::from_reader(file);
         for  row in reader.deserialize() {
@@ -134,45 +148,41 @@ impl AllStoredNodeExporterValues {
     pub async fn read_nodeexporter(
         hosts: &Vec<&str>,
         ports: &Vec<&str>,
-        parallel: usize
     ) -> AllStoredNodeExporterValues
     {
         info!("begin parallel http read");
         let timer = Instant::now();
 
-        let pool = rayon::ThreadPoolBuilder::new().num_threads(parallel).build().unwrap();
-        let (tx, rx) = channel();
-        pool.scope(move |s| {
-            for host in hosts {
-                for port in ports {
-                    let tx = tx.clone();
-                    s.spawn(move |_| {
-                        let detail_snapshot_time = Local::now();
-                        let node_exporter_values = AllStoredNodeExporterValues::read_http(host, port);
-                        tx.send((format!("{}:{}", host, port), detail_snapshot_time, node_exporter_values)).expect("error sending data via tx (node_exporter)");
-                    });
+        let mut handles = Vec::new();
+        for host in hosts {
+            for port in ports {
+                if !has_role(host, port, ROLES) {
+                    continue;
                 }
+                let handle = async move {
+                    let detail_snapshot_time = Local::now();
+                    let node_exporter_values = AllStoredNodeExporterValues::read_http(host, port).await;
+                    (format!("{}:{}", host, port), detail_snapshot_time, node_exporter_values)
+                };
+                handles.push(handle);
             }
-        });
+        }
+        let results = join_all(handles).await;
 
         info!("end parallel http read {:?}", timer.elapsed());
 
         let mut allstorednodeexportervalues = AllStoredNodeExporterValues { stored_nodeexportervalues: Vec::new() };
-        for (hostname_port, _detail_snapshot_time, node_exporter_values) in rx {
+        for (hostname_port, _detail_snapshot_time, node_exporter_values) in results {
             AllStoredNodeExporterValues::add_to_vector(node_exporter_values, &hostname_port, &mut allstorednodeexportervalues);
         }
         allstorednodeexportervalues
     }
-    pub fn read_http(
+    pub async fn read_http(
         host: &str,
         port: &str,
     ) -> Vec<NodeExporterValues>
     {
-        let data_from_http = if scan_host_port( host, port) {
-            http_get(host, port, "metrics")
-        } else {
-            String::new()
-        };
+        let data_from_http = http_get(host, port, "metrics").await;
         AllStoredNodeExporterValues::parse_nodeexporter(data_from_http)
     }
     fn parse_nodeexporter( node_exporter_data: String ) -> Vec<NodeExporterValues>
@@ -318,10 +328,9 @@ impl SnapshotDiffBTreeMapNodeExporter {
         &mut self,
         hosts: &Vec<&str>,
         ports: &Vec<&str>,
-        parallel: usize,
     )
     {
-        let allstorednodeexportervalues = AllStoredNodeExporterValues::read_nodeexporter(hosts, ports, parallel).await;
+        let allstorednodeexportervalues = AllStoredNodeExporterValues::read_nodeexporter(hosts, ports).await;
         self.first_snapshot(allstorednodeexportervalues);
     }
     fn first_snapshot(
@@ -340,13 +349,30 @@ impl SnapshotDiffBTreeMapNodeExporter {
         &mut self,
         hosts: &Vec<&str>,
         ports: &Vec<&str>,
-        parallel: usize,
         first_snapshot_time: &DateTime<Local>,
     )
     {
-        let allstorednodeexporter = AllStoredNodeExporterValues::read_nodeexporter(hosts, ports, parallel).await;
+        let allstorednodeexporter = AllStoredNodeExporterValues::read_nodeexporter(hosts, ports).await;
         self.second_snapshot(allstorednodeexporter, first_snapshot_time);
     }
+    /// This function reads the next snapshot data from the http endpoints itself, after the second snapshot is turned into the first snapshot (=top mode).
+    /// This way, the struct keeps the previous snapshot, and only one snapshot is read per interval.
+    /// Values that are not present in the second snapshot (anymore) are removed: only values higher than 0 are stored.
+    /// The previous_snapshot_time is used as first_snapshot_time for values that are new in the next snapshot.
+    pub async fn adhoc_read_next_snapshot(
+        &mut self,
+        hosts: &Vec<&str>,
+        ports: &Vec<&str>,
+        previous_snapshot_time: &DateTime<Local>,
+    )
+    {
+        let allstorednodeexporter = AllStoredNodeExporterValues::read_nodeexporter(hosts, ports).await;
+        self.btreemap_snapshotdiff_nodeexporter.retain(|_, nodeexporter_diff_row| nodeexporter_diff_row.second_value > 0.);
+        for nodeexporter_diff_row in self.btreemap_snapshotdiff_nodeexporter.values_mut() {
+            *nodeexporter_diff_row = SnapshotDiffNodeExporter::second_snapshot_as_first(nodeexporter_diff_row);
+        }
+        self.second_snapshot(allstorednodeexporter, previous_snapshot_time);
+    }
     fn second_snapshot(
         &mut self,
         allstorednodeexporter: AllStoredNodeExporterValues,
@@ -368,6 +394,46 @@ impl SnapshotDiffBTreeMapNodeExporter {
             }
         }
     }
+    /// This function returns the statistics that are printed by [SnapshotDiffBTreeMapNodeExporter::print] as records for the machine-readable output, see [crate::output].
+    /// The name of a record is the node_exporter name with its labels.
+    pub fn records(
+        &self,
+        hostname_filter: &Regex,
+        stat_name_filter: &Regex,
+        gauges_enable: &bool,
+        details_enable: &bool,
+    ) -> Vec<DiffRecord>
+    {
+        let mut records = Vec::new();
+        for ((hostname, nodeexporter_name), nodeexporter_row) in &self.btreemap_snapshotdiff_nodeexporter {
+            if !hostname_filter.is_match(hostname)
+                || !stat_name_filter.is_match(nodeexporter_name)
+                || (*details_enable && nodeexporter_row.category == "summary")
+                || (!*details_enable && nodeexporter_row.category == "detail") {
+                continue;
+            }
+            let record = DiffRecord {
+                hostname_port: hostname.to_string(),
+                metric_type: nodeexporter_row.node_exporter_type.to_string(),
+                name: nodeexporter_name.to_string(),
+                delta: Some(nodeexporter_row.second_value - nodeexporter_row.first_value),
+                ..Default::default()
+            };
+            if nodeexporter_row.node_exporter_type == "counter"
+                && nodeexporter_row.second_value - nodeexporter_row.first_value != 0.0 {
+                records.push(DiffRecord::statistic("node_exporter", nodeexporter_row.first_snapshot_time, nodeexporter_row.second_snapshot_time, record));
+            } else if nodeexporter_row.node_exporter_type == "gauge"
+                && *gauges_enable {
+                // the difference of a gauge has no rate.
+                records.push(DiffRecord {
+                    value: Some(nodeexporter_row.second_value),
+                    rate: None,
+                    ..DiffRecord::statistic("node_exporter", nodeexporter_row.first_snapshot_time, nodeexporter_row.second_snapshot_time, record)
+                });
+            }
+        }
+        records
+    }
     pub fn print(
         &self,
         hostname_filter: &Regex,
@@ -376,6 +442,10 @@ impl SnapshotDiffBTreeMapNodeExporter {
         details_enable: &bool,
     )
     {
+        if output::is_machine_readable() {
+            output::write_records(&self.records(hostname_filter, stat_name_filter, gauges_enable, details_enable));
+            return;
+        }
         for ((hostname, nodeexporter_name), nodeexporter_row) in &self.btreemap_snapshotdiff_nodeexporter {
             if hostname_filter.is_match(hostname)
                 && stat_name_filter.is_match(nodeexporter_name)
@@ -756,8 +826,8 @@ request_duration_sum 22.978489699999997
 
     use crate::utility;
 
-    #[test]
-    fn integration_parse_node_exporter() {
+    #[tokio::test]
+    async fn integration_parse_node_exporter() {
         let hostname = utility::get_hostname_node_exporter();
         if hostname == *"SKIP" {
             // workaround for allowing integration tests where no node exporter is present.
@@ -766,7 +836,7 @@ request_duration_sum 22.978489699999997
         let port = utility::get_port_node_exporter();
 
         let mut allstorednodeexportervalues = AllStoredNodeExporterValues { stored_nodeexportervalues: Vec::new() };
-        let node_exporter_values = AllStoredNodeExporterValues::read_http(hostname.as_str(), port.as_str());
+        let node_exporter_values = AllStoredNodeExporterValues::read_http(hostname.as_str(), port.as_str()).await;
         AllStoredNodeExporterValues::add_to_vector(node_exporter_values, format!("{}:{}",hostname, port).as_ref(), &mut allstorednodeexportervalues);
         // a node exporter endpoint will generate entries in the stored_nodeexportervalues vector.
         assert!(!allstorednodeexportervalues.stored_nodeexportervalues.is_empty());
@@ -0,0 +1,227 @@
+//! The module for the machine-readable output of the snapshot-diff and ad-hoc reports.
+//!
+//! The output is set with `--output text|json|csv` (default text). With json or csv, the print functions of the diffs
+//! don't print the fixed-width text, but write every line of the report as a [DiffRecord] to stdout:
+//! - json: a JSON object per line (JSON lines).
+//! - csv: a CSV record per line, with a single header line for all the sections.
+//!
+//! All the sections use the same record, so the output of a report can be loaded as a single table.
+//! The fields that don't apply to a section are empty.
+use std::{fmt, io, str::FromStr, sync::{OnceLock, atomic::{AtomicBool, Ordering}}};
+use chrono::{DateTime, Local};
+use log::*;
+
+/// The output of the reports, which is set once in main using [set_output_format].
+static OUTPUT_FORMAT: OnceLock<OutputFormat> = OnceLock::new();
+/// The CSV header is written once, before the first record.
+static CSV_HEADER_WRITTEN: AtomicBool = AtomicBool::new(false);
+
+/// The output of the snapshot-diff and ad-hoc reports.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OutputFormat {
+    #[default]
+    Text,
+    Json,
+    Csv,
+}
+
+impl FromStr for OutputFormat {
+    type Err = String;
+
+    fn from_str(output_format: &str) -> Result<Self, Self::Err> {
+        match output_format {
+            "text" => Ok(OutputFormat::Text),
+            "json" => Ok(OutputFormat::Json),
+            "csv" => Ok(OutputFormat::Csv),
+            _ => Err(format!("invalid output: {}, valid outputs are: text, json, csv", output_format)),
+        }
+    }
+}
+
+impl fmt::Display for OutputFormat {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            OutputFormat::Text => write!(f, "text"),
+            OutputFormat::Json => write!(f, "json"),
+            OutputFormat::Csv => write!(f, "csv"),
+        }
+    }
+}
+
+/// This function sets the output of the reports for the whole run of yb_stats.
+pub fn set_output_format(output_format: OutputFormat) {
+    OUTPUT_FORMAT.set(output_format).unwrap_or_else(|_| warn!("output is already set"));
+}
+
+/// The output of the reports.
+pub fn output_format() -> OutputFormat {
+    OUTPUT_FORMAT.get().copied().unwrap_or_default()
+}
+
+/// This function tells if the reports are written as records, which means other messages should not be printed to stdout.
+pub fn is_machine_readable() -> bool {
+    output_format() != OutputFormat::Text
+}
+
+/// This function prints a message of a report: to stdout, or to stderr if the reports are written as records, so the records are not mixed with text.
+pub fn print_message(message: &str) {
+    if is_machine_readable() {
+        eprintln!("{}", message);
+    } else {
+        println!("{}", message);
+    }
+}
+
+/// A line of a diff report.
+/// - For statistics, the difference is in `delta`, and the difference per second in `rate`. The current value of a gauge is in `value`.
+/// - For statistics with a count and a sum, `delta` and `rate` are the count, `sum_delta` is the sum difference and `average` is the sum difference divided by the count difference.
+/// - For objects (entities, masters, tablet servers, vars and versions), `change` is `+` (added), `-` (removed) or `*` (changed).
+///   An added or removed object has its description in `second` or `first`, a changed object has a record for every changed `attribute`, with the value before in `first` and after in `second`.
+#[derive(Serialize, Debug, Default, Clone, PartialEq)]
+pub struct DiffRecord {
+    pub section: String,
+    pub hostname_port: String,
+    pub metric_type: String,
+    pub metric_id: String,
+    pub namespace: String,
+    pub table_name: String,
+    pub name: String,
+    pub change: String,
+    pub attribute: String,
+    pub first: String,
+    pub second: String,
+    pub value: Option<f64>,
+    pub delta: Option<f64>,
+    pub seconds: Option<f64>,
+    pub rate: Option<f64>,
+    pub average: Option<f64>,
+    pub sum_delta: Option<f64>,
+    pub rows_delta: Option<f64>,
+    pub unit: String,
+}
+
+impl DiffRecord {
+    /// This function creates a record for a statistic, and sets the seconds between the snapshots, and the rate if the delta is set.
+    pub fn statistic(
+        section: &str,
+        first_snapshot_time: DateTime<Local>,
+        second_snapshot_time: DateTime<Local>,
+        record: DiffRecord,
+    ) -> Self
+    {
+        let seconds = (second_snapshot_time - first_snapshot_time).num_milliseconds() as f64 / 1000_f64;
+        DiffRecord {
+            section: section.to_string(),
+            seconds: Some(seconds),
+            rate: record.delta.filter(|_| seconds > 0_f64).map(|delta| delta / seconds),
+            ..record
+        }
+    }
+    /// This function creates a record for an added (`+`), removed (`-`) or changed (`*`) object.
+    pub fn object(
+        section: &str,
+        change: &str,
+        record: DiffRecord,
+    ) -> Self
+    {
+        DiffRecord {
+            section: section.to_string(),
+            change: change.to_string(),
+            ..record
+        }
+    }
+}
+
+/// This function adds a record for a changed attribute of an object, if the first and second value are different.
+pub fn push_changed_attribute(
+    records: &mut Vec<DiffRecord>,
+    record: &DiffRecord,
+    attribute: &str,
+    first: &str,
+    second: &str,
+)
+{
+    if first != second {
+        records.push(DiffRecord {
+            attribute: attribute.to_string(),
+            first: first.to_string(),
+            second: second.to_string(),
+            ..record.clone()
+        });
+    }
+}
+
+/// This function writes the records to stdout in the output format.
+pub fn write_records<T: serde::Serialize>(records: &[T]) {
+    write(records, output_format(), io::stdout().lock())
+        .unwrap_or_else(|e| error!("error writing the records: {}", e));
+}
+
+fn write<T: serde::Serialize, W: io::Write>(
+    records: &[T],
+    output_format: OutputFormat,
+    mut writer: W,
+) -> Result<(), Box<dyn std::error::Error>>
+{
+    match output_format {
+        OutputFormat::Text => {},
+        OutputFormat::Json => {
+            for record in records {
+                serde_json::to_writer(&mut writer, record)?;
+                writeln!(writer)?;
+            }
+        },
+        OutputFormat::Csv => {
+            // The header is written with the first record, and only once for all the sections.
+            let header = !records.is_empty() && !CSV_HEADER_WRITTEN.swap(true, Ordering::SeqCst);
+            let mut csv_writer = csv::WriterBuilder::new().has_headers(header).from_writer(writer);
+            for record in records {
+                csv_writer.serialize(record)?;
+            }
+            csv_writer.flush()?;
+        },
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn unit_output_format_parse() {
+        assert_eq!("csv".parse::<OutputFormat>(), Ok(OutputFormat::Csv));
+        assert_eq!(OutputFormat::Json.to_string(), "json");
+        assert!("xml".parse::<OutputFormat>().is_err());
+    }
+    #[test]
+    fn unit_diff_record_statistic() {
+        let record = DiffRecord::statistic(
+            "metrics",
+            Local.with_ymd_and_hms(2023, 1, 10, 10, 0, 0).unwrap(),
+            Local.with_ymd_and_hms(2023, 1, 10, 10, 0, 20).unwrap(),
+            DiffRecord { name: String::from("rows_inserted"), delta: Some(100_f64), ..Default::default() },
+        );
+        assert_eq!((record.section.as_str(), record.seconds, record.rate), ("metrics", Some(20_f64), Some(5_f64)));
+        let mut records = Vec::new();
+        let tablet = DiffRecord::object("entities", "*", DiffRecord { name: String::from("abc"), ..Default::default() });
+        push_changed_attribute(&mut records, &tablet, "state", "RUNNING", "RUNNING");
+        push_changed_attribute(&mut records, &tablet, "leader", "yb-1", "yb-2");
+        assert_eq!(records.len(), 1);
+        assert_eq!((records[0].change.as_str(), records[0].attribute.as_str(), records[0].first.as_str(), records[0].second.as_str()), ("*", "leader", "yb-1", "yb-2"));
+    }
+    #[test]
+    fn unit_write_records() {
+        let records = vec![DiffRecord { section: String::from("statements"), name: String::from("select 1, 2"), delta: Some(3_f64), ..Default::default() }];
+        let mut csv = Vec::new();
+        write(&records, OutputFormat::Csv, &mut csv).unwrap();
+        write::<DiffRecord, _>(&[], OutputFormat::Csv, &mut csv).unwrap();
+        write(&records, OutputFormat::Csv, &mut csv).unwrap();
+        let record = "statements,,,,,,\"select 1, 2\",,,,,,3.0,,,,,,\n";
+        assert_eq!(String::from_utf8(csv).unwrap(), format!("section,hostname_port,metric_type,metric_id,namespace,table_name,name,change,attribute,first,second,value,delta,seconds,rate,average,sum_delta,rows_delta,unit\n{}{}", record, record));
+        let mut json = Vec::new();
+        write(&records, OutputFormat::Json, &mut json).unwrap();
+        assert!(String::from_utf8(json).unwrap().starts_with("{\"section\":\"statements\",\"hostname_port\":\"\""));
+    }
+}
@@ -1,20 +1,20 @@
-use std::path::PathBuf;
-use std::fs;
 use std::io::Write;
 use std::process;
-use std::sync::mpsc::channel;
 use log::*;
-use crate::utility::{scan_host_port, http_get};
+use futures::future::join_all;
+use crate::utility::http_get;
+use crate::endpoints::has_role;
+use crate::compression::create_snapshot_file;
+use crate::snapshot::snapshot_directory;
 
-pub fn read_pprof(
+/// The endpoint roles that provide /pprof/growth.
+pub const ROLES: &[&str] = &["master", "tserver"];
+
+pub async fn read_pprof(
     host: &str,
     port: &str,
 ) -> String {
-    if scan_host_port( host, port) {
-        http_get(host, port, "pprof/growth")
-    } else {
-        String::new()
-    }
+    http_get(host, port, "pprof/growth").await
 }
 
 #[allow(clippy::ptr_arg)]
@@ -22,38 +22,39 @@ pub async fn perform_pprof_snapshot(
     hosts: &Vec<&str>,
     ports: &Vec<&str>,
     snapshot_number: i32,
-    yb_stats_directory: &PathBuf,
-    parallel: usize
 ) {
     info!("perform_pprof_snapshot");
-    let pool = rayon::ThreadPoolBuilder::new().num_threads(parallel).build().unwrap();
-    let (tx, rx) = channel();
-    pool.scope(move |s| {
-        for host in hosts {
-            for port in ports {
-                let tx = tx.clone();
-                s.spawn(move |_| {
-                    let pprof_data = read_pprof(host, port);
-                    tx.send((format!("{}:{}", host, port), pprof_data)).expect("error sending data via tx (pprof)");
-                });
-            }}
-    });
-    for (hostname_port, pprof) in rx {
+    let mut handles = Vec::new();
+    for host in hosts {
+        for port in ports {
+            if !has_role(host, port, ROLES) {
+                continue;
+            }
+            let handle = async move {
+                let pprof_data = read_pprof(host, port).await;
+                (format!("{}:{}", host, port), pprof_data)
+            };
+            handles.push(handle);
+        }
+    }
+    let results = join_all(handles).await;
+    for (hostname_port, pprof) in results {
 
         if pprof.starts_with("heap profile") {
-            let current_snapshot_directory = &yb_stats_directory.join(&snapshot_number.to_string());
+            let current_snapshot_directory = &snapshot_directory().join(&snapshot_number.to_string());
             let pprof_file = &current_snapshot_directory.join(format!("pprof_growth_{}", hostname_port));
-            let mut file = fs::OpenOptions::new()
-                .create(true)
-                .write(true)
-                .open(pprof_file)
+            let mut file = create_snapshot_file(pprof_file)
                 .unwrap_or_else(|e| {
                     error!("Fatal: error writing pprof growth data in snapshot directory {}: {}", &pprof_file.clone().into_os_string().into_string().unwrap(), e);
                     process::exit(1);
                 });
             //let mut writer = csv::Writer::from_writer(file);
             file.write_all(pprof.as_bytes()).unwrap_or_else(|e| {
-                error!("Fatal: error pprof growth data in snapshot directory {:?}: {}", &file, e);
+                error!("Fatal: error writing pprof growth data in snapshot directory {}: {}", &pprof_file.clone().into_os_string().into_string().unwrap(), e);
+                process::exit(1);
+            });
+            file.finish().unwrap_or_else(|e| {
+                error!("Fatal: error writing pprof growth data in snapshot directory {}: {}", &pprof_file.clone().into_os_string().into_string().unwrap(), e);
                 process::exit(1);
             });
         };
@@ -67,20 +68,20 @@ mod tests {
 
     use crate::utility;
 
-    #[test]
-    fn integration_parse_pprof_growth_tserver() {
+    #[tokio::test]
+    async fn integration_parse_pprof_growth_tserver() {
         // currently, the pprof "parsing" is not much parsing.
         // What currently is done, is that the hostname:port/pprof/growth output is stored in a file in the snapshot directory named <hostname>:<port>_pprof_growth.
         let hostname = utility::get_hostname_tserver();
         let port = utility::get_port_tserver();
-        read_pprof(&hostname, &port);
+        read_pprof(&hostname, &port).await;
     }
-    #[test]
-    fn integration_parse_pprof_growth_master() {
+    #[tokio::test]
+    async fn integration_parse_pprof_growth_master() {
         // currently, the pprof "parsing" is not much parsing.
         // What currently is done, is that the hostname:port/pprof/growth output is stored in a file in the snapshot directory named <hostname>:<port>_pprof_growth.
         let hostname = utility::get_hostname_master();
         let port = utility::get_port_master();
-        read_pprof(&hostname, &port);
+        read_pprof(&hostname, &port).await;
     }
 }
@@ -0,0 +1,218 @@
+//! The module for the rates of the metrics over an interval, which are shared by the top mode ([crate::top]) and the exporter mode ([crate::exporter]).
+//!
+//! Both modes keep the previous read in a [SnapshotDiffBTreeMapsMetrics] (see [SnapshotDiffBTreeMapsMetrics::next_snapshot]),
+//! and take the differences of the counters over the interval with [interval_differences]:
+//! - value statistics that are counters: the difference of the value. Gauges are skipped, because these do not have a rate.
+//! - countsum statistics: the difference of the count and the sum.
+//! - countsumrows (YSQL) statistics: the difference of the count, the sum and the rows. The sum is in microseconds, and is converted to milliseconds.
+//!
+//! A statistic that is gone (such as of a deleted table), that is new in the second snapshot, or that has a negative difference (the server was restarted) is skipped,
+//! before the differences are added up per [StatisticKey] with the [Grouping] of the mode.
+use std::collections::BTreeMap;
+use chrono::{DateTime, Local};
+use crate::metrics::SnapshotDiffBTreeMapsMetrics;
+use crate::value_statistic_details::ValueStatistics;
+use crate::utility::Filters;
+
+/// The number of seconds between two timestamps, as a float.
+pub fn seconds_between(first: &DateTime<Local>, second: &DateTime<Local>) -> f64 {
+    (*second - *first).num_milliseconds() as f64 / 1000_f64
+}
+
+/// The type of statistic of a difference.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum StatisticType {
+    Value,
+    CountSum,
+    CountSumRows,
+}
+
+/// How the statistics of the tables and tablets are added up.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Grouping {
+    /// Per server: the namespace, table name and metric_id are "-".
+    Server,
+    /// Per table: the metric_id is "-", so the tablets of a table are added up.
+    Table,
+    /// Per metric_id: every table and tablet separately.
+    Id,
+}
+
+/// The key of the differences that are added up.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct StatisticKey {
+    pub statistic_type: StatisticType,
+    pub hostname_port: String,
+    pub metric_type: String,
+    pub metric_name: String,
+    pub namespace: String,
+    pub table_name: String,
+    pub metric_id: String,
+}
+
+impl StatisticKey {
+    fn new(
+        statistic_type: StatisticType,
+        (hostname_port, metric_type, metric_id, metric_name): &(String, String, String, String),
+        namespace: &str,
+        table_name: &str,
+        grouping: Grouping,
+    ) -> Self
+    {
+        let (namespace, table_name, metric_id) = match grouping {
+            Grouping::Server => ("-", "-", "-"),
+            Grouping::Table => (namespace, table_name, "-"),
+            Grouping::Id => (namespace, table_name, metric_id.as_str()),
+        };
+        StatisticKey {
+            statistic_type,
+            hostname_port: hostname_port.to_string(),
+            metric_type: metric_type.to_string(),
+            metric_name: metric_name.to_string(),
+            namespace: namespace.to_string(),
+            table_name: table_name.to_string(),
+            metric_id: metric_id.to_string(),
+        }
+    }
+}
+
+/// The differences of a statistic over the interval, added up for the key.
+/// The count is the difference of the value for value statistics.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Difference {
+    pub count: f64,
+    pub sum: f64,
+    pub rows: f64,
+    pub seconds: f64,
+}
+
+impl Difference {
+    /// The count difference per second.
+    pub fn rate(&self) -> f64 {
+        self.count / self.seconds
+    }
+    /// The rows difference per second.
+    pub fn rows_rate(&self) -> f64 {
+        self.rows / self.seconds
+    }
+    /// The sum difference per count, if the count changed.
+    pub fn average(&self) -> Option<f64> {
+        if self.count > 0_f64 { Some(self.sum / self.count) } else { None }
+    }
+}
+
+/// This function takes the differences in [SnapshotDiffBTreeMapsMetrics], filtered with the filters, and adds these up per [StatisticKey] with the grouping.
+pub fn interval_differences(
+    metrics: &SnapshotDiffBTreeMapsMetrics,
+    filters: &Filters,
+    grouping: Grouping,
+) -> BTreeMap<StatisticKey, Difference>
+{
+    let value_statistics = ValueStatistics::create();
+    let mut differences: BTreeMap<StatisticKey, Difference> = BTreeMap::new();
+
+    for (key, row) in &metrics.btreemap_snapshotdiff_values {
+        // a second value of 0 means the statistic is gone, such as for a deleted table.
+        if row.second_snapshot_value == 0
+            || row.second_snapshot_value < row.first_snapshot_value
+            || row.second_snapshot_time <= row.first_snapshot_time
+            || value_statistics.lookup(&key.3).stat_type == "gauge"
+            || !filters.hostname_filter.is_match(&key.0)
+            || !filters.stat_name_filter.is_match(&key.3)
+            || !filters.table_name_filter.is_match(&row.table_name) {
+            continue;
+        }
+        let difference = differences.entry(StatisticKey::new(StatisticType::Value, key, &row.namespace, &row.table_name, grouping)).or_default();
+        difference.count += (row.second_snapshot_value - row.first_snapshot_value) as f64;
+        difference.seconds = seconds_between(&row.first_snapshot_time, &row.second_snapshot_time);
+    }
+    for (key, row) in &metrics.btreemap_snapshotdiff_countsum {
+        if row.second_snapshot_total_count == 0
+            || row.second_snapshot_total_count < row.first_snapshot_total_count
+            || row.second_snapshot_total_sum < row.first_snapshot_total_sum
+            || row.second_snapshot_time <= row.first_snapshot_time
+            || !filters.hostname_filter.is_match(&key.0)
+            || !filters.stat_name_filter.is_match(&key.3)
+            || !filters.table_name_filter.is_match(&row.table_name) {
+            continue;
+        }
+        let difference = differences.entry(StatisticKey::new(StatisticType::CountSum, key, &row.namespace, &row.table_name, grouping)).or_default();
+        difference.count += (row.second_snapshot_total_count - row.first_snapshot_total_count) as f64;
+        difference.sum += (row.second_snapshot_total_sum - row.first_snapshot_total_sum) as f64;
+        difference.seconds = seconds_between(&row.first_snapshot_time, &row.second_snapshot_time);
+    }
+    for (key, row) in &metrics.btreemap_snapshotdiff_countsumrows {
+        if row.second_snapshot_count < row.first_snapshot_count
+            || row.second_snapshot_sum < row.first_snapshot_sum
+            || row.second_snapshot_rows < row.first_snapshot_rows
+            || row.second_snapshot_time <= row.first_snapshot_time
+            || !filters.hostname_filter.is_match(&key.0)
+            || !filters.stat_name_filter.is_match(&key.3) {
+            continue;
+        }
+        let difference = differences.entry(StatisticKey::new(StatisticType::CountSumRows, key, &row.namespace, &row.table_name, grouping)).or_default();
+        difference.count += (row.second_snapshot_count - row.first_snapshot_count) as f64;
+        // the sum of countsumrows statistics is in microseconds, the average is in milliseconds.
+        difference.sum += (row.second_snapshot_sum - row.first_snapshot_sum) as f64 / 1000_f64;
+        difference.rows += (row.second_snapshot_rows - row.first_snapshot_rows) as f64;
+        difference.seconds = seconds_between(&row.first_snapshot_time, &row.second_snapshot_time);
+    }
+    differences
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+    use regex::Regex;
+    use crate::metrics::{SnapshotDiffValues, SnapshotDiffCountSumRows};
+
+    fn time(second: u32) -> DateTime<Local> {
+        Local.with_ymd_and_hms(2023, 1, 10, 10, 0, second).unwrap()
+    }
+
+    #[test]
+    fn unit_rates_grouping_and_skipped_rows() {
+        let mut metrics = SnapshotDiffBTreeMapsMetrics::new();
+        let value = |first_snapshot_time: DateTime<Local>, first_snapshot_value: i64, second_snapshot_value: i64| SnapshotDiffValues {
+            table_name: String::from("t1"),
+            namespace: String::from("yugabyte"),
+            first_snapshot_time,
+            second_snapshot_time: time(10),
+            first_snapshot_value,
+            second_snapshot_value,
+        };
+        let key = |metric_id: &str| (String::from("yb-1:9000"), String::from("tablet"), metric_id.to_string(), String::from("rows_inserted"));
+        metrics.btreemap_snapshotdiff_values.insert(key("tablet1"), value(time(0), 100, 200));
+        metrics.btreemap_snapshotdiff_values.insert(key("tablet2"), value(time(0), 100, 150));
+        // gone, reset by a restart and new: skipped.
+        metrics.btreemap_snapshotdiff_values.insert(key("tablet3"), value(time(0), 100, 0));
+        metrics.btreemap_snapshotdiff_values.insert(key("tablet4"), value(time(0), 100, 10));
+        metrics.btreemap_snapshotdiff_values.insert(key("tablet5"), value(time(10), 0, 1000));
+        metrics.btreemap_snapshotdiff_countsumrows.insert((String::from("yb-1:13000"), String::from("server"), String::from("yb.ysqlserver"), String::from("handler_latency_yb_ysqlserver_SQLProcessor_SelectStmt")), SnapshotDiffCountSumRows {
+            table_name: String::from("-"),
+            namespace: String::from("-"),
+            first_snapshot_time: time(0),
+            second_snapshot_time: time(10),
+            first_snapshot_count: 10,
+            first_snapshot_sum: 1000,
+            first_snapshot_rows: 10,
+            second_snapshot_count: 30,
+            second_snapshot_sum: 51000,
+            second_snapshot_rows: 110,
+        });
+        let match_all = Regex::new(".*").unwrap();
+        let filters = Filters { hostname_filter: &match_all, stat_name_filter: &match_all, table_name_filter: &match_all, details_enable: false, gauges_enable: false, sql_length: 80 };
+
+        let differences = interval_differences(&metrics, &filters, Grouping::Table);
+        let values = differences.iter().filter(|(key, _)| key.statistic_type == StatisticType::Value).collect::<Vec<_>>();
+        assert_eq!(values.len(), 1);
+        assert_eq!((values[0].0.table_name.as_str(), values[0].0.metric_id.as_str(), values[0].1.rate()), ("t1", "-", 15_f64));
+        // 50000 microseconds for 20 calls.
+        let countsumrows = differences.iter().find(|(key, _)| key.statistic_type == StatisticType::CountSumRows).unwrap().1;
+        assert_eq!((countsumrows.rate(), countsumrows.rows_rate(), countsumrows.average()), (2_f64, 10_f64, Some(2.5_f64)));
+
+        assert_eq!(interval_differences(&metrics, &filters, Grouping::Id).len(), 3);
+        assert!(interval_differences(&metrics, &filters, Grouping::Server).keys().all(|key| key.table_name == "-"));
+    }
+}
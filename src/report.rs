@@ -0,0 +1,367 @@
+//! The module for the HTML report of a snapshot diff (`--html-report <file>`).
+//!
+//! The report is a single HTML file without external assets (the style and the script to sort the tables are inline), so it can be shared with people who don't run yb_stats.
+//! It is built from the same diffs as `--snapshot-diff`, using the records of the machine-readable output (see [crate::output]), so the filters and switches are applied in the same way.
+//!
+//! The report contains:
+//! - The begin and end snapshot.
+//! - The cluster topology of the end snapshot: the endpoints, the masters and the tablet servers.
+//! - The metrics, statements and node_exporter statistics, in tables that are sorted by clicking on a column header.
+//! - The changes of the entities, masters, tablet servers, vars and versions.
+use std::{collections::BTreeMap, error::Error, fmt::Write, fs};
+use chrono::Local;
+use log::*;
+use crate::output::DiffRecord;
+use crate::utility::Filters;
+use crate::snapshot::Snapshot;
+use crate::manifest::{self, SnapshotManifest};
+use crate::{entities, masters, metrics, node_exporter, statements, tservers, vars, versions};
+
+/// The inline style of the report.
+const STYLE: &str = r#"body { font-family: sans-serif; font-size: 13px; margin: 20px; }
+h1 { font-size: 20px; } h2 { font-size: 16px; margin-top: 30px; }
+table { border-collapse: collapse; margin-bottom: 10px; }
+th, td { border: 1px solid #ccc; padding: 2px 6px; text-align: left; }
+th { background: #eee; }
+table.sortable th { cursor: pointer; }
+th[data-order=asc]::after { content: " \25b2"; } th[data-order=desc]::after { content: " \25bc"; }
+td.number { text-align: right; font-family: monospace; }
+td.query { font-family: monospace; white-space: pre-wrap; max-width: 800px; }
+p.empty { color: #888; }"#;
+
+/// The inline script to sort a table by clicking on a column header. Numbers are sorted by their value, empty numbers first.
+const SCRIPT: &str = r#"document.querySelectorAll("table.sortable th").forEach(function (th) {
+  th.addEventListener("click", function () {
+    var table = th.closest("table"), tbody = table.tBodies[0], index = th.cellIndex;
+    var ascending = th.dataset.order !== "asc";
+    table.querySelectorAll("th").forEach(function (other) { delete other.dataset.order; });
+    th.dataset.order = ascending ? "asc" : "desc";
+    var key = function (cell) {
+      if (!("value" in cell.dataset)) { return cell.textContent; }
+      var value = parseFloat(cell.dataset.value);
+      return isNaN(value) ? -Infinity : value;
+    };
+    Array.from(tbody.rows)
+      .sort(function (a, b) {
+        var x = key(a.cells[index]), y = key(b.cells[index]);
+        var result = typeof x === "string" ? x.localeCompare(y) : (x < y ? -1 : x > y ? 1 : 0);
+        return ascending ? result : -result;
+      })
+      .forEach(function (row) { tbody.appendChild(row); });
+  });
+});"#;
+
+/// A cell of a table: text, or a number that is sorted by its value.
+#[derive(Debug, Clone, PartialEq)]
+enum Cell {
+    Text(String),
+    Number(Option<f64>),
+    Query(String),
+}
+
+/// A column of a table of records.
+type Column = (&'static str, fn(&DiffRecord) -> Cell);
+
+/// This function escapes the characters that have a meaning in HTML.
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for character in text.chars() {
+        match character {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            _ => escaped.push(character),
+        }
+    }
+    escaped
+}
+
+/// This function formats a number: whole numbers without decimals, other numbers with 3 decimals.
+fn format_number(number: f64) -> String {
+    if number.fract() == 0_f64 && number.abs() < 1e15 {
+        format!("{:.0}", number)
+    } else {
+        format!("{:.3}", number)
+    }
+}
+
+/// This function renders a sortable table.
+fn table(
+    headers: &[&str],
+    rows: &[Vec<Cell>],
+) -> String
+{
+    if rows.is_empty() {
+        return String::from("<p class=\"empty\">No rows.</p>\n");
+    }
+    let mut html = String::from("<table class=\"sortable\">\n<thead><tr>");
+    for header in headers {
+        write!(html, "<th>{}</th>", escape(header)).unwrap();
+    }
+    html.push_str("</tr></thead>\n<tbody>\n");
+    for row in rows {
+        html.push_str("<tr>");
+        for cell in row {
+            match cell {
+                Cell::Text(text) => write!(html, "<td>{}</td>", escape(text)).unwrap(),
+                Cell::Query(text) => write!(html, "<td class=\"query\">{}</td>", escape(text)).unwrap(),
+                Cell::Number(Some(number)) => write!(html, "<td class=\"number\" data-value=\"{}\">{}</td>", number, format_number(*number)).unwrap(),
+                Cell::Number(None) => html.push_str("<td class=\"number\" data-value=\"\"></td>"),
+            }
+        }
+        html.push_str("</tr>\n");
+    }
+    html.push_str("</tbody>\n</table>\n");
+    html
+}
+
+/// This function renders a table of records with the columns.
+fn records_table(
+    records: &[DiffRecord],
+    columns: &[Column],
+) -> String
+{
+    let headers = columns.iter().map(|(header, _)| *header).collect::<Vec<&str>>();
+    let rows = records.iter()
+        .map(|record| columns.iter().map(|(_, cell)| cell(record)).collect())
+        .collect::<Vec<Vec<Cell>>>();
+    table(&headers, &rows)
+}
+
+fn metrics_columns(details_enable: bool) -> Vec<Column> {
+    let mut columns: Vec<Column> = vec![
+        ("hostname_port", |r| Cell::Text(r.hostname_port.clone())),
+        ("type", |r| Cell::Text(r.metric_type.clone())),
+    ];
+    if details_enable {
+        columns.push(("id", |r| Cell::Text(r.metric_id.clone())));
+        columns.push(("namespace", |r| Cell::Text(r.namespace.clone())));
+        columns.push(("table", |r| Cell::Text(r.table_name.clone())));
+    }
+    columns.extend_from_slice(&[
+        ("name", (|r| Cell::Text(r.name.clone())) as fn(&DiffRecord) -> Cell),
+        ("value", |r| Cell::Number(r.value)),
+        ("difference", |r| Cell::Number(r.delta)),
+        ("per second", |r| Cell::Number(r.rate)),
+        ("average", |r| Cell::Number(r.average)),
+        ("total", |r| Cell::Number(r.sum_delta)),
+        ("unit", |r| Cell::Text(r.unit.clone())),
+    ]);
+    columns
+}
+
+const STATEMENTS_COLUMNS: &[Column] = &[
+    ("hostname_port", |r| Cell::Text(r.hostname_port.clone())),
+    ("calls", |r| Cell::Number(r.delta)),
+    ("calls per second", |r| Cell::Number(r.rate)),
+    ("average ms", |r| Cell::Number(r.average)),
+    ("total ms", |r| Cell::Number(r.sum_delta)),
+    ("rows", |r| Cell::Number(r.rows_delta)),
+    ("query", |r| Cell::Query(r.name.clone())),
+];
+
+const NODE_EXPORTER_COLUMNS: &[Column] = &[
+    ("hostname_port", |r| Cell::Text(r.hostname_port.clone())),
+    ("type", |r| Cell::Text(r.metric_type.clone())),
+    ("name", |r| Cell::Text(r.name.clone())),
+    ("value", |r| Cell::Number(r.value)),
+    ("difference", |r| Cell::Number(r.delta)),
+    ("per second", |r| Cell::Number(r.rate)),
+];
+
+const CHANGES_COLUMNS: &[Column] = &[
+    ("source", |r| Cell::Text(r.section.clone())),
+    ("change", |r| Cell::Text(r.change.clone())),
+    ("object", |r| Cell::Text(r.metric_type.clone())),
+    ("hostname_port", |r| Cell::Text(r.hostname_port.clone())),
+    ("namespace", |r| Cell::Text(r.namespace.clone())),
+    ("table", |r| Cell::Text(r.table_name.clone())),
+    ("name", |r| Cell::Text(r.name.clone())),
+    ("attribute", |r| Cell::Text(r.attribute.clone())),
+    ("before", |r| Cell::Text(r.first.clone())),
+    ("after", |r| Cell::Text(r.second.clone())),
+];
+
+/// This function renders the cluster topology of a snapshot: the endpoints from the manifest, the masters and the tablet servers.
+/// The parts that are not in the snapshot are left out.
+fn topology(
+    snapshot_number: &String,
+) -> String
+{
+    let mut html = String::new();
+    if let Ok(snapshotmanifest) = SnapshotManifest::read_snapshot(snapshot_number) {
+        let rows = snapshotmanifest.endpoints.iter()
+            .map(|endpoint| vec![Cell::Text(endpoint.hostname_port.clone()), Cell::Text(endpoint.role.clone())])
+            .collect::<Vec<Vec<Cell>>>();
+        html.push_str("<h3>Endpoints</h3>\n");
+        html.push_str(&table(&["hostname_port", "role"], &rows));
+    }
+    if let Ok(allstoredmasters) = masters::AllStoredMasters::read_snapshot(snapshot_number) {
+        // every master reports all the masters.
+        let masters = allstoredmasters.stored_masters.iter()
+            .map(|master| (master.instance_permanent_uuid.clone(), master))
+            .collect::<BTreeMap<_, _>>();
+        let rows = masters.values()
+            .map(|master| {
+                let http_addresses = allstoredmasters.stored_http_addresses.iter()
+                    .filter(|address| address.instance_permanent_uuid == master.instance_permanent_uuid)
+                    .map(|address| format!("{}:{}", address.host, address.port))
+                    .collect::<Vec<String>>();
+                vec![
+                    Cell::Text(http_addresses.join(" ")),
+                    Cell::Text(master.instance_permanent_uuid.clone()),
+                    Cell::Text(master.role.clone()),
+                    Cell::Text(master.registration_cloud_placement_cloud.clone()),
+                    Cell::Text(master.registration_cloud_placement_region.clone()),
+                    Cell::Text(master.registration_cloud_placement_zone.clone()),
+                ]
+            })
+            .collect::<Vec<Vec<Cell>>>();
+        html.push_str("<h3>Masters</h3>\n");
+        html.push_str(&table(&["http address", "permanent uuid", "role", "cloud", "region", "zone"], &rows));
+    }
+    if let Ok(allstoredtabletservers) = tservers::AllStoredTabletServers::read_snapshot(snapshot_number) {
+        // every master reports all the tablet servers.
+        let tablet_servers = allstoredtabletservers.stored_tabletservers.iter()
+            .map(|tablet_server| (tablet_server.tserver_hostname_port.clone(), tablet_server))
+            .collect::<BTreeMap<_, _>>();
+        let rows = tablet_servers.values()
+            .map(|tablet_server| vec![
+                Cell::Text(tablet_server.tserver_hostname_port.clone()),
+                Cell::Text(tablet_server.status.clone()),
+                Cell::Text(tablet_server.cloud.clone()),
+                Cell::Text(tablet_server.region.clone()),
+                Cell::Text(tablet_server.zone.clone()),
+                Cell::Number(Some(tablet_server.uptime_seconds as f64)),
+                Cell::Number(Some(tablet_server.user_tablets_total as f64)),
+                Cell::Number(Some(tablet_server.user_tablets_leaders as f64)),
+            ])
+            .collect::<Vec<Vec<Cell>>>();
+        html.push_str("<h3>Tablet servers</h3>\n");
+        html.push_str(&table(&["hostname_port", "status", "cloud", "region", "zone", "uptime seconds", "user tablets", "user tablet leaders"], &rows));
+    }
+    if html.is_empty() {
+        html.push_str("<p class=\"empty\">The snapshot has no topology.</p>\n");
+    }
+    html
+}
+
+/// This function renders the snapshot diff of the begin and end snapshot as an HTML document.
+pub fn render(
+    begin_snapshot: &String,
+    end_snapshot: &String,
+    filters: &Filters,
+) -> String
+{
+    let snapshots = Snapshot::read_snapshots();
+    let snapshot_row = |snapshot_number: &String| snapshots.iter()
+        .find(|snapshot| snapshot.number.to_string() == *snapshot_number)
+        .map(|snapshot| vec![
+            Cell::Text(snapshot.number.to_string()),
+            Cell::Text(snapshot.timestamp.format("%Y-%m-%d %H:%M:%S%.3f %:z").to_string()),
+            Cell::Text(snapshot.cluster.clone()),
+            Cell::Text(snapshot.comment.clone()),
+        ])
+        .unwrap_or_else(|| vec![Cell::Text(snapshot_number.to_string()), Cell::Text(String::new()), Cell::Text(String::new()), Cell::Text(String::new())]);
+    let begin_snapshot_timestamp = snapshots.iter()
+        .find(|snapshot| snapshot.number.to_string() == *begin_snapshot)
+        .map(|snapshot| snapshot.timestamp)
+        .unwrap_or_else(Local::now);
+    let collected = |source: &str| manifest::source_in_snapshots(begin_snapshot, end_snapshot, source);
+
+    let mut html = String::new();
+    let title = format!("yb_stats report: snapshot {} to {}", begin_snapshot, end_snapshot);
+    write!(html, "<!DOCTYPE html>\n<html lang=\"en\">\n<head>\n<meta charset=\"utf-8\">\n<title>{}</title>\n<style>\n{}\n</style>\n</head>\n<body>\n", escape(&title), STYLE).unwrap();
+    write!(html, "<h1>{}</h1>\n<p>Generated by yb_stats {} at {}.</p>\n", escape(&title), env!("CARGO_PKG_VERSION"), Local::now().format("%Y-%m-%d %H:%M:%S %:z")).unwrap();
+    html.push_str(&table(&["snapshot", "time", "cluster", "comment"], &[snapshot_row(begin_snapshot), snapshot_row(end_snapshot)]));
+
+    html.push_str("<h2 id=\"topology\">Cluster topology</h2>\n");
+    html.push_str(&topology(end_snapshot));
+
+    html.push_str("<h2 id=\"metrics\">Metrics</h2>\n");
+    if collected("metrics") {
+        let metrics_diff = metrics::SnapshotDiffBTreeMapsMetrics::snapshot_diff(begin_snapshot, end_snapshot, &begin_snapshot_timestamp);
+        let records = metrics_diff.records(filters.hostname_filter, filters.stat_name_filter, filters.table_name_filter, &filters.details_enable, &filters.gauges_enable);
+        html.push_str(&records_table(&records, &metrics_columns(filters.details_enable)));
+    } else {
+        html.push_str("<p class=\"empty\">Not collected.</p>\n");
+    }
+
+    html.push_str("<h2 id=\"statements\">Statements</h2>\n");
+    if collected("statements") {
+        let statements_diff = statements::SnapshotDiffBTreeMapStatements::snapshot_diff(begin_snapshot, end_snapshot, &begin_snapshot_timestamp);
+        html.push_str(&records_table(&statements_diff.records(filters.hostname_filter), STATEMENTS_COLUMNS));
+    } else {
+        html.push_str("<p class=\"empty\">Not collected.</p>\n");
+    }
+
+    html.push_str("<h2 id=\"node_exporter\">node_exporter</h2>\n");
+    if collected("node_exporter") {
+        let nodeexporter_diff = node_exporter::SnapshotDiffBTreeMapNodeExporter::snapshot_diff(begin_snapshot, end_snapshot, &begin_snapshot_timestamp);
+        let records = nodeexporter_diff.records(filters.hostname_filter, filters.stat_name_filter, &filters.gauges_enable, &filters.details_enable);
+        html.push_str(&records_table(&records, NODE_EXPORTER_COLUMNS));
+    } else {
+        html.push_str("<p class=\"empty\">Not collected.</p>\n");
+    }
+
+    html.push_str("<h2 id=\"changes\">Changes</h2>\n");
+    let mut changes = Vec::new();
+    if collected("entities") {
+        changes.extend(entities::SnapshotDiffBTreeMapsEntities::snapshot_diff(begin_snapshot, end_snapshot, &filters.details_enable).records());
+    }
+    if collected("masters") {
+        changes.extend(masters::SnapshotDiffBTreeMapsMasters::snapshot_diff(begin_snapshot, end_snapshot).records());
+    }
+    if collected("tservers") {
+        changes.extend(tservers::SnapshotDiffBTreeMapsTabletServers::snapshot_diff(begin_snapshot, end_snapshot).records());
+    }
+    if collected("vars") {
+        changes.extend(vars::SnapshotDiffBTreeMapsVars::snapshot_diff(begin_snapshot, end_snapshot).records());
+    }
+    if collected("versions") {
+        changes.extend(versions::SnapshotDiffBTreeMapsVersions::snapshot_diff(begin_snapshot, end_snapshot).records());
+    }
+    html.push_str(&records_table(&changes, CHANGES_COLUMNS));
+
+    write!(html, "<script>\n{}\n</script>\n</body>\n</html>\n", SCRIPT).unwrap();
+    html
+}
+
+/// This function writes the HTML report of the snapshot diff of the begin and end snapshot to a file.
+pub fn write_html_report(
+    begin_snapshot: &String,
+    end_snapshot: &String,
+    filters: &Filters,
+    report_file: &str,
+) -> Result<(), Box<dyn Error>>
+{
+    info!("html report of snapshot {} to {}: {}", begin_snapshot, end_snapshot, report_file);
+    fs::write(report_file, render(begin_snapshot, end_snapshot, filters))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unit_report_escape_and_format() {
+        assert_eq!(escape("select * from t where a < 1 and b = 'x' & \"y\""), "select * from t where a &lt; 1 and b = &#39;x&#39; &amp; &quot;y&quot;");
+        assert_eq!(format_number(1017.), "1017");
+        assert_eq!(format_number(301.15487), "301.155");
+    }
+    #[test]
+    fn unit_report_records_table() {
+        let records = vec![
+            DiffRecord { section: String::from("statements"), hostname_port: String::from("yb-1:13000"), name: String::from("select <1>"), delta: Some(20.), rate: Some(2.), average: Some(2.5), ..Default::default() },
+        ];
+        let html = records_table(&records, STATEMENTS_COLUMNS);
+        assert!(html.starts_with("<table class=\"sortable\">\n<thead><tr><th>hostname_port</th><th>calls</th>"));
+        assert!(html.contains("<td>yb-1:13000</td><td class=\"number\" data-value=\"20\">20</td><td class=\"number\" data-value=\"2\">2</td><td class=\"number\" data-value=\"2.5\">2.500</td><td class=\"number\" data-value=\"\"></td>"));
+        assert!(html.contains("<td class=\"query\">select &lt;1&gt;</td>"));
+        assert_eq!(records_table(&[], STATEMENTS_COLUMNS), "<p class=\"empty\">No rows.</p>\n");
+        assert_eq!(metrics_columns(true).len(), metrics_columns(false).len() + 3);
+    }
+}
@@ -1,13 +1,19 @@
 use serde_derive::{Serialize,Deserialize};
 use chrono::{DateTime, Local};
-use std::{fs, process};
+use std::{process};
 use log::*;
-use std::sync::mpsc::channel;
-use std::path::PathBuf;
+use futures::future::join_all;
 use regex::Regex;
 use std::collections::BTreeMap;
 use crate::rpcs::AllConnections::{Connections, InAndOutboundConnections};
-use crate::utility::{scan_host_port, http_get};
+use crate::utility::http_get;
+use crate::endpoints::has_role;
+use crate::compression::create_snapshot_file;
+use crate::schema::open_snapshot_csv;
+use crate::snapshot::snapshot_directory;
+
+/// The endpoint roles that provide /rpcz.
+pub const ROLES: &[&str] = &["master", "tserver", "ysql", "ycql", "yedis"];
 
 #[derive(Serialize, Deserialize, Debug)]
 #[serde(untagged)]
@@ -226,48 +232,44 @@ pub struct StoredHeaders {
 
 type AllStoredRpcs = (Vec<StoredYsqlRpc>, Vec<StoredInboundRpc>, Vec<StoredOutboundRpc>, Vec<StoredCqlDetails>, Vec<StoredHeaders>);
 
-fn read_rpcs_into_vectors(
+async fn read_rpcs_into_vectors(
     hosts: &Vec<&str>,
     ports: &Vec<&str>,
-    parallel: usize,
 ) -> AllStoredRpcs {
-    let pool = rayon::ThreadPoolBuilder::new().num_threads(parallel).build().unwrap();
-    let (tx, rx) = channel();
-    pool.scope(move |s| {
-        for host in hosts {
-            for port in ports {
-                let tx = tx.clone();
-                s.spawn(move |_| {
-                    let detail_snapshot_time = Local::now();
-                    let rpcs = read_rpcs(host, port);
-                    debug!("allconnections: host: {}:{}, {:?}", host, port, &rpcs);
-                    tx.send((format!("{}:{}", host, port), detail_snapshot_time, rpcs)).expect("error sending data via tx (rpcs)");
-                });
+    let mut handles = Vec::new();
+    for host in hosts {
+        for port in ports {
+            if !has_role(host, port, ROLES) {
+                continue;
             }
+            let handle = async move {
+                let detail_snapshot_time = Local::now();
+                let rpcs = read_rpcs(host, port).await;
+                debug!("allconnections: host: {}:{}, {:?}", host, port, &rpcs);
+                (format!("{}:{}", host, port), detail_snapshot_time, rpcs)
+            };
+            handles.push(handle);
         }
-    });
+    }
+    let results = join_all(handles).await;
     let mut stored_ysqlrpc: Vec<StoredYsqlRpc> = Vec::new();
     let mut stored_inboundrpc: Vec<StoredInboundRpc> = Vec::new();
     let mut stored_outboundrpc: Vec<StoredOutboundRpc> = Vec::new();
     let mut stored_cqldetails: Vec<StoredCqlDetails> = Vec::new();
     let mut stored_header: Vec<StoredHeaders> = Vec::new();
-    for (hostname_port, detail_snapshot_time, rpcs) in rx {
+    for (hostname_port, detail_snapshot_time, rpcs) in results {
         trace!("read_rpcs_into_vectors host: {}, {:?}", &hostname_port, rpcs);
         add_to_rpcs_vectors(rpcs, &hostname_port, detail_snapshot_time, &mut stored_ysqlrpc, &mut stored_inboundrpc, &mut stored_outboundrpc, &mut stored_cqldetails, &mut stored_header);
     }
     (stored_ysqlrpc, stored_inboundrpc, stored_outboundrpc, stored_cqldetails, stored_header)
 }
 
-pub fn read_rpcs(
+pub async fn read_rpcs(
     host: &str,
     port: &str,
 ) -> AllConnections
 {
-    let data_from_http = if scan_host_port( host, port) {
-        http_get(host, port, "rpcz")
-    } else {
-        String::new()
-    };
+    let data_from_http = http_get(host, port, "rpcz").await;
     parse_rpcs(data_from_http, host, port)
 }
 
@@ -387,19 +389,14 @@ pub async fn perform_rpcs_snapshot(
     hosts: &Vec<&str>,
     ports: &Vec<&str>,
     snapshot_number: i32,
-    yb_stats_directory: &PathBuf,
-    parallel: usize,
 ) {
     info!("perform_rpcs_snapshot");
 
-    let (stored_ysqlrpc, stored_inboundrpc, stored_outboundrpc, stored_cqldetails, stored_headers) = read_rpcs_into_vectors(hosts, ports, parallel);
+    let (stored_ysqlrpc, stored_inboundrpc, stored_outboundrpc, stored_cqldetails, stored_headers) = read_rpcs_into_vectors(hosts, ports).await;
 
-    let current_snapshot_directory = &yb_stats_directory.join(&snapshot_number.to_string());
+    let current_snapshot_directory = &snapshot_directory().join(&snapshot_number.to_string());
     let ysqlrpc_file = &current_snapshot_directory.join("ysqlrpc");
-    let file = fs::OpenOptions::new()
-        .create(true)
-        .write(true)
-        .open(ysqlrpc_file)
+    let file = create_snapshot_file(ysqlrpc_file)
         .unwrap_or_else(|e| {
             error!("Fatal: error writing ysqlrpc data in snapshot directory {}: {}", &ysqlrpc_file.clone().into_os_string().into_string().unwrap(), e);
             process::exit(1);
@@ -408,14 +405,11 @@ pub async fn perform_rpcs_snapshot(
     for row in stored_ysqlrpc {
         writer.serialize(row).unwrap();
     }
-    writer.flush().unwrap();
+    writer.into_inner().unwrap().finish().unwrap();
 
-    let current_snapshot_directory = &yb_stats_directory.join(&snapshot_number.to_string());
+    let current_snapshot_directory = &snapshot_directory().join(&snapshot_number.to_string());
     let inboundrpc_file = &current_snapshot_directory.join("inboundrpc");
-    let file = fs::OpenOptions::new()
-        .create(true)
-        .write(true)
-        .open(inboundrpc_file)
+    let file = create_snapshot_file(inboundrpc_file)
         .unwrap_or_else(|e| {
             error!("Fatal: error writing inboundrpc data in snapshot directory {}: {}", &inboundrpc_file.clone().into_os_string().into_string().unwrap(), e);
             process::exit(1);
@@ -424,14 +418,11 @@ pub async fn perform_rpcs_snapshot(
     for row in stored_inboundrpc {
         writer.serialize(row).unwrap();
     }
-    writer.flush().unwrap();
+    writer.into_inner().unwrap().finish().unwrap();
 
-    let current_snapshot_directory = &yb_stats_directory.join(&snapshot_number.to_string());
+    let current_snapshot_directory = &snapshot_directory().join(&snapshot_number.to_string());
     let outboundrpc_file = &current_snapshot_directory.join("outboundrpc");
-    let file = fs::OpenOptions::new()
-        .create(true)
-        .write(true)
-        .open(outboundrpc_file)
+    let file = create_snapshot_file(outboundrpc_file)
         .unwrap_or_else(|e| {
             error!("Fatal: error writing outboundrpc data in snapshot directory {}: {}", &outboundrpc_file.clone().into_os_string().into_string().unwrap(), e);
             process::exit(1);
@@ -440,14 +431,11 @@ pub async fn perform_rpcs_snapshot(
     for row in stored_outboundrpc {
         writer.serialize(row).unwrap();
     }
-    writer.flush().unwrap();
+    writer.into_inner().unwrap().finish().unwrap();
 
-    let current_snapshot_directory = &yb_stats_directory.join(&snapshot_number.to_string());
+    let current_snapshot_directory = &snapshot_directory().join(&snapshot_number.to_string());
     let cqldetails_file = &current_snapshot_directory.join("cqldetails");
-    let file = fs::OpenOptions::new()
-        .create(true)
-        .write(true)
-        .open(cqldetails_file)
+    let file = create_snapshot_file(cqldetails_file)
         .unwrap_or_else(|e| {
             error!("Fatal: error writing cqldetails data in snapshot directory {}: {}", &cqldetails_file.clone().into_os_string().into_string().unwrap(), e);
             process::exit(1);
@@ -456,14 +444,11 @@ pub async fn perform_rpcs_snapshot(
     for row in stored_cqldetails {
         writer.serialize(row).unwrap();
     }
-    writer.flush().unwrap();
+    writer.into_inner().unwrap().finish().unwrap();
 
-    let current_snapshot_directory = &yb_stats_directory.join(&snapshot_number.to_string());
+    let current_snapshot_directory = &snapshot_directory().join(&snapshot_number.to_string());
     let headers_file = &current_snapshot_directory.join("headers");
-    let file = fs::OpenOptions::new()
-        .create(true)
-        .write(true)
-        .open(headers_file)
+    let file = create_snapshot_file(headers_file)
         .unwrap_or_else(|e| {
             error!("Fatal: error writing headers data in snapshot directory {}: {}", &headers_file.clone().into_os_string().into_string().unwrap(), e);
             process::exit(1);
@@ -472,18 +457,17 @@ pub async fn perform_rpcs_snapshot(
     for row in stored_headers {
         writer.serialize(row).unwrap();
     }
-    writer.flush().unwrap();
+    writer.into_inner().unwrap().finish().unwrap();
 }
 
 #[allow(clippy::ptr_arg)]
 pub fn read_ysqlrpc_snapshot(
     snapshot_number: &String,
-    yb_stats_directory: &PathBuf,
 ) -> Vec<StoredYsqlRpc>
 {
     let mut stored_ysqlrpc: Vec<StoredYsqlRpc> = Vec::new();
-    let ysqlrpc_file = &yb_stats_directory.join(snapshot_number).join("ysqlrpc");
-    let file = fs::File::open( ysqlrpc_file )
+    let ysqlrpc_file = &snapshot_directory().join(snapshot_number).join("ysqlrpc");
+    let file = open_snapshot_csv(ysqlrpc_file)
         .unwrap_or_else(|e| {
             error!("Fatal: error reading file: {}: {}", &ysqlrpc_file.clone().into_os_string().into_string().unwrap(), e);
             process::exit(1);
@@ -499,12 +483,11 @@ pub fn read_ysqlrpc_snapshot(
 #[allow(clippy::ptr_arg)]
 pub fn read_inboundrpc_snapshot(
     snapshot_number: &String,
-    yb_stats_directory: &PathBuf,
 ) -> Vec<StoredInboundRpc>
 {
     let mut stored_inboundrpc: Vec<StoredInboundRpc> = Vec::new();
-    let inboundrpc_file = &yb_stats_directory.join(snapshot_number).join("inboundrpc");
-    let file = fs::File::open( inboundrpc_file )
+    let inboundrpc_file = &snapshot_directory().join(snapshot_number).join("inboundrpc");
+    let file = open_snapshot_csv(inboundrpc_file)
         .unwrap_or_else(|e| {
             error!("Fatal: error reading file: {}: {}", &inboundrpc_file.clone().into_os_string().into_string().unwrap(), e);
             process::exit(1);
@@ -520,12 +503,11 @@ pub fn read_inboundrpc_snapshot(
 #[allow(clippy::ptr_arg)]
 pub fn read_outboundrpc_snapshot(
     snapshot_number: &String,
-    yb_stats_directory: &PathBuf,
 ) -> Vec<StoredOutboundRpc>
 {
     let mut stored_outboundrpc: Vec<StoredOutboundRpc> = Vec::new();
-    let outboundrpc_file = &yb_stats_directory.join(snapshot_number).join("outboundrpc");
-    let file = fs::File::open( outboundrpc_file )
+    let outboundrpc_file = &snapshot_directory().join(snapshot_number).join("outboundrpc");
+    let file = open_snapshot_csv(outboundrpc_file)
         .unwrap_or_else(|e| {
             error!("Fatal: error reading file: {}: {}", &outboundrpc_file.clone().into_os_string().into_string().unwrap(), e);
             process::exit(1);
@@ -541,12 +523,11 @@ pub fn read_outboundrpc_snapshot(
 #[allow(clippy::ptr_arg)]
 pub fn read_cqldetails_snapshot(
     snapshot_number: &String,
-    yb_stats_directory: &PathBuf,
 ) -> Vec<StoredCqlDetails>
 {
     let mut stored_cqldetails: Vec<StoredCqlDetails> = Vec::new();
-    let cqldetails_file = &yb_stats_directory.join(snapshot_number).join("cqldetails");
-    let file = fs::File::open( cqldetails_file )
+    let cqldetails_file = &snapshot_directory().join(snapshot_number).join("cqldetails");
+    let file = open_snapshot_csv(cqldetails_file)
         .unwrap_or_else(|e| {
             error!("Fatal: error reading file: {}: {}", &cqldetails_file.clone().into_os_string().into_string().unwrap(), e);
             process::exit(1);
@@ -562,12 +543,11 @@ pub fn read_cqldetails_snapshot(
 #[allow(clippy::ptr_arg)]
 pub fn read_headers_snapshot(
     snapshot_number: &String,
-    yb_stats_directory: &PathBuf,
 ) -> Vec<StoredHeaders>
 {
     let mut stored_headers: Vec<StoredHeaders> = Vec::new();
-    let headers_file = &yb_stats_directory.join(snapshot_number).join("headers");
-    let file = fs::File::open( headers_file )
+    let headers_file = &snapshot_directory().join(snapshot_number).join("headers");
+    let file = open_snapshot_csv(headers_file)
         .unwrap_or_else(|e| {
             error!("Fatal: error reading file: {}: {}", &headers_file.clone().into_os_string().into_string().unwrap(), e);
             process::exit(1);
@@ -582,16 +562,15 @@ pub fn read_headers_snapshot(
 
 pub fn print_rpcs(
     snapshot_number: &String,
-    yb_stats_directory: &PathBuf,
     hostname_filter: &Regex,
     details_enable: &bool,
 ) {
     info!("print_rpcs");
-    let stored_ysqlrpc: Vec<StoredYsqlRpc> = read_ysqlrpc_snapshot(snapshot_number, yb_stats_directory);
-    let stored_inboundrpcs: Vec<StoredInboundRpc> = read_inboundrpc_snapshot(snapshot_number, yb_stats_directory);
-    let stored_outboundrpcs: Vec<StoredOutboundRpc> = read_outboundrpc_snapshot(snapshot_number, yb_stats_directory);
-    let stored_cqldetails: Vec<StoredCqlDetails> = read_cqldetails_snapshot(snapshot_number, yb_stats_directory);
-    let stored_headers: Vec<StoredHeaders> = read_headers_snapshot(snapshot_number, yb_stats_directory);
+    let stored_ysqlrpc: Vec<StoredYsqlRpc> = read_ysqlrpc_snapshot(snapshot_number);
+    let stored_inboundrpcs: Vec<StoredInboundRpc> = read_inboundrpc_snapshot(snapshot_number);
+    let stored_outboundrpcs: Vec<StoredOutboundRpc> = read_outboundrpc_snapshot(snapshot_number);
+    let stored_cqldetails: Vec<StoredCqlDetails> = read_cqldetails_snapshot(snapshot_number);
+    let stored_headers: Vec<StoredHeaders> = read_headers_snapshot(snapshot_number);
     let mut endpoint_count: BTreeMap<String, usize> = BTreeMap::new();
     for row in &stored_ysqlrpc {
         *endpoint_count.entry(row.hostname_port.clone()).or_default() += 1;
@@ -1320,8 +1299,8 @@ mod tests {
 
     use crate::utility;
 
-    #[test]
-    fn integration_parse_rpcs_tserver() {
+    #[tokio::test]
+    async fn integration_parse_rpcs_tserver() {
         let mut stored_ysqlrpc: Vec<StoredYsqlRpc> = Vec::new();
         let mut stored_inboundrpc: Vec<StoredInboundRpc> = Vec::new();
         let mut stored_outboundrpc: Vec<StoredOutboundRpc> = Vec::new();
@@ -1329,7 +1308,7 @@ mod tests {
         let mut stored_header: Vec<StoredHeaders> = Vec::new();
         let hostname = utility::get_hostname_tserver();
         let port = utility::get_port_tserver();
-        let data_parsed_from_json = read_rpcs( hostname.as_str(), port.as_str());
+        let data_parsed_from_json = read_rpcs( hostname.as_str(), port.as_str()).await;
         add_to_rpcs_vectors(data_parsed_from_json, format!("{}:{}", hostname, port).as_str(), Local::now(), &mut stored_ysqlrpc, &mut stored_inboundrpc, &mut stored_outboundrpc, &mut stored_cqldetails, &mut stored_header);
         // a tserver / port 9000 does not have YSQL rpcs, port 13000 has.
         assert!(stored_ysqlrpc.is_empty());
@@ -1338,8 +1317,8 @@ mod tests {
         // a tserver will have outbound RPCs, even RF=1 / 1 tserver.
         assert!(!stored_outboundrpc.is_empty());
     }
-    #[test]
-    fn integration_parse_rpcs_master() {
+    #[tokio::test]
+    async fn integration_parse_rpcs_master() {
         let mut stored_ysqlrpc: Vec<StoredYsqlRpc> = Vec::new();
         let mut stored_inboundrpc: Vec<StoredInboundRpc> = Vec::new();
         let mut stored_outboundrpc: Vec<StoredOutboundRpc> = Vec::new();
@@ -1347,15 +1326,15 @@ mod tests {
         let mut stored_header: Vec<StoredHeaders> = Vec::new();
         let hostname = utility::get_hostname_master();
         let port = utility::get_port_master();
-        let data_parsed_from_json = read_rpcs( hostname.as_str(), port.as_str());
+        let data_parsed_from_json = read_rpcs( hostname.as_str(), port.as_str()).await;
         add_to_rpcs_vectors(data_parsed_from_json, format!("{}:{}", hostname, port).as_str(), Local::now(), &mut stored_ysqlrpc, &mut stored_inboundrpc, &mut stored_outboundrpc, &mut stored_cqldetails, &mut stored_header);
         // a master / port 7000 does not have YSQL rpcs, port 13000 has.
         assert!(stored_ysqlrpc.is_empty());
         // a master will have inbound RPCs, even RF=1 / 1 tserver.
         assert!(!stored_inboundrpc.is_empty());
     }
-    #[test]
-    fn integration_parse_rpcs_ysql() {
+    #[tokio::test]
+    async fn integration_parse_rpcs_ysql() {
         let mut stored_ysqlrpc: Vec<StoredYsqlRpc> = Vec::new();
         let mut stored_inboundrpc: Vec<StoredInboundRpc> = Vec::new();
         let mut stored_outboundrpc: Vec<StoredOutboundRpc> = Vec::new();
@@ -1363,7 +1342,7 @@ mod tests {
         let mut stored_header: Vec<StoredHeaders> = Vec::new();
         let hostname = utility::get_hostname_ysql();
         let port = utility::get_port_ysql();
-        let data_parsed_from_json = read_rpcs( hostname.as_str(), port.as_str());
+        let data_parsed_from_json = read_rpcs( hostname.as_str(), port.as_str()).await;
         add_to_rpcs_vectors(data_parsed_from_json, format!("{}:{}", hostname, port).as_str(), Local::now(), &mut stored_ysqlrpc, &mut stored_inboundrpc, &mut stored_outboundrpc, &mut stored_cqldetails, &mut stored_header);
         // ysql does have a single RPC connection by default after startup, which is the checkpointer process
         assert!(!stored_ysqlrpc.is_empty());
@@ -0,0 +1,184 @@
+//! The module for the format version of the snapshot files, and the upgrade of the CSV files of older format versions when these are read.
+//!
+//! The layout of a CSV file is defined by the serde struct that is used to store and read it, such as [crate::metrics::StoredValues].
+//! The format version ([SNAPSHOT_FORMAT_VERSION]) is recorded in the manifest of every snapshot.
+//! A snapshot without a manifest, or with a manifest without a format version, has format version 1.
+//!
+//! When the layout of a CSV file is changed, the format version is increased, and the change is added to [UPGRADES].
+//! The CSV files are read with [open_snapshot_csv], which applies the upgrades for the format version of the snapshot,
+//! so snapshots taken with an older version of yb_stats can be read with the current serde structs.
+//!
+//! The format versions are:
+//! 1. The files of yb_stats 0.8.9 and earlier.
+//! 2. The files `endpoints` and `collection_errors` are added, and the format version is recorded in the manifest.
+use std::{io::{self, Read}, path::Path, error::Error};
+use log::*;
+use crate::compression::open_snapshot_file;
+use crate::manifest::SnapshotManifest;
+
+/// The format version of the snapshot files that are written by this version of yb_stats.
+pub const SNAPSHOT_FORMAT_VERSION: u32 = 2;
+/// The format version of a snapshot that has no format version recorded.
+pub const FIRST_FORMAT_VERSION: u32 = 1;
+
+/// A change of the layout of a CSV file.
+/// Not all kinds of changes are used by the current [UPGRADES].
+#[allow(dead_code)]
+#[derive(Debug)]
+pub enum Change {
+    /// A column is added: the column is added with the default value to the files that don't have it.
+    AddColumn { column: &'static str, default: &'static str },
+    /// A column is renamed.
+    RenameColumn { from: &'static str, to: &'static str },
+    /// The file is added: the file is read as a file without rows from the snapshots that don't have it.
+    AddFile,
+}
+
+/// A change of the layout of a CSV file in a format version.
+#[derive(Debug)]
+pub struct Upgrade {
+    /// The format version that introduced the change.
+    pub version: u32,
+    /// The name of the CSV file in the snapshot directory, without a compression extension.
+    pub file: &'static str,
+    pub change: Change,
+}
+
+/// The changes of the layout of the CSV files, in the order of the format versions.
+pub const UPGRADES: &[Upgrade] = &[
+    Upgrade { version: 2, file: "endpoints", change: Change::AddFile },
+    Upgrade { version: 2, file: "collection_errors", change: Change::AddFile },
+];
+
+/// This function is used by serde for a manifest without a format version.
+pub fn first_format_version() -> u32 {
+    FIRST_FORMAT_VERSION
+}
+
+/// This function returns the format version of the snapshot in the snapshot directory, which is recorded in the manifest.
+pub fn format_version(
+    current_snapshot_directory: &Path,
+) -> u32
+{
+    open_snapshot_file(&current_snapshot_directory.join("manifest")).ok()
+        .and_then(|file| serde_json::from_reader::<_, SnapshotManifest>(file).ok())
+        .map(|snapshotmanifest| snapshotmanifest.format_version)
+        .unwrap_or(FIRST_FORMAT_VERSION)
+}
+
+/// This function opens a CSV file in a snapshot directory for reading like [open_snapshot_file],
+/// and upgrades the file to the current layout if the snapshot has an older format version.
+/// The path is the name of the file without a compression extension.
+pub fn open_snapshot_csv(
+    path: &Path,
+) -> io::Result<Box<dyn Read + Send>>
+{
+    let file = path.file_name().and_then(|file| file.to_str()).unwrap_or_default();
+    let version = path.parent().map(format_version).unwrap_or(FIRST_FORMAT_VERSION);
+    let upgrades = UPGRADES.iter()
+        .filter(|upgrade| upgrade.file == file && upgrade.version > version)
+        .collect::<Vec<&Upgrade>>();
+    if upgrades.is_empty() {
+        return open_snapshot_file(path);
+    }
+    let data = match open_snapshot_file(path) {
+        Err(e) if e.kind() == io::ErrorKind::NotFound && upgrades.iter().any(|upgrade| matches!(upgrade.change, Change::AddFile)) => {
+            info!("file {} is added in format version {}, the snapshot has format version {}", file, SNAPSHOT_FORMAT_VERSION, version);
+            return Ok(Box::new(io::empty()));
+        },
+        data => data?,
+    };
+    info!("upgrade file {} from format version {}", path.display(), version);
+    let data = upgrade(data, &upgrades)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("error upgrading {}: {}", path.display(), e)))?;
+    Ok(Box::new(io::Cursor::new(data)))
+}
+
+/// This function applies the upgrades to the CSV data, and returns the upgraded CSV data.
+fn upgrade(
+    data: impl Read,
+    upgrades: &[&Upgrade],
+) -> Result<Vec<u8>, Box<dyn Error>>
+{
+    let mut reader = csv::Reader::from_reader(data);
+    let mut headers = reader.headers()?.clone();
+    let mut records = reader.records().collect::<Result<Vec<csv::StringRecord>, csv::Error>>()?;
+    // A file without a header has no rows.
+    if headers.is_empty() {
+        return Ok(Vec::new());
+    }
+    for upgrade in upgrades {
+        match upgrade.change {
+            Change::AddColumn { column, default } => {
+                if !headers.iter().any(|header| header == column) {
+                    headers.push_field(column);
+                    records.iter_mut().for_each(|record| record.push_field(default));
+                }
+            },
+            Change::RenameColumn { from, to } => {
+                headers = headers.iter().map(|header| if header == from { to } else { header }).collect();
+            },
+            Change::AddFile => {},
+        }
+    }
+    let mut writer = csv::Writer::from_writer(Vec::new());
+    writer.write_record(&headers)?;
+    for record in &records {
+        writer.write_record(record)?;
+    }
+    Ok(writer.into_inner()?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Deserialize, Debug)]
+    struct StoredTest {
+        hostname_port: String,
+        value_name: String,
+        unit: String,
+    }
+
+    #[test]
+    fn unit_upgrade_add_and_rename_column() {
+        let data = "hostname_port,name\n192.168.66.80:9000,rows_inserted\n";
+        let add = Upgrade { version: 3, file: "values", change: Change::AddColumn { column: "unit", default: "rows" } };
+        let rename = Upgrade { version: 3, file: "values", change: Change::RenameColumn { from: "name", to: "value_name" } };
+        let upgraded = upgrade(data.as_bytes(), &[&add, &rename]).unwrap();
+        let mut reader = csv::Reader::from_reader(upgraded.as_slice());
+        let rows = reader.deserialize().collect::<Result<Vec<StoredTest>, csv::Error>>().unwrap();
+        assert_eq!(rows.len(), 1);
+        assert_eq!(rows[0].hostname_port, "192.168.66.80:9000");
+        assert_eq!(rows[0].value_name, "rows_inserted");
+        assert_eq!(rows[0].unit, "rows");
+    }
+    #[test]
+    fn unit_upgrade_keeps_existing_column_and_empty_file() {
+        let data = "hostname_port,value_name,unit\n192.168.66.80:9000,rows_inserted,bytes\n";
+        let add = Upgrade { version: 3, file: "values", change: Change::AddColumn { column: "unit", default: "rows" } };
+        let upgraded = upgrade(data.as_bytes(), &[&add]).unwrap();
+        assert_eq!(String::from_utf8(upgraded).unwrap(), data);
+        assert!(upgrade("".as_bytes(), &[&add]).unwrap().is_empty());
+    }
+    #[test]
+    fn unit_open_snapshot_csv_added_file() {
+        let directory = std::env::temp_dir().join(format!("yb_stats_schema_{}", std::process::id())).join("0");
+        std::fs::create_dir_all(&directory).unwrap();
+        // a snapshot without a manifest has format version 1, and is read as if it has an empty endpoints file.
+        assert_eq!(format_version(&directory), FIRST_FORMAT_VERSION);
+        let mut data = String::new();
+        open_snapshot_csv(&directory.join("endpoints")).unwrap().read_to_string(&mut data).unwrap();
+        assert!(data.is_empty());
+        // a snapshot of the current format version must have the endpoints file.
+        std::fs::write(directory.join("manifest"), format!(r#"{{"yb_stats_version":"0.8.9","format_version":{},"snapshot_number":0,"cluster":"","cluster_uuid":"","start_time":"2023-01-10T10:12:31+01:00","end_time":"2023-01-10T10:12:32+01:00","duration_ms":1000,"endpoints":[],"sources":[],"files":[]}}"#, SNAPSHOT_FORMAT_VERSION)).unwrap();
+        assert_eq!(format_version(&directory), SNAPSHOT_FORMAT_VERSION);
+        assert!(open_snapshot_csv(&directory.join("endpoints")).is_err());
+        std::fs::remove_dir_all(directory.parent().unwrap()).unwrap();
+    }
+    #[test]
+    fn unit_upgrades_are_ordered_and_current() {
+        assert!(UPGRADES.windows(2).all(|upgrades| upgrades[0].version <= upgrades[1].version));
+        assert!(UPGRADES.iter().all(|upgrade| upgrade.version > FIRST_FORMAT_VERSION && upgrade.version <= SNAPSHOT_FORMAT_VERSION));
+    }
+}
@@ -0,0 +1,197 @@
+//! The module for the time-series report of a range of snapshots (`--snapshot-series`).
+//!
+//! `--snapshot-diff` compares two snapshots. The time-series report takes the snapshots from `--begin` to `--end`,
+//! and compares every consecutive pair of snapshots with the same arithmetic as `--snapshot-diff` (see [SnapshotDiffBTreeMapsMetrics]),
+//! so it shows how the rate of every metric developed during a test. The filters, `--details-enable` and `--gauges-enable` are applied in the same way.
+//!
+//! - With text output, the report is a table with a row per metric and a column per interval, with the rate per second (or the value of a gauge).
+//! - With `--output csv` or `--output json`, the report is written in long format: a [SeriesRecord] per metric per interval, with the difference, the rate and the average.
+//!
+//! A pair of snapshots of which one snapshot has not collected the metrics (such as a light snapshot) is skipped.
+use std::collections::BTreeMap;
+use chrono::{DateTime, Local};
+use substring::Substring;
+use log::*;
+use crate::metrics::SnapshotDiffBTreeMapsMetrics;
+use crate::manifest;
+use crate::snapshot::Snapshot;
+use crate::utility::Filters;
+
+/// A metric in an interval between two consecutive snapshots, for the long format of the report.
+#[derive(Serialize, Debug, Default, Clone, PartialEq)]
+pub struct SeriesRecord {
+    pub begin_snapshot: i32,
+    pub end_snapshot: i32,
+    pub end_time: Option<DateTime<Local>>,
+    pub hostname_port: String,
+    pub metric_type: String,
+    pub metric_id: String,
+    pub namespace: String,
+    pub table_name: String,
+    pub name: String,
+    pub value: Option<f64>,
+    pub delta: Option<f64>,
+    pub seconds: Option<f64>,
+    pub rate: Option<f64>,
+    pub average: Option<f64>,
+    pub unit: String,
+}
+
+/// This function returns the snapshots from the begin to the end snapshot number, ordered by number.
+pub fn snapshot_range(
+    snapshots: Vec<Snapshot>,
+    begin_snapshot: i32,
+    end_snapshot: i32,
+) -> Result<Vec<Snapshot>, String>
+{
+    let mut range = snapshots.into_iter()
+        .filter(|snapshot| snapshot.number >= begin_snapshot && snapshot.number <= end_snapshot)
+        .collect::<Vec<Snapshot>>();
+    range.sort_by_key(|snapshot| snapshot.number);
+    if range.len() < 2 {
+        return Err(format!("the snapshot range {}-{} must contain at least 2 snapshots", begin_snapshot, end_snapshot));
+    }
+    Ok(range)
+}
+
+/// This function compares every consecutive pair of snapshots, and returns the metrics per interval.
+pub fn series(
+    snapshots: &[Snapshot],
+    filters: &Filters,
+) -> Vec<SeriesRecord>
+{
+    let mut records = Vec::new();
+    for pair in snapshots.windows(2) {
+        let (begin, end) = (&pair[0], &pair[1]);
+        let (begin_snapshot, end_snapshot) = (begin.number.to_string(), end.number.to_string());
+        if !manifest::source_in_snapshots(&begin_snapshot, &end_snapshot, "metrics") {
+            continue;
+        }
+        info!("series interval {} to {}", begin_snapshot, end_snapshot);
+        let metrics_diff = SnapshotDiffBTreeMapsMetrics::snapshot_diff(&begin_snapshot, &end_snapshot, &begin.timestamp);
+        records.extend(metrics_diff.records(filters.hostname_filter, filters.stat_name_filter, filters.table_name_filter, &filters.details_enable, &filters.gauges_enable)
+            .into_iter()
+            .map(|record| SeriesRecord {
+                begin_snapshot: begin.number,
+                end_snapshot: end.number,
+                end_time: Some(end.timestamp),
+                hostname_port: record.hostname_port,
+                metric_type: record.metric_type,
+                metric_id: record.metric_id,
+                namespace: record.namespace,
+                table_name: record.table_name,
+                name: record.name,
+                value: record.value,
+                delta: record.delta,
+                seconds: record.seconds,
+                rate: record.rate,
+                average: record.average,
+                unit: record.unit,
+            }));
+    }
+    records
+}
+
+/// The intervals of the table, and the rate (or the value of a gauge) of every metric per interval.
+type SeriesTable = (Vec<(i32, i32)>, BTreeMap<(String, String, String, String), Vec<Option<f64>>>);
+
+/// This function turns the records into a table with a row per metric and a column per interval.
+/// The intervals without records, such as the intervals of which a snapshot has not collected the metrics, are left out.
+fn series_table(
+    snapshots: &[Snapshot],
+    records: &[SeriesRecord],
+) -> SeriesTable
+{
+    let intervals = snapshots.windows(2)
+        .map(|pair| (pair[0].number, pair[1].number))
+        .filter(|interval| records.iter().any(|record| (record.begin_snapshot, record.end_snapshot) == *interval))
+        .collect::<Vec<(i32, i32)>>();
+    let mut rows: BTreeMap<(String, String, String, String), Vec<Option<f64>>> = BTreeMap::new();
+    for record in records {
+        let Some(column) = intervals.iter().position(|interval| *interval == (record.begin_snapshot, record.end_snapshot)) else { continue };
+        rows.entry((record.hostname_port.clone(), record.metric_type.clone(), record.metric_id.clone(), record.name.clone()))
+            .or_insert_with(|| vec![None; intervals.len()])[column] = record.rate.or(record.value);
+    }
+    (intervals, rows)
+}
+
+/// This function prints the table with a row per metric and a column per interval.
+pub fn print(
+    snapshots: &[Snapshot],
+    records: &[SeriesRecord],
+    details_enable: bool,
+) {
+    let (intervals, rows) = series_table(snapshots, records);
+    if intervals.is_empty() {
+        println!("No metrics found in the snapshots {} to {}.", snapshots[0].number, snapshots[snapshots.len() - 1].number);
+        return;
+    }
+    let id_width = if details_enable { 16 } else { 0 };
+    print!("{:20} {:8} {:id_width$}{:70}", "hostname_port", "type", "", "name (per second)");
+    for (begin_snapshot, end_snapshot) in &intervals {
+        print!(" {:>12}", format!("{}-{}", begin_snapshot, end_snapshot));
+    }
+    println!();
+    for ((hostname, metric_type, metric_id, metric_name), values) in rows {
+        let id = if details_enable {
+            let adaptive_length = if metric_id.len() < 15 { 0 } else { metric_id.len() - 15 };
+            format!("{:15} ", metric_id.substring(adaptive_length, metric_id.len()))
+        } else {
+            String::new()
+        };
+        print!("{:20} {:8} {}{:70}", hostname, metric_type, id, metric_name);
+        for value in values {
+            match value {
+                Some(value) => print!(" {:12.3}", value),
+                None => print!(" {:>12}", ""),
+            }
+        }
+        println!();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn snapshot(number: i32) -> Snapshot {
+        Snapshot {
+            number,
+            timestamp: Local.with_ymd_and_hms(2023, 1, 10, 10, number as u32, 0).unwrap(),
+            comment: String::new(),
+            cluster: String::new(),
+        }
+    }
+    fn record(begin_snapshot: i32, name: &str, rate: f64) -> SeriesRecord {
+        SeriesRecord {
+            begin_snapshot,
+            end_snapshot: begin_snapshot + 1,
+            hostname_port: String::from("yb-1:9000"),
+            metric_type: String::from("tablet"),
+            metric_id: String::from("-"),
+            name: name.to_string(),
+            rate: Some(rate),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn unit_snapshot_range() {
+        let range = snapshot_range(vec![snapshot(3), snapshot(1), snapshot(2), snapshot(5)], 1, 3).unwrap();
+        assert_eq!(range.iter().map(|snapshot| snapshot.number).collect::<Vec<i32>>(), vec![1, 2, 3]);
+        assert!(snapshot_range(vec![snapshot(1), snapshot(2)], 2, 4).is_err());
+    }
+    #[test]
+    fn unit_series_table() {
+        let snapshots = vec![snapshot(1), snapshot(2), snapshot(3), snapshot(4)];
+        // the interval 2-3 has no records (a light snapshot without metrics), and is left out.
+        let records = vec![record(1, "rows_inserted", 10.), record(1, "rocksdb_number_db_seek", 5.), record(3, "rows_inserted", 20.)];
+        let (intervals, rows) = series_table(&snapshots, &records);
+        assert_eq!(intervals, vec![(1, 2), (3, 4)]);
+        assert_eq!(rows.len(), 2);
+        let key = |name: &str| (String::from("yb-1:9000"), String::from("tablet"), String::from("-"), name.to_string());
+        assert_eq!(rows[&key("rows_inserted")], vec![Some(10.), Some(20.)]);
+        assert_eq!(rows[&key("rocksdb_number_db_seek")], vec![Some(5.), None]);
+    }
+}
@@ -1,68 +1,105 @@
 //! Utility module for the [Snapshot] struct and snapshot CSV file.
 //!
 //! This currently leaves a single snapshot function in lib.rs which performs the complete snapshot of all modules.
-//! Because all the interaction of [Snapshot] is including reading and writing to a CSV file, there are no unittests.
+//! Because most of the interaction of [Snapshot] is including reading and writing to a CSV file, only the snapshot numbering and the selection of snapshots for deletion have unittests.
+//!
+//! Snapshots are deleted with [Snapshot::delete_snapshots] using a [SnapshotRetention], see there for how the snapshots are selected.
 use log::*;
-use std::{fs, process, path::Path, env};
+use std::{fs, io, process, path::{Path, PathBuf}, env, error::Error, sync::OnceLock};
 use std::io::{stdin, stdout, Write};
-use chrono::{DateTime, Local};
+use chrono::{DateTime, Duration, Local};
+use regex::Regex;
+use fs2::FileExt;
+use crate::manifest::SnapshotManifest;
+use crate::archive::SnapshotImport;
+use crate::storage;
+
+/// The directory that contains the snapshots, which is set once in main using [set_snapshot_directory].
+static SNAPSHOT_DIRECTORY: OnceLock<PathBuf> = OnceLock::new();
+
+/// This function sets the directory that contains the snapshot index and the snapshot directories for the whole run of yb_stats.
+pub fn set_snapshot_directory(directory: PathBuf) {
+    SNAPSHOT_DIRECTORY.set(directory).unwrap_or_else(|_| warn!("snapshot directory is already set"));
+}
+
+/// The directory that contains the snapshots: the directory set with [set_snapshot_directory], or yb_stats.snapshots in the current working directory.
+pub fn snapshot_directory() -> PathBuf {
+    match SNAPSHOT_DIRECTORY.get() {
+        Some(directory) => directory.clone(),
+        None => env::current_dir().unwrap().join("yb_stats.snapshots"),
+    }
+}
+
 /// Struct to represent the snapshots in yb_stats in a vector as well as on disk as CSV using serde.
 /// The comment can be empty, unless a snapshot is made with the `--snapshot-comment` flag and a comment.
+/// The cluster is the name of the inventory profile that is used with `--cluster`, and is empty if no profile is used.
+/// The snapshot.index files of older versions do not have the cluster column, which then is read as empty.
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct Snapshot {
     pub number: i32,
     pub timestamp: DateTime<Local>,
     pub comment: String,
+    #[serde(default)]
+    pub cluster: String,
 }
 
 impl Snapshot {
-    /// This is a public function to use the stored CSV snapshot file, determine the highest snapshot number and insert a new snapshot with current timestamp with a snapshot number one higher.
-    /// If the snapshot file doesn't exist, it will be created (yb_stats.snapshots/snapshot.index).
-    /// If the file does exist, the snapshots are read into a vector and the highest snapshot number is determined.
-    /// Then a struct is added to the vector, and the file is overwritten with the new vector.
-    /// The last things done are: the snapshot directory for the data is created (yb_stats.snapshots/<nr>) and the snapshot number is returned.
-    pub fn insert_new_snapshot_number(snapshot_comment: Option<String>) -> i32
+    /// This is a public function that reserves a new snapshot number by creating the snapshot directory for the data (yb_stats.snapshots/<nr>), and returns the snapshot number.
+    /// The snapshot number is one higher than the highest snapshot number in the snapshot.index file and the highest snapshot directory,
+    /// so a directory with data that is not in the snapshot.index file is never overwritten.
+    /// The snapshot.index lock is held while the number is determined and the directory is created, which makes the snapshot number unique across yb_stats processes.
+    /// The snapshot is added to the snapshot.index file with [Snapshot::insert_snapshot] after the data is collected.
+    pub fn reserve_snapshot_number() -> i32
     {
-        info!("read_snapshot_number");
-        let mut snapshots: Vec<Snapshot> = Vec::new();
-        let mut snapshot_number: i32 = 0;
-        let current_directory = env::current_dir().unwrap();
-        let yb_stats_directory = current_directory.join("yb_stats.snapshots");
-        // If the &yb_stats_directory does not exist, create it.
-        // If it does exist already, nothing happens and continue.
-        fs::create_dir_all(&yb_stats_directory)
+        info!("reserve_snapshot_number");
+        let yb_stats_directory = snapshot_directory();
+        let _lock = SnapshotIndexLock::acquire()
             .unwrap_or_else(|e| {
-                error!("Fatal: error creating directory {}: {}", &yb_stats_directory.clone().into_os_string().into_string().unwrap(), e);
+                error!("Fatal: error locking the snapshot index in {}: {}", yb_stats_directory.display(), e);
                 process::exit(1);
             });
-        // If &yb_stats_directory/snapshot.index exists, read snapshots into snapshots vector,
-        // and determine the highest snapshot number, add one and assign it to snapshot_number.
-        // If it doesn't exist, snapshot_number 0 is okay.
-        let snapshot_index = &yb_stats_directory.join("snapshot.index");
-        if Path::new(&snapshot_index).exists() {
-            snapshots = Snapshot::read_snapshots();
-            let record_with_highest_snapshot_number = snapshots.iter().max_by_key(|k| k.number).unwrap();
-            snapshot_number = record_with_highest_snapshot_number.number + 1;
-        }
-        // create a new snapshot vector and assign it the new_snapshot, and add it to the snapshots vector.
-        let new_snapshot: Snapshot = Snapshot { number: snapshot_number, timestamp: Local::now(), comment: snapshot_comment.unwrap_or_default() };
-        snapshots.push(new_snapshot);
-        Snapshot::write_snapshots(snapshots);
-        // Create the snapshot number directory in the &yb_stats_directory
-        let current_snapshot_directory = &yb_stats_directory.join(&snapshot_number.to_string());
-        fs::create_dir_all(current_snapshot_directory)
+        let snapshots = Snapshot::read_index()
             .unwrap_or_else(|e| {
-                error!("Fatal: error creating directory {}: {}", &current_snapshot_directory.clone().into_os_string().into_string().unwrap(), e);
+                error!("Fatal: error reading the snapshot index in {}: {}", yb_stats_directory.display(), e);
                 process::exit(1);
             });
+        let mut snapshot_number = next_snapshot_number(&snapshots, &yb_stats_directory);
+        // Another process that does not use the lock (an older yb_stats) can have created the directory in the meantime.
+        loop {
+            let current_snapshot_directory = yb_stats_directory.join(snapshot_number.to_string());
+            match fs::create_dir(&current_snapshot_directory) {
+                Ok(_) => break,
+                Err(e) if e.kind() == io::ErrorKind::AlreadyExists => snapshot_number += 1,
+                Err(e) => {
+                    error!("Fatal: error creating directory {}: {}", current_snapshot_directory.display(), e);
+                    process::exit(1);
+                }
+            }
+        }
         snapshot_number
     }
-    /// This is a private function to read the snapshots file, and return a vector with the snapshots.
-    fn read_snapshots() -> Vec<Snapshot>
+    /// This is a public function that adds a snapshot with a number that is reserved with [Snapshot::reserve_snapshot_number] to the snapshot.index file.
+    /// The timestamp is the start of the collection, or the timestamp of the original snapshot for an imported snapshot.
+    /// If this fails, the data of the snapshot is kept in the snapshot directory, and the snapshot number is not reused.
+    pub fn insert_snapshot(
+        snapshot_number: i32,
+        timestamp: DateTime<Local>,
+        comment: String,
+        cluster: String,
+    ) -> Result<(), Box<dyn Error>>
     {
-        let mut snapshots: Vec<Snapshot> = Vec::new();
-        let current_directory = env::current_dir().unwrap();
-        let yb_stats_directory = current_directory.join("yb_stats.snapshots");
+        info!("insert_snapshot: {}", snapshot_number);
+        let _lock = SnapshotIndexLock::acquire()?;
+        let mut snapshots = Snapshot::read_index()?;
+        snapshots.push(Snapshot { number: snapshot_number, timestamp, comment, cluster });
+        // A snapshot that takes longer can finish after a snapshot that is started later.
+        snapshots.sort_by_key(|snapshot| snapshot.number);
+        Snapshot::write_snapshots(snapshots)
+    }
+    /// This is a public function to read the snapshots file, and return a vector with the snapshots.
+    pub fn read_snapshots() -> Vec<Snapshot>
+    {
+        let yb_stats_directory = snapshot_directory();
         let snapshot_index = &yb_stats_directory.join("snapshot.index");
 
         let file = fs::File::open(snapshot_index)
@@ -70,6 +107,7 @@ impl Snapshot {
                 error!("Fatal: error opening file {}: {}", &snapshot_index.clone().into_os_string().into_string().unwrap(), e);
                 process::exit(1);
             });
+        let mut snapshots: Vec<Snapshot> = Vec::new();
         let mut reader = csv::Reader::from_reader(file);
         for row in reader.deserialize() {
             let data: Snapshot = row.unwrap();
@@ -77,35 +115,115 @@ impl Snapshot {
         }
         snapshots
     }
-    /// This is a private function to write the vector to the snapshots file. The file gets truncated and overwritten.
-    fn write_snapshots(snapshots: Vec<Snapshot>)
+    /// This is a private function to read the snapshots file for an update, which returns an empty vector if the file does not exist yet.
+    fn read_index() -> Result<Vec<Snapshot>, Box<dyn Error>>
+    {
+        let snapshot_index = snapshot_directory().join("snapshot.index");
+        let mut snapshots: Vec<Snapshot> = Vec::new();
+        if !snapshot_index.exists() {
+            return Ok(snapshots);
+        }
+        let mut reader = csv::Reader::from_reader(fs::File::open(&snapshot_index)?);
+        for row in reader.deserialize() {
+            let data: Snapshot = row?;
+            snapshots.push(data);
+        }
+        Ok(snapshots)
+    }
+    /// This is a private function to write the vector to the snapshots file.
+    /// The vector is written to a temporary file, which then is renamed to the snapshots file, so the snapshots file is never partially written.
+    /// This must be called with the [SnapshotIndexLock] held.
+    fn write_snapshots(snapshots: Vec<Snapshot>) -> Result<(), Box<dyn Error>>
     {
-        let current_directory = env::current_dir().unwrap();
-        let yb_stats_directory = current_directory.join("yb_stats.snapshots");
+        let yb_stats_directory = snapshot_directory();
         let snapshot_index = &yb_stats_directory.join("snapshot.index");
+        let temporary_index = &yb_stats_directory.join(format!("snapshot.index.{}.tmp", process::id()));
 
-        // Open the snapshot.index file, but truncate it and write the new snapshots vector to it.
-        let file = fs::OpenOptions::new()
-            .create(true)
-            .write(true)
-            .truncate(true)
-            .open(snapshot_index)
-            .unwrap_or_else(|e| {
-                error!("Fatal: error writing {}: {}", &snapshot_index.clone().into_os_string().into_string().unwrap(), e);
-                process::exit(1);
-            });
-        let mut writer = csv::Writer::from_writer(file);
-        for row in snapshots {
-            writer.serialize(row).unwrap();
+        let result = (|| -> Result<(), Box<dyn Error>> {
+            let file = fs::File::create(temporary_index)?;
+            let mut writer = csv::Writer::from_writer(file);
+            for row in snapshots {
+                writer.serialize(row)?;
+            }
+            writer.into_inner()?.sync_all()?;
+            fs::rename(temporary_index, snapshot_index)?;
+            Ok(())
+        })();
+        if result.is_err() {
+            let _ = fs::remove_file(temporary_index);
         }
-        writer.flush().unwrap();
+        result
     }
     /// This is a public function that reads the snapshots file into a vector and print the contents of it.
     /// The main use is to display the current snapshots to the user.
     pub fn print() {
+        Snapshot::print_list(&false);
+    }
+    /// This is a public function that prints the snapshots like [Snapshot::print], and with details_enable the manifest of every snapshot that has one, and the origin of an imported snapshot.
+    pub fn print_list(details_enable: &bool) {
         let snapshots = Snapshot::read_snapshots();
         for row in &snapshots {
-            println!("{:>3} {:30} {:50}", row.number, row.timestamp, row.comment);
+            println!("{:>3} {:30} {:20} {:50}", row.number, row.timestamp, row.cluster, row.comment);
+            if *details_enable {
+                if let Ok(snapshotmanifest) = SnapshotManifest::read_snapshot(&row.number.to_string()) {
+                    snapshotmanifest.print();
+                }
+                if let Ok(snapshotimport) = SnapshotImport::read_snapshot(&row.number.to_string()) {
+                    snapshotimport.print();
+                }
+            }
+        }
+    }
+    /// This is a public function that deletes the snapshots that are selected with the [SnapshotRetention] from the snapshot.index file and removes their directories.
+    /// The deleted snapshots are printed. With dry_run, the snapshots that would be deleted are printed, and nothing is deleted.
+    /// The snapshot.index file is written before the directories are removed, so the index never lists a snapshot of which the directory is removed.
+    /// The snapshot.index lock is held, so no snapshot is added by another yb_stats process in the meantime.
+    pub fn delete_snapshots(
+        retention: &SnapshotRetention,
+        dry_run: bool,
+    )
+    {
+        let _lock = SnapshotIndexLock::acquire()
+            .unwrap_or_else(|e| {
+                error!("Fatal: error locking the snapshot index in {}: {}", snapshot_directory().display(), e);
+                process::exit(1);
+            });
+        let snapshots = Snapshot::read_index()
+            .unwrap_or_else(|e| {
+                error!("Fatal: error reading the snapshot index in {}: {}", snapshot_directory().display(), e);
+                process::exit(1);
+            });
+        if snapshots.is_empty() {
+            println!("No snapshots found in {}", snapshot_directory().display());
+            return;
+        }
+        let delete = retention.select(&snapshots, Local::now());
+        if delete.is_empty() {
+            println!("No snapshots selected for deletion");
+            return;
+        }
+        for row in &delete {
+            println!("{} {:>3} {:30} {:20} {:50}", if dry_run { "would delete" } else { "delete" }, row.number, row.timestamp, row.cluster, row.comment);
+        }
+        if dry_run {
+            return;
+        }
+        let remaining = snapshots.into_iter()
+            .filter(|snapshot| !delete.iter().any(|deleted| deleted.number == snapshot.number))
+            .collect::<Vec<Snapshot>>();
+        Snapshot::write_snapshots(remaining)
+            .unwrap_or_else(|e| {
+                error!("Fatal: error writing the snapshot index in {}: {}", snapshot_directory().display(), e);
+                process::exit(1);
+            });
+        for row in &delete {
+            let current_snapshot_directory = snapshot_directory().join(row.number.to_string());
+            if current_snapshot_directory.exists() {
+                fs::remove_dir_all(&current_snapshot_directory)
+                    .unwrap_or_else(|e| warn!("Warning: error removing directory {}: {}", current_snapshot_directory.display(), e));
+            }
+            storage::delete_snapshot(row.number)
+                .unwrap_or_else(|e| warn!("Warning: error deleting snapshot {} from database {}: {}", row.number, storage::database_path().display(), e));
         }
     }
     /// This is a public function that validates begin and end provided values, and if these are not specified are requested interactively, after which the begin and end snapshot numbers and the struct with the begin snapshot are returned as record.
@@ -157,4 +275,205 @@ impl Snapshot {
         };
         (begin_snapshot.to_string(), end_snapshot.to_string(), begin_snapshot_row)
     }
-}
\ No newline at end of file
+}
+/// The lock on the snapshot.index file, which is held while the snapshot.index file is read and written, so yb_stats processes that create or delete snapshots at the same time do not overwrite each other.
+/// The lock is an exclusive lock on the file snapshot.index.lock in the snapshot directory, and is released when the struct is dropped.
+struct SnapshotIndexLock {
+    _file: fs::File,
+}
+
+impl SnapshotIndexLock {
+    /// This function waits for the lock, and creates the snapshot directory if it does not exist.
+    fn acquire() -> Result<SnapshotIndexLock, Box<dyn Error>> {
+        let yb_stats_directory = snapshot_directory();
+        fs::create_dir_all(&yb_stats_directory)?;
+        let file = fs::OpenOptions::new()
+            .create(true)
+            .write(true)
+            .truncate(false)
+            .open(yb_stats_directory.join("snapshot.index.lock"))?;
+        file.lock_exclusive()?;
+        Ok(SnapshotIndexLock { _file: file })
+    }
+}
+
+/// This function returns the snapshot number after the highest snapshot number in the snapshots and the highest snapshot directory, or 0 if there are none.
+fn next_snapshot_number(
+    snapshots: &[Snapshot],
+    yb_stats_directory: &Path,
+) -> i32
+{
+    let highest_index_number = snapshots.iter().map(|snapshot| snapshot.number).max();
+    let highest_directory_number = fs::read_dir(yb_stats_directory).into_iter()
+        .flatten()
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.path().is_dir())
+        .filter_map(|entry| entry.file_name().to_str().and_then(|name| name.parse::<i32>().ok()))
+        .max();
+    highest_index_number.max(highest_directory_number).map(|number| number + 1).unwrap_or(0)
+}
+
+/// The selection of snapshots to be deleted by [Snapshot::delete_snapshots].
+///
+/// Every criterion that is set selects snapshots, and a snapshot is deleted when it is selected by all the criteria that are set:
+/// - range: the snapshots with a number in the range (`--snapshot-delete nr` or `--snapshot-delete begin-end`).
+/// - keep_last: the snapshots that are not in the last N snapshots (`--snapshot-keep N`).
+/// - older_than: the snapshots that are older than the age (`--snapshot-older-than age`).
+///
+/// A snapshot with a comment that matches protect (`--snapshot-protect regex`) is never deleted, and is not counted for keep_last.
+/// If no criterion is set, no snapshot is selected.
+#[derive(Debug, Default)]
+pub struct SnapshotRetention {
+    pub range: Option<(i32, i32)>,
+    pub keep_last: Option<usize>,
+    pub older_than: Option<Duration>,
+    pub protect: Option<Regex>,
+}
+
+impl SnapshotRetention {
+    /// This function parses the switches for the retention, and returns an error if one of these is invalid.
+    pub fn new(
+        delete: Option<String>,
+        keep_last: Option<usize>,
+        older_than: Option<String>,
+        protect: Option<String>,
+    ) -> Result<SnapshotRetention, Box<dyn Error>>
+    {
+        Ok(SnapshotRetention {
+            range: delete.map(|delete| parse_range(&delete)).transpose()?,
+            keep_last,
+            older_than: older_than.map(|older_than| parse_age(&older_than)).transpose()?,
+            protect: protect.map(|protect| Regex::new(&protect)).transpose()?,
+        })
+    }
+    /// This function returns the snapshots that are selected for deletion.
+    fn select(
+        &self,
+        snapshots: &[Snapshot],
+        now: DateTime<Local>,
+    ) -> Vec<Snapshot>
+    {
+        if self.range.is_none() && self.keep_last.is_none() && self.older_than.is_none() {
+            return Vec::new();
+        }
+        let mut unprotected = snapshots.iter()
+            .filter(|snapshot| !self.protect.as_ref().map(|protect| protect.is_match(&snapshot.comment)).unwrap_or(false))
+            .collect::<Vec<&Snapshot>>();
+        unprotected.sort_by_key(|snapshot| snapshot.number);
+        let keep_from = self.keep_last.map(|keep_last| unprotected.len().saturating_sub(keep_last)).unwrap_or(unprotected.len());
+
+        unprotected.iter()
+            .enumerate()
+            .filter(|(position, _)| self.keep_last.is_none() || *position < keep_from)
+            .filter(|(_, snapshot)| self.range.map(|(begin, end)| snapshot.number >= begin && snapshot.number <= end).unwrap_or(true))
+            .filter(|(_, snapshot)| self.older_than.map(|age| snapshot.timestamp < now - age).unwrap_or(true))
+            .map(|(_, snapshot)| (*snapshot).clone())
+            .collect()
+    }
+}
+
+/// This function parses a snapshot number (`5`) or a range of snapshot numbers (`5-10`).
+fn parse_range(
+    range: &str,
+) -> Result<(i32, i32), Box<dyn Error>>
+{
+    let (begin, end) = match range.split_once('-') {
+        Some((begin, end)) => (begin.trim().parse::<i32>()?, end.trim().parse::<i32>()?),
+        None => {
+            let number = range.trim().parse::<i32>()?;
+            (number, number)
+        }
+    };
+    if begin > end {
+        return Err(format!("invalid snapshot range: {}, the begin is higher than the end", range).into());
+    }
+    Ok((begin, end))
+}
+
+/// This function parses an age: a number with the unit s (seconds), m (minutes), h (hours), d (days) or w (weeks), for example `7d`.
+fn parse_age(
+    age: &str,
+) -> Result<Duration, Box<dyn Error>>
+{
+    let age = age.trim();
+    let unit_position = age.find(|c: char| !c.is_ascii_digit())
+        .ok_or_else(|| format!("invalid age: {}, the age needs a unit: s, m, h, d or w", age))?;
+    let (number, unit) = age.split_at(unit_position);
+    let number = number.parse::<i64>()
+        .map_err(|e| format!("invalid age: {}: {}", age, e))?;
+    match unit {
+        "s" => Ok(Duration::seconds(number)),
+        "m" => Ok(Duration::minutes(number)),
+        "h" => Ok(Duration::hours(number)),
+        "d" => Ok(Duration::days(number)),
+        "w" => Ok(Duration::weeks(number)),
+        _ => Err(format!("invalid age: {}, valid units are: s, m, h, d, w", age).into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn snapshots(now: DateTime<Local>) -> Vec<Snapshot> {
+        (0..6).map(|number| Snapshot {
+            number,
+            timestamp: now - Duration::days(10 - number as i64),
+            comment: if number == 1 { "baseline".to_string() } else { String::new() },
+            cluster: String::new(),
+        }).collect()
+    }
+    fn numbers(snapshots: Vec<Snapshot>) -> Vec<i32> {
+        snapshots.iter().map(|snapshot| snapshot.number).collect()
+    }
+
+    #[test]
+    fn unit_next_snapshot_number() {
+        let now = Local::now();
+        let directory = env::temp_dir().join(format!("yb_stats_next_snapshot_number_{}", process::id()));
+        fs::create_dir_all(&directory).unwrap();
+        assert_eq!(next_snapshot_number(&[], &directory), 0);
+        assert_eq!(next_snapshot_number(&snapshots(now), &directory), 6);
+        // a snapshot directory that is not in the index is not reused.
+        fs::create_dir_all(directory.join("8")).unwrap();
+        fs::create_dir_all(directory.join(".import.1")).unwrap();
+        assert_eq!(next_snapshot_number(&snapshots(now), &directory), 9);
+        fs::remove_dir_all(&directory).unwrap();
+    }
+    #[test]
+    fn unit_parse_range_and_age() {
+        assert_eq!(parse_range("5").unwrap(), (5, 5));
+        assert_eq!(parse_range("5-10").unwrap(), (5, 10));
+        assert!(parse_range("10-5").is_err());
+        assert!(parse_range("a").is_err());
+        assert_eq!(parse_age("7d").unwrap(), Duration::days(7));
+        assert_eq!(parse_age("12h").unwrap(), Duration::hours(12));
+        assert!(parse_age("7").is_err());
+        assert!(parse_age("7y").is_err());
+    }
+    #[test]
+    fn unit_retention_select() {
+        let now = Local::now();
+        let snapshots = snapshots(now);
+        // snapshot 0 is 10 days old, snapshot 5 is 5 days old.
+        let range = SnapshotRetention::new(Some("1-3".to_string()), None, None, None).unwrap();
+        assert_eq!(numbers(range.select(&snapshots, now)), vec![1, 2, 3]);
+        let keep_last = SnapshotRetention::new(None, Some(2), None, None).unwrap();
+        assert_eq!(numbers(keep_last.select(&snapshots, now)), vec![0, 1, 2, 3]);
+        let older_than = SnapshotRetention::new(None, None, Some("8d".to_string()), None).unwrap();
+        assert_eq!(numbers(older_than.select(&snapshots, now)), vec![0, 1]);
+        let combined = SnapshotRetention::new(None, Some(5), Some("8d".to_string()), None).unwrap();
+        assert_eq!(numbers(combined.select(&snapshots, now)), vec![0]);
+        assert!(SnapshotRetention::default().select(&snapshots, now).is_empty());
+    }
+    #[test]
+    fn unit_retention_select_protect() {
+        let now = Local::now();
+        let snapshots = snapshots(now);
+        let protect = SnapshotRetention::new(Some("0-5".to_string()), None, None, Some("^base".to_string())).unwrap();
+        assert_eq!(numbers(protect.select(&snapshots, now)), vec![0, 2, 3, 4, 5]);
+        // the protected snapshot is not counted for keep_last.
+        let keep_last = SnapshotRetention::new(None, Some(2), None, Some("^base".to_string())).unwrap();
+        assert_eq!(numbers(keep_last.select(&snapshots, now)), vec![0, 2, 3]);
+    }
+}
@@ -14,7 +14,7 @@
 //! 2. For statements, this is done via the [AllStoredStatements::perform_snapshot] method. This method performs two calls.
 //!
 //!    * The method [AllStoredStatements::read_statements]
-//!      * This method starts a rayon threadpool, and runs the general function [AllStoredStatements::read_http] for all host and port combinations.
+//!      * This method concurrently runs the general function [AllStoredStatements::read_http] for all host and port combinations.
 //!        * [AllStoredStatements::read_http] calls [AllStoredStatements::parse_statements] to parse the http JSON output into a vector of [Statement].
 //!        * The vector is iterated over and processed using the function [AllStoredStatements::add_and_sum_statements], which produces a struct [AllStoredStatements], which contains a vector [StoredStatements].
 //!
@@ -38,7 +38,7 @@
 //! 1. [SnapshotDiffBTreeMapStatements::adhoc_read_first_snapshot]
 //!
 //!   * The method calls [AllStoredStatements::read_statements]
-//!      * This method starts a rayon threadpool, and runs the general function [AllStoredStatements::read_http] for all host and port combinations.
+//!      * This method concurrently runs the general function [AllStoredStatements::read_http] for all host and port combinations.
 //!        * [AllStoredStatements::read_http] calls [AllStoredStatements::parse_statements] to parse the http JSON output into a vector of [Statement].
 //!        * The vector is iterated over and processed using the function [AllStoredStatements::add_and_sum_statements], which produces a struct [AllStoredStatements], which contains a vector [StoredStatements].
 //!   * The method calls [SnapshotDiffBTreeMapStatements::first_snapshot] with [AllStoredStatements] as argument to insert the first snapshot data.
@@ -48,7 +48,7 @@
 //! 3. [SnapshotDiffBTreeMapStatements::adhoc_read_second_snapshot]
 //!
 //!   * The method calls [AllStoredStatements::read_statements]
-//!      * This method starts a rayon threadpool, and runs the general function [AllStoredStatements::read_http] for all host and port combinations.
+//!      * This method concurrently runs the general function [AllStoredStatements::read_http] for all host and port combinations.
 //!        * [AllStoredStatements::read_http] calls [AllStoredStatements::parse_statements] to parse the http JSON output into a vector of [Statement].
 //!        * The vector is iterated over and processed using the function [AllStoredStatements::add_and_sum_statements], which produces a struct [AllStoredStatements], which contains a vector [StoredStatements].
 //!   * The method calls [SnapshotDiffBTreeMapStatements::second_snapshot] with [AllStoredStatements] as argument to insert the second snapshot data.
@@ -57,11 +57,20 @@
 //!
 use chrono::{DateTime, Local};
 use serde_derive::{Serialize,Deserialize};
-use std::{fs, process, error::Error, collections::BTreeMap, env, sync::mpsc::channel, time::Instant};
+use std::{process, error::Error, collections::BTreeMap, time::Instant};
 use regex::Regex;
 use substring::Substring;
 use log::*;
-use crate::utility::{scan_host_port, http_get};
+use crate::snapshot::snapshot_directory;
+use futures::future::join_all;
+use crate::utility::http_get;
+use crate::endpoints::has_role;
+use crate::compression::create_snapshot_file;
+use crate::schema::open_snapshot_csv;
+use crate::output::{self, DiffRecord};
+
+/// The endpoint roles that provide /statements.
+pub const ROLES: &[&str] = &["ysql"];
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct Statement {
@@ -196,6 +205,18 @@ impl SnapshotDiffStatements {
             second_rows: statement.rows,
         }
     }
+    fn second_snapshot_as_first(statements_diff_row: &SnapshotDiffStatements) -> Self {
+        Self {
+            first_snapshot_time: statements_diff_row.second_snapshot_time,
+            second_snapshot_time: statements_diff_row.second_snapshot_time,
+            first_calls: statements_diff_row.second_calls,
+            second_calls: 0,
+            first_total_time: statements_diff_row.second_total_time,
+            second_total_time: 0.,
+            first_rows: statements_diff_row.second_rows,
+            second_rows: 0,
+        }
+    }
 }
 
 type BTreeMapSnapshotDiffStatements = BTreeMap<(String, String), SnapshotDiffStatements>;
@@ -267,29 +288,74 @@ impl SnapshotDiffBTreeMapStatements {
         &mut self,
         hosts: &Vec<&str>,
         ports: &Vec<&str>,
-        parallel: usize,
     )
     {
-        let allstoredstatements = AllStoredStatements::read_statements(hosts, ports, parallel).await;
+        let allstoredstatements = AllStoredStatements::read_statements(hosts, ports).await;
         self.first_snapshot(allstoredstatements);
     }
     pub async fn adhoc_read_second_snapshot(
         &mut self,
         hosts: &Vec<&str>,
         ports: &Vec<&str>,
-        parallel: usize,
         first_snapshot_time: &DateTime<Local>,
     )
     {
-        let allstoredstatements = AllStoredStatements::read_statements(hosts, ports, parallel).await;
+        let allstoredstatements = AllStoredStatements::read_statements(hosts, ports).await;
         self.second_snapshot(allstoredstatements, first_snapshot_time);
     }
+    /// This function reads the next snapshot data from the http endpoints itself, after the second snapshot is turned into the first snapshot (=top mode).
+    /// This way, the struct keeps the previous snapshot, and only one snapshot is read per interval.
+    /// Statements that are not present in the second snapshot (anymore) are removed.
+    /// The previous_snapshot_time is used as first_snapshot_time for statements that are new in the next snapshot.
+    pub async fn adhoc_read_next_snapshot(
+        &mut self,
+        hosts: &Vec<&str>,
+        ports: &Vec<&str>,
+        previous_snapshot_time: &DateTime<Local>,
+    )
+    {
+        let allstoredstatements = AllStoredStatements::read_statements(hosts, ports).await;
+        self.btreemap_snapshotdiff_statements.retain(|_, statements_diff_row| statements_diff_row.second_calls > 0);
+        for statements_diff_row in self.btreemap_snapshotdiff_statements.values_mut() {
+            *statements_diff_row = SnapshotDiffStatements::second_snapshot_as_first(statements_diff_row);
+        }
+        self.second_snapshot(allstoredstatements, previous_snapshot_time);
+    }
+    /// This function returns the statements that are printed by [SnapshotDiffBTreeMapStatements::print] as records for the machine-readable output, see [crate::output].
+    /// The records contain the complete query, the delta and rate are the number of calls, and the sum and average are the time in milliseconds.
+    pub fn records(
+        &self,
+        hostname_filter: &Regex,
+    ) -> Vec<DiffRecord>
+    {
+        self.btreemap_snapshotdiff_statements.iter()
+            .filter(|((hostname, _), statements_row)| hostname_filter.is_match(hostname) && statements_row.second_calls - statements_row.first_calls != 0)
+            .map(|((hostname, query), statements_row)| {
+                let calls = (statements_row.second_calls - statements_row.first_calls) as f64;
+                let total_time = statements_row.second_total_time - statements_row.first_total_time;
+                DiffRecord::statistic("statements", statements_row.first_snapshot_time, statements_row.second_snapshot_time, DiffRecord {
+                    hostname_port: hostname.to_string(),
+                    name: query.to_string(),
+                    delta: Some(calls),
+                    average: Some(total_time / calls),
+                    sum_delta: Some(total_time),
+                    rows_delta: Some((statements_row.second_rows - statements_row.first_rows) as f64),
+                    unit: String::from("milliseconds"),
+                    ..Default::default()
+                })
+            })
+            .collect()
+    }
     pub async fn print(
         &self,
         hostname_filter: &Regex,
         sql_length: usize,
     )
     {
+        if output::is_machine_readable() {
+            output::write_records(&self.records(hostname_filter));
+            return;
+        }
         for ((hostname, query), statements_row) in &self.btreemap_snapshotdiff_statements {
             if hostname_filter.is_match(hostname)
                 && statements_row.second_calls - statements_row.first_calls != 0 {
@@ -320,12 +386,11 @@ impl AllStoredStatements {
         hosts: &Vec<&str>,
         ports: &Vec<&str>,
         snapshot_number: i32,
-        parallel: usize
     ) {
         info!("begin snapshot");
         let timer = Instant::now();
 
-        let allstoredstatements = AllStoredStatements::read_statements(hosts, ports, parallel);
+        let allstoredstatements = AllStoredStatements::read_statements(hosts, ports);
         allstoredstatements.await.save_snapshot(snapshot_number)
             .unwrap_or_else(|e| {
                 error!("error saving snapshot: {}", e);
@@ -336,19 +401,15 @@ impl AllStoredStatements {
     }
     fn save_snapshot ( self, snapshot_number: i32 ) -> Result<(), Box<dyn Error>>
     {
-        let current_directory = env::current_dir()?;
-        let current_snapshot_directory = current_directory.join("yb_stats.snapshots").join(&snapshot_number.to_string());
+        let current_snapshot_directory = snapshot_directory().join(snapshot_number.to_string());
 
         let statements_file = &current_snapshot_directory.join("statements");
-        let file = fs::OpenOptions::new()
-            .create(true)
-            .write(true)
-            .open(statements_file)?;
+        let file = create_snapshot_file(statements_file)?;
         let mut writer = csv::Writer::from_writer(file);
         for row in self.stored_statements {
             writer.serialize(row)?;
         }
-        writer.flush()?;
+        writer.into_inner()?.finish()?;
 
         Ok(())
     }
@@ -356,11 +417,10 @@ impl AllStoredStatements {
     {
         let mut allstoredstatements = AllStoredStatements { stored_statements: Vec::new() };
 
-        let current_directory = env::current_dir()?;
-        let current_snapshot_directory = current_directory.join("yb_stats.snapshots").join(snapshot_number);
+        let current_snapshot_directory = snapshot_directory().join(snapshot_number);
 
         let statements_file = &current_snapshot_directory.join("statements");
-        let file = fs::File::open(statements_file)?;
+        let file = open_snapshot_csv(statements_file)?;
 
         let mut reader = csv::Reader::from_reader(file);
         for  row in reader.deserialize() {
@@ -373,31 +433,31 @@ impl AllStoredStatements {
     pub async fn read_statements (
         hosts: &Vec<&str>,
         ports: &Vec<&str>,
-        parallel: usize
     ) -> AllStoredStatements
     {
         info!("begin parallel http read");
         let timer = Instant::now();
 
-        let pool = rayon::ThreadPoolBuilder::new().num_threads(parallel).build().unwrap();
-        let (tx, rx) = channel();
-        pool.scope(move |s| {
-            for host in hosts {
-                for port in ports {
-                    let tx = tx.clone();
-                    s.spawn(move |_| {
-                        let detail_snapshot_time = Local::now();
-                        let statements = AllStoredStatements::read_http(host, port);
-                        tx.send((format!("{}:{}", host, port), detail_snapshot_time, statements)).expect("error sending data via tx (statements)");
-                    });
+        let mut handles = Vec::new();
+        for host in hosts {
+            for port in ports {
+                if !has_role(host, port, ROLES) {
+                    continue;
                 }
+                let handle = async move {
+                    let detail_snapshot_time = Local::now();
+                    let statements = AllStoredStatements::read_http(host, port).await;
+                    (format!("{}:{}", host, port), detail_snapshot_time, statements)
+                };
+                handles.push(handle);
             }
-        });
+        }
+        let results = join_all(handles).await;
 
         info!("end parallel http read {:?}", timer.elapsed());
 
         let mut allstoredstatements = AllStoredStatements { stored_statements: Vec::new() };
-        for (hostname_port, detail_snapshot_time, statements) in rx {
+        for (hostname_port, detail_snapshot_time, statements) in results {
             AllStoredStatements::add_and_sum_statements(statements, &hostname_port, detail_snapshot_time, &mut allstoredstatements);
         }
         allstoredstatements
@@ -431,16 +491,12 @@ impl AllStoredStatements {
             allstoredstatements.stored_statements.push( StoredStatements::new(hostname, snapshot_time, query, unique_statement_data) );
         }
     }
-    pub fn read_http(
+    pub async fn read_http(
         host: &str,
         port: &str,
     ) -> Statement
     {
-        let data_from_http = if scan_host_port( host, port) {
-            http_get(host, port, "statements")
-        } else {
-            String::new()
-        };
+        let data_from_http = http_get(host, port, "statements").await;
         AllStoredStatements::parse_statements(data_from_http)
 /*
         if ! scan_port_addr( format!("{}:{}", host, port) ) {
@@ -467,6 +523,26 @@ impl AllStoredStatements {
 mod tests {
     use super::*;
 
+    #[test]
+    fn unit_statements_records() {
+        use chrono::TimeZone;
+        let mut statements = SnapshotDiffBTreeMapStatements::default();
+        let row = |first_calls: i64, second_calls: i64| SnapshotDiffStatements {
+            first_snapshot_time: Local.with_ymd_and_hms(2023, 1, 10, 10, 0, 0).unwrap(),
+            second_snapshot_time: Local.with_ymd_and_hms(2023, 1, 10, 10, 0, 10).unwrap(),
+            first_calls,
+            second_calls,
+            first_total_time: 100.,
+            second_total_time: 150.,
+            first_rows: 10,
+            second_rows: 30,
+        };
+        statements.btreemap_snapshotdiff_statements.insert((String::from("yb-1:13000"), String::from("select * from t")), row(10, 30));
+        statements.btreemap_snapshotdiff_statements.insert((String::from("yb-1:13000"), String::from("select 1")), row(10, 10));
+        let records = statements.records(&Regex::new(".*").unwrap());
+        assert_eq!(records.len(), 1);
+        assert_eq!((records[0].name.as_str(), records[0].delta, records[0].rate, records[0].average, records[0].sum_delta, records[0].rows_delta), ("select * from t", Some(20.), Some(2.), Some(2.5), Some(50.), Some(20.)));
+    }
     #[test]
     fn unit_parse_statements_simple() {
         // This is a very simple example of the statements json.
@@ -570,13 +646,13 @@ mod tests {
 
     use crate::utility;
 
-    #[test]
-    fn integration_parse_statements_ysql() {
+    #[tokio::test]
+    async fn integration_parse_statements_ysql() {
         let mut allstoredstatements = AllStoredStatements { stored_statements: Vec::new() };
         let hostname = utility::get_hostname_ysql();
         let port = utility::get_port_ysql();
 
-        let result = AllStoredStatements::read_http(hostname.as_str(), port.as_str());
+        let result = AllStoredStatements::read_http(hostname.as_str(), port.as_str()).await;
         AllStoredStatements::add_and_sum_statements(result, &hostname, Local::now(), &mut allstoredstatements);
         // likely in a test scenario, there are no SQL commands executed, and thus no rows are returned.
         // to make sure this test works in both the scenario of no statements, and with statements, perform no assertion.
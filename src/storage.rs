@@ -0,0 +1,471 @@
+//! The module for the storage backend of the snapshot data.
+//!
+//! The storage is set with `--storage csv|sqlite` (default csv):
+//! - csv: every source is stored in a CSV file in the snapshot directory (`yb_stats.snapshots/<nr>/values`).
+//! - sqlite: every source is stored in a table in the SQLite database `snapshots.sqlite` in the snapshot directory.
+//!   The table has the name of the CSV file, and has the columns of the stored struct, such as [crate::metrics::StoredValues],
+//!   plus the columns snapshot_number and row_number: `select * from "values" where snapshot_number = 12 order by row_number`.
+//!   The files that are not CSV (the manifest, pprof and mems data) are stored in the table snapshot_files.
+//!
+//! The table snapshot_files contains a row for every file of every snapshot in the database with the columns of the CSV file, or the data of a file that is not CSV.
+//! The snapshot.index file and the snapshot directories are used for both storage backends, so the snapshot numbers are assigned the same way.
+//!
+//! The storage is used via [crate::compression::create_snapshot_file] and [crate::compression::open_snapshot_file]:
+//! a file that is not found in the snapshot directory is read from the database, so all the functions that read snapshots work with both backends,
+//! and snapshots of both backends can be used together, for example for a diff.
+//! CSV snapshots are converted into the database with [convert_snapshots] (`--sqlite-convert`).
+use std::{fmt, fs, io::{self, Write}, path::{Path, PathBuf}, error::Error, str::FromStr, sync::OnceLock, time::Duration};
+use tokio::runtime::Handle;
+use rusqlite::{Connection, OptionalExtension, TransactionBehavior, params, types::Value};
+use log::*;
+use crate::snapshot::{Snapshot, snapshot_directory};
+use crate::compression::{open_snapshot_file, strip_compression_extension};
+
+/// The name of the SQLite database in the snapshot directory.
+pub const DATABASE: &str = "snapshots.sqlite";
+/// The files in a snapshot directory that are not CSV.
+const NON_CSV_FILES: [&str; 2] = ["manifest", "import"];
+/// The prefixes of the files in a snapshot directory that are not CSV.
+const NON_CSV_FILE_PREFIXES: [&str; 2] = ["pprof_growth_", "mems_"];
+/// The time to wait for a lock on the database by another yb_stats process or task.
+const BUSY_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// The storage backend of the snapshot data, which is set once in main using [set_storage].
+static STORAGE: OnceLock<Storage> = OnceLock::new();
+
+/// The storage backend of the snapshot data.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Storage {
+    #[default]
+    Csv,
+    Sqlite,
+}
+
+impl FromStr for Storage {
+    type Err = String;
+
+    fn from_str(storage: &str) -> Result<Self, Self::Err> {
+        match storage {
+            "csv" => Ok(Storage::Csv),
+            "sqlite" => Ok(Storage::Sqlite),
+            _ => Err(format!("invalid storage: {}, valid storages are: csv, sqlite", storage)),
+        }
+    }
+}
+
+impl fmt::Display for Storage {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Storage::Csv => write!(f, "csv"),
+            Storage::Sqlite => write!(f, "sqlite"),
+        }
+    }
+}
+
+/// This function sets the storage backend of the snapshot data for the whole run of yb_stats.
+pub fn set_storage(storage: Storage) {
+    STORAGE.set(storage).unwrap_or_else(|_| warn!("storage is already set"));
+}
+
+/// The storage backend of the snapshot data.
+pub fn storage() -> Storage {
+    STORAGE.get().copied().unwrap_or_default()
+}
+
+/// This function returns true if the file in a snapshot directory is a CSV file.
+pub fn is_csv_file(
+    file_name: &str,
+) -> bool
+{
+    let file_name = strip_compression_extension(file_name);
+    !NON_CSV_FILES.contains(&file_name) && !NON_CSV_FILE_PREFIXES.iter().any(|prefix| file_name.starts_with(prefix))
+}
+
+/// The path of the SQLite database.
+pub fn database_path() -> PathBuf {
+    snapshot_directory().join(DATABASE)
+}
+
+/// A file in the database, as it is shown in the manifest.
+#[derive(Debug)]
+pub struct DatabaseFile {
+    pub name: String,
+    pub bytes: u64,
+    pub rows: Option<u64>,
+}
+
+/// The writer of [crate::compression::SnapshotFile] with the sqlite storage.
+/// The data is collected, and is saved in the database with [DatabaseWriter::finish], which is when the file would be closed.
+pub struct DatabaseWriter {
+    path: PathBuf,
+    data: Vec<u8>,
+}
+
+impl DatabaseWriter {
+    pub fn new(path: &Path) -> DatabaseWriter {
+        DatabaseWriter { path: path.to_path_buf(), data: Vec::new() }
+    }
+    /// This function saves the data in the database.
+    /// Saving can wait for the lock on the database, so in a task of the runtime the worker thread is handed over to the other tasks in the meantime.
+    /// This requires the multi-threaded runtime, which is the runtime of yb_stats (`#[tokio::main]`).
+    pub fn finish(self) -> Result<(), Box<dyn Error>> {
+        let save = || save_file(&self.path, &self.data)
+            .map_err(|e| format!("error saving {} in database {}: {}", self.path.display(), database_path().display(), e));
+        match Handle::try_current() {
+            Ok(_) => tokio::task::block_in_place(save)?,
+            _ => save()?,
+        }
+        Ok(())
+    }
+}
+
+impl Write for DatabaseWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.data.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// This function opens the database, and creates the snapshot_files table if it does not exist.
+fn open_database(
+    database: &Path,
+) -> Result<Connection, Box<dyn Error>>
+{
+    let connection = Connection::open(database)?;
+    connection.busy_timeout(BUSY_TIMEOUT)?;
+    connection.pragma_update(None, "journal_mode", "WAL")?;
+    connection.execute_batch(
+        "create table if not exists snapshot_files (
+            snapshot_number integer not null,
+            name text not null,
+            columns text,
+            bytes integer not null,
+            rows integer,
+            data blob,
+            primary key (snapshot_number, name)
+        )")?;
+    Ok(connection)
+}
+
+/// The snapshot number and the name of the file of a path in a snapshot directory, or None if the path is not in a snapshot directory.
+fn snapshot_file(
+    path: &Path,
+) -> Option<(i32, String)>
+{
+    let name = path.file_name()?.to_str()?;
+    let snapshot_number = path.parent()?.file_name()?.to_str()?.parse::<i32>().ok()?;
+    Some((snapshot_number, strip_compression_extension(name).to_string()))
+}
+
+/// The quoted name of a table or column.
+fn quote(
+    identifier: &str,
+) -> String
+{
+    format!("\"{}\"", identifier.replace('"', "\"\""))
+}
+
+/// This function converts a CSV field to a SQLite value: an integer or real if the text is exactly the same when it's read back, otherwise text.
+fn to_value(
+    field: &str,
+) -> Value
+{
+    if let Ok(integer) = field.parse::<i64>() {
+        if integer.to_string() == field {
+            return Value::Integer(integer);
+        }
+    }
+    if let Ok(real) = field.parse::<f64>() {
+        if real.is_finite() && real.to_string() == field {
+            return Value::Real(real);
+        }
+    }
+    Value::Text(field.to_string())
+}
+
+/// This function converts a SQLite value back to a CSV field.
+fn to_field(
+    value: Value,
+) -> String
+{
+    match value {
+        Value::Null => String::new(),
+        Value::Integer(integer) => integer.to_string(),
+        Value::Real(real) => real.to_string(),
+        Value::Text(text) => text,
+        Value::Blob(blob) => String::from_utf8_lossy(&blob).to_string(),
+    }
+}
+
+/// This function saves the data of a file in a snapshot directory in the database, and replaces the data of the file if it was saved before.
+pub fn save_file(
+    path: &Path,
+    data: &[u8],
+) -> Result<(), Box<dyn Error>>
+{
+    save_file_in_database(&database_path(), path, data)
+}
+
+fn save_file_in_database(
+    database: &Path,
+    path: &Path,
+    data: &[u8],
+) -> Result<(), Box<dyn Error>>
+{
+    let (snapshot_number, name) = snapshot_file(path)
+        .ok_or_else(|| format!("{} is not a file in a snapshot directory", path.display()))?;
+    let mut connection = open_database(database)?;
+    let transaction = connection.transaction_with_behavior(TransactionBehavior::Immediate)?;
+    delete_file(&transaction, snapshot_number, &name)?;
+
+    if !is_csv_file(&name) {
+        transaction.execute("insert into snapshot_files (snapshot_number, name, bytes, data) values (?1, ?2, ?3, ?4)",
+                            params![snapshot_number, name, data.len() as i64, data])?;
+        return Ok(transaction.commit()?);
+    }
+
+    let mut reader = csv::ReaderBuilder::new().has_headers(false).from_reader(data);
+    let mut records = reader.records();
+    let headers = match records.next() {
+        Some(headers) => headers?,
+        None => csv::StringRecord::new(),
+    };
+    // A file without a header has no rows, and has no columns.
+    let columns = if headers.is_empty() {
+        String::new()
+    } else {
+        let mut columns = csv::Writer::from_writer(Vec::new());
+        columns.write_record(&headers)?;
+        String::from_utf8(columns.into_inner()?)?.trim_end().to_string()
+    };
+
+    let mut rows: i64 = 0;
+    if !headers.is_empty() {
+        let table = quote(&name);
+        transaction.execute(&format!("create table if not exists {} (snapshot_number integer not null, row_number integer not null)", table), [])?;
+        let existing_columns = transaction.prepare(&format!("select name from pragma_table_info({})", quote(&name).replace('"', "'")))?
+            .query_map([], |row| row.get::<_, String>(0))?
+            .collect::<Result<Vec<String>, rusqlite::Error>>()?;
+        // A newer layout of a file can have more columns.
+        for header in headers.iter().filter(|header| !existing_columns.iter().any(|column| column == header)) {
+            transaction.execute(&format!("alter table {} add column {}", table, quote(header)), [])?;
+        }
+        transaction.execute(&format!("create index if not exists {} on {} (snapshot_number)", quote(&format!("{}_snapshot_number", name)), table), [])?;
+
+        let mut insert = transaction.prepare(&format!("insert into {} (snapshot_number, row_number, {}) values (?, ?, {})",
+                                                      table,
+                                                      headers.iter().map(quote).collect::<Vec<_>>().join(", "),
+                                                      headers.iter().map(|_| "?").collect::<Vec<_>>().join(", ")))?;
+        for record in records {
+            let record = record?;
+            let mut values = vec![Value::Integer(snapshot_number as i64), Value::Integer(rows)];
+            values.extend(record.iter().map(to_value));
+            insert.execute(rusqlite::params_from_iter(values))?;
+            rows += 1;
+        }
+    }
+    transaction.execute("insert into snapshot_files (snapshot_number, name, columns, bytes, rows) values (?1, ?2, ?3, ?4, ?5)",
+                        params![snapshot_number, name, columns, data.len() as i64, rows])?;
+    Ok(transaction.commit()?)
+}
+
+/// This function deletes the data of a file of a snapshot from the database.
+fn delete_file(
+    connection: &Connection,
+    snapshot_number: i32,
+    name: &str,
+) -> Result<(), Box<dyn Error>>
+{
+    let columns: Option<Option<String>> = connection.query_row("select columns from snapshot_files where snapshot_number = ?1 and name = ?2",
+                                                               params![snapshot_number, name], |row| row.get(0)).optional()?;
+    if let Some(Some(columns)) = columns {
+        if !columns.is_empty() {
+            connection.execute(&format!("delete from {} where snapshot_number = ?1", quote(name)), params![snapshot_number])?;
+        }
+    }
+    connection.execute("delete from snapshot_files where snapshot_number = ?1 and name = ?2", params![snapshot_number, name])?;
+    Ok(())
+}
+
+/// This function reads the data of a file in a snapshot directory from the database, and returns the data in the same format as the file.
+/// If the database or the file in the database doesn't exist, None is returned.
+pub fn read_file(
+    path: &Path,
+) -> Result<Option<Vec<u8>>, Box<dyn Error>>
+{
+    read_file_from_database(&database_path(), path)
+}
+
+fn read_file_from_database(
+    database: &Path,
+    path: &Path,
+) -> Result<Option<Vec<u8>>, Box<dyn Error>>
+{
+    let Some((snapshot_number, name)) = snapshot_file(path) else { return Ok(None) };
+    if !database.exists() {
+        return Ok(None);
+    }
+    let connection = open_database(database)?;
+    let file: Option<(Option<String>, Option<Vec<u8>>)> = connection.query_row("select columns, data from snapshot_files where snapshot_number = ?1 and name = ?2",
+                                                                               params![snapshot_number, name], |row| Ok((row.get(0)?, row.get(1)?))).optional()?;
+    let columns = match file {
+        None => return Ok(None),
+        Some((_, Some(data))) => return Ok(Some(data)),
+        Some((None, None)) => return Ok(Some(Vec::new())),
+        Some((Some(columns), None)) => columns,
+    };
+    if columns.is_empty() {
+        return Ok(Some(Vec::new()));
+    }
+    let headers = csv::ReaderBuilder::new().has_headers(false).from_reader(columns.as_bytes())
+        .records().next().transpose()?.unwrap_or_default();
+
+    let mut writer = csv::Writer::from_writer(Vec::new());
+    writer.write_record(&headers)?;
+    let mut select = connection.prepare(&format!("select {} from {} where snapshot_number = ?1 order by row_number",
+                                                 headers.iter().map(quote).collect::<Vec<_>>().join(", "),
+                                                 quote(&name)))?;
+    let mut rows = select.query(params![snapshot_number])?;
+    while let Some(row) = rows.next()? {
+        let record = (0..headers.len())
+            .map(|column| row.get::<_, Value>(column).map(to_field))
+            .collect::<Result<Vec<String>, rusqlite::Error>>()?;
+        writer.write_record(&record)?;
+    }
+    Ok(Some(writer.into_inner()?))
+}
+
+/// This function returns the files of a snapshot in the database.
+pub fn database_files(
+    snapshot_number: i32,
+) -> Result<Vec<DatabaseFile>, Box<dyn Error>>
+{
+    if !database_path().exists() {
+        return Ok(Vec::new());
+    }
+    let connection = open_database(&database_path())?;
+    let mut select = connection.prepare("select name, bytes, rows from snapshot_files where snapshot_number = ?1 order by name")?;
+    let files = select.query_map(params![snapshot_number], |row| {
+        Ok(DatabaseFile { name: row.get(0)?, bytes: row.get::<_, i64>(1)? as u64, rows: row.get::<_, Option<i64>>(2)?.map(|rows| rows as u64) })
+    })?.collect::<Result<Vec<DatabaseFile>, rusqlite::Error>>()?;
+    Ok(files)
+}
+
+/// This function deletes all the data of a snapshot from the database.
+pub fn delete_snapshot(
+    snapshot_number: i32,
+) -> Result<(), Box<dyn Error>>
+{
+    if !database_path().exists() {
+        return Ok(());
+    }
+    let mut connection = open_database(&database_path())?;
+    let transaction = connection.transaction_with_behavior(TransactionBehavior::Immediate)?;
+    let names = transaction.prepare("select name from snapshot_files where snapshot_number = ?1")?
+        .query_map(params![snapshot_number], |row| row.get::<_, String>(0))?
+        .collect::<Result<Vec<String>, rusqlite::Error>>()?;
+    for name in names {
+        delete_file(&transaction, snapshot_number, &name)?;
+    }
+    Ok(transaction.commit()?)
+}
+
+/// This function converts the CSV snapshots from begin to end (both optional) into the database, and returns the converted snapshot numbers with the number of files.
+/// The files of a snapshot are removed from the snapshot directory after the files are saved in the database.
+pub fn convert_snapshots(
+    begin: Option<i32>,
+    end: Option<i32>,
+) -> Result<Vec<(i32, usize)>, Box<dyn Error>>
+{
+    let snapshots = Snapshot::read_snapshots().into_iter()
+        .filter(|snapshot| begin.map(|begin| snapshot.number >= begin).unwrap_or(true))
+        .filter(|snapshot| end.map(|end| snapshot.number <= end).unwrap_or(true))
+        .collect::<Vec<Snapshot>>();
+    let mut converted = Vec::new();
+    for snapshot in snapshots {
+        let current_snapshot_directory = snapshot_directory().join(snapshot.number.to_string());
+        if !current_snapshot_directory.is_dir() {
+            warn!("Warning: snapshot directory {} is not found", current_snapshot_directory.display());
+            continue;
+        }
+        let mut files = Vec::new();
+        for entry in fs::read_dir(&current_snapshot_directory)? {
+            let entry = entry?;
+            if entry.path().is_file() {
+                files.push(entry.path());
+            }
+        }
+        for file in &files {
+            let name = strip_compression_extension(file.file_name().and_then(|name| name.to_str()).unwrap_or_default()).to_string();
+            let path = current_snapshot_directory.join(name);
+            let mut data = Vec::new();
+            io::copy(&mut open_snapshot_file(&path)?, &mut data)?;
+            save_file(&path, &data)?;
+        }
+        // The files are only removed when all files of the snapshot are saved in the database.
+        for file in &files {
+            fs::remove_file(file)?;
+        }
+        converted.push((snapshot.number, files.len()));
+    }
+    Ok(converted)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unit_storage_from_str() {
+        assert_eq!("csv".parse::<Storage>(), Ok(Storage::Csv));
+        assert_eq!("sqlite".parse::<Storage>(), Ok(Storage::Sqlite));
+        assert!("parquet".parse::<Storage>().is_err());
+    }
+    #[test]
+    fn unit_is_csv_file() {
+        assert!(is_csv_file("values"));
+        assert!(is_csv_file("values.zst"));
+        assert!(!is_csv_file("manifest"));
+        assert!(!is_csv_file("pprof_growth_192.168.66.80:9000"));
+        assert!(!is_csv_file("mems_192.168.66.80:7000.gz"));
+    }
+    #[test]
+    fn unit_value_round_trip() {
+        for field in ["42", "-7", "0.5", "1.0", "007", "1e5", "192.168.66.80:9000", "", "18446744073709551615", "3b8a4e5c1d2f"] {
+            assert_eq!(to_field(to_value(field)), field);
+        }
+        assert_eq!(to_value("42"), Value::Integer(42));
+        assert_eq!(to_value("0.5"), Value::Real(0.5));
+        assert_eq!(to_value("1e5"), Value::Text("1e5".to_string()));
+    }
+    #[test]
+    fn unit_snapshot_file() {
+        assert_eq!(snapshot_file(Path::new("/tmp/yb_stats.snapshots/12/values.zst")), Some((12, "values".to_string())));
+        assert_eq!(snapshot_file(Path::new("/tmp/yb_stats.snapshots/snapshot.index")), None);
+    }
+    #[test]
+    fn unit_save_and_read_file() {
+        let directory = std::env::temp_dir().join(format!("yb_stats_storage_{}", std::process::id()));
+        fs::create_dir_all(&directory).unwrap();
+        let database = directory.join(DATABASE);
+        let values = directory.join("12").join("values");
+        assert!(read_file_from_database(&database, &values).unwrap().is_none());
+
+        let data = "hostname_port,value_name,value\n192.168.66.80:9000,rows_inserted,42\n192.168.66.80:9000,\"a, b\",0.5\n";
+        save_file_in_database(&database, &values, data.as_bytes()).unwrap();
+        assert_eq!(String::from_utf8(read_file_from_database(&database, &values).unwrap().unwrap()).unwrap(), data);
+        // saving the file again replaces the rows, and a newer layout can have more columns.
+        let data = "hostname_port,value_name,value,unit\n192.168.66.81:9000,rows_inserted,7,rows\n";
+        save_file_in_database(&database, &values, data.as_bytes()).unwrap();
+        assert_eq!(String::from_utf8(read_file_from_database(&database, &values).unwrap().unwrap()).unwrap(), data);
+
+        let manifest = directory.join("12").join("manifest");
+        save_file_in_database(&database, &manifest, b"{\"version\": 1}").unwrap();
+        assert_eq!(read_file_from_database(&database, &manifest).unwrap().unwrap(), b"{\"version\": 1}");
+        assert!(save_file_in_database(&database, &directory.join("snapshot.index"), b"").is_err());
+        fs::remove_dir_all(&directory).unwrap();
+    }
+}
@@ -1,14 +1,19 @@
 use chrono::{DateTime, Local};
-use std::path::PathBuf;
-use std::fs;
 use std::process;
 use serde_derive::{Serialize,Deserialize};
 use regex::Regex;
 //use rayon;
-use std::sync::mpsc::channel;
 use scraper::{ElementRef, Html, Selector};
 use log::*;
-use crate::utility::{scan_host_port, http_get};
+use futures::future::join_all;
+use crate::utility::http_get;
+use crate::endpoints::has_role;
+use crate::compression::create_snapshot_file;
+use crate::schema::open_snapshot_csv;
+use crate::snapshot::snapshot_directory;
+
+/// The endpoint roles that provide /threadz.
+pub const ROLES: &[&str] = &["master", "tserver"];
 
 #[derive(Debug)]
 pub struct Threads {
@@ -36,36 +41,31 @@ pub async fn perform_threads_snapshot(
     hosts: &Vec<&str>,
     ports: &Vec<&str>,
     snapshot_number: i32,
-    yb_stats_directory: &PathBuf,
-    parallel: usize
 ) {
     info!("perform_threads_snapshot");
-    let pool = rayon::ThreadPoolBuilder::new().num_threads(parallel).build().unwrap();
-    let (tx, rx) = channel();
-
-    pool.scope(move |s| {
-        for host in hosts {
-            for port in ports {
-                let tx = tx.clone();
-                s.spawn(move |_| {
-                    let detail_snapshot_time = Local::now();
-                    let threads = read_threads(host, port);
-                    tx.send((format!("{}:{}", host, port), detail_snapshot_time, threads)).expect("error sending data via tx (threads)");
-                });
+    let mut handles = Vec::new();
+    for host in hosts {
+        for port in ports {
+            if !has_role(host, port, ROLES) {
+                continue;
             }
+            let handle = async move {
+                let detail_snapshot_time = Local::now();
+                let threads = read_threads(host, port).await;
+                (format!("{}:{}", host, port), detail_snapshot_time, threads)
+            };
+            handles.push(handle);
         }
-    });
+    }
+    let results = join_all(handles).await;
     let mut stored_threads: Vec<StoredThreads> = Vec::new();
-    for (hostname_port, detail_snapshot_time, threads) in rx {
+    for (hostname_port, detail_snapshot_time, threads) in results {
         add_to_threads_vector(threads, &hostname_port, detail_snapshot_time, &mut stored_threads);
     }
 
-    let current_snapshot_directory = &yb_stats_directory.join( &snapshot_number.to_string());
+    let current_snapshot_directory = &snapshot_directory().join( &snapshot_number.to_string());
     let threads_file = &current_snapshot_directory.join("threads");
-    let file = fs::OpenOptions::new()
-        .create(true)
-        .write(true)
-        .open(threads_file)
+    let file = create_snapshot_file(threads_file)
         .unwrap_or_else(|e| {
             error!("Fatal: error writing threads data in snapshots directory {}: {}", &threads_file.clone().into_os_string().into_string().unwrap(), e);
             process::exit(1);
@@ -74,20 +74,16 @@ pub async fn perform_threads_snapshot(
     for row in stored_threads {
         writer.serialize(row).unwrap();
     }
-    writer.flush().unwrap();
+    writer.into_inner().unwrap().finish().unwrap();
 }
 
 #[allow(dead_code)]
-pub fn read_threads(
+pub async fn read_threads(
     host: &str,
     port: &str,
 ) -> Vec<Threads>
 {
-    let data_from_http = if scan_host_port( host, port) {
-        http_get(host, port, "threadz?group=all")
-    } else {
-        String::new()
-    };
+    let data_from_http = http_get(host, port, "threadz?group=all").await;
     parse_threads(data_from_http)
         /*
     if ! scan_port_addr( format!("{}:{}", host, port) ) {
@@ -179,11 +175,10 @@ fn find_table(http_data: &str) -> Option<(Vec<String>, Vec<Vec<String>>)> {
 #[allow(dead_code)]
 pub fn print_threads_data(
     snapshot_number: &String,
-    yb_stats_directory: &PathBuf,
     hostname_filter: &Regex
 ) {
     info!("print_threads");
-    let stored_threads: Vec<StoredThreads> = read_threads_snapshot(snapshot_number, yb_stats_directory);
+    let stored_threads: Vec<StoredThreads> = read_threads_snapshot(snapshot_number);
     let mut previous_hostname_port = String::from("");
     for row in stored_threads {
         if hostname_filter.is_match(&row.hostname_port) {
@@ -210,11 +205,10 @@ pub fn print_threads_data(
 #[allow(clippy::ptr_arg)]
 fn read_threads_snapshot(
     snapshot_number: &String,
-    yb_stats_directory: &PathBuf
 ) -> Vec<StoredThreads> {
     let mut stored_threads: Vec<StoredThreads> = Vec::new();
-    let threads_file = &yb_stats_directory.join(snapshot_number).join("threads");
-    let file = fs::File::open(threads_file)
+    let threads_file = &snapshot_directory().join(snapshot_number).join("threads");
+    let file = open_snapshot_csv(threads_file)
         .unwrap_or_else(|e| {
             eprintln!("Fatal: error reading file: {}: {}", &threads_file.clone().into_os_string().into_string().unwrap(), e);
             process::exit(1);
@@ -392,26 +386,26 @@ server uuid 4ce571a18f8c4a9a8b35246222d12025 local time 2022-03-16 12:33:37.6344
 
     use crate::utility;
 
-    #[test]
-    fn integration_parse_threadsdata_master() {
+    #[tokio::test]
+    async fn integration_parse_threadsdata_master() {
         let mut stored_threadsdata: Vec<StoredThreads> = Vec::new();
         let detail_snapshot_time = Local::now();
         let hostname = utility::get_hostname_master();
         let port = utility::get_port_master();
 
-        let data_parsed_from_json = read_threads(hostname.as_str(), port.as_str());
+        let data_parsed_from_json = read_threads(hostname.as_str(), port.as_str()).await;
         add_to_threads_vector(data_parsed_from_json, format!("{}:{}", hostname, port).as_str(), detail_snapshot_time, &mut stored_threadsdata);
         // each daemon should return one row.
         assert!(stored_threadsdata.len() > 1);
     }
-    #[test]
-    fn integration_parse_threadsdata_tserver() {
+    #[tokio::test]
+    async fn integration_parse_threadsdata_tserver() {
         let mut stored_threadsdata: Vec<StoredThreads> = Vec::new();
         let detail_snapshot_time = Local::now();
         let hostname = utility::get_hostname_tserver();
         let port = utility::get_port_tserver();
 
-        let data_parsed_from_json = read_threads(hostname.as_str(), port.as_str());
+        let data_parsed_from_json = read_threads(hostname.as_str(), port.as_str()).await;
         add_to_threads_vector(data_parsed_from_json, format!("{}:{}", hostname, port).as_str(), detail_snapshot_time, &mut stored_threadsdata);
         // each daemon should return one row.
         assert!(stored_threadsdata.len() > 1);
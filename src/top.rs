@@ -0,0 +1,351 @@
+//! The module for the live top-style mode (`--top <seconds>`).
+//!
+//! The top mode continuously refreshes a terminal view, which is useful during incidents:
+//! every interval it reads metrics, statements and node_exporter from the http endpoints, using the same `adhoc_read_*` functions as the ad-hoc and exporter modes,
+//! calculates the rates per second over the interval (for the metrics with [crate::rates], like the exporter mode) and redraws a table per source with the `--top-rows` highest rates.
+//! - The `--hostname-match` filter applies to all sources.
+//! - The `--stat-name-match` filter applies to the metric and node_exporter names, the `--table-name-match` filter applies to the metrics.
+//! - With `--details-enable`, the table and tablet metrics are shown per table and tablet, otherwise these are added up per server.
+//!
+//! Only statistics that are counters are shown, gauges do not have a rate. The top mode runs until it is stopped with Ctrl-C.
+use chrono::{DateTime, Local};
+use substring::Substring;
+use log::*;
+use crate::metrics::SnapshotDiffBTreeMapsMetrics;
+use crate::statements::SnapshotDiffBTreeMapStatements;
+use crate::node_exporter::SnapshotDiffBTreeMapNodeExporter;
+use crate::value_statistic_details::ValueStatistics;
+use crate::countsum_statistic_details::CountSumStatistics;
+use crate::collection_errors;
+use crate::rates::{interval_differences, seconds_between, Grouping, StatisticType};
+use crate::utility::Filters;
+
+/// The ANSI escape codes to clear the terminal and move the cursor to the top left.
+const CLEAR_SCREEN: &str = "\x1b[2J\x1b[H";
+
+/// A statistic with its rate over the interval.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TopRow {
+    pub hostname_port: String,
+    pub name: String,
+    /// The difference of the statistic over the interval.
+    pub difference: f64,
+    /// The difference per second.
+    pub rate: f64,
+    /// The average per occurrence for statistics that have a count and a sum (countsum metrics and statements), with its unit.
+    pub average: Option<(f64, String)>,
+    pub unit: String,
+}
+
+/// The rows per source, sorted by rate.
+#[derive(Debug, Default)]
+pub struct TopRows {
+    pub metrics: Vec<TopRow>,
+    pub statements: Vec<TopRow>,
+    pub node_exporter: Vec<TopRow>,
+}
+
+impl TopRows {
+    /// This function takes the snapshot diffs of the sources, and creates the rows with the rates, filtered and sorted by rate, highest first.
+    pub fn from_diffs(
+        metrics: &SnapshotDiffBTreeMapsMetrics,
+        statements: &SnapshotDiffBTreeMapStatements,
+        node_exporter: &SnapshotDiffBTreeMapNodeExporter,
+        filters: &Filters,
+    ) -> TopRows
+    {
+        let mut toprows = TopRows {
+            metrics: Self::metrics_rows(metrics, filters),
+            statements: Self::statements_rows(statements, filters),
+            node_exporter: Self::node_exporter_rows(node_exporter, filters),
+        };
+        for rows in [&mut toprows.metrics, &mut toprows.statements, &mut toprows.node_exporter] {
+            rows.sort_by(|a, b| b.rate.total_cmp(&a.rate).then_with(|| a.hostname_port.cmp(&b.hostname_port)).then_with(|| a.name.cmp(&b.name)));
+        }
+        toprows
+    }
+    /// The name of a metric: with `--details-enable` the metric of a table or tablet includes the namespace and table name.
+    fn metric_name(
+        metric_name: &str,
+        namespace: &str,
+        table_name: &str,
+        details_enable: bool,
+    ) -> String
+    {
+        if details_enable && table_name != "-" {
+            format!("{} ({}.{})", metric_name, namespace, table_name)
+        } else {
+            metric_name.to_string()
+        }
+    }
+    fn metrics_rows(
+        metrics: &SnapshotDiffBTreeMapsMetrics,
+        filters: &Filters,
+    ) -> Vec<TopRow>
+    {
+        let value_statistics = ValueStatistics::create();
+        let countsum_statistics = CountSumStatistics::create();
+        // with details, the table and tablet metrics are shown per table and tablet, otherwise these are added up per server.
+        let grouping = if filters.details_enable { Grouping::Id } else { Grouping::Server };
+        interval_differences(metrics, filters, grouping).into_iter()
+            .filter(|(_, difference)| difference.count > 0_f64)
+            .map(|(key, difference)| {
+                let (average, unit) = match key.statistic_type {
+                    StatisticType::Value => (None, value_statistics.lookup(&key.metric_name).unit_suffix.to_string()),
+                    StatisticType::CountSum => (difference.average().map(|average| (average, countsum_statistics.lookup(&key.metric_name).unit_suffix.to_string())), String::new()),
+                    StatisticType::CountSumRows => (difference.average().map(|average| (average, String::from("ms"))), String::new()),
+                };
+                TopRow {
+                    hostname_port: key.hostname_port.to_string(),
+                    name: Self::metric_name(&key.metric_name, &key.namespace, &key.table_name, filters.details_enable),
+                    difference: difference.count,
+                    rate: difference.rate(),
+                    average,
+                    unit,
+                }
+            })
+            .collect()
+    }
+    fn statements_rows(
+        statements: &SnapshotDiffBTreeMapStatements,
+        filters: &Filters,
+    ) -> Vec<TopRow>
+    {
+        statements.btreemap_snapshotdiff_statements.iter()
+            .filter(|((hostname_port, _query), row)| row.second_calls > row.first_calls && filters.hostname_filter.is_match(hostname_port))
+            .filter_map(|((hostname_port, query), row)| {
+                let seconds = seconds_between(&row.first_snapshot_time, &row.second_snapshot_time);
+                if seconds <= 0_f64 {
+                    return None;
+                }
+                let calls = (row.second_calls - row.first_calls) as f64;
+                Some(TopRow {
+                    hostname_port: hostname_port.to_string(),
+                    name: query.substring(0, filters.sql_length).escape_default().to_string(),
+                    difference: calls,
+                    rate: calls / seconds,
+                    average: Some(((row.second_total_time - row.first_total_time) / calls, String::from("ms"))),
+                    unit: String::new(),
+                })
+            })
+            .collect()
+    }
+    fn node_exporter_rows(
+        node_exporter: &SnapshotDiffBTreeMapNodeExporter,
+        filters: &Filters,
+    ) -> Vec<TopRow>
+    {
+        node_exporter.btreemap_snapshotdiff_nodeexporter.iter()
+            .filter(|((hostname_port, name), row)| row.node_exporter_type == "counter"
+                && row.second_value > row.first_value
+                && !(filters.details_enable && row.category == "summary")
+                && (filters.details_enable || row.category != "detail")
+                && filters.hostname_filter.is_match(hostname_port)
+                && filters.stat_name_filter.is_match(name))
+            .filter_map(|((hostname_port, name), row)| {
+                let seconds = seconds_between(&row.first_snapshot_time, &row.second_snapshot_time);
+                if seconds <= 0_f64 {
+                    return None;
+                }
+                Some(TopRow {
+                    hostname_port: hostname_port.to_string(),
+                    name: name.to_string(),
+                    difference: row.second_value - row.first_value,
+                    rate: (row.second_value - row.first_value) / seconds,
+                    average: None,
+                    unit: String::new(),
+                })
+            })
+            .collect()
+    }
+    /// This function clears the terminal and prints the `top_rows` highest rates of every source.
+    pub fn print(
+        &self,
+        top_rows: usize,
+        first_snapshot_time: &DateTime<Local>,
+        second_snapshot_time: &DateTime<Local>,
+    )
+    {
+        print!("{}", CLEAR_SCREEN);
+        println!("yb_stats top: {}, interval: {:8.3} seconds, press Ctrl-C to stop",
+                 second_snapshot_time.format("%Y-%m-%d %H:%M:%S"),
+                 seconds_between(first_snapshot_time, second_snapshot_time),
+        );
+        for (title, rows) in [("metrics", &self.metrics), ("statements", &self.statements), ("node_exporter", &self.node_exporter)] {
+            println!();
+            println!("{:20} {:70} {:>15} {:6} {:>15}    {:>15}", title, "name", "difference", "", "rate", "average");
+            if rows.is_empty() {
+                println!("(no changes)");
+            }
+            for row in rows.iter().take(top_rows) {
+                let average = match &row.average {
+                    Some((average, unit)) => format!("{:15.3} {}", average, unit),
+                    None => String::new(),
+                };
+                println!("{:20} {:70} {:15.0} {:6} {:>15.3} /s {}",
+                         row.hostname_port,
+                         row.name.substring(0, 70),
+                         row.difference,
+                         row.unit,
+                         row.rate,
+                         average,
+                );
+            }
+        }
+    }
+}
+
+/// This function runs the top mode: every `interval` seconds the sources are read and the top rates are shown, until yb_stats is stopped.
+pub async fn top(
+    hosts: &Vec<&str>,
+    ports: &Vec<&str>,
+    interval: u64,
+    top_rows: usize,
+    filters: &Filters<'_>,
+)
+{
+    let mut ticker = tokio::time::interval(std::time::Duration::from_secs(interval));
+    ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    ticker.tick().await;
+    let mut metrics = SnapshotDiffBTreeMapsMetrics::new();
+    let mut statements = SnapshotDiffBTreeMapStatements::new();
+    let mut node_exporter = SnapshotDiffBTreeMapNodeExporter::new();
+    let mut previous_snapshot_time = Local::now();
+    // The data read for a refresh is kept as the first snapshot of the next refresh, so the sources are read once per interval.
+    // The first read only fills the second snapshot, which becomes the first snapshot of the first refresh.
+    tokio::join!(
+        metrics.adhoc_read_next_snapshot(hosts, ports, &previous_snapshot_time),
+        statements.adhoc_read_next_snapshot(hosts, ports, &previous_snapshot_time),
+        node_exporter.adhoc_read_next_snapshot(hosts, ports, &previous_snapshot_time),
+    );
+    loop {
+        ticker.tick().await;
+        let snapshot_time = Local::now();
+        tokio::join!(
+            metrics.adhoc_read_next_snapshot(hosts, ports, &previous_snapshot_time),
+            statements.adhoc_read_next_snapshot(hosts, ports, &previous_snapshot_time),
+            node_exporter.adhoc_read_next_snapshot(hosts, ports, &previous_snapshot_time),
+        );
+
+        TopRows::from_diffs(&metrics, &statements, &node_exporter, filters)
+            .print(top_rows, &previous_snapshot_time, &snapshot_time);
+        previous_snapshot_time = snapshot_time;
+        let errors = collection_errors::clear_recorded();
+        if errors > 0 {
+            info!("top refresh: {} endpoint(s) could not be read", errors);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+    use regex::Regex;
+    use crate::metrics::{SnapshotDiffValues, SnapshotDiffCountSumRows};
+    use crate::statements::SnapshotDiffStatements;
+    use crate::node_exporter::SnapshotDiffNodeExporter;
+
+    fn time(second: u32) -> DateTime<Local> {
+        Local.with_ymd_and_hms(2023, 1, 10, 10, 0, second).unwrap()
+    }
+    fn value(table_name: &str, first_value: i64, second_value: i64) -> SnapshotDiffValues {
+        SnapshotDiffValues {
+            table_name: table_name.to_string(),
+            namespace: String::from("yugabyte"),
+            first_snapshot_time: time(0),
+            second_snapshot_time: time(10),
+            first_snapshot_value: first_value,
+            second_snapshot_value: second_value,
+        }
+    }
+    fn filters<'a>(hostname_filter: &'a Regex, match_all: &'a Regex, details_enable: bool) -> Filters<'a> {
+        Filters { hostname_filter, stat_name_filter: match_all, table_name_filter: match_all, details_enable, gauges_enable: false, sql_length: 80 }
+    }
+
+    #[test]
+    fn unit_top_metrics_summed_and_sorted() {
+        let mut metrics = SnapshotDiffBTreeMapsMetrics::new();
+        let key = |hostname: &str, metric_id: &str, metric_name: &str| (hostname.to_string(), String::from("tablet"), metric_id.to_string(), metric_name.to_string());
+        metrics.btreemap_snapshotdiff_values.insert(key("tserver1:9000", "tablet1", "rows_inserted"), value("t1", 100, 200));
+        metrics.btreemap_snapshotdiff_values.insert(key("tserver1:9000", "tablet2", "rows_inserted"), value("t2", 100, 300));
+        metrics.btreemap_snapshotdiff_values.insert(key("tserver2:9000", "tablet3", "rows_inserted"), value("t1", 0, 1000));
+        // gone (deleted table), gauge and not changed: not shown.
+        metrics.btreemap_snapshotdiff_values.insert(key("tserver1:9000", "tablet4", "rows_inserted"), value("t3", 100, 0));
+        metrics.btreemap_snapshotdiff_values.insert(key("tserver1:9000", "tablet1", "active_task_metrics_compaction_tasks_added"), value("t1", 1, 5));
+        metrics.btreemap_snapshotdiff_values.insert(key("tserver1:9000", "tablet1", "rocksdb_number_db_seek"), value("t1", 5, 5));
+        let statements = SnapshotDiffBTreeMapStatements::new();
+        let node_exporter = SnapshotDiffBTreeMapNodeExporter::new();
+        let match_all = Regex::new(".*").unwrap();
+
+        let toprows = TopRows::from_diffs(&metrics, &statements, &node_exporter, &filters(&match_all, &match_all, false));
+        assert_eq!(toprows.metrics.len(), 2);
+        assert_eq!((toprows.metrics[0].hostname_port.as_str(), toprows.metrics[0].rate), ("tserver2:9000", 100_f64));
+        assert_eq!((toprows.metrics[1].hostname_port.as_str(), toprows.metrics[1].difference, toprows.metrics[1].rate), ("tserver1:9000", 300_f64, 30_f64));
+
+        let toprows = TopRows::from_diffs(&metrics, &statements, &node_exporter, &filters(&match_all, &match_all, true));
+        assert_eq!(toprows.metrics.len(), 3);
+        assert_eq!(toprows.metrics[1].name, "rows_inserted (yugabyte.t2)");
+
+        let hostname_filter = Regex::new("tserver1").unwrap();
+        let toprows = TopRows::from_diffs(&metrics, &statements, &node_exporter, &filters(&hostname_filter, &match_all, false));
+        assert_eq!(toprows.metrics.len(), 1);
+    }
+    #[test]
+    fn unit_top_countsumrows_average() {
+        let mut metrics = SnapshotDiffBTreeMapsMetrics::new();
+        metrics.btreemap_snapshotdiff_countsumrows.insert((String::from("tserver1:13000"), String::from("server"), String::from("yb.ysqlserver"), String::from("handler_latency_yb_ysqlserver_SQLProcessor_SelectStmt")), SnapshotDiffCountSumRows {
+            table_name: String::from("-"),
+            namespace: String::from("-"),
+            first_snapshot_time: time(0),
+            second_snapshot_time: time(10),
+            first_snapshot_count: 10,
+            first_snapshot_sum: 1000,
+            first_snapshot_rows: 10,
+            second_snapshot_count: 30,
+            second_snapshot_sum: 51000,
+            second_snapshot_rows: 110,
+        });
+        let match_all = Regex::new(".*").unwrap();
+        let toprows = TopRows::from_diffs(&metrics, &SnapshotDiffBTreeMapStatements::new(), &SnapshotDiffBTreeMapNodeExporter::new(), &filters(&match_all, &match_all, false));
+        // 50000 microseconds for 20 calls.
+        assert_eq!((toprows.metrics[0].rate, toprows.metrics[0].average.clone()), (2_f64, Some((2.5_f64, String::from("ms")))));
+    }
+    #[test]
+    fn unit_top_statements_and_node_exporter() {
+        let metrics = SnapshotDiffBTreeMapsMetrics::new();
+        let mut statements = SnapshotDiffBTreeMapStatements::new();
+        let statement = |first_calls: i64, second_calls: i64, second_total_time: f64| SnapshotDiffStatements {
+            first_snapshot_time: time(0),
+            second_snapshot_time: time(4),
+            first_calls,
+            second_calls,
+            first_total_time: 0.,
+            second_total_time,
+            first_rows: 0,
+            second_rows: 0,
+        };
+        statements.btreemap_snapshotdiff_statements.insert((String::from("tserver1:13000"), String::from("select 1")), statement(0, 8, 4.));
+        statements.btreemap_snapshotdiff_statements.insert((String::from("tserver1:13000"), String::from("select 2")), statement(10, 50, 400.));
+        statements.btreemap_snapshotdiff_statements.insert((String::from("tserver1:13000"), String::from("select 3")), statement(10, 10, 0.));
+        let mut node_exporter = SnapshotDiffBTreeMapNodeExporter::new();
+        let nodeexporter = |node_exporter_type: &str, category: &str, first_value: f64, second_value: f64| SnapshotDiffNodeExporter {
+            first_snapshot_time: time(0),
+            second_snapshot_time: time(2),
+            node_exporter_type: node_exporter_type.to_string(),
+            category: category.to_string(),
+            first_value,
+            second_value,
+        };
+        node_exporter.btreemap_snapshotdiff_nodeexporter.insert((String::from("node1:9300"), String::from("node_cpu_seconds_total")), nodeexporter("counter", "summary", 10., 12.));
+        node_exporter.btreemap_snapshotdiff_nodeexporter.insert((String::from("node1:9300"), String::from("node_cpu_seconds_total_user")), nodeexporter("counter", "detail", 10., 11.));
+        node_exporter.btreemap_snapshotdiff_nodeexporter.insert((String::from("node1:9300"), String::from("node_load1")), nodeexporter("gauge", "all", 1., 3.));
+        let match_all = Regex::new(".*").unwrap();
+
+        let toprows = TopRows::from_diffs(&metrics, &statements, &node_exporter, &filters(&match_all, &match_all, false));
+        assert_eq!(toprows.statements.iter().map(|row| row.name.as_str()).collect::<Vec<_>>(), vec!["select 2", "select 1"]);
+        assert_eq!((toprows.statements[0].rate, toprows.statements[0].average.clone()), (10_f64, Some((10_f64, String::from("ms")))));
+        assert_eq!(toprows.node_exporter.len(), 1);
+        assert_eq!((toprows.node_exporter[0].name.as_str(), toprows.node_exporter[0].rate), ("node_cpu_seconds_total", 1_f64));
+    }
+}
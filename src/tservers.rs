@@ -1,10 +1,19 @@
 use serde_derive::{Serialize,Deserialize};
 use chrono::{DateTime, Local};
-use std::{fs, process, sync::mpsc::channel, time::Instant, env, error::Error, collections::{HashMap, BTreeMap}};
+use std::{process, time::Instant, error::Error, collections::{HashMap, BTreeMap}};
 use log::*;
+use crate::snapshot::snapshot_directory;
+use futures::future::join_all;
 use colored::*;
 use crate::isleader::AllStoredIsLeader;
-use crate::utility::{scan_host_port, http_get};
+use crate::utility::http_get;
+use crate::endpoints::has_role;
+use crate::compression::create_snapshot_file;
+use crate::schema::open_snapshot_csv;
+use crate::output::{self, DiffRecord, push_changed_attribute};
+
+/// The endpoint roles that provide /api/v1/tablet-servers.
+pub const ROLES: &[&str] = &["master"];
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct StoredTabletServers {
@@ -85,7 +94,7 @@ pub struct TabletServers {
 
 #[derive(Serialize, Deserialize, Debug, Default)]
 pub struct AllStoredTabletServers {
-    stored_tabletservers: Vec<StoredTabletServers>,
+    pub stored_tabletservers: Vec<StoredTabletServers>,
     stored_pathmetrics: Vec<StoredPathMetrics>,
 }
 
@@ -94,12 +103,11 @@ impl AllStoredTabletServers {
         hosts: &Vec<&str>,
         ports: &Vec<&str>,
         snapshot_number: i32,
-        parallel: usize,
     ) {
         info!("begin snapshot");
         let timer = Instant::now();
 
-        let alltabletservers = AllStoredTabletServers::read_tabletservers(hosts, ports, parallel);
+        let alltabletservers = AllStoredTabletServers::read_tabletservers(hosts, ports);
 
         alltabletservers.await.save_snapshot(snapshot_number)
             .unwrap_or_else(|e| {
@@ -115,32 +123,32 @@ impl AllStoredTabletServers {
     pub async fn read_tabletservers(
         hosts: &Vec<&str>,
         ports: &Vec<&str>,
-        parallel: usize,
     ) -> AllStoredTabletServers
     {
         info!("begin parallel http read");
         let timer = Instant::now();
 
-        let pool = rayon::ThreadPoolBuilder::new().num_threads(parallel).build().unwrap();
-        let (tx, rx) = channel();
-        pool.scope(move |s| {
-            for host in hosts {
-                for port in ports {
-                    let tx = tx.clone();
-                    s.spawn(move |_| {
-                        let detail_snapshot_time = Local::now();
-                        let tablet_servers = AllStoredTabletServers::read_http(host, port);
-                        tx.send((format!("{}:{}", host, port), detail_snapshot_time, tablet_servers)).expect("error sending data via tx");
-                    });
+        let mut handles = Vec::new();
+        for host in hosts {
+            for port in ports {
+                if !has_role(host, port, ROLES) {
+                    continue;
                 }
+                let handle = async move {
+                    let detail_snapshot_time = Local::now();
+                    let tablet_servers = AllStoredTabletServers::read_http(host, port).await;
+                    (format!("{}:{}", host, port), detail_snapshot_time, tablet_servers)
+                };
+                handles.push(handle);
             }
-        });
+        }
+        let results = join_all(handles).await;
 
         info!("end parallel http read {:?}", timer.elapsed());
 
         let mut allstoredtabletservers = AllStoredTabletServers::new();
 
-        for (hostname_port, detail_snapshot_time, tablet_servers) in rx {
+        for (hostname_port, detail_snapshot_time, tablet_servers) in results {
             allstoredtabletservers.split_into_vectors(tablet_servers, &hostname_port, detail_snapshot_time);
         }
 
@@ -192,16 +200,12 @@ impl AllStoredTabletServers {
             }
         }
     }
-    pub fn read_http(
+    pub async fn read_http(
         host: &str,
         port: &str,
     ) -> AllTabletServers
     {
-        let data_from_http = if scan_host_port( host, port) {
-            http_get(host, port, "api/v1/tablet-servers")
-        } else {
-            String::new()
-        };
+        let data_from_http = http_get(host, port, "api/v1/tablet-servers").await;
         AllStoredTabletServers::parse_tabletservers(data_from_http, host, port)
 
 /*
@@ -233,30 +237,23 @@ impl AllStoredTabletServers {
     }
     fn save_snapshot ( self, snapshot_number: i32 ) -> Result<(), Box<dyn Error>>
     {
-        let current_directory = env::current_dir()?;
-        let current_snapshot_directory = current_directory.join("yb_stats.snapshots").join(&snapshot_number.to_string());
+        let current_snapshot_directory = snapshot_directory().join(snapshot_number.to_string());
 
         let tablet_servers_file = &current_snapshot_directory.join("tablet_servers");
-        let file = fs::OpenOptions::new()
-            .create(true)
-            .write(true)
-            .open(tablet_servers_file)?;
+        let file = create_snapshot_file(tablet_servers_file)?;
         let mut writer = csv::Writer::from_writer(file);
         for row in self.stored_tabletservers {
             writer.serialize(row)?;
         }
-        writer.flush()?;
+        writer.into_inner()?.finish()?;
 
         let tablet_servers_pathmetrics_file = &current_snapshot_directory.join("tablet_servers_pathmetrics");
-        let file = fs::OpenOptions::new()
-            .create(true)
-            .write(true)
-            .open(tablet_servers_pathmetrics_file)?;
+        let file = create_snapshot_file(tablet_servers_pathmetrics_file)?;
         let mut writer = csv::Writer::from_writer(file);
         for row in self.stored_pathmetrics {
             writer.serialize(row)?;
         }
-        writer.flush()?;
+        writer.into_inner()?.finish()?;
 
         Ok(())
     }
@@ -267,11 +264,10 @@ impl AllStoredTabletServers {
             stored_pathmetrics: Default::default(),
         };
 
-        let current_directory = env::current_dir()?;
-        let current_snapshot_directory = current_directory.join("yb_stats.snapshots").join(snapshot_number);
+        let current_snapshot_directory = snapshot_directory().join(snapshot_number);
 
         let tablet_servers_file = &current_snapshot_directory.join("tablet_servers");
-        let file = fs::File::open(tablet_servers_file)?;
+        let file = open_snapshot_csv(tablet_servers_file)?;
 
         let mut reader = csv::Reader::from_reader(file);
         for row in reader.deserialize() {
@@ -280,7 +276,7 @@ impl AllStoredTabletServers {
         };
 
         let tablet_servers_pathmetrics_file = &current_snapshot_directory.join("tablet_servers_pathmetrics");
-        let file = fs::File::open(tablet_servers_pathmetrics_file)?;
+        let file = open_snapshot_csv(tablet_servers_pathmetrics_file)?;
 
         let mut reader = csv::Reader::from_reader(file);
         for row in reader.deserialize() {
@@ -333,12 +329,11 @@ impl AllStoredTabletServers {
         details_enable: &bool,
         hosts: &Vec<&str>,
         ports: &Vec<&str>,
-        parallel: usize,
     )
     {
         info!("print adhoc tablet servers");
 
-        let leader_hostname = AllStoredIsLeader::return_leader_http(hosts, ports, parallel).await;
+        let leader_hostname = AllStoredIsLeader::return_leader_http(hosts, ports).await;
 
         for row in &self.stored_tabletservers {
             if row.hostname_port == leader_hostname
@@ -507,12 +502,53 @@ impl SnapshotDiffBTreeMapsTabletServers {
             }
         }
     }
+    /// This function returns the changes that are printed by [SnapshotDiffBTreeMapsTabletServers::print] as records for the machine-readable output, see [crate::output].
+    /// A lower uptime in the second snapshot is a change of the uptime (a reboot), a higher uptime is not.
+    pub fn records(
+        &self,
+    ) -> Vec<DiffRecord>
+    {
+        let mut records = Vec::new();
+        if ! self.master_found {
+            return records;
+        }
+        for (hostname, status) in self.btreemap_snapshotdiff_tabletservers.iter() {
+            let record = DiffRecord {
+                hostname_port: hostname.to_string(),
+                metric_type: String::from("tserver"),
+                name: hostname.to_string(),
+                ..Default::default()
+            };
+            if status.second_status == *"" {
+                records.push(DiffRecord {
+                    first: format!("status: {}, uptime: {} s", status.first_status, status.first_uptime_seconds),
+                    ..DiffRecord::object("tservers", "-", record)
+                });
+            } else if status.first_status == *"" {
+                records.push(DiffRecord {
+                    second: format!("status: {}, uptime: {} s", status.second_status, status.second_uptime_seconds),
+                    ..DiffRecord::object("tservers", "+", record)
+                });
+            } else {
+                let record = DiffRecord::object("tservers", "*", record);
+                push_changed_attribute(&mut records, &record, "status", &status.first_status, &status.second_status);
+                if status.second_uptime_seconds < status.first_uptime_seconds {
+                    push_changed_attribute(&mut records, &record, "uptime", &status.first_uptime_seconds.to_string(), &status.second_uptime_seconds.to_string());
+                }
+            }
+        }
+        records
+    }
     pub fn print(
         &self,
     )
     {
        if ! self.master_found {
-           println!("Master leader was not found in hosts specified, skipping tablet servers diff.");
+           output::print_message("Master leader was not found in hosts specified, skipping tablet servers diff.");
+           return;
+       }
+       if output::is_machine_readable() {
+           output::write_records(&self.records());
            return;
        }
         for (hostname, status) in self.btreemap_snapshotdiff_tabletservers.iter() {
@@ -539,22 +575,20 @@ impl SnapshotDiffBTreeMapsTabletServers {
         &mut self,
         hosts: &Vec<&str>,
         ports: &Vec<&str>,
-        parallel: usize,
     )
     {
-        let allstoredtabletservers = AllStoredTabletServers::read_tabletservers(hosts, ports, parallel).await;
-        let master_leader = AllStoredIsLeader::return_leader_http(hosts, ports, parallel).await;
+        let allstoredtabletservers = AllStoredTabletServers::read_tabletservers(hosts, ports).await;
+        let master_leader = AllStoredIsLeader::return_leader_http(hosts, ports).await;
         self.first_snapshot(allstoredtabletservers, master_leader);
     }
     pub async fn adhoc_read_second_snapshot(
         &mut self,
         hosts: &Vec<&str>,
         ports: &Vec<&str>,
-        parallel: usize,
     )
     {
-        let allstoredtabletservers = AllStoredTabletServers::read_tabletservers(hosts, ports, parallel).await;
-        let master_leader = AllStoredIsLeader::return_leader_http(hosts, ports, parallel).await;
+        let allstoredtabletservers = AllStoredTabletServers::read_tabletservers(hosts, ports).await;
+        let master_leader = AllStoredIsLeader::return_leader_http(hosts, ports).await;
         self.second_snapshot(allstoredtabletservers, master_leader);
     }
 }
@@ -612,14 +646,14 @@ mod tests {
 
     use crate::utility;
 
-    #[test]
-    fn integration_parse_tabletserver() {
+    #[tokio::test]
+    async fn integration_parse_tabletserver() {
         let mut allstoredtabletservers = AllStoredTabletServers::new();
 
         let hostname = utility::get_hostname_master();
         let port = utility::get_port_master();
 
-        let data_parsed_from_json = AllStoredTabletServers::read_http(&hostname, &port);
+        let data_parsed_from_json = AllStoredTabletServers::read_http(&hostname, &port).await;
         allstoredtabletservers.split_into_vectors(data_parsed_from_json, format!("{}:{}",hostname, port).as_ref(), Local::now());
 
         println!("{:?}", allstoredtabletservers);
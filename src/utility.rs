@@ -1,8 +1,348 @@
 //! Utilities
-use std::env;
-use port_scanner::scan_port_addr;
+use std::{env, fmt, fs, process, str::FromStr, collections::HashMap, sync::{Mutex, OnceLock}, time::{Duration, Instant}};
+use tokio::sync::Semaphore;
+use regex::Regex;
 use log::*;
 use crate::ACCEPT_INVALID_CERTS;
+use crate::collection_errors::record_collection_error;
+use crate::endpoints::endpoint_role;
+
+/// The default time to wait for a connection to be established, in milliseconds.
+pub const DEFAULT_CONNECT_TIMEOUT: u64 = 1000;
+/// The default time to wait for a complete response, in milliseconds.
+pub const DEFAULT_READ_TIMEOUT: u64 = 10000;
+/// The default number of retries after a failed request.
+pub const DEFAULT_RETRIES: u32 = 2;
+/// The wait time before the first retry, in milliseconds, which is doubled for every next retry.
+const RETRY_BACKOFF: u64 = 200;
+
+/// The filters and switches that select and show the statistics, which are set with `--hostname-match`, `--stat-name-match`, `--table-name-match`,
+/// `--details-enable`, `--gauges-enable` and `--sql-length`. A mode uses the filters that apply to its statistics.
+pub struct Filters<'a> {
+    pub hostname_filter: &'a Regex,
+    pub stat_name_filter: &'a Regex,
+    pub table_name_filter: &'a Regex,
+    pub details_enable: bool,
+    pub gauges_enable: bool,
+    pub sql_length: usize,
+}
+
+/// The scheme that is used to contact a host:port http endpoint.
+/// `Auto` tries http first, and if that doesn't give a usable answer, tries https.
+/// The scheme that worked is remembered for the host:port combination for the rest of the run.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Scheme {
+    #[default]
+    Auto,
+    Http,
+    Https,
+}
+
+impl FromStr for Scheme {
+    type Err = String;
+
+    fn from_str(scheme: &str) -> Result<Self, Self::Err> {
+        match scheme.to_lowercase().as_str() {
+            "auto" => Ok(Scheme::Auto),
+            "http" => Ok(Scheme::Http),
+            "https" => Ok(Scheme::Https),
+            other => Err(format!("invalid scheme: {}, valid schemes are: auto, http, https", other)),
+        }
+    }
+}
+
+/// A credential that is sent with a request to a protected endpoint.
+/// The Debug implementation does not show the secrets, so these don't end up in log output.
+#[derive(Clone, PartialEq, Eq)]
+pub enum Credential {
+    Basic { username: String, password: String },
+    Bearer(String),
+}
+
+impl fmt::Debug for Credential {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Credential::Basic { username, .. } => write!(f, "Basic {{ username: {}, password: <redacted> }}", username),
+            Credential::Bearer(_) => write!(f, "Bearer(<redacted>)"),
+        }
+    }
+}
+
+impl Credential {
+    /// Create a credential from the type (basic or bearer) and the secret.
+    /// The secret for basic is `username:password`, the secret for bearer is the token.
+    fn new(auth_type: &str, secret: &str) -> Result<Self, String> {
+        match auth_type.to_lowercase().as_str() {
+            "basic" => {
+                let (username, password) = secret
+                    .split_once(':')
+                    .ok_or_else(|| "invalid basic credential, format is username:password".to_string())?;
+                Ok(Credential::Basic { username: username.to_string(), password: password.to_string() })
+            },
+            "bearer" => Ok(Credential::Bearer(secret.to_string())),
+            other => Err(format!("invalid credential type: {}, valid types are: basic, bearer", other)),
+        }
+    }
+}
+
+/// The name of the role of an endpoint, based on the default YugabyteDB and node_exporter port numbers.
+pub fn port_role(port: &str) -> &'static str {
+    match port {
+        "7000" => "master",
+        "9000" => "tserver",
+        "12000" => "ycql",
+        "13000" => "ysql",
+        "11000" => "yedis",
+        "9300" => "node_exporter",
+        _ => "unknown",
+    }
+}
+
+/// The settings for the http client that is used by [http_get].
+/// These are set once in main using [set_http_settings].
+#[derive(Debug, Clone)]
+pub struct HttpSettings {
+    /// The scheme for all ports, unless a port is specified in port_schemes.
+    pub scheme: Scheme,
+    /// Per port scheme, overriding the general scheme.
+    pub port_schemes: HashMap<String, Scheme>,
+    /// A PEM file with one or more CA certificates to validate the server certificates with.
+    pub ca_cert_file: Option<String>,
+    /// A PEM file with the client certificate, for mutual TLS.
+    pub client_cert_file: Option<String>,
+    /// A PEM file with the (PKCS#8) private key belonging to the client certificate.
+    pub client_key_file: Option<String>,
+    /// Credentials by scope: a port number, a role name (see [crate::endpoints::has_role]), or "*" for all endpoints.
+    pub credentials: HashMap<String, Credential>,
+    /// The time to wait for a connection to be established.
+    pub connect_timeout: Duration,
+    /// The time to wait for a complete response, including the time to connect.
+    pub read_timeout: Duration,
+    /// The number of times a request is retried after a connect error, a timeout or a server error (5xx).
+    pub retries: u32,
+}
+
+impl Default for HttpSettings {
+    fn default() -> Self {
+        Self {
+            scheme: Scheme::Auto,
+            port_schemes: HashMap::new(),
+            ca_cert_file: None,
+            client_cert_file: None,
+            client_key_file: None,
+            credentials: HashMap::new(),
+            connect_timeout: Duration::from_millis(DEFAULT_CONNECT_TIMEOUT),
+            read_timeout: Duration::from_millis(DEFAULT_READ_TIMEOUT),
+            retries: DEFAULT_RETRIES,
+        }
+    }
+}
+
+/// The kind of error of a failed http request, which is recorded in the collection_errors of a snapshot.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HttpErrorKind {
+    /// The connection could not be established.
+    Connect,
+    /// The connection or the response took longer than the timeout.
+    Timeout,
+    /// The response was 400 (Bad Request), which is what a TLS webserver returns for a plain http request.
+    BadRequest,
+    /// The response has a status that is not success, and not 400 or 404.
+    Status(u16),
+    /// The request failed otherwise, for example because the connection was closed.
+    Request,
+    /// The body of the response could not be read.
+    Body,
+}
+
+impl fmt::Display for HttpErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HttpErrorKind::Connect => write!(f, "connect"),
+            HttpErrorKind::Timeout => write!(f, "timeout"),
+            HttpErrorKind::BadRequest => write!(f, "bad_request"),
+            HttpErrorKind::Status(status) => write!(f, "status_{}", status),
+            HttpErrorKind::Request => write!(f, "request"),
+            HttpErrorKind::Body => write!(f, "body"),
+        }
+    }
+}
+
+impl HttpErrorKind {
+    fn from_reqwest_error(error: &reqwest::Error) -> Self {
+        if error.is_timeout() {
+            HttpErrorKind::Timeout
+        } else if error.is_connect() {
+            HttpErrorKind::Connect
+        } else if error.is_body() || error.is_decode() {
+            HttpErrorKind::Body
+        } else {
+            HttpErrorKind::Request
+        }
+    }
+    /// Connect errors, timeouts and server errors can be temporary, and are retried.
+    fn is_retryable(&self) -> bool {
+        match self {
+            HttpErrorKind::Connect | HttpErrorKind::Timeout => true,
+            HttpErrorKind::Status(status) => *status >= 500,
+            _ => false,
+        }
+    }
+}
+
+/// The wait time before retry number `retry` (starting at 1): the backoff doubles with every retry.
+fn retry_backoff(retry: u32) -> Duration {
+    Duration::from_millis(RETRY_BACKOFF.saturating_mul(1 << (retry.saturating_sub(1)).min(16)))
+}
+
+impl HttpSettings {
+    /// Parse a port to scheme list in the form of: `port=scheme,port=scheme`, for example: `7000=https,9000=https`.
+    pub fn parse_port_schemes(port_schemes: &str) -> Result<HashMap<String, Scheme>, String> {
+        let mut parsed = HashMap::new();
+        for port_scheme in port_schemes.split(',').filter(|p| !p.is_empty()) {
+            let (port, scheme) = port_scheme
+                .split_once('=')
+                .ok_or_else(|| format!("invalid port scheme: {}, format is port=scheme", port_scheme))?;
+            parsed.insert(port.trim().to_string(), scheme.trim().parse::<Scheme>()?);
+        }
+        Ok(parsed)
+    }
+    fn scheme_for_port(&self, port: &str) -> Scheme {
+        *self.port_schemes.get(port).unwrap_or(&self.scheme)
+    }
+    /// Read the credentials from the credentials file, if specified, and from the environment.
+    ///
+    /// The credentials file contains a line per credential: `<scope> basic <username>:<password>` or `<scope> bearer <token>`.
+    /// Empty lines and lines starting with '#' are skipped.
+    ///
+    /// The environment variables are `YBSTATS_BASIC_AUTH` (`username:password`) and `YBSTATS_BEARER_TOKEN` for all endpoints,
+    /// and `YBSTATS_BASIC_AUTH_<SCOPE>` and `YBSTATS_BEARER_TOKEN_<SCOPE>` for a port or role, for example `YBSTATS_BEARER_TOKEN_9300` or `YBSTATS_BASIC_AUTH_MASTER`.
+    /// A credential set in the environment overrides the credential for the same scope from the file.
+    pub fn read_credentials(credentials_file: Option<&str>) -> Result<HashMap<String, Credential>, String> {
+        let mut credentials = match credentials_file {
+            Some(credentials_file) => {
+                warn_if_readable_by_others(credentials_file);
+                let data = fs::read_to_string(credentials_file).map_err(|e| format!("error reading credentials file {}: {}", credentials_file, e))?;
+                HttpSettings::parse_credentials(&data)?
+            },
+            None => HashMap::new(),
+        };
+        for (name, value) in env::vars() {
+            for (prefix, auth_type) in [("YBSTATS_BASIC_AUTH", "basic"), ("YBSTATS_BEARER_TOKEN", "bearer")] {
+                if let Some(suffix) = name.strip_prefix(prefix) {
+                    let scope = match suffix.strip_prefix('_') {
+                        Some(scope) => scope.to_lowercase(),
+                        None if suffix.is_empty() => "*".to_string(),
+                        None => continue,
+                    };
+                    credentials.insert(scope, Credential::new(auth_type, &value).map_err(|e| format!("{}: {}", name, e))?);
+                }
+            }
+        }
+        Ok(credentials)
+    }
+    fn parse_credentials(data: &str) -> Result<HashMap<String, Credential>, String> {
+        let mut credentials = HashMap::new();
+        for (line_number, line) in data.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            if fields.len() != 3 {
+                return Err(format!("credentials line {}: format is: <scope> <basic|bearer> <secret>", line_number + 1));
+            }
+            let credential = Credential::new(fields[1], fields[2]).map_err(|e| format!("credentials line {}: {}", line_number + 1, e))?;
+            credentials.insert(fields[0].to_lowercase(), credential);
+        }
+        Ok(credentials)
+    }
+    /// Find the credential for a port with a role: a credential for the port goes before a credential for the role, which goes before a credential for all endpoints.
+    fn credential_for_port(&self, port: &str, role: &str) -> Option<&Credential> {
+        self.credentials.get(port)
+            .or_else(|| self.credentials.get(role))
+            .or_else(|| self.credentials.get("*"))
+    }
+}
+
+#[cfg(unix)]
+fn warn_if_readable_by_others(file_name: &str) {
+    use std::os::unix::fs::PermissionsExt;
+    if let Ok(metadata) = fs::metadata(file_name) {
+        if metadata.permissions().mode() & 0o077 != 0 {
+            warn!("credentials file {} is accessible by group or others, consider: chmod 600 {}", file_name, file_name);
+        }
+    }
+}
+
+#[cfg(not(unix))]
+fn warn_if_readable_by_others(_file_name: &str) {}
+
+static HTTP_SETTINGS: OnceLock<HttpSettings> = OnceLock::new();
+static HTTP_CLIENT: OnceLock<reqwest::Client> = OnceLock::new();
+static HTTP_SEMAPHORE: OnceLock<Semaphore> = OnceLock::new();
+static DETECTED_SCHEMES: OnceLock<Mutex<HashMap<String, Scheme>>> = OnceLock::new();
+
+/// This function sets the http settings for the whole run of yb_stats.
+/// The certificate and key files are validated here, so a wrong setting is found before any data is fetched.
+pub fn set_http_settings(http_settings: HttpSettings) {
+    if let Err(e) = http_client_builder(&http_settings) {
+        error!("Fatal: error creating http client: {}", e);
+        process::exit(1);
+    };
+    HTTP_SETTINGS.set(http_settings).unwrap_or_else(|_| warn!("http settings are already set"));
+}
+
+fn http_settings() -> &'static HttpSettings {
+    HTTP_SETTINGS.get_or_init(Default::default)
+}
+
+/// This function sets the maximum number of concurrent http requests for the whole run of yb_stats.
+/// All sources share this limit, so the number of requests in flight never exceeds it, regardless of the number of sources.
+pub fn set_parallel(parallel: usize) {
+    HTTP_SEMAPHORE.set(Semaphore::new(parallel.max(1))).unwrap_or_else(|_| warn!("parallel is already set"));
+}
+
+fn http_semaphore() -> &'static Semaphore {
+    HTTP_SEMAPHORE.get_or_init(|| Semaphore::new(1))
+}
+
+/// This function creates the reqwest client builder using the [HttpSettings].
+/// Invalid certificates are only accepted if no CA certificate file is specified.
+fn http_client_builder(http_settings: &HttpSettings) -> Result<reqwest::ClientBuilder, String> {
+    let mut builder = reqwest::Client::builder()
+        .danger_accept_invalid_certs(ACCEPT_INVALID_CERTS && http_settings.ca_cert_file.is_none())
+        .connect_timeout(http_settings.connect_timeout)
+        .timeout(http_settings.read_timeout);
+    if let Some(ca_cert_file) = &http_settings.ca_cert_file {
+        let pem = fs::read(ca_cert_file).map_err(|e| format!("error reading CA certificate file {}: {}", ca_cert_file, e))?;
+        let certificate = reqwest::Certificate::from_pem(&pem).map_err(|e| format!("error parsing CA certificate file {}: {}", ca_cert_file, e))?;
+        builder = builder.add_root_certificate(certificate);
+    }
+    match (&http_settings.client_cert_file, &http_settings.client_key_file) {
+        (Some(client_cert_file), Some(client_key_file)) => {
+            let cert = fs::read(client_cert_file).map_err(|e| format!("error reading client certificate file {}: {}", client_cert_file, e))?;
+            let key = fs::read(client_key_file).map_err(|e| format!("error reading client key file {}: {}", client_key_file, e))?;
+            let identity = reqwest::Identity::from_pkcs8_pem(&cert, &key).map_err(|e| format!("error parsing client certificate and key: {}", e))?;
+            builder = builder.identity(identity);
+        },
+        (None, None) => {},
+        _ => return Err("client certificate and client key must be specified together".to_string()),
+    }
+    Ok(builder)
+}
+
+/// The client is created once, and shared by all requests, so connections are reused.
+fn http_client() -> &'static reqwest::Client {
+    HTTP_CLIENT.get_or_init(|| {
+        http_client_builder(http_settings())
+            .and_then(|builder| builder.build().map_err(|e| e.to_string()))
+            .unwrap_or_else(|e| {
+                error!("Fatal: error creating http client: {}", e);
+                process::exit(1);
+            })
+    })
+}
 
 #[allow(dead_code)]
 pub fn get_hostname_master() -> String {
@@ -89,43 +429,214 @@ pub fn get_port_node_exporter() -> String {
     }
 }
 
-pub fn scan_host_port(
+/// This function performs a http GET request for the url on the host and port, and returns the body as String.
+/// The scheme is taken from the [HttpSettings]: if it's `Auto`, http is tried first, and https if http fails.
+/// The credentials are only sent over https, or over http if http is set explicitly as scheme, so these don't cross the network in cleartext for the http try of `Auto`.
+/// If no usable answer is obtained, the error is recorded as collection error (see [crate::collection_errors]), and an empty String is returned.
+pub async fn http_get(
     host: &str,
     port: &str,
-) -> bool
+    url: &str,
+) -> String
 {
-    if ! scan_port_addr( format!("{}:{}", host, port)) {
-        warn!("Port scanner: hostname:port {}:{} cannot be reached, skipping",host ,port);
-        false
-    } else {
-        true
+    let hostname_port = format!("{}:{}", host, port);
+    let timer = Instant::now();
+    let configured_scheme = http_settings().scheme_for_port(port);
+    let scheme = match configured_scheme {
+        Scheme::Auto => *DETECTED_SCHEMES.get_or_init(Default::default).lock().unwrap().get(&hostname_port).unwrap_or(&Scheme::Auto),
+        scheme => scheme,
+    };
+    let http_credentials = configured_scheme == Scheme::Http;
+    let result = match scheme {
+        Scheme::Http => http_get_retry("http", host, port, url, http_credentials).await,
+        Scheme::Https => http_get_retry("https", host, port, url, true).await,
+        Scheme::Auto => {
+            match http_get_retry("http", host, port, url, false).await {
+                Ok(data_from_web_request) => {
+                    debug!("Scheme detected for {}: http", hostname_port);
+                    DETECTED_SCHEMES.get_or_init(Default::default).lock().unwrap().insert(hostname_port.clone(), Scheme::Http);
+                    Ok(data_from_web_request)
+                },
+                // if the port can't be connected to, https will not be able to connect either.
+                Err((HttpErrorKind::Connect, attempts)) => Err((HttpErrorKind::Connect, attempts)),
+                Err((_, http_attempts)) => {
+                    match http_get_retry("https", host, port, url, true).await {
+                        Ok(data_from_web_request) => {
+                            debug!("Scheme detected for {}: https", hostname_port);
+                            DETECTED_SCHEMES.get_or_init(Default::default).lock().unwrap().insert(hostname_port.clone(), Scheme::Https);
+                            Ok(data_from_web_request)
+                        },
+                        Err((error_kind, attempts)) => Err((error_kind, http_attempts + attempts)),
+                    }
+                },
+            }
+        },
+    };
+    match result {
+        Ok(data_from_web_request) => data_from_web_request,
+        Err((error_kind, attempts)) => {
+            if error_kind == HttpErrorKind::Connect {
+                warn!("hostname:port {} cannot be reached, skipping", hostname_port);
+            } else {
+                warn!("hostname:port {} url {}: {} error after {} attempt(s), skipping", hostname_port, url, error_kind, attempts);
+            }
+            record_collection_error(&hostname_port, url, error_kind, timer.elapsed(), attempts);
+            String::new()
+        },
     }
 }
 
-pub fn http_get(
+/// This function performs the request with the given scheme, and retries it with backoff if the error is retryable, up to the number of retries in the [HttpSettings].
+/// It returns the body, or the error kind of the last attempt and the number of attempts.
+async fn http_get_retry(
+    scheme: &str,
     host: &str,
     port: &str,
     url: &str,
-) -> String
+    credentials: bool,
+) -> Result<String, (HttpErrorKind, u32)>
 {
-    if let Ok(data_from_web_request) = reqwest::blocking::Client::builder()
-        .danger_accept_invalid_certs(ACCEPT_INVALID_CERTS)
-        .build()
-        .unwrap()
-        .get(format!("http://{}:{}/{}", host, port, url))
-        .send()
-    {
-        if ! &data_from_web_request.status().is_success()
-        {
-            debug!("Non success response: {}:{}/{} = {}", host, port, url, &data_from_web_request.status());
-        }
-        else
-        {
-           debug!("Success response: {}:{}/{} = {}", host, port, url, &data_from_web_request.status());
+    let mut attempt: u32 = 0;
+    loop {
+        attempt += 1;
+        match http_get_scheme(scheme, host, port, url, credentials).await {
+            Ok(data_from_web_request) => return Ok(data_from_web_request),
+            Err(error_kind) if error_kind.is_retryable() && attempt <= http_settings().retries => {
+                debug!("Retry {} for {}://{}:{}/{} after {} error", attempt, scheme, host, port, url, error_kind);
+                tokio::time::sleep(retry_backoff(attempt)).await;
+            },
+            Err(error_kind) => return Err((error_kind, attempt)),
         }
-        data_from_web_request.text().unwrap()
-    } else {
-        debug!("Non-Ok success response: {}:{}/{}", host, port, url);
-        String::new()
     }
-}
\ No newline at end of file
+}
+
+/// This function performs the actual request with the given scheme.
+/// A 404 (Not Found) response is returned as body, because not every endpoint exists on every server type.
+/// A permit of the concurrency limit is held for the duration of the request.
+/// The credential of the endpoint is only added if credentials is true.
+async fn http_get_scheme(
+    scheme: &str,
+    host: &str,
+    port: &str,
+    url: &str,
+    credentials: bool,
+) -> Result<String, HttpErrorKind>
+{
+    let mut request = http_client().get(format!("{}://{}:{}/{}", scheme, host, port, url));
+    // The role of the endpoint is only known after classification: before that, such as for the probes, the role is based on the port number.
+    let role = endpoint_role(host, port).unwrap_or_else(|| port_role(port));
+    request = match http_settings().credential_for_port(port, role).filter(|_| credentials) {
+        Some(Credential::Basic { username, password }) => request.basic_auth(username, Some(password)),
+        Some(Credential::Bearer(token)) => request.bearer_auth(token),
+        None => request,
+    };
+    let _permit = http_semaphore().acquire().await.expect("http semaphore is closed");
+    match request.send().await
+    {
+        Ok(data_from_web_request) => {
+            let status = data_from_web_request.status();
+            if status == reqwest::StatusCode::BAD_REQUEST {
+                debug!("Bad request response: {}://{}:{}/{} = {}", scheme, host, port, url, &status);
+                return Err(HttpErrorKind::BadRequest);
+            }
+            if ! status.is_success()
+            {
+                debug!("Non success response: {}://{}:{}/{} = {}", scheme, host, port, url, &status);
+                if status != reqwest::StatusCode::NOT_FOUND {
+                    return Err(HttpErrorKind::Status(status.as_u16()));
+                }
+            }
+            else
+            {
+                debug!("Success response: {}://{}:{}/{} = {}", scheme, host, port, url, &status);
+            }
+            data_from_web_request.text().await.map_err(|e| HttpErrorKind::from_reqwest_error(&e))
+        },
+        Err(e) => {
+            debug!("Non-Ok success response: {}://{}:{}/{}: {}", scheme, host, port, url, e);
+            Err(HttpErrorKind::from_reqwest_error(&e))
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unit_parse_scheme() {
+        assert_eq!("HTTPS".parse::<Scheme>().unwrap(), Scheme::Https);
+        assert_eq!("auto".parse::<Scheme>().unwrap(), Scheme::Auto);
+        assert!("ftp".parse::<Scheme>().is_err());
+    }
+
+    #[test]
+    fn unit_parse_port_schemes() {
+        let port_schemes = HttpSettings::parse_port_schemes("7000=https,9000=http").unwrap();
+        assert_eq!(port_schemes.get("7000"), Some(&Scheme::Https));
+        assert_eq!(port_schemes.get("9000"), Some(&Scheme::Http));
+        assert!(HttpSettings::parse_port_schemes("7000").is_err());
+    }
+
+    #[test]
+    fn unit_scheme_for_port() {
+        let http_settings = HttpSettings {
+            scheme: Scheme::Http,
+            port_schemes: HttpSettings::parse_port_schemes("7000=https").unwrap(),
+            ..Default::default()
+        };
+        assert_eq!(http_settings.scheme_for_port("7000"), Scheme::Https);
+        assert_eq!(http_settings.scheme_for_port("9000"), Scheme::Http);
+    }
+
+    #[test]
+    fn unit_parse_credentials() {
+        let credentials = r#"
+# node_exporter is behind a reverse proxy
+9300 bearer abc123
+master basic admin:secret:with:colons
+* basic yugabyte:yugabyte
+"#;
+        let credentials = HttpSettings::parse_credentials(credentials).unwrap();
+        assert_eq!(credentials.get("9300"), Some(&Credential::Bearer("abc123".to_string())));
+        assert_eq!(credentials.get("master"), Some(&Credential::Basic { username: "admin".to_string(), password: "secret:with:colons".to_string() }));
+        assert!(HttpSettings::parse_credentials("9300 bearer").is_err());
+        assert!(HttpSettings::parse_credentials("9300 digest abc").is_err());
+    }
+
+    #[test]
+    fn unit_credential_for_port() {
+        let http_settings = HttpSettings {
+            credentials: HttpSettings::parse_credentials("9300 bearer abc123\nmaster bearer def456\n* bearer ghi789").unwrap(),
+            ..Default::default()
+        };
+        assert_eq!(http_settings.credential_for_port("9300", "node_exporter"), Some(&Credential::Bearer("abc123".to_string())));
+        assert_eq!(http_settings.credential_for_port("7000", "master"), Some(&Credential::Bearer("def456".to_string())));
+        // a master on a port that is not the default master port.
+        assert_eq!(http_settings.credential_for_port("17000", "master"), Some(&Credential::Bearer("def456".to_string())));
+        assert_eq!(http_settings.credential_for_port("9000", "tserver"), Some(&Credential::Bearer("ghi789".to_string())));
+    }
+
+    #[test]
+    fn unit_credential_debug_redacts_secrets() {
+        let credential = Credential::Basic { username: "admin".to_string(), password: "secret".to_string() };
+        assert!(!format!("{:?}", credential).contains("secret"));
+    }
+
+    #[test]
+    fn unit_http_error_kind_is_retryable() {
+        assert!(HttpErrorKind::Connect.is_retryable());
+        assert!(HttpErrorKind::Timeout.is_retryable());
+        assert!(HttpErrorKind::Status(503).is_retryable());
+        assert!(!HttpErrorKind::Status(401).is_retryable());
+        assert!(!HttpErrorKind::BadRequest.is_retryable());
+        assert_eq!(HttpErrorKind::Status(503).to_string(), "status_503");
+    }
+
+    #[test]
+    fn unit_retry_backoff() {
+        assert_eq!(retry_backoff(1), Duration::from_millis(RETRY_BACKOFF));
+        assert_eq!(retry_backoff(2), Duration::from_millis(RETRY_BACKOFF * 2));
+        assert_eq!(retry_backoff(3), Duration::from_millis(RETRY_BACKOFF * 4));
+    }
+}
@@ -1,10 +1,19 @@
 use chrono::{DateTime, Local};
 use regex::Regex;
-use std::{fs, process, sync::mpsc::channel, time::Instant, error::Error, env, collections::BTreeMap};
+use std::{process, time::Instant, error::Error, collections::BTreeMap};
 use serde_derive::{Serialize,Deserialize};
 use log::*;
+use crate::snapshot::snapshot_directory;
+use futures::future::join_all;
 use colored::*;
-use crate::utility::{scan_host_port, http_get};
+use crate::utility::http_get;
+use crate::endpoints::has_role;
+use crate::compression::create_snapshot_file;
+use crate::schema::open_snapshot_csv;
+use crate::output::{self, DiffRecord, push_changed_attribute};
+
+/// The endpoint roles that provide /api/v1/varz.
+pub const ROLES: &[&str] = &["master", "tserver"];
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct AllVars {
@@ -38,13 +47,12 @@ impl AllStoredVars {
         hosts: &Vec<&str>,
         ports: &Vec<&str>,
         snapshot_number: i32,
-        parallel: usize,
     )
     {
         info!("begin snapshot");
         let timer = Instant::now();
 
-        let allvars = AllStoredVars::read_vars(hosts, ports, parallel);
+        let allvars = AllStoredVars::read_vars(hosts, ports);
 
         allvars.await.save_snapshot(snapshot_number)
             .unwrap_or_else(|e| {
@@ -57,33 +65,33 @@ impl AllStoredVars {
     pub async fn read_vars(
         hosts: &Vec<&str>,
         ports: &Vec<&str>,
-        parallel: usize,
     ) -> AllStoredVars
     {
         info!("begin parallel http read");
         let timer = Instant::now();
 
-        let pool = rayon::ThreadPoolBuilder::new().num_threads(parallel).build().unwrap();
-        let (tx, rx) = channel();
-        pool.scope(move |s| {
-            for host in hosts {
-                for port in ports {
-                    let tx = tx.clone();
-                    s.spawn(move |_| {
-                        let detail_snapshot_time = Local::now();
-                        let vars = AllStoredVars::read_http(host, port);
-                        tx.send((format!("{}:{}", host, port), detail_snapshot_time, vars)).expect("error sending data via tx");
-                    });
+        let mut handles = Vec::new();
+        for host in hosts {
+            for port in ports {
+                if !has_role(host, port, ROLES) {
+                    continue;
                 }
+                let handle = async move {
+                    let detail_snapshot_time = Local::now();
+                    let vars = AllStoredVars::read_http(host, port).await;
+                    (format!("{}:{}", host, port), detail_snapshot_time, vars)
+                };
+                handles.push(handle);
             }
-        });
+        }
+        let results = join_all(handles).await;
 
         info!("end parallel http read {:?}", timer.elapsed());
 
         let mut allstoredvars = AllStoredVars {
             stored_vars: Vec::new(),
         };
-        for (hostname_port, detail_snapshot_time, vars) in rx {
+        for (hostname_port, detail_snapshot_time, vars) in results {
             AllStoredVars::split_into_vectors(vars, &hostname_port, detail_snapshot_time, &mut allstoredvars);
         }
 
@@ -106,16 +114,12 @@ impl AllStoredVars {
             });
         }
     }
-    pub fn read_http(
+    pub async fn read_http(
         host: &str,
         port: &str,
     ) -> AllVars
     {
-        let data_from_http = if scan_host_port( host, port) {
-            http_get(host, port, "api/v1/varz")
-        } else {
-            String::new()
-        };
+        let data_from_http = http_get(host, port, "api/v1/varz").await;
         AllStoredVars::parse_vars(data_from_http, host, port)
 
 /*
@@ -147,19 +151,15 @@ impl AllStoredVars {
     }
     fn save_snapshot ( self, snapshot_number: i32 ) -> Result<(), Box<dyn Error>>
     {
-        let current_directory = env::current_dir()?;
-        let current_snapshot_directory = current_directory.join("yb_stats.snapshots").join(&snapshot_number.to_string());
+        let current_snapshot_directory = snapshot_directory().join(snapshot_number.to_string());
 
         let vars_file = &current_snapshot_directory.join("vars");
-        let file = fs::OpenOptions::new()
-            .create(true)
-            .write(true)
-            .open(vars_file)?;
+        let file = create_snapshot_file(vars_file)?;
         let mut writer = csv::Writer::from_writer(file);
         for row in self.stored_vars {
             writer.serialize(row)?;
         }
-        writer.flush()?;
+        writer.into_inner()?.finish()?;
 
         Ok(())
     }
@@ -169,11 +169,10 @@ impl AllStoredVars {
             stored_vars: Vec::new(),
         };
 
-        let current_directory = env::current_dir()?;
-        let current_snapshot_directory = current_directory.join("yb_stats.snapshots").join(snapshot_number);
+        let current_snapshot_directory = snapshot_directory().join(snapshot_number);
 
         let vars_file = &current_snapshot_directory.join("vars");
-        let file = fs::File::open(vars_file)?;
+        let file = open_snapshot_csv(vars_file)?;
 
         let mut reader = csv::Reader::from_reader(file);
         for row in reader.deserialize() {
@@ -325,10 +324,37 @@ impl SnapshotDiffBTreeMapsVars {
             }
         }
     }
+    /// This function returns the changed vars of [SnapshotDiffBTreeMapsVars::print] as records for the machine-readable output, see [crate::output].
+    /// Only the value and the type that have changed have a record.
+    pub fn records(
+        &self,
+    ) -> Vec<DiffRecord>
+    {
+        let mut records = Vec::new();
+        for ((hostname_port, name), row) in self.btreemap_snapshotdiff_vars.iter() {
+            // a server that started or stopped during the snapshot is not reported.
+            if row.first_value.is_empty() || row.second_value.is_empty() {
+                continue;
+            }
+            let record = DiffRecord::object("vars", "*", DiffRecord {
+                hostname_port: hostname_port.to_string(),
+                metric_type: row.second_vars_type.to_string(),
+                name: name.to_string(),
+                ..Default::default()
+            });
+            push_changed_attribute(&mut records, &record, "value", &row.first_value, &row.second_value);
+            push_changed_attribute(&mut records, &record, "type", &row.first_vars_type, &row.second_vars_type);
+        }
+        records
+    }
     pub fn print(
         &self,
     )
     {
+        if output::is_machine_readable() {
+            output::write_records(&self.records());
+            return;
+        }
         for ((hostname_port, name), row) in self.btreemap_snapshotdiff_vars.iter() {
             // first value empty means a server started/became available during the snapshot. Do not report
             if row.first_value.is_empty() || row.second_value.is_empty() {
@@ -357,20 +383,18 @@ impl SnapshotDiffBTreeMapsVars {
         &mut self,
         hosts: &Vec<&str>,
         ports: &Vec<&str>,
-        parallel: usize,
     )
     {
-        let allstoredvars = AllStoredVars::read_vars(hosts, ports, parallel).await;
+        let allstoredvars = AllStoredVars::read_vars(hosts, ports).await;
         SnapshotDiffBTreeMapsVars::first_snapshot(allstoredvars);
     }
     pub async fn adhoc_read_second_snapshot(
         &mut self,
         hosts: &Vec<&str>,
         ports: &Vec<&str>,
-        parallel: usize,
     )
     {
-        let allstoredvars = AllStoredVars::read_vars(hosts, ports, parallel).await;
+        let allstoredvars = AllStoredVars::read_vars(hosts, ports).await;
         self.second_snapshot(allstoredvars);
     }
 }
@@ -445,26 +469,26 @@ mod tests {
 
     /*
     use crate::utility;
-    #[test]
-    fn integration_parse_gflags_master() {
+    #[tokio::test]
+    async fn integration_parse_gflags_master() {
         let mut stored_gflags: Vec<StoredGFlags> = Vec::new();
         let detail_snapshot_time = Local::now();
         let hostname = utility::get_hostname_master();
         let port = utility::get_port_master();
 
-        let gflags = read_gflags(hostname.as_str(), port.as_str());
+        let gflags = read_gflags(hostname.as_str(), port.as_str()).await;
         add_to_gflags_vector(gflags, format!("{}:{}", hostname, port).as_str(), detail_snapshot_time, &mut stored_gflags);
         // the master must have gflags
         assert!(!stored_gflags.is_empty());
     }
-    #[test]
-    fn integration_parse_gflags_tserver() {
+    #[tokio::test]
+    async fn integration_parse_gflags_tserver() {
         let mut stored_gflags: Vec<StoredGFlags> = Vec::new();
         let detail_snapshot_time = Local::now();
         let hostname = utility::get_hostname_tserver();
         let port = utility::get_port_tserver();
 
-        let gflags = read_gflags(hostname.as_str(), port.as_str());
+        let gflags = read_gflags(hostname.as_str(), port.as_str()).await;
         add_to_gflags_vector(gflags, format!("{}:{}", hostname, port).as_str(), detail_snapshot_time, &mut stored_gflags);
         // the tserver must have gflags
         assert!(!stored_gflags.is_empty());
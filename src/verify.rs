@@ -0,0 +1,386 @@
+//! The module for the verification of snapshots (`--snapshot-verify`).
+//!
+//! A snapshot can be partially written, for example because the disk is full, or because a source stopped yb_stats while other sources kept running.
+//! The verification reads every file of a snapshot the same way the reports read it: the CSV files with [open_snapshot_csv] into the serde struct of the source,
+//! such as [crate::metrics::StoredValues], so a file that is missing or cannot be parsed is found before a diff fails on it.
+//!
+//! For every snapshot, the verification reports:
+//! - The rows per file and per hostname:port.
+//! - The files that are missing or cannot be parsed, and the files that have another number of rows than recorded in the manifest.
+//! - The hostname:port endpoints that are missing from a source that every endpoint of its role should return data for ([VerifyFile::every_endpoint]),
+//!   while the endpoint is present in the endpoints file or in another such source.
+//!
+//! The files of a source that is disabled in the manifest, such as threads with `--disable-threads`, are not verified.
+use std::{fs, io, collections::{BTreeMap, BTreeSet}, error::Error, path::Path};
+use serde::de::DeserializeOwned;
+use log::*;
+use crate::snapshot::{Snapshot, snapshot_directory};
+use crate::compression::{open_snapshot_file, strip_compression_extension};
+use crate::schema::{format_version, open_snapshot_csv};
+use crate::manifest::SnapshotManifest;
+use crate::endpoints::{AllStoredEndpoints, StoredEndpoints};
+use crate::collection_errors::AllStoredCollectionErrors;
+use crate::storage::{database_files, is_csv_file};
+use crate::{collection_errors, endpoints, entities, gflags, isleader, loglines, masters, memtrackers, metrics, node_exporter, rpcs, statements, threads, tservers, vars, versions};
+
+/// The function that reads a CSV file with a serde struct, and returns the rows per hostname:port.
+type ReadRows = fn(&Path) -> Result<BTreeMap<String, u64>, Box<dyn Error>>;
+
+/// A CSV file of a snapshot, with the source that writes it.
+pub struct VerifyFile {
+    /// The name of the source in the manifest.
+    pub source: &'static str,
+    pub file: &'static str,
+    /// The roles of the endpoints the source is read from.
+    pub roles: &'static [&'static str],
+    /// The source returns rows for every endpoint of its roles, so an endpoint without rows is flagged.
+    pub every_endpoint: bool,
+    /// The function that reads the file with the serde struct of the source, and returns the rows per hostname:port.
+    read: ReadRows,
+}
+
+/// The CSV files of a snapshot.
+pub const VERIFY_FILES: &[VerifyFile] = &[
+    VerifyFile { source: "endpoints", file: "endpoints", roles: &[], every_endpoint: false, read: count_rows::<endpoints::StoredEndpoints> },
+    VerifyFile { source: "collection_errors", file: "collection_errors", roles: &[], every_endpoint: false, read: count_rows::<collection_errors::StoredCollectionError> },
+    VerifyFile { source: "metrics", file: "values", roles: metrics::ROLES, every_endpoint: false, read: count_rows::<metrics::StoredValues> },
+    VerifyFile { source: "metrics", file: "countsum", roles: metrics::ROLES, every_endpoint: false, read: count_rows::<metrics::StoredCountSum> },
+    VerifyFile { source: "metrics", file: "countsumrows", roles: metrics::ROLES, every_endpoint: false, read: count_rows::<metrics::StoredCountSumRows> },
+    VerifyFile { source: "statements", file: "statements", roles: statements::ROLES, every_endpoint: false, read: count_rows::<statements::StoredStatements> },
+    VerifyFile { source: "node_exporter", file: "nodeexporter", roles: node_exporter::ROLES, every_endpoint: true, read: count_rows::<node_exporter::StoredNodeExporterValues> },
+    VerifyFile { source: "isleader", file: "isleader", roles: isleader::ROLES, every_endpoint: false, read: count_rows::<isleader::StoredIsLeader> },
+    VerifyFile { source: "entities", file: "keyspaces", roles: entities::ROLES, every_endpoint: false, read: count_rows::<entities::StoredKeyspaces> },
+    VerifyFile { source: "entities", file: "tables", roles: entities::ROLES, every_endpoint: false, read: count_rows::<entities::StoredTables> },
+    VerifyFile { source: "entities", file: "tablets", roles: entities::ROLES, every_endpoint: false, read: count_rows::<entities::StoredTablets> },
+    VerifyFile { source: "entities", file: "replicas", roles: entities::ROLES, every_endpoint: false, read: count_rows::<entities::StoredReplicas> },
+    VerifyFile { source: "masters", file: "masters", roles: masters::ROLES, every_endpoint: false, read: count_rows::<masters::StoredMasters> },
+    VerifyFile { source: "masters", file: "master_rpc_addresses", roles: masters::ROLES, every_endpoint: false, read: count_rows::<masters::StoredRpcAddresses> },
+    VerifyFile { source: "masters", file: "master_http_addresses", roles: masters::ROLES, every_endpoint: false, read: count_rows::<masters::StoredHttpAddresses> },
+    VerifyFile { source: "masters", file: "master_errors", roles: masters::ROLES, every_endpoint: false, read: count_rows::<masters::StoredMasterError> },
+    VerifyFile { source: "tservers", file: "tablet_servers", roles: tservers::ROLES, every_endpoint: false, read: count_rows::<tservers::StoredTabletServers> },
+    VerifyFile { source: "tservers", file: "tablet_servers_pathmetrics", roles: tservers::ROLES, every_endpoint: false, read: count_rows::<tservers::StoredPathMetrics> },
+    VerifyFile { source: "vars", file: "vars", roles: vars::ROLES, every_endpoint: true, read: count_rows::<vars::StoredVars> },
+    VerifyFile { source: "versions", file: "versions", roles: versions::ROLES, every_endpoint: true, read: count_rows::<versions::StoredVersion> },
+    VerifyFile { source: "gflags", file: "gflags", roles: gflags::ROLES, every_endpoint: true, read: count_rows::<gflags::StoredGFlags> },
+    VerifyFile { source: "threads", file: "threads", roles: threads::ROLES, every_endpoint: true, read: count_rows::<threads::StoredThreads> },
+    VerifyFile { source: "memtrackers", file: "memtrackers", roles: memtrackers::ROLES, every_endpoint: true, read: count_rows::<memtrackers::StoredMemTrackers> },
+    VerifyFile { source: "loglines", file: "loglines", roles: loglines::ROLES, every_endpoint: false, read: count_rows::<loglines::StoredLogLines> },
+    VerifyFile { source: "rpcs", file: "ysqlrpc", roles: rpcs::ROLES, every_endpoint: false, read: count_rows::<rpcs::StoredYsqlRpc> },
+    VerifyFile { source: "rpcs", file: "inboundrpc", roles: rpcs::ROLES, every_endpoint: false, read: count_rows::<rpcs::StoredInboundRpc> },
+    VerifyFile { source: "rpcs", file: "outboundrpc", roles: rpcs::ROLES, every_endpoint: false, read: count_rows::<rpcs::StoredOutboundRpc> },
+    VerifyFile { source: "rpcs", file: "cqldetails", roles: rpcs::ROLES, every_endpoint: false, read: count_rows::<rpcs::StoredCqlDetails> },
+    VerifyFile { source: "rpcs", file: "headers", roles: rpcs::ROLES, every_endpoint: false, read: count_rows::<rpcs::StoredHeaders> },
+];
+
+/// The result of reading a file of a snapshot.
+#[derive(Debug)]
+pub enum FileStatus {
+    /// The rows per hostname:port for a CSV file, or the bytes for a file that is not CSV.
+    Ok(BTreeMap<String, u64>),
+    Missing,
+    Invalid(String),
+    /// The source is disabled in the manifest.
+    Disabled,
+}
+
+/// A verified file of a snapshot.
+#[derive(Debug)]
+pub struct VerifiedFile {
+    pub source: String,
+    pub file: String,
+    pub status: FileStatus,
+}
+
+/// An endpoint that has no rows in a file, while it's present in the endpoints or in another source.
+#[derive(Debug, PartialEq, Eq)]
+pub struct MissingHost {
+    pub hostname_port: String,
+    pub file: String,
+    /// The endpoints file or the files in which the endpoint is present.
+    pub present_in: Vec<String>,
+}
+
+/// The verification of a snapshot.
+#[derive(Debug, Default)]
+pub struct SnapshotVerification {
+    pub snapshot_number: String,
+    pub format_version: u32,
+    pub files: Vec<VerifiedFile>,
+    pub problems: Vec<String>,
+}
+
+impl SnapshotVerification {
+    /// This function verifies the snapshots from begin to end (both optional), prints the results, and returns the number of problems.
+    pub fn verify_snapshots(
+        begin: Option<i32>,
+        end: Option<i32>,
+        details_enable: &bool,
+    ) -> usize
+    {
+        let snapshots = Snapshot::read_snapshots().into_iter()
+            .filter(|snapshot| begin.map(|begin| snapshot.number >= begin).unwrap_or(true))
+            .filter(|snapshot| end.map(|end| snapshot.number <= end).unwrap_or(true))
+            .collect::<Vec<Snapshot>>();
+        let mut problems = 0;
+        for snapshot in snapshots {
+            let verification = SnapshotVerification::verify_snapshot(&snapshot.number.to_string());
+            verification.print(details_enable);
+            problems += verification.problems.len();
+        }
+        problems
+    }
+    /// This function verifies a snapshot: it reads all the files of the snapshot, and records the problems it finds.
+    pub fn verify_snapshot(
+        snapshot_number: &String,
+    ) -> SnapshotVerification
+    {
+        info!("verify snapshot {}", snapshot_number);
+        let mut verification = SnapshotVerification { snapshot_number: snapshot_number.to_string(), ..Default::default() };
+        let current_snapshot_directory = snapshot_directory().join(snapshot_number);
+        if !current_snapshot_directory.is_dir() {
+            verification.problems.push(format!("snapshot directory {} is not found", current_snapshot_directory.display()));
+            return verification;
+        }
+        verification.format_version = format_version(&current_snapshot_directory);
+
+        // A snapshot of format version 1 has no manifest.
+        let manifest = match SnapshotManifest::read_snapshot(snapshot_number) {
+            Ok(manifest) => Some(manifest),
+            Err(e) if is_not_found(e.as_ref()) && verification.format_version == 1 => None,
+            Err(e) => {
+                verification.problems.push(format!("manifest: {}", e));
+                None
+            },
+        };
+        let disabled_sources = manifest.iter()
+            .flat_map(|manifest| manifest.sources.iter())
+            .filter(|source| !source.enabled)
+            .map(|source| source.name.as_str())
+            .collect::<BTreeSet<&str>>();
+
+        for verify_file in VERIFY_FILES {
+            let status = if disabled_sources.contains(verify_file.source) {
+                FileStatus::Disabled
+            } else {
+                match (verify_file.read)(&current_snapshot_directory.join(verify_file.file)) {
+                    Ok(rows) => FileStatus::Ok(rows),
+                    Err(e) if is_not_found(e.as_ref()) => FileStatus::Missing,
+                    Err(e) => FileStatus::Invalid(e.to_string()),
+                }
+            };
+            verification.files.push(VerifiedFile { source: verify_file.source.to_string(), file: verify_file.file.to_string(), status });
+        }
+        for (source, file) in other_files(snapshot_number, &current_snapshot_directory) {
+            let status = match read_bytes(&current_snapshot_directory.join(&file)) {
+                Ok(bytes) => FileStatus::Ok(BTreeMap::from([(String::new(), bytes)])),
+                Err(e) => FileStatus::Invalid(e.to_string()),
+            };
+            verification.files.push(VerifiedFile { source, file, status });
+        }
+
+        for file in &verification.files {
+            match &file.status {
+                FileStatus::Missing => verification.problems.push(format!("{}: file {} is missing", file.source, file.file)),
+                FileStatus::Invalid(e) => verification.problems.push(format!("{}: file {} cannot be read: {}", file.source, file.file, e)),
+                _ => {},
+            }
+        }
+        if let Some(manifest) = &manifest {
+            for manifest_file in &manifest.files {
+                let Some(manifest_rows) = manifest_file.rows else { continue };
+                let name = strip_compression_extension(&manifest_file.name);
+                if let Some(VerifiedFile { status: FileStatus::Ok(rows), .. }) = verification.files.iter().find(|file| file.file == name) {
+                    let rows = rows.values().sum::<u64>();
+                    if rows != manifest_rows {
+                        verification.problems.push(format!("file {} has {} rows, the manifest records {} rows", name, rows, manifest_rows));
+                    }
+                }
+            }
+        }
+
+        let endpoints = AllStoredEndpoints::read_snapshot(snapshot_number).unwrap_or_default();
+        let collection_errors = AllStoredCollectionErrors::read_snapshot(snapshot_number).unwrap_or_default();
+        for missing_host in missing_hosts(&verification.files, &endpoints.stored_endpoints) {
+            let errors = collection_errors.stored_collection_errors.iter()
+                .filter(|error| error.hostname_port == missing_host.hostname_port)
+                .map(|error| format!("{} ({})", error.endpoint, error.error_kind))
+                .collect::<Vec<String>>();
+            let mut problem = format!("{}: hostname_port {} has no rows, but is present in: {}", missing_host.file, missing_host.hostname_port, missing_host.present_in.join(", "));
+            if !errors.is_empty() {
+                problem.push_str(&format!(", collection errors: {}", errors.join(", ")));
+            }
+            verification.problems.push(problem);
+        }
+        verification
+    }
+    /// This function prints the verification: the rows per file, and per hostname:port with details enabled, and the problems.
+    pub fn print(
+        &self,
+        details_enable: &bool,
+    )
+    {
+        println!("snapshot {}, format version {}", self.snapshot_number, self.format_version);
+        for file in &self.files {
+            match &file.status {
+                FileStatus::Ok(rows) if !is_csv_file(&file.file) => println!("  {:20} {:40} {:10} {:>12} bytes", file.source, file.file, "ok", rows.values().sum::<u64>()),
+                FileStatus::Ok(rows) => {
+                    println!("  {:20} {:40} {:10} {:>12} rows", file.source, file.file, "ok", rows.values().sum::<u64>());
+                    if *details_enable {
+                        for (hostname_port, rows) in rows {
+                            println!("  {:20} {:40} {:10} {:>12} rows  {}", "", "", "", rows, hostname_port);
+                        }
+                    }
+                },
+                FileStatus::Missing => println!("  {:20} {:40} {:10}", file.source, file.file, "missing"),
+                FileStatus::Invalid(_) => println!("  {:20} {:40} {:10}", file.source, file.file, "invalid"),
+                FileStatus::Disabled => println!("  {:20} {:40} {:10}", file.source, file.file, "disabled"),
+            }
+        }
+        for problem in &self.problems {
+            println!("  problem: {}", problem);
+        }
+        match self.problems.len() {
+            0 => println!("snapshot {}: ok", self.snapshot_number),
+            problems => println!("snapshot {}: {} problems", self.snapshot_number, problems),
+        }
+    }
+}
+
+/// This function reads a CSV file of a snapshot with the serde struct of the source, and returns the rows per hostname:port.
+fn count_rows<T: DeserializeOwned>(
+    path: &Path,
+) -> Result<BTreeMap<String, u64>, Box<dyn Error>>
+{
+    let mut reader = csv::Reader::from_reader(open_snapshot_csv(path)?);
+    let headers = reader.headers()?.clone();
+    let hostname_port = headers.iter().position(|header| header == "hostname_port");
+    let mut rows = BTreeMap::new();
+    for record in reader.records() {
+        let record = record?;
+        record.deserialize::<T>(Some(&headers))?;
+        let hostname_port = hostname_port.and_then(|column| record.get(column)).unwrap_or_default();
+        *rows.entry(hostname_port.to_string()).or_insert(0) += 1;
+    }
+    Ok(rows)
+}
+
+/// This function reads a file that is not CSV, and returns the number of bytes.
+fn read_bytes(
+    path: &Path,
+) -> Result<u64, Box<dyn Error>>
+{
+    Ok(io::copy(&mut open_snapshot_file(path)?, &mut io::sink())?)
+}
+
+/// The sources and names of the files of a snapshot that are not CSV, such as the pprof and mems files, in the snapshot directory and in the database.
+fn other_files(
+    snapshot_number: &str,
+    current_snapshot_directory: &Path,
+) -> Vec<(String, String)>
+{
+    let mut names = fs::read_dir(current_snapshot_directory).into_iter()
+        .flatten()
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| entry.file_name().to_str().map(|name| strip_compression_extension(name).to_string()))
+        .collect::<BTreeSet<String>>();
+    names.extend(snapshot_number.parse::<i32>().ok()
+        .and_then(|snapshot_number| database_files(snapshot_number).ok())
+        .unwrap_or_default()
+        .into_iter()
+        .map(|file| file.name));
+    names.into_iter()
+        .filter(|name| !is_csv_file(name))
+        .map(|name| {
+            let source = match name.split_once('_') {
+                Some(("pprof", _)) => "pprof",
+                Some(("mems", _)) => "mems",
+                _ => "snapshot",
+            };
+            (source.to_string(), name)
+        })
+        .collect()
+}
+
+/// This function tells if the error is a file that is not found.
+fn is_not_found(
+    error: &(dyn Error + 'static),
+) -> bool
+{
+    matches!(error.downcast_ref::<io::Error>(), Some(e) if e.kind() == io::ErrorKind::NotFound)
+}
+
+/// This function finds the endpoints that have no rows in a file of a source that returns rows for every endpoint of its roles ([VerifyFile::every_endpoint]).
+/// The endpoints that should be present are the endpoints with one of the roles of the source in the endpoints file,
+/// and the endpoints that are present in another such file of a source with the same roles.
+fn missing_hosts(
+    files: &[VerifiedFile],
+    endpoints: &[StoredEndpoints],
+) -> Vec<MissingHost>
+{
+    let every_endpoint_file = |file: &VerifiedFile| VERIFY_FILES.iter().find(|verify_file| verify_file.file == file.file && verify_file.every_endpoint);
+    let mut missing_hosts = Vec::new();
+    for file in files {
+        let (Some(verify_file), FileStatus::Ok(rows)) = (every_endpoint_file(file), &file.status) else { continue };
+        let mut present: BTreeMap<&str, Vec<String>> = BTreeMap::new();
+        for endpoint in endpoints.iter().filter(|endpoint| verify_file.roles.contains(&endpoint.role.as_str())) {
+            present.entry(&endpoint.hostname_port).or_default().push("endpoints".to_string());
+        }
+        for other_file in files.iter().filter(|other_file| other_file.file != file.file) {
+            let (Some(other_verify_file), FileStatus::Ok(other_rows)) = (every_endpoint_file(other_file), &other_file.status) else { continue };
+            if other_verify_file.roles != verify_file.roles {
+                continue;
+            }
+            for hostname_port in other_rows.keys() {
+                present.entry(hostname_port).or_default().push(other_file.file.to_string());
+            }
+        }
+        for (hostname_port, present_in) in present.into_iter().filter(|(hostname_port, _)| !rows.contains_key(*hostname_port)) {
+            missing_hosts.push(MissingHost { hostname_port: hostname_port.to_string(), file: file.file.to_string(), present_in });
+        }
+    }
+    missing_hosts
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ok(file: &str, hostname_ports: &[&str]) -> VerifiedFile {
+        VerifiedFile { source: file.to_string(), file: file.to_string(), status: FileStatus::Ok(hostname_ports.iter().map(|hostname_port| (hostname_port.to_string(), 1)).collect()) }
+    }
+
+    #[test]
+    fn unit_count_rows() {
+        let directory = std::env::temp_dir().join(format!("yb_stats_verify_{}", std::process::id())).join("0");
+        fs::create_dir_all(&directory).unwrap();
+        fs::write(directory.join("gflags"), "hostname_port,timestamp,gflag_name,gflag_value\n\
+            192.168.66.80:7000,2023-01-10T10:12:31+01:00,fs_data_dirs,/mnt/d0\n\
+            192.168.66.80:7000,2023-01-10T10:12:31+01:00,log_dir,/mnt/d0/yb-data/master/logs\n\
+            192.168.66.80:9000,2023-01-10T10:12:31+01:00,fs_data_dirs,/mnt/d0\n").unwrap();
+        let rows = count_rows::<gflags::StoredGFlags>(&directory.join("gflags")).unwrap();
+        assert_eq!(rows, BTreeMap::from([("192.168.66.80:7000".to_string(), 2), ("192.168.66.80:9000".to_string(), 1)]));
+        // a file that is cut off while it was written.
+        fs::write(directory.join("gflags"), "hostname_port,timestamp,gflag_name,gflag_value\n192.168.66.80:7000,2023-01-10T10:1").unwrap();
+        assert!(!is_not_found(count_rows::<gflags::StoredGFlags>(&directory.join("gflags")).unwrap_err().as_ref()));
+        assert!(is_not_found(count_rows::<vars::StoredVars>(&directory.join("vars")).unwrap_err().as_ref()));
+        fs::remove_dir_all(directory.parent().unwrap()).unwrap();
+    }
+    #[test]
+    fn unit_missing_hosts() {
+        let files = vec![
+            ok("gflags", &["192.168.66.80:7000", "192.168.66.80:9000"]),
+            ok("vars", &["192.168.66.80:7000"]),
+            ok("nodeexporter", &[]),
+            // a file of a source that does not return rows for every endpoint is not checked.
+            ok("values", &[]),
+            VerifiedFile { source: "threads".to_string(), file: "threads".to_string(), status: FileStatus::Disabled },
+        ];
+        let endpoints = vec![
+            StoredEndpoints { hostname_port: "192.168.66.80:9300".to_string(), host: "192.168.66.80".to_string(), port: "9300".to_string(), role: "node_exporter".to_string(), detected_by: "port".to_string(), timestamp: chrono::Local::now() },
+        ];
+        assert_eq!(missing_hosts(&files, &endpoints), vec![
+            MissingHost { hostname_port: "192.168.66.80:9000".to_string(), file: "vars".to_string(), present_in: vec!["gflags".to_string()] },
+            MissingHost { hostname_port: "192.168.66.80:9300".to_string(), file: "nodeexporter".to_string(), present_in: vec!["endpoints".to_string()] },
+        ]);
+    }
+}
@@ -1,10 +1,19 @@
 use chrono::{DateTime, Local};
-use std::{fs, process, sync::mpsc::channel, time::Instant, error::Error, env, collections::BTreeMap};
+use std::{process, time::Instant, error::Error, collections::BTreeMap};
 use colored::Colorize;
 use regex::Regex;
 use serde_derive::{Serialize,Deserialize};
 use log::*;
-use crate::utility::{scan_host_port, http_get};
+use crate::snapshot::snapshot_directory;
+use futures::future::join_all;
+use crate::utility::http_get;
+use crate::endpoints::has_role;
+use crate::compression::create_snapshot_file;
+use crate::schema::open_snapshot_csv;
+use crate::output::{self, DiffRecord, push_changed_attribute};
+
+/// The endpoint roles that provide /api/v1/version.
+pub const ROLES: &[&str] = &["master", "tserver"];
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct Version {
@@ -78,13 +87,12 @@ impl AllStoredVersions {
         hosts: &Vec<&str>,
         ports: &Vec<&str>,
         snapshot_number: i32,
-        parallel: usize,
     )
     {
         info!("begin snapshot");
         let timer = Instant::now();
 
-        let allstoredversions = AllStoredVersions::read_versions(hosts, ports, parallel);
+        let allstoredversions = AllStoredVersions::read_versions(hosts, ports);
 
         allstoredversions.await.save_snapshot(snapshot_number)
             .unwrap_or_else(|e| {
@@ -100,32 +108,32 @@ impl AllStoredVersions {
     pub async fn read_versions(
         hosts: &Vec<&str>,
         ports: &Vec<&str>,
-        parallel: usize,
     ) -> AllStoredVersions
     {
         info!("begin parallel http read");
         let timer = Instant::now();
 
-        let pool = rayon::ThreadPoolBuilder::new().num_threads(parallel).build().unwrap();
-        let (tx, rx) = channel();
-        pool.scope(move |s| {
-            for host in hosts {
-                for port in ports {
-                    let tx = tx.clone();
-                    s.spawn(move |_| {
-                        let detail_snapshot_time = Local::now();
-                        let versions = AllStoredVersions::read_http(host, port);
-                        tx.send((format!("{}:{}", host, port), detail_snapshot_time, versions)).expect("error sending data via tx");
-                    });
+        let mut handles = Vec::new();
+        for host in hosts {
+            for port in ports {
+                if !has_role(host, port, ROLES) {
+                    continue;
                 }
+                let handle = async move {
+                    let detail_snapshot_time = Local::now();
+                    let versions = AllStoredVersions::read_http(host, port).await;
+                    (format!("{}:{}", host, port), detail_snapshot_time, versions)
+                };
+                handles.push(handle);
             }
-        });
+        }
+        let results = join_all(handles).await;
 
         info!("end parallel http read {:?}", timer.elapsed());
 
         let mut allstoredversions = AllStoredVersions::new();
 
-        for (hostname_port, detail_snapshot_time, versions) in rx {
+        for (hostname_port, detail_snapshot_time, versions) in results {
             allstoredversions.split_into_vectors(versions, &hostname_port, detail_snapshot_time);
         }
         allstoredversions
@@ -141,16 +149,12 @@ impl AllStoredVersions {
             self.stored_versions.push(StoredVersion::new_from_version(hostname_port, detail_snapshot_time, version));
         }
     }
-    pub fn read_http(
+    pub async fn read_http(
         host: &str,
         port: &str,
     ) -> Version
     {
-        let data_from_http = if scan_host_port( host, port) {
-            http_get(host, port, "api/v1/version")
-        } else {
-            String::new()
-        };
+        let data_from_http = http_get(host, port, "api/v1/version").await;
         AllStoredVersions::parse_version(data_from_http, host, port)
 
 /*
@@ -185,19 +189,15 @@ impl AllStoredVersions {
         snapshot_number: i32
     ) -> Result<(), Box<dyn Error>>
     {
-        let current_directory = env::current_dir()?;
-        let current_snapshot_directory = current_directory.join("yb_stats.snapshots").join(&snapshot_number.to_string());
+        let current_snapshot_directory = snapshot_directory().join(snapshot_number.to_string());
 
         let versions_file = &current_snapshot_directory.join("versions");
-        let file = fs::OpenOptions::new()
-            .create(true)
-            .write(true)
-            .open(versions_file)?;
+        let file = create_snapshot_file(versions_file)?;
         let mut writer = csv::Writer::from_writer(file);
         for row in self.stored_versions {
             writer.serialize(row)?;
         }
-        writer.flush()?;
+        writer.into_inner()?.finish()?;
 
         Ok(())
     }
@@ -209,11 +209,10 @@ impl AllStoredVersions {
             stored_versions: Vec::new(),
         };
 
-        let current_directory = env::current_dir()?;
-        let current_snapshot_directory = current_directory.join("yb_stats.snapshots").join(snapshot_number);
+        let current_snapshot_directory = snapshot_directory().join(snapshot_number);
 
         let versions_file = &current_snapshot_directory.join("versions");
-        let file = fs::File::open(versions_file)?;
+        let file = open_snapshot_csv(versions_file)?;
 
         let mut reader = csv::Reader::from_reader(file);
         for row in reader.deserialize() {
@@ -433,10 +432,40 @@ impl SnapshotDiffBTreeMapsVersions {
             }
         }
     }
+    /// This function returns the changed versions of [SnapshotDiffBTreeMapsVersions::print] as records for the machine-readable output, see [crate::output].
+    /// Only the version fields that have changed have a record.
+    pub fn records(
+        &self,
+    ) -> Vec<DiffRecord>
+    {
+        let mut records = Vec::new();
+        for (hostname, row) in self.btreemap_snapshotdiff_versions.iter() {
+            // a server that started or stopped during the snapshot is not reported.
+            if row.first_git_hash.is_empty() || row.second_git_hash.is_empty() {
+                continue;
+            }
+            let record = DiffRecord::object("versions", "*", DiffRecord {
+                hostname_port: hostname.to_string(),
+                metric_type: String::from("version"),
+                name: hostname.to_string(),
+                ..Default::default()
+            });
+            push_changed_attribute(&mut records, &record, "version_number", &row.first_version_number, &row.second_version_number);
+            push_changed_attribute(&mut records, &record, "build_number", &row.first_build_number, &row.second_build_number);
+            push_changed_attribute(&mut records, &record, "build_type", &row.first_build_type, &row.second_build_type);
+            push_changed_attribute(&mut records, &record, "build_timestamp", &row.first_build_timestamp, &row.second_build_timestamp);
+            push_changed_attribute(&mut records, &record, "git_hash", &row.first_git_hash, &row.second_git_hash);
+        }
+        records
+    }
     pub fn print(
         &self,
     )
     {
+        if output::is_machine_readable() {
+            output::write_records(&self.records());
+            return;
+        }
         for (hostname, row) in self.btreemap_snapshotdiff_versions.iter() {
             if row.first_git_hash.is_empty() || row.second_git_hash.is_empty() {
                 //println!("{} {:20} Versions: {:15} {:10} {:10} {:24} {:10}", "+".to_string().green(), hostname, row.second_version_number, row.second_build_number, row.second_build_type, row.second_build_timestamp, row.second_git_hash);
@@ -478,20 +507,18 @@ impl SnapshotDiffBTreeMapsVersions {
         &mut self,
         hosts: &Vec<&str>,
         ports: &Vec<&str>,
-        parallel: usize,
     )
     {
-        let allstoredversions = AllStoredVersions::read_versions(hosts, ports, parallel).await;
+        let allstoredversions = AllStoredVersions::read_versions(hosts, ports).await;
         SnapshotDiffBTreeMapsVersions::first_snapshot(allstoredversions);
     }
     pub async fn adhoc_read_second_snapshot(
         &mut self,
         hosts: &Vec<&str>,
         ports: &Vec<&str>,
-        parallel: usize,
     )
     {
-        let allstoredversions = AllStoredVersions::read_versions(hosts, ports, parallel).await;
+        let allstoredversions = AllStoredVersions::read_versions(hosts, ports).await;
         self.second_snapshot(allstoredversions);
     }
 }
@@ -520,26 +547,26 @@ mod tests {
 
     use crate::utility;
 
-    #[test]
-    fn integration_parse_versiondata_master() {
+    #[tokio::test]
+    async fn integration_parse_versiondata_master() {
         let hostname = utility::get_hostname_master();
         let port = utility::get_port_master();
 
         let mut allstoredversions = AllStoredVersions::new();
-        let data_parsed_from_json = AllStoredVersions::read_http(hostname.as_str(), port.as_str());
+        let data_parsed_from_json = AllStoredVersions::read_http(hostname.as_str(), port.as_str()).await;
         allstoredversions.split_into_vectors(data_parsed_from_json, format!("{}:{}", hostname, port).as_str(), Local::now());
 
         println!("{:?}", allstoredversions);
         // each daemon should return one row.
         assert!(allstoredversions.stored_versions.len() == 1);
     }
-    #[test]
-    fn integration_parse_versiondata_tserver() {
+    #[tokio::test]
+    async fn integration_parse_versiondata_tserver() {
         let hostname = utility::get_hostname_tserver();
         let port = utility::get_port_tserver();
 
         let mut allstoredversions = AllStoredVersions::new();
-        let data_parsed_from_json = AllStoredVersions::read_http(hostname.as_str(), port.as_str());
+        let data_parsed_from_json = AllStoredVersions::read_http(hostname.as_str(), port.as_str()).await;
         allstoredversions.split_into_vectors(data_parsed_from_json, format!("{}:{}", hostname, port).as_str(), Local::now());
 
         // each daemon should return one row.
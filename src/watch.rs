@@ -0,0 +1,504 @@
+//! The module for the watch mode (`--watch <condition>`): taking a snapshot when a condition on the metrics is met.
+//!
+//! The watch mode polls the metrics (and node_exporter, if a condition uses it) every `--watch-interval` seconds (default 10),
+//! and evaluates the conditions for every host against the previous poll. A condition is:
+//!
+//! `[node_exporter] <statistic name> [avg|rate|value] <operator> <threshold>[unit]`
+//!
+//! - `avg`: the sum difference divided by the count difference, of a statistic that has a count and a sum, such as a latency. This is the default for these statistics.
+//! - `rate`: the difference per second. This is the default for counters.
+//! - `value`: the current value. This is the default for gauges.
+//! - The operator is one of `>`, `>=`, `<` or `<=`. The threshold can have a time unit (`ns`, `us`, `ms` or `s`), which is converted to the unit of the statistic.
+//! - The statistics of the tablets of a table are added up per host.
+//!
+//! For example: `handler_latency_yb_tserver_TabletServerService_Write avg > 50ms` or `node_exporter node_load1 > 16`.
+//!
+//! When a condition is met on a host, a full snapshot is taken with a comment describing the trigger.
+//! With `--watch-pre-trigger`, the previous poll is saved as a snapshot first, so the situation before the trigger can be compared with the situation of the trigger.
+//! A condition fires again only after it has not been met during a poll and the `--watch-cooldown` seconds (default 300) since the last trigger have passed.
+use std::collections::BTreeMap;
+use std::fmt;
+use std::time::Instant;
+use chrono::{DateTime, Local};
+use log::*;
+use crate::metrics::AllStoredMetrics;
+use crate::node_exporter::AllStoredNodeExporterValues;
+use crate::value_statistic_details::ValueStatistics;
+use crate::countsum_statistic_details::CountSumStatistics;
+use crate::manifest::{ManifestSource, SnapshotManifest, SOURCES};
+use crate::endpoints::AllStoredEndpoints;
+use crate::snapshot::Snapshot;
+use crate::collection_errors::AllStoredCollectionErrors;
+
+/// The default poll interval in seconds.
+pub const DEFAULT_WATCH_INTERVAL: u64 = 10;
+/// The default number of seconds after a trigger before a condition can fire again.
+pub const DEFAULT_WATCH_COOLDOWN: u64 = 300;
+
+/// The source of the statistic of a condition.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WatchSource {
+    Metrics,
+    NodeExporter,
+}
+
+/// How the statistic of a condition is evaluated.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Aggregate {
+    Average,
+    Rate,
+    Value,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operator {
+    Greater,
+    GreaterOrEqual,
+    Less,
+    LessOrEqual,
+}
+
+impl Operator {
+    fn parse(operator: &str) -> Option<Operator> {
+        match operator {
+            ">" => Some(Operator::Greater),
+            ">=" => Some(Operator::GreaterOrEqual),
+            "<" => Some(Operator::Less),
+            "<=" => Some(Operator::LessOrEqual),
+            _ => None,
+        }
+    }
+    fn is_met(&self, observed: f64, threshold: f64) -> bool {
+        match self {
+            Operator::Greater => observed > threshold,
+            Operator::GreaterOrEqual => observed >= threshold,
+            Operator::Less => observed < threshold,
+            Operator::LessOrEqual => observed <= threshold,
+        }
+    }
+}
+
+/// The number of nanoseconds of a time unit, for the conversion of a threshold to the unit of a statistic.
+fn nanoseconds(unit: &str) -> Option<f64> {
+    match unit {
+        "ns" | "nanoseconds" => Some(1_f64),
+        "us" | "microseconds" => Some(1_000_f64),
+        "ms" | "milliseconds" => Some(1_000_000_f64),
+        "s" | "seconds" => Some(1_000_000_000_f64),
+        _ => None,
+    }
+}
+
+/// A condition of the watch mode.
+#[derive(Debug, Clone)]
+pub struct Condition {
+    /// The condition as specified.
+    pub text: String,
+    pub source: WatchSource,
+    pub name: String,
+    /// The aggregate, or None for the default of the statistic.
+    pub aggregate: Option<Aggregate>,
+    pub operator: Operator,
+    pub threshold: f64,
+    /// The time unit of the threshold.
+    pub unit: Option<String>,
+}
+
+impl fmt::Display for Condition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.text)
+    }
+}
+
+impl Condition {
+    /// This function parses a condition: `[node_exporter] <name> [avg|rate|value] <operator> <threshold>[unit]`.
+    pub fn parse(text: &str) -> Result<Condition, String> {
+        let mut words = text.split_whitespace().collect::<Vec<&str>>();
+        let source = if words.first() == Some(&"node_exporter") {
+            words.remove(0);
+            WatchSource::NodeExporter
+        } else {
+            WatchSource::Metrics
+        };
+        let (name, aggregate, operator, threshold) = match words[..] {
+            [name, operator, threshold] => (name, None, operator, threshold),
+            [name, aggregate, operator, threshold] => {
+                let aggregate = match aggregate {
+                    "avg" => Aggregate::Average,
+                    "rate" => Aggregate::Rate,
+                    "value" => Aggregate::Value,
+                    _ => return Err(format!("invalid condition: {}: {} is not avg, rate or value", text, aggregate)),
+                };
+                (name, Some(aggregate), operator, threshold)
+            },
+            _ => return Err(format!("invalid condition: {}: the format is: [node_exporter] <name> [avg|rate|value] <operator> <threshold>", text)),
+        };
+        if source == WatchSource::NodeExporter && aggregate == Some(Aggregate::Average) {
+            return Err(format!("invalid condition: {}: node_exporter statistics have no average", text));
+        }
+        let operator = Operator::parse(operator)
+            .ok_or_else(|| format!("invalid condition: {}: {} is not one of >, >=, < or <=", text, operator))?;
+        let number_length = threshold.find(|c: char| !(c.is_ascii_digit() || c == '.' || c == '-')).unwrap_or(threshold.len());
+        let (number, unit) = threshold.split_at(number_length);
+        let threshold = number.parse::<f64>()
+            .map_err(|_| format!("invalid condition: {}: {} is not a number", text, number))?;
+        let unit = match unit {
+            "" => None,
+            unit if nanoseconds(unit).is_some() => Some(unit.to_string()),
+            unit => return Err(format!("invalid condition: {}: {} is not a time unit (ns, us, ms or s)", text, unit)),
+        };
+        Ok(Condition { text: text.split_whitespace().collect::<Vec<&str>>().join(" "), source, name: name.to_string(), aggregate, operator, threshold, unit })
+    }
+    /// The threshold converted to the unit of the statistic. If the statistic is not in a time unit, the threshold is used as is.
+    fn threshold_in(&self, statistic_unit: &str) -> f64 {
+        match (self.unit.as_deref().and_then(nanoseconds), nanoseconds(statistic_unit)) {
+            (Some(threshold_unit), Some(statistic_unit)) => self.threshold * threshold_unit / statistic_unit,
+            _ => self.threshold,
+        }
+    }
+    /// This function evaluates the condition for every host, and returns the hosts for which the condition is met, with the observed value.
+    /// The conditions that need a difference (avg and rate) can only be evaluated if there is a previous poll.
+    pub fn evaluate(
+        &self,
+        previous: Option<&Poll>,
+        current: &Poll,
+    ) -> Vec<(String, f64)>
+    {
+        let current_statistics = current.statistics(self);
+        let previous_statistics = previous.map(|previous| previous.statistics(self)).unwrap_or_default();
+        let mut met = Vec::new();
+        for (hostname_port, statistic) in current_statistics {
+            let aggregate = self.aggregate.unwrap_or(statistic.default_aggregate);
+            let observed = match aggregate {
+                Aggregate::Value => Some(statistic.value),
+                Aggregate::Rate | Aggregate::Average => previous_statistics.get(&hostname_port).and_then(|previous_statistic| {
+                    let seconds = (statistic.timestamp - previous_statistic.timestamp).num_milliseconds() as f64 / 1000_f64;
+                    let count = statistic.value - previous_statistic.value;
+                    // a negative difference means the server was restarted.
+                    if seconds <= 0_f64 || count < 0_f64 {
+                        None
+                    } else if aggregate == Aggregate::Rate {
+                        Some(count / seconds)
+                    } else if count > 0_f64 && statistic.has_sum {
+                        Some((statistic.sum - previous_statistic.sum) / count)
+                    } else {
+                        None
+                    }
+                }),
+            };
+            if let Some(observed) = observed {
+                if self.operator.is_met(observed, self.threshold_in(&statistic.unit)) {
+                    met.push((hostname_port, observed));
+                }
+            }
+        }
+        met
+    }
+}
+
+/// A statistic of a host in a poll: the values of the tablets of a table are added up.
+#[derive(Debug, Clone)]
+struct Statistic {
+    timestamp: DateTime<Local>,
+    /// The value, or the count for statistics that have a count and a sum.
+    value: f64,
+    sum: f64,
+    has_sum: bool,
+    unit: String,
+    default_aggregate: Aggregate,
+}
+
+/// The data of a poll.
+pub struct Poll {
+    pub time: DateTime<Local>,
+    pub duration_ms: u128,
+    pub metrics: Option<AllStoredMetrics>,
+    pub node_exporter: Option<AllStoredNodeExporterValues>,
+    pub collection_errors: AllStoredCollectionErrors,
+}
+
+impl Poll {
+    /// This function reads the sources that are used by the conditions.
+    pub async fn read(
+        hosts: &Vec<&str>,
+        ports: &Vec<&str>,
+        conditions: &[Condition],
+    ) -> Poll
+    {
+        let time = Local::now();
+        let timer = Instant::now();
+        let uses = |source: WatchSource| conditions.iter().any(|condition| condition.source == source);
+        let metrics = async {
+            if uses(WatchSource::Metrics) { Some(AllStoredMetrics::read_metrics(hosts, ports).await) } else { None }
+        };
+        let node_exporter = async {
+            if uses(WatchSource::NodeExporter) { Some(AllStoredNodeExporterValues::read_nodeexporter(hosts, ports).await) } else { None }
+        };
+        let (metrics, node_exporter) = tokio::join!(metrics, node_exporter);
+        // the errors of the poll are taken, so that these do not accumulate in polls that are not saved.
+        let collection_errors = AllStoredCollectionErrors::take_recorded();
+        Poll { time, duration_ms: timer.elapsed().as_millis(), metrics, node_exporter, collection_errors }
+    }
+    /// This function returns the statistic of the condition per host.
+    fn statistics(
+        &self,
+        condition: &Condition,
+    ) -> BTreeMap<String, Statistic>
+    {
+        let mut statistics: BTreeMap<String, Statistic> = BTreeMap::new();
+        let mut add = |hostname_port: &str, statistic: Statistic| {
+            statistics.entry(hostname_port.to_string())
+                .and_modify(|existing| {
+                    existing.value += statistic.value;
+                    existing.sum += statistic.sum;
+                })
+                .or_insert(statistic);
+        };
+        match condition.source {
+            WatchSource::Metrics => {
+                let Some(metrics) = &self.metrics else { return statistics };
+                let value_statistics = ValueStatistics::create();
+                for row in metrics.stored_values.iter().filter(|row| row.metric_name == condition.name) {
+                    let details = value_statistics.lookup(&row.metric_name);
+                    add(&row.hostname_port, Statistic {
+                        timestamp: row.timestamp,
+                        value: row.metric_value as f64,
+                        sum: 0_f64,
+                        has_sum: false,
+                        unit: details.unit.to_string(),
+                        default_aggregate: if details.stat_type == "gauge" { Aggregate::Value } else { Aggregate::Rate },
+                    });
+                }
+                let countsum_statistics = CountSumStatistics::create();
+                for row in metrics.stored_countsum.iter().filter(|row| row.metric_name == condition.name) {
+                    add(&row.hostname_port, Statistic {
+                        timestamp: row.timestamp,
+                        value: row.metric_total_count as f64,
+                        sum: row.metric_total_sum as f64,
+                        has_sum: true,
+                        unit: countsum_statistics.lookup(&row.metric_name).unit.to_string(),
+                        default_aggregate: Aggregate::Average,
+                    });
+                }
+                for row in metrics.stored_countsumrows.iter().filter(|row| row.metric_name == condition.name) {
+                    add(&row.hostname_port, Statistic {
+                        timestamp: row.timestamp,
+                        value: row.metric_count as f64,
+                        sum: row.metric_sum as f64,
+                        has_sum: true,
+                        unit: String::from("microseconds"),
+                        default_aggregate: Aggregate::Average,
+                    });
+                }
+            },
+            WatchSource::NodeExporter => {
+                let Some(node_exporter) = &self.node_exporter else { return statistics };
+                for row in node_exporter.stored_nodeexportervalues.iter().filter(|row| row.node_exporter_name == condition.name) {
+                    add(&row.hostname_port, Statistic {
+                        timestamp: row.timestamp,
+                        value: row.node_exporter_value,
+                        sum: 0_f64,
+                        has_sum: false,
+                        unit: String::new(),
+                        default_aggregate: if row.node_exporter_type == "counter" { Aggregate::Rate } else { Aggregate::Value },
+                    });
+                }
+            },
+        }
+        statistics
+    }
+    /// This function saves the poll as a snapshot, with the sources that are not polled disabled in the manifest.
+    /// It returns the snapshot number.
+    pub async fn save_snapshot(
+        self,
+        endpoints: &AllStoredEndpoints,
+        comment: String,
+        cluster: Option<String>,
+    ) -> i32
+    {
+        let snapshot_number = Snapshot::reserve_snapshot_number();
+        endpoints.save_snapshot(snapshot_number)
+            .unwrap_or_else(|e| error!("error saving snapshot {}: {}", snapshot_number, e));
+        let polled_source = |name: &str| ManifestSource {
+            name: name.to_string(),
+            enabled: true,
+            start_time: Some(self.time),
+            end_time: Some(self.time + chrono::Duration::milliseconds(self.duration_ms as i64)),
+            duration_ms: Some(self.duration_ms),
+        };
+        let mut sources = Vec::new();
+        for source in SOURCES {
+            match *source {
+                "metrics" if self.metrics.is_some() => sources.push(polled_source(source)),
+                "node_exporter" if self.node_exporter.is_some() => sources.push(polled_source(source)),
+                _ => sources.push(ManifestSource::disabled(source)),
+            }
+        }
+        if let Some(metrics) = self.metrics {
+            metrics.save_snapshot(snapshot_number)
+                .unwrap_or_else(|e| error!("error saving snapshot {}: {}", snapshot_number, e));
+        }
+        if let Some(node_exporter) = self.node_exporter {
+            node_exporter.save_snapshot(snapshot_number)
+                .unwrap_or_else(|e| error!("error saving snapshot {}: {}", snapshot_number, e));
+        }
+        // The errors of reading the cluster UUID belong to this snapshot, together with the errors of the poll.
+        let cluster_uuid = SnapshotManifest::read_cluster_uuid(endpoints).await;
+        let mut collection_errors = self.collection_errors;
+        collection_errors.stored_collection_errors.append(&mut AllStoredCollectionErrors::take_recorded().stored_collection_errors);
+        collection_errors.perform_save(snapshot_number);
+        SnapshotManifest::perform_snapshot(snapshot_number, cluster.clone(), cluster_uuid, self.time, endpoints, sources).await;
+        Snapshot::insert_snapshot(snapshot_number, self.time, comment, cluster.unwrap_or_default())
+            .unwrap_or_else(|e| error!("error adding snapshot {} to the snapshot index: {}", snapshot_number, e));
+        snapshot_number
+    }
+}
+
+/// The state of a condition between polls.
+pub struct WatchedCondition {
+    pub condition: Condition,
+    /// The condition is met in the last poll.
+    met: bool,
+    last_trigger: Option<Instant>,
+}
+
+impl WatchedCondition {
+    pub fn new(condition: Condition) -> Self {
+        WatchedCondition { condition, met: false, last_trigger: None }
+    }
+    /// This function evaluates the condition, and returns the description of the trigger if it fires:
+    /// the condition must be met, must not have been met in the previous poll, and the cooldown since the last trigger must have passed.
+    pub fn check(
+        &mut self,
+        previous: Option<&Poll>,
+        current: &Poll,
+        cooldown: u64,
+    ) -> Option<String>
+    {
+        let met_hosts = self.condition.evaluate(previous, current);
+        let was_met = self.met;
+        self.met = !met_hosts.is_empty();
+        let cooled_down = self.last_trigger.map(|last_trigger| last_trigger.elapsed().as_secs() >= cooldown).unwrap_or(true);
+        if !self.met || was_met || !cooled_down {
+            return None;
+        }
+        self.last_trigger = Some(Instant::now());
+        let unit = self.condition.unit.as_deref().unwrap_or_default();
+        let hosts = met_hosts.iter()
+            .map(|(hostname_port, observed)| {
+                // the observed value is shown in the unit of the threshold.
+                let observed = match (self.condition.unit.as_deref().and_then(nanoseconds), nanoseconds(&self.statistic_unit(current, hostname_port))) {
+                    (Some(threshold_unit), Some(statistic_unit)) => *observed * statistic_unit / threshold_unit,
+                    _ => *observed,
+                };
+                format!("{}: {:.3}{}", hostname_port, observed, unit)
+            })
+            .collect::<Vec<String>>()
+            .join(", ");
+        Some(format!("{} ({})", self.condition, hosts))
+    }
+    /// The unit of the statistic of the condition for the host.
+    fn statistic_unit(
+        &self,
+        current: &Poll,
+        hostname_port: &str,
+    ) -> String
+    {
+        current.statistics(&self.condition).get(hostname_port).map(|statistic| statistic.unit.to_string()).unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+    use crate::metrics::{StoredCountSum, StoredValues};
+    use crate::node_exporter::StoredNodeExporterValues;
+
+    fn countsum(hostname_port: &str, second: u32, metric_total_count: u64, metric_total_sum: u64) -> StoredCountSum {
+        StoredCountSum {
+            hostname_port: hostname_port.to_string(),
+            timestamp: Local.with_ymd_and_hms(2023, 1, 10, 10, 0, second).unwrap(),
+            metric_type: String::from("server"),
+            metric_id: String::from("yb.tabletserver"),
+            attribute_namespace: String::from("-"),
+            attribute_table_name: String::from("-"),
+            metric_name: String::from("handler_latency_yb_tserver_TabletServerService_Write"),
+            metric_total_count,
+            metric_min: 0,
+            metric_mean: 0.,
+            metric_percentile_75: 0,
+            metric_percentile_95: 0,
+            metric_percentile_99: 0,
+            metric_percentile_99_9: 0,
+            metric_percentile_99_99: 0,
+            metric_max: 0,
+            metric_total_sum,
+        }
+    }
+    fn poll(second: u32, countsum: Vec<StoredCountSum>, values: Vec<StoredValues>, node_exporter: Vec<StoredNodeExporterValues>) -> Poll {
+        Poll {
+            time: Local.with_ymd_and_hms(2023, 1, 10, 10, 0, second).unwrap(),
+            duration_ms: 0,
+            metrics: Some(AllStoredMetrics { stored_values: values, stored_countsum: countsum, stored_countsumrows: Vec::new() }),
+            node_exporter: Some(AllStoredNodeExporterValues { stored_nodeexportervalues: node_exporter }),
+            collection_errors: AllStoredCollectionErrors::default(),
+        }
+    }
+
+    #[test]
+    fn unit_watch_condition_parse() {
+        let condition = Condition::parse("handler_latency_yb_tserver_TabletServerService_Write  avg > 50ms").unwrap();
+        assert_eq!((condition.source, condition.aggregate, condition.operator, condition.threshold, condition.unit.as_deref()), (WatchSource::Metrics, Some(Aggregate::Average), Operator::Greater, 50_f64, Some("ms")));
+        assert_eq!(condition.to_string(), "handler_latency_yb_tserver_TabletServerService_Write avg > 50ms");
+        assert_eq!(condition.threshold_in("microseconds"), 50_000_f64);
+        let condition = Condition::parse("node_exporter node_load1 >= 16").unwrap();
+        assert_eq!((condition.source, condition.name.as_str(), condition.aggregate, condition.operator), (WatchSource::NodeExporter, "node_load1", None, Operator::GreaterOrEqual));
+        assert_eq!(condition.threshold_in("?"), 16_f64);
+        assert!(Condition::parse("rows_inserted > ").is_err());
+        assert!(Condition::parse("rows_inserted max > 5").is_err());
+        assert!(Condition::parse("rows_inserted rate != 5").is_err());
+        assert!(Condition::parse("rows_inserted rate > 5kb").is_err());
+        assert!(Condition::parse("node_exporter node_load1 avg > 5").is_err());
+    }
+    #[test]
+    fn unit_watch_condition_evaluate() {
+        let condition = Condition::parse("handler_latency_yb_tserver_TabletServerService_Write avg > 50ms").unwrap();
+        let previous = poll(0, vec![countsum("yb-1:9000", 0, 100, 1_000_000), countsum("yb-2:9000", 0, 100, 1_000_000)], Vec::new(), Vec::new());
+        // yb-1: 100 writes of 72ms on average, yb-2: 100 writes of 10ms.
+        let current = poll(10, vec![countsum("yb-1:9000", 10, 200, 8_200_000), countsum("yb-2:9000", 10, 200, 2_000_000)], Vec::new(), Vec::new());
+        assert!(condition.evaluate(None, &current).is_empty());
+        assert_eq!(condition.evaluate(Some(&previous), &current), vec![(String::from("yb-1:9000"), 72_000_f64)]);
+        let condition = Condition::parse("handler_latency_yb_tserver_TabletServerService_Write rate >= 10").unwrap();
+        assert_eq!(condition.evaluate(Some(&previous), &current).len(), 2);
+
+        let node_exporter = |value: f64| StoredNodeExporterValues {
+            hostname_port: String::from("yb-1:9300"),
+            timestamp: Local.with_ymd_and_hms(2023, 1, 10, 10, 0, 0).unwrap(),
+            node_exporter_name: String::from("node_load1"),
+            node_exporter_type: String::from("gauge"),
+            node_exporter_labels: String::new(),
+            node_exporter_category: String::from("all"),
+            node_exporter_value: value,
+        };
+        let condition = Condition::parse("node_exporter node_load1 > 16").unwrap();
+        assert!(condition.evaluate(None, &poll(0, Vec::new(), Vec::new(), vec![node_exporter(3.)])).is_empty());
+        assert_eq!(condition.evaluate(None, &poll(0, Vec::new(), Vec::new(), vec![node_exporter(17.5)])), vec![(String::from("yb-1:9300"), 17.5_f64)]);
+    }
+    #[test]
+    fn unit_watch_condition_fires_once() {
+        let mut watched = WatchedCondition::new(Condition::parse("handler_latency_yb_tserver_TabletServerService_Write avg > 50ms").unwrap());
+        let first = poll(0, vec![countsum("yb-1:9000", 0, 100, 1_000_000)], Vec::new(), Vec::new());
+        let slow = poll(10, vec![countsum("yb-1:9000", 10, 200, 8_200_000)], Vec::new(), Vec::new());
+        let still_slow = poll(20, vec![countsum("yb-1:9000", 20, 300, 15_400_000)], Vec::new(), Vec::new());
+        let fast = poll(30, vec![countsum("yb-1:9000", 30, 400, 16_400_000)], Vec::new(), Vec::new());
+        let slow_again = poll(40, vec![countsum("yb-1:9000", 40, 500, 23_600_000)], Vec::new(), Vec::new());
+        assert_eq!(watched.check(Some(&first), &slow, 0), Some(String::from("handler_latency_yb_tserver_TabletServerService_Write avg > 50ms (yb-1:9000: 72.000ms)")));
+        assert_eq!(watched.check(Some(&slow), &still_slow, 0), None);
+        assert_eq!(watched.check(Some(&still_slow), &fast, 0), None);
+        // met again, but within the cooldown.
+        assert_eq!(watched.check(Some(&fast), &slow_again, 300), None);
+        assert_eq!(watched.check(Some(&slow_again), &fast, 0), None);
+        assert!(watched.check(Some(&fast), &slow_again, 0).is_some());
+    }
+}